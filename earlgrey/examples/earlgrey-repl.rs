@@ -0,0 +1,31 @@
+#![cfg_attr(feature = "strict", deny(warnings))]
+
+// Try out an EBNF grammar, one input per line (tokens split on whitespace)
+// cargo run --example earlgrey-repl -- <grammar.ebnf> <start-symbol>
+// Commands: :reload :all :chart :expect <prefix>
+
+use rustyline::error::ReadlineError;
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (path, start) = match &args[..] {
+        [path, start] => (path, start),
+        _ => return Err("usage: earlgrey-repl <grammar.ebnf> <start-symbol>".to_string()),
+    };
+    let mut repl = earlgrey::repl::GrammarRepl::open(path, start)?;
+
+    let mut rl = rustyline::DefaultEditor::new().map_err(|e| e.to_string())?;
+    loop {
+        match rl.readline("~> ") {
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(format!("Readline err: {:?}", e)),
+            Ok(line) => {
+                let _ = rl.add_history_entry(&line);
+                let out = repl.handle(&line);
+                if !out.is_empty() {
+                    println!("{}", out);
+                }
+            }
+        }
+    }
+}
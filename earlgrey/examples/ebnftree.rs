@@ -1,4 +1,4 @@
-#![deny(warnings)]
+#![cfg_attr(feature = "strict", deny(warnings))]
 
 struct Tokenizer<I: Iterator<Item=char>>(lexers::Scanner<I>);
 
@@ -0,0 +1,281 @@
+#![cfg_attr(feature = "strict", deny(warnings))]
+
+// Parse INI files with typed values into nested maps: section -> key -> value
+// cargo run --features regex --example ini -- <config.ini>
+//
+//   # comments start with '#' or ';' and run to the end of the line
+//   name = "tox"        ; keys before any section go to section ""
+//   [server]
+//   port = 8080
+//   ratio = -0.75
+//   debug = true
+//
+// Each line is parsed on its own with the grammar below, so errors point at
+// a line and the column of the token where the parse died. Sections can be
+// repeated, their keys are merged. Setting a key twice in the same section
+// is an error reporting both lines.
+//
+// The grammar has no skip terminals, earlgrey matches every token it's given,
+// so the tokenizer drops whitespace and comments instead. Nor does it use
+// flattened trees: actions already build the Config and there's no tree to
+// print. Keys, numbers and strings are regex terminals.
+
+use earlgrey::{EarleyForest, EarleyParser, EbnfGrammarParser};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+
+const GRAMMAR: &str = r#"
+    line    := section | pair ;
+    section := '[' Key ']' ;
+    pair    := Key '=' value ;
+    value   := String | Number | 'true' | 'false' ;
+"#;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+pub type Config = HashMap<String, HashMap<String, Value>>;
+
+// Where parsing failed, line and col start at 1
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.message)
+    }
+}
+
+// What each reduction builds, a line ends up as a Section or a Pair
+#[derive(Debug, Clone)]
+enum Ini {
+    Token(String),
+    Value(Value),
+    Section(String),
+    Pair(String, Value),
+}
+
+// Tokens of a line and the column each starts at. Whitespace and comments
+// are dropped here so the grammar doesn't need to mention them.
+fn tokenize(line: &str) -> Result<Vec<(String, usize)>, usize> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+    while idx < chars.len() {
+        let start = idx;
+        match chars[idx] {
+            c if c.is_whitespace() => { idx += 1; continue; }
+            '#' | ';' => break,
+            '[' | ']' | '=' => idx += 1,
+            '"' => {
+                idx += 1;
+                while idx < chars.len() && chars[idx] != '"' {
+                    idx += if chars[idx] == '\\' { 2 } else { 1 };
+                }
+                if idx >= chars.len() {
+                    return Err(start + 1);
+                }
+                idx += 1;
+            },
+            _ => while idx < chars.len() && !chars[idx].is_whitespace() &&
+                       !"[]=#;\"".contains(chars[idx]) {
+                idx += 1;
+            },
+        }
+        tokens.push((chars[start..idx].iter().collect(), start + 1));
+    }
+    Ok(tokens)
+}
+
+fn unquote(token: &str) -> String {
+    let mut out = String::new();
+    let mut chars = token[1..token.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some(other) => out.push(other),
+                None => (),
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn regex(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| e.to_string())
+}
+
+fn ini_parser() -> Result<(EarleyParser, EarleyForest<'static, Ini>), String> {
+    let grammar = EbnfGrammarParser::new(GRAMMAR, "line")
+        .plug_regex("Key", regex(r"^[A-Za-z_][A-Za-z0-9_.-]*$")?)
+        .plug_regex("Number", regex(r"^-?[0-9]+(\.[0-9]+)?$")?)
+        .plug_regex("String", regex(r#"^"(\\.|[^"\\])*"$"#)?)
+        .into_grammar()?;
+    let mut forest = EarleyForest::new(|symbol, token| match symbol {
+        "String" => Ini::Value(Value::Str(unquote(token))),
+        "Number" => Ini::Value(match token.parse::<i64>() {
+            Ok(int) => Value::Int(int),
+            Err(_) => Value::Float(token.parse().expect("BUG: Number isn't a number")),
+        }),
+        "true" | "false" => Ini::Value(Value::Bool(token == "true")),
+        _ => Ini::Token(token.to_string()),
+    });
+    let token = |node: &Ini| match node {
+        Ini::Token(token) => token.clone(),
+        other => panic!("BUG: expected a token, found {:?}", other),
+    };
+    forest.action("line -> section", |mut n| n.remove(0));
+    forest.action("line -> pair", |mut n| n.remove(0));
+    forest.action("section -> [ Key ]", move |n| Ini::Section(token(&n[1])));
+    forest.action("pair -> Key = value", move |mut n| match n.remove(2) {
+        Ini::Value(value) => Ini::Pair(token(&n[0]), value),
+        other => panic!("BUG: expected a value, found {:?}", other),
+    });
+    for rule in ["value -> String", "value -> Number", "value -> true", "value -> false"] {
+        forest.action(rule, |mut n| n.remove(0));
+    }
+    Ok((EarleyParser::new(grammar), forest))
+}
+
+pub fn parse_ini(source: &str) -> Result<Config, ParseError> {
+    let (parser, forest) = ini_parser().expect("BUG: bad INI grammar");
+    let mut config = Config::new();
+    let mut section = String::new();
+    // line where each (section, key) was first set
+    let mut defined = HashMap::new();
+    for (idx, text) in source.lines().enumerate() {
+        let line = idx + 1;
+        let error = |col, message| ParseError{line, col, message};
+        let tokens = tokenize(text)
+            .map_err(|col| error(col, "unterminated string".to_string()))?;
+        if tokens.is_empty() {
+            continue;
+        }
+        let (parsed, stats) = parser.parse_chart(tokens.iter().map(|(token, _)| token));
+        let trees = match (parsed, stats.dead_at()) {
+            (Ok(trees), _) => trees,
+            (Err(_), Some(dead)) => {
+                let prefix = parser.parse_chart(tokens[..dead].iter().map(|(token, _)| token)).1;
+                let expected = match prefix.expected.is_empty() {
+                    true => "the line should end before it".to_string(),
+                    false => format!("expected one of: {}", prefix.expected.join(" ")),
+                };
+                return Err(error(tokens[dead].1, format!("unexpected {:?}, {}", tokens[dead].0, expected)));
+            },
+            (Err(_), None) => return Err(error(text.chars().count() + 1, format!(
+                "incomplete line, expected one of: {}", stats.expected.join(" ")))),
+        };
+        match forest.eval(&trees).map_err(|e| error(1, e))? {
+            Ini::Section(name) => {
+                config.entry(name.clone()).or_default();
+                section = name;
+            },
+            Ini::Pair(key, value) => {
+                if let Some(first) = defined.insert((section.clone(), key.clone()), line) {
+                    return Err(error(tokens[0].1, format!(
+                        "duplicate key {:?} in section {:?}, first set at line {}", key, section, first)));
+                }
+                config.entry(section.clone()).or_default().insert(key, value);
+            },
+            other => panic!("BUG: a line evaluated to {:?}", other),
+        }
+    }
+    Ok(config)
+}
+
+fn main() -> Result<(), String> {
+    let path = std::env::args().nth(1).ok_or("usage: ini <config.ini>")?;
+    let source = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+    let config = parse_ini(&source).map_err(|e| format!("{}:{}", path, e))?;
+    let mut sections: Vec<_> = config.iter().collect();
+    sections.sort_by_key(|(name, _)| name.as_str());
+    for (name, pairs) in sections {
+        println!("[{}]", name);
+        let mut pairs: Vec<_> = pairs.iter().collect();
+        pairs.sort_by_key(|(key, _)| key.as_str());
+        for (key, value) in pairs {
+            println!("  {} = {:?}", key, value);
+        }
+    }
+    Ok(())
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_ini, ParseError, Value};
+
+    fn error(line: usize, col: usize, message: &str) -> Result<super::Config, ParseError> {
+        Err(ParseError{line, col, message: message.to_string()})
+    }
+
+    #[test]
+    fn valid_files() {
+        let config = parse_ini(r#"
+            # top level keys go to section ""
+            name = "tox parser"   ; a trailing comment
+            [server]
+            port = 8080
+            ratio=-0.75
+            debug = true
+            motd = "say \"hi\" # not a comment"
+
+            [db.replica]
+            enabled = false
+            [server]
+            host = "localhost"
+        "#).unwrap();
+        assert_eq!(config.len(), 3);
+        assert_eq!(config[""]["name"], Value::Str("tox parser".to_string()));
+        let server = &config["server"];
+        assert_eq!(server.len(), 5);
+        assert_eq!(server["port"], Value::Int(8080));
+        assert_eq!(server["ratio"], Value::Float(-0.75));
+        assert_eq!(server["debug"], Value::Bool(true));
+        assert_eq!(server["motd"], Value::Str("say \"hi\" # not a comment".to_string()));
+        // repeated sections merge
+        assert_eq!(server["host"], Value::Str("localhost".to_string()));
+        assert_eq!(config["db.replica"]["enabled"], Value::Bool(false));
+        // empty sections are kept, empty files are fine
+        assert!(parse_ini("[empty]").unwrap()["empty"].is_empty());
+        assert!(parse_ini("\n  # nothing\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        assert_eq!(parse_ini("[a]\nx = 1\n[b]\nx = 2\n[a]\n  x = 3"), error(
+            6, 3, "duplicate key \"x\" in section \"a\", first set at line 2"));
+        assert_eq!(parse_ini("x = 1\n x = 1"), error(
+            2, 2, "duplicate key \"x\" in section \"\", first set at line 1"));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        // a token that doesn't fit, reported with what could go there
+        assert_eq!(parse_ini("[a]\nport = 80 80"), error(2, 11, "unexpected \"80\", the line should end before it"));
+        assert_eq!(parse_ini("key = yes"), error(
+            1, 7, "unexpected \"yes\", expected one of: Number String false true"));
+        assert_eq!(parse_ini("[1st]"), error(1, 2, "unexpected \"1st\", expected one of: Key"));
+        // the line ends too soon
+        assert_eq!(parse_ini("\n[server"), error(2, 8, "incomplete line, expected one of: ]"));
+        assert_eq!(parse_ini("key =   # no value"), error(
+            1, 19, "incomplete line, expected one of: Number String false true"));
+        // strings must close on their line
+        assert_eq!(parse_ini("a = 1\nb = \"open\n\""), error(2, 5, "unterminated string"));
+        assert_eq!(parse_ini("c = \"\\\""), error(1, 5, "unterminated string"));
+    }
+}
@@ -0,0 +1,127 @@
+use super::grammar::{Grammar, Rule, Symbol};
+use super::parser::EarleyParser;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// Rough parse cost of a grammar in the number of tokens `n`, see
+/// `EarleyParser::complexity_estimate`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComplexityBound {
+    pub worst_case: String,
+    pub expected_case: String,
+    // What the estimate is based on, eg: rules making the grammar ambiguous
+    pub note: String,
+}
+
+// NonTerms that can derive the empty string
+fn nullables(grammar: &Grammar) -> HashSet<&str> {
+    let mut nullable = HashSet::new();
+    loop {
+        let before = nullable.len();
+        for rule in &grammar.rules {
+            if rule.spec.iter().all(|s| nullable.contains(s.name()) && !s.is_terminal()) {
+                nullable.insert(rule.head.as_str());
+            }
+        }
+        if nullable.len() == before {
+            return nullable;
+        }
+    }
+}
+
+// NonTerms each head reaches (transitively) through the symbols `edges`
+// picks from its rules
+fn reach<'g>(grammar: &'g Grammar, edges: impl Fn(&'g Rule) -> Vec<&'g str>)
+        -> HashMap<&'g str, HashSet<&'g str>> {
+    let mut direct: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for rule in &grammar.rules {
+        direct.entry(rule.head.as_str()).or_default().extend(edges(rule));
+    }
+    let mut reached = HashMap::new();
+    for &head in direct.keys() {
+        let mut seen = HashSet::new();
+        let mut pending: Vec<_> = direct[head].iter().cloned().collect();
+        while let Some(next) = pending.pop() {
+            if seen.insert(next) {
+                pending.extend(direct.get(next).into_iter().flatten());
+            }
+        }
+        reached.insert(head, seen);
+    }
+    reached
+}
+
+// NonTerms up to (including) the first symbol that isn't nullable, from
+// either end of a rule
+fn edge<'g>(symbols: impl Iterator<Item = &'g Rc<Symbol>>, nullable: &HashSet<&str>) -> Vec<&'g str> {
+    let mut edge = Vec::new();
+    for symbol in symbols {
+        if !symbol.is_terminal() {
+            edge.push(symbol.name());
+        }
+        if !nullable.contains(symbol.name()) {
+            break;
+        }
+    }
+    edge
+}
+
+// NonTerms that can be all a rule derives, the rest being nullable
+fn alone<'g>(rule: &'g Rule, nullable: &HashSet<&str>) -> Vec<&'g str> {
+    rule.spec.iter().enumerate()
+        .filter(|(idx, s)| !s.is_terminal() && rule.spec.iter().enumerate()
+                .all(|(other, s)| other == *idx || nullable.contains(s.name())))
+        .map(|(_, s)| s.name())
+        .collect()
+}
+
+impl EarleyParser {
+    /// Estimate how parse time grows with the input for `grammar`. Earley
+    /// parsing is O(n³) at worst, O(n²) for unambiguous grammars and O(n)
+    /// for deterministic ones (right recursion included, Leo items keep it
+    /// linear). The analysis is a heuristic: it flags rules that are left
+    /// and right recursive at once (eg: `E -> E + E`) and derivation cycles
+    /// (eg: `A -> A`) as ambiguous, it can't prove a grammar deterministic.
+    pub fn complexity_estimate(grammar: &Grammar, input_length: usize) -> ComplexityBound {
+        let nullable = nullables(grammar);
+        let left = reach(grammar, |rule| edge(rule.spec.iter(), &nullable));
+        let right = reach(grammar, |rule| edge(rule.spec.iter().rev(), &nullable));
+        let unit = reach(grammar, |rule| alone(rule, &nullable));
+        let reaches = |from: &HashMap<&str, HashSet<&str>>, sym: &str, head: &str|
+            sym == head || from.get(sym).is_some_and(|r| r.contains(head));
+
+        let mut cyclic: Vec<_> = unit.iter()
+            .filter(|(head, r)| r.contains(*head))
+            .map(|(head, _)| head.to_string())
+            .collect();
+        cyclic.sort();
+        let ambiguous: Vec<_> = grammar.rules.iter()
+            .filter(|rule| rule.spec.len() > 1)
+            .filter(|rule| {
+                edge(rule.spec.iter(), &nullable).iter().any(|s| reaches(&left, s, &rule.head)) &&
+                    edge(rule.spec.iter().rev(), &nullable).iter().any(|s| reaches(&right, s, &rule.head))
+            })
+            .map(|rule| rule.to_string())
+            .collect();
+        let nested = reach(grammar, |rule| rule.spec.iter()
+                           .filter(|s| !s.is_terminal()).map(|s| s.name()).collect());
+        let recursive = nested.iter().any(|(head, r)| r.contains(head));
+
+        let (power, mut note) = if !cyclic.is_empty() {
+            (3, format!("derivation cycles through {} make parses infinitely ambiguous",
+                        cyclic.join(", ")))
+        } else if !ambiguous.is_empty() {
+            (3, format!("rules that are left and right recursive make the grammar ambiguous: {}",
+                        ambiguous.join(", ")))
+        } else if !recursive {
+            (1, "no recursive rules, only bounded inputs can parse".to_string())
+        } else {
+            (2, "no ambiguous rule found, deterministic grammars parse in O(n)".to_string())
+        };
+        let operations = |power| (input_length as u128).saturating_pow(power);
+        note += &format!(", about {} chart operations for {} tokens (up to {})",
+                         operations(power), input_length, operations(3));
+        let order = |power| ["O(n)", "O(n²)", "O(n³)"][power as usize - 1].to_string();
+        ComplexityBound{worst_case: order(3), expected_case: order(power), note}
+    }
+}
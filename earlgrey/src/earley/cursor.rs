@@ -0,0 +1,139 @@
+use super::parser::{EarleyParser, ParseTrees};
+use std::fmt::Debug;
+
+/// Token stream that can be rewound, to hand one input to several parsers.
+/// Tokens pulled from the underlying iterator are kept so they can be read
+/// again after a `rewind`, eg: the lookahead `parse_prefix` didn't use.
+pub struct TokenCursor<I: Iterator> {
+    source: I,
+    pulled: Vec<I::Item>,
+    position: usize,
+}
+
+impl<I: Iterator> TokenCursor<I> where I::Item: Clone {
+    pub fn new(tokens: I) -> Self {
+        TokenCursor{source: tokens, pulled: Vec::new(), position: 0}
+    }
+
+    /// Number of tokens consumed so far
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Go back (or forward) to a position already pulled from the stream
+    pub fn rewind(&mut self, position: usize) -> Result<(), String> {
+        if position > self.pulled.len() {
+            return Err(format!("TokenCursor can't rewind to {}, only {} tokens read",
+                               position, self.pulled.len()));
+        }
+        self.position = position;
+        Ok(())
+    }
+
+    /// True once there are no tokens left at the current position
+    pub fn is_done(&mut self) -> bool {
+        self.peek().is_none()
+    }
+
+    pub fn peek(&mut self) -> Option<&I::Item> {
+        if self.position == self.pulled.len() {
+            self.pulled.push(self.source.next()?);
+        }
+        self.pulled.get(self.position)
+    }
+
+    /// Iterator over the rest of the stream that advances this cursor
+    pub fn view(&mut self) -> &mut Self {
+        self
+    }
+
+    /// Parse the longest prefix from the current position with `parser` and
+    /// leave the cursor right after it, see `EarleyParser::parse_prefix`.
+    /// Spans positions are relative to where the prefix started. The cursor
+    /// doesn't move on errors.
+    pub fn parse_prefix(&mut self, parser: &EarleyParser) -> Result<(ParseTrees, usize), String>
+            where I::Item: Debug + AsRef<str> {
+        let start = self.position;
+        let parsed = parser.parse_prefix(self.view());
+        let consumed = parsed.as_ref().map_or(0, |(_, consumed)| *consumed);
+        self.position = start + consumed;
+        parsed
+    }
+}
+
+impl<I: Iterator> Iterator for TokenCursor<I> where I::Item: Clone {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let token = self.peek()?.clone();
+        self.position += 1;
+        Some(token)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::TokenCursor;
+    use crate::earley::{EarleyForest, EarleyParser, GrammarBuilder};
+
+    #[test]
+    fn rewind() {
+        let mut cursor = TokenCursor::new("a b c".split_whitespace());
+        assert_eq!(cursor.view().take(2).collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!(cursor.position(), 2);
+        cursor.rewind(1).unwrap();
+        assert_eq!(cursor.peek(), Some(&"b"));
+        assert_eq!(cursor.view().collect::<Vec<_>>(), vec!["b", "c"]);
+        assert!(cursor.is_done());
+        assert_eq!(cursor.rewind(4).unwrap_err(), "TokenCursor can't rewind to 4, only 3 tokens read");
+    }
+
+    #[test]
+    fn alternate_grammars() {
+        // Sum -> Sum + Num | Sum * Num | Num, evaluated left to right
+        let math = EarleyParser::new(GrammarBuilder::default()
+          .nonterm("Sum")
+          .terminal("Num", |n| n.chars().all(|c| c.is_ascii_digit()))
+          .literal("+")
+          .literal("*")
+          .rule("Sum", &["Sum", "+", "Num"])
+          .rule("Sum", &["Sum", "*", "Num"])
+          .rule("Sum", &["Num"])
+          .into_grammar("Sum").unwrap());
+        let mut sums = EarleyForest::new(|_, n| n.parse().unwrap_or(0));
+        sums.action("Sum -> Sum + Num", |n| n[0] + n[2]);
+        sums.action("Sum -> Sum * Num", |n| n[0] * n[2]);
+        sums.action("Sum -> Num", |n| n[0]);
+        // Words -> Words Word | Word
+        let prose = EarleyParser::new(GrammarBuilder::default()
+          .nonterm("Words")
+          .terminal("Word", |w| w.chars().all(|c| c.is_alphabetic()))
+          .rule("Words", &["Words", "Word"])
+          .rule("Words", &["Word"])
+          .into_grammar("Words").unwrap());
+        let mut words = EarleyForest::new(|_, w| w.to_string());
+        words.action("Words -> Words Word", |w| format!("{} {}", w[0], w[1]));
+        words.action("Words -> Word", |w| w[0].clone());
+
+        let mut cursor = TokenCursor::new("1 + 2 add up to 3 * 4 bye".split_whitespace());
+        let mut segments = Vec::new();
+        while !cursor.is_done() {
+            let start = cursor.position();
+            if let Ok((trees, _)) = cursor.parse_prefix(&math) {
+                segments.push((start, sums.eval(&trees).unwrap().to_string()));
+            } else {
+                let (trees, _) = cursor.parse_prefix(&prose).unwrap();
+                segments.push((start, words.eval(&trees).unwrap()));
+            }
+        }
+        assert_eq!(segments, vec![(0, "3".to_string()), (3, "add up to".to_string()),
+                                  (6, "12".to_string()), (9, "bye".to_string())]);
+        assert_eq!(cursor.position(), 10);
+        // nothing parses, the cursor stays in place
+        cursor.rewind(3).unwrap();
+        assert!(cursor.parse_prefix(&math).is_err());
+        assert_eq!(cursor.position(), 3);
+    }
+}
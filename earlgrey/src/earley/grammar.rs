@@ -1,6 +1,4 @@
-#![deny(warnings)]
-
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use std::{fmt, hash};
 
@@ -36,7 +34,8 @@ impl hash::Hash for Symbol {
         match self {
             Symbol::Term(name, matcher) => {
                 name.hash(state);
-                (matcher as *const dyn Fn(&str) -> bool).hash(state);
+                // only the address, vtables can be duplicated across codegen units
+                (&**matcher as *const dyn Fn(&str) -> bool as *const ()).hash(state);
             }
             Symbol::NonTerm(name) => name.hash(state),
         }
@@ -107,6 +106,42 @@ impl fmt::Debug for Rule {
 pub struct Grammar {
     pub start: String,
     pub rules: Vec<Rc<Rule>>,
+    // Terminals that only match their own name, eg: "+" or "while"
+    pub literals: HashSet<String>,
+}
+
+impl Grammar {
+    /// Same rules but parsing from another (existing) NonTerm, eg: to
+    /// reuse a large grammar for different entry points.
+    pub fn with_start(&self, start: &str) -> Result<Grammar, String> {
+        let symbol = self.rules.iter()
+            .flat_map(|rule| rule.spec.iter())
+            .find(|symbol| symbol.name() == start);
+        let is_head = self.rules.iter().any(|rule| rule.head == start);
+        match symbol {
+            Some(symbol) if symbol.is_terminal() =>
+                Err(format!("Grammar start must be NonTerm: {}", start)),
+            None if !is_head => Err(format!("Missing start Symbol: {}", start)),
+            _ => Ok(Grammar{start: start.to_string(), ..self.clone()}),
+        }
+    }
+
+    /// The rule `lhs -> rhs` if the grammar has it, symbols by name.
+    pub fn rule_for(&self, lhs: &str, rhs: &[&str]) -> Option<&Rule> {
+        self.rules.iter()
+            .map(|rule| &**rule)
+            .find(|rule| rule.head == lhs && rule.spec.len() == rhs.len() &&
+                         rule.spec.iter().zip(rhs).all(|(s, name)| s.name() == *name))
+    }
+
+    /// Rules for `lhs` whose right hand side starts with `first_sym`.
+    pub fn rules_starting_with<'a>(&'a self, lhs: &'a str, first_sym: &'a str)
+            -> impl Iterator<Item=&'a Rule> + 'a {
+        self.rules.iter()
+            .map(|rule| &**rule)
+            .filter(move |rule| rule.head == lhs &&
+                                rule.spec.first().is_some_and(|s| s.name() == first_sym))
+    }
 }
 
 impl fmt::Debug for Grammar {
@@ -138,6 +173,7 @@ impl fmt::Debug for Grammar {
 pub struct GrammarBuilder {
     symbols: HashMap<String, Rc<Symbol>>,
     rules: Vec<Rc<Rule>>,
+    literals: HashSet<String>,
     error: Option<String>,
 }
 
@@ -164,6 +200,50 @@ impl GrammarBuilder {
         self
     }
 
+    // A terminal matching tokens the regex accepts, anchor it (^...$) to match
+    // whole tokens. Takes the compiled regex so it can be shared elsewhere.
+    #[cfg(feature = "regex")]
+    pub fn symbol_regex(self, name: &str, pattern: regex::Regex) -> Self {
+        self.terminal(name, move |s| pattern.is_match(s))
+    }
+
+    // A terminal matching exactly its name, eg: literal("+")
+    pub fn literal(mut self, name: &str) -> Self {
+        self.add_literal(name, false);
+        self
+    }
+
+    // Keep a terminal from matching `words`, eg: keywords an identifier would
+    // also accept. Otherwise every terminal that matches a token scans it.
+    pub fn reserved(mut self, terminal: &str, words: &[&str]) -> Self {
+        let previous = match self.symbols.get(terminal) {
+            Some(s) if s.is_terminal() => s.clone(),
+            Some(_) => {
+                self.error = Some(format!("Reserved words need a Term: {}", terminal));
+                return self;
+            }
+            None => {
+                self.error = Some(format!("Missing Symbol: {}", terminal));
+                return self;
+            }
+        };
+        let words: HashSet<String> = words.iter().map(|w| w.to_string()).collect();
+        let matcher = previous.clone();
+        let symbol = Rc::new(Symbol::Term(terminal.into(), Box::new(
+            move |s| !words.contains(s) && matcher.matches(s))));
+        // Rules added earlier must use the restricted terminal too
+        self.rules = self.rules.iter()
+            .map(|rule| Rc::new(Rule {
+                head: rule.head.clone(),
+                spec: rule.spec.iter()
+                    .map(|s| if Rc::ptr_eq(s, &previous) { symbol.clone() } else { s.clone() })
+                    .collect(),
+            }))
+            .collect();
+        self.symbols.insert(terminal.to_string(), symbol);
+        self
+    }
+
     pub fn nonterm_try(&mut self, name: &str) {
         self.add_symbol(Symbol::NonTerm(name.into()), true);
     }
@@ -172,6 +252,19 @@ impl GrammarBuilder {
         self.add_symbol(Symbol::Term(name.into(), Box::new(pred)), true);
     }
 
+    pub fn literal_try(&mut self, name: &str) {
+        self.add_literal(name, true);
+    }
+
+    // Literals are tracked so tokenizers can be derived from the Grammar
+    fn add_literal(&mut self, name: &str, ignore_dups: bool) {
+        if !self.symbols.contains_key(name) {
+            self.literals.insert(name.to_string());
+        }
+        let lit = name.to_string();
+        self.add_symbol(Symbol::Term(name.into(), Box::new(move |s| s == lit)), ignore_dups);
+    }
+
     // Register new rules for the grammar
     fn add_rule(&mut self, head: &str, spec: &[&str], ignore_dups: bool) {
         // First check that all symbols have been registered (need references)
@@ -224,6 +317,7 @@ impl GrammarBuilder {
             Ok(Grammar {
                 start,
                 rules: self.rules,
+                literals: self.literals,
             }),
             Err,
         )
@@ -277,6 +371,27 @@ mod tests {
         assert!(g.is_ok());
     }
 
+    #[cfg(feature = "regex")]
+    #[test]
+    fn symbol_regex() {
+        use crate::EarleyParser;
+        let ordinal = regex::Regex::new(r"^[0-9]+(st|nd|rd|th)$").unwrap();
+        let g = GrammarBuilder::default()
+            .nonterm("Date")
+            .literal("of")
+            .symbol_regex("ordinal", ordinal.clone())
+            .terminal("month", |m| m == "march")
+            .rule("Date", &["ordinal", "of", "month"])
+            .into_grammar("Date")
+            .unwrap();
+        let parser = EarleyParser::new(g);
+        assert!(parser.parse("22nd of march".split_whitespace()).is_ok());
+        assert!(parser.parse("22 of march".split_whitespace()).is_err());
+        assert!(parser.parse("x22nd of march".split_whitespace()).is_err());
+        // the regex is still usable after building the grammar
+        assert!(ordinal.is_match("3rd"));
+    }
+
     #[test]
     fn grammar_has_dup_symbol() {
         let g = GrammarBuilder::default()
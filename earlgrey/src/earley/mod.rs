@@ -1,14 +1,31 @@
-#![deny(warnings)]
-
 mod grammar;
-pub use grammar::{GrammarBuilder, Grammar};
+pub use grammar::{GrammarBuilder, Grammar, Rule};
+pub(crate) use grammar::Symbol;
+
+mod tokenizer;
+pub use tokenizer::{GrammarTokenizer, GrammarTokens};
 
 mod spans; 
 mod parser;
-pub use parser::EarleyParser;
+pub use parser::{EarleyParser, ParseContext, ParseOrLexError, CANCELLED, CANCEL_CHECK_EVERY};
+
+mod complexity;
+pub use complexity::ComplexityBound;
+
+mod stats;
+pub use stats::{ParseStats, RuleCoverage, RuleStats};
 
 mod trees;
-pub use trees::EarleyForest;
+pub use trees::{boxed_action, fold, EarleyForest, SemAction, SpanInfo, Subtree};
+
+mod rewrite;
+pub use rewrite::TreeRewriter;
+
+mod cursor;
+pub use cursor::TokenCursor;
+
+#[cfg(feature = "serialize")]
+mod serialize;
 
 #[cfg(test)]
 mod parser_test;
@@ -1,117 +1,644 @@
-#![deny(warnings)]
-
-use super::grammar::{Grammar, Symbol};
-use super::spans::{Span, SpanSource};
-use std::collections::HashSet;
+use super::grammar::{Grammar, Rule, Symbol};
+use super::spans::{LeoLink, Span, SpanSource};
+use super::stats::{ParseStats, RuleCoverage};
+use std::cell::OnceCell;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
+
+// Whether a rule for some nonterminal can start at some position (and token)
+type PositionOracle = Box<dyn Fn(usize, &str, Option<&str>) -> bool>;
 
 pub struct EarleyParser {
     pub grammar: Grammar,
+    // Parses fail past these, see `max_tokens` and `max_items`
+    max_tokens: Option<usize>,
+    max_items: Option<usize>,
+    // Predictions it rejects aren't added, see `with_oracle`
+    oracle: Option<PositionOracle>,
 }
 
-#[derive(Debug)]
 pub struct ParseTrees(pub Vec<Rc<Span>>);
 
+impl ParseTrees {
+    /// Number of distinct derivations (saturates at u64::MAX). Counted with
+    /// an explicit stack on the shared Span graph, deep inputs are fine.
+    /// Cycles (eg: from `A -> A`) aren't followed, they'd be infinite.
+    pub fn derivations(&self) -> u64 {
+        let mut counts: HashMap<*const Span, u64> = HashMap::new();
+        let mut expanded_spans = HashSet::new();
+        let mut pending: Vec<_> = self.0.iter().map(|r| (r.clone(), false)).collect();
+        while let Some((span, expanded)) = pending.pop() {
+            let key = Rc::as_ptr(&span);
+            if counts.contains_key(&key) {
+                continue;
+            }
+            let sources = span.sources();
+            // each source's parts must be counted before the span itself
+            if !expanded {
+                if !expanded_spans.insert(key) {
+                    continue;
+                }
+                pending.push((span.clone(), true));
+                for source in sources.iter() {
+                    match source {
+                        SpanSource::Completion(src, trigger) => {
+                            pending.push((src.clone(), false));
+                            pending.push((trigger.clone(), false));
+                        },
+                        SpanSource::Scan(src, _) => pending.push((src.clone(), false)),
+                    }
+                }
+                continue;
+            }
+            let count = |span: &Rc<Span>| counts.get(&Rc::as_ptr(span)).cloned().unwrap_or(0);
+            let total = match sources.len() {
+                0 => 1,
+                _ => sources.iter().fold(0u64, |acc, source| acc.saturating_add(match source {
+                    SpanSource::Completion(src, trigger) =>
+                        count(src).saturating_mul(count(trigger)),
+                    SpanSource::Scan(src, _) => count(src),
+                })),
+            };
+            counts.insert(key, total);
+        }
+        self.0.iter().fold(0u64, |acc, root| acc.saturating_add(counts[&Rc::as_ptr(root)]))
+    }
+}
+
+// eg: "2 derivations from 1 root, ambiguous"
+impl fmt::Display for ParseTrees {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let derivations = self.derivations();
+        let plural = |n| if n == 1 { "" } else { "s" };
+        write!(f, "{} derivation{} from {} root{}, {}",
+               derivations, plural(derivations as usize),
+               self.0.len(), plural(self.0.len()),
+               if derivations > 1 { "ambiguous" } else { "unambiguous" })
+    }
+}
+
+// Lists the rule of each root, eg: ParseTrees(["E -> E + E"])
+impl Debug for ParseTrees {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rules: Vec<_> = self.0.iter().map(|root| root.rule.to_string()).collect();
+        f.debug_tuple("ParseTrees").field(&rules).finish()
+    }
+}
+
+/// Error of `parse_fallible`, either the tokenizer or the parser failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseOrLexError<E> {
+    // lexer error at token index (0 based)
+    Lex(usize, E),
+    Parse(String),
+}
+
+impl<E: fmt::Display> fmt::Display for ParseOrLexError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseOrLexError::Lex(idx, err) => write!(f, "lex error at token {}: {}", idx, err),
+            ParseOrLexError::Parse(err) => write!(f, "{}", err),
+        }
+    }
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 
+/// Items of a StateSet. Predictions (dot at 0, no backpointers) are about
+/// half of the chart, they're kept as indices into the Grammar's rules and
+/// only turned into Spans when some other item needs to point to them.
+#[derive(Default)]
+struct StateSet {
+    predicted: Vec<Prediction>,
+    // rules in `predicted`, to skip repeated predictions without a scan
+    predicted_rules: HashSet<usize>,
+    items: HashSet<Rc<Span>>,
+    // Deterministic completions by NonTerm, set once the StateSet is done
+    leo: HashMap<String, Rc<LeoLink>>,
+}
+
+struct Prediction {
+    rule: usize,
+    // materialized Span, shared by all items extending this prediction
+    span: OnceCell<Rc<Span>>,
+}
+
+impl StateSet {
+    fn len(&self) -> usize {
+        self.predicted.len() + self.items.len()
+    }
+
+    fn add_prediction(&mut self, rule: usize) {
+        if self.predicted_rules.insert(rule) {
+            self.predicted.push(Prediction{rule, span: OnceCell::new()});
+        }
+    }
+
+    /// Spans that a. are complete, b. start at `origin` (usually 0), and c.
+    /// the name of the rule matches the starting symbol. They span the input
+    /// up to `end`, the position of this StateSet (empty predictions only at
+    /// the origin).
+    fn complete_parses(&self, grammar: &Grammar, origin: usize, end: usize) -> Vec<Rc<Span>> {
+        let rules = &grammar.rules;
+        self.items.iter()
+            .filter(|item| item.start == origin && item.complete() &&
+                           item.rule.head == grammar.start)
+            .cloned()
+            .chain(self.predicted.iter()
+                .filter(|p| end == origin && rules[p.rule].spec.is_empty() &&
+                            rules[p.rule].head == grammar.start)
+                .map(|p| p.span.get_or_init(|| Rc::new(Span::new(&rules[p.rule], end))).clone()))
+            .collect()
+    }
+
+    /// Items as `{:?}` of their Span, predictions first, sorted within each
+    fn dump(&self, rules: &[Rc<Rule>], pos: usize) -> Vec<String> {
+        let mut items: Vec<_> = self.items.iter().map(|item| format!("{:?}", item)).collect();
+        items.sort();
+        self.predicted.iter()
+            .map(|p| format!("{:?}", Span::new(&rules[p.rule], pos)))
+            .chain(items)
+            .collect()
+    }
+
+    /// Names of the terminals that some item in this StateSet can scan next
+    fn expected_terminals(&self, rules: &[Rc<Rule>]) -> Vec<String> {
+        let mut names: Vec<_> = self.predicted.iter()
+            .filter_map(|p| rules[p.rule].spec.first().map(|s| &**s))
+            .chain(self.items.iter().filter_map(|item| item.next_symbol()))
+            .filter(|s| s.is_terminal())
+            .map(|s| s.name().to_string())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // Keeps allocated capacity
+    fn clear(&mut self) {
+        self.predicted.clear();
+        self.predicted_rules.clear();
+        self.items.clear();
+        self.leo.clear();
+    }
+
+    /// Leo's optimization, keeps right recursion (eg: `A -> x A | x`) linear.
+    /// A NonTerm waited on by a single item `A -> α · B` (that started
+    /// earlier) deterministically completes `A` when completed. If `A` is
+    /// also deterministic where the item started, both links chain and only
+    /// the top item of the chain is completed, see `Span::leo`.
+    /// Without it each completion cascades down all the open recursion levels.
+    fn leo_links(&self, rules: &[Rc<Rule>], pos: usize, earlier: &[StateSet])
+            -> HashMap<String, Rc<LeoLink>> {
+        // NonTerms waited on by a single item that qualifies, None otherwise
+        let mut waiting: HashMap<&str, Option<&Rc<Span>>> = HashMap::new();
+        for prediction in &self.predicted {
+            if let Some(Symbol::NonTerm(name)) = rules[prediction.rule].spec.first().map(|s| &**s) {
+                waiting.insert(name, None);
+            }
+        }
+        for item in &self.items {
+            if let Some(Symbol::NonTerm(name)) = item.next_symbol() {
+                let last = item.dot + 1 == item.rule.spec.len() && item.start < pos;
+                waiting.entry(name)
+                    .and_modify(|single| *single = None)
+                    .or_insert(last.then_some(item));
+            }
+        }
+        waiting.into_iter()
+            .filter_map(|(name, item)| Some((name, item?)))
+            .map(|(name, item)| {
+                let next = earlier[item.start].leo.get(&item.rule.head).cloned();
+                let top = next.as_ref().map_or_else(|| item.clone(), |n| n.top.clone());
+                (name.to_string(), Rc::new(LeoLink{waiting: item.clone(), next, top}))
+            })
+            .collect()
+    }
+
+    /// Once the next StateSet is scanned only Spans waiting on a NonTerm can
+    /// still be extended (by completions). Drop the rest, Spans on the parse
+    /// paths live on through the backpointers of items that extended them.
+    fn retain_waiting(&mut self, rules: &[Rc<Rule>]) {
+        let waiting = |s: Option<&Symbol>| matches!(s, Some(Symbol::NonTerm(_)));
+        self.predicted.retain(|p| waiting(rules[p.rule].spec.first().map(|s| &**s)));
+        self.predicted.shrink_to_fit();
+        // nothing is predicted here anymore
+        self.predicted_rules = HashSet::new();
+        self.items.retain(|span| waiting(span.next_symbol()));
+        self.items.shrink_to_fit();
+    }
+
+    /// Spans in this StateSet whose next symbol passes `accept`
+    fn waiting_on<'s>(&'s self, rules: &'s [Rc<Rule>], pos: usize,
+                      accept: impl Fn(&Symbol) -> bool + 's)
+            -> impl Iterator<Item=Rc<Span>> + 's {
+        let predicted = self.predicted.iter()
+            .filter(|p| rules[p.rule].spec.first().is_some_and(|s| accept(s)))
+            .map(move |p| p.span.get_or_init(|| Rc::new(Span::new(&rules[p.rule], pos))).clone());
+        let items = self.items.iter()
+            .filter(|span| span.next_symbol().is_some_and(&accept))
+            .cloned();
+        items.chain(predicted).collect::<Vec<_>>().into_iter()
+    }
+}
+
 impl EarleyParser {
     pub fn new(grammar: Grammar) -> EarleyParser {
-        EarleyParser{grammar}
+        EarleyParser{grammar, max_tokens: None, max_items: None, oracle: None}
+    }
+
+    /// Ask `oracle` before predicting rules of a nonterminal, with the
+    /// position of the token the rule would start at, the nonterminal's name
+    /// and that token (None at the end of input). It's asked once per
+    /// nonterminal and position. Returning false prunes the prediction, eg:
+    /// no `year` can start at a token that isn't a number. Pruning makes the
+    /// chart smaller, it doesn't make parses faster by itself: an oracle
+    /// pruning a rule that starts some parse makes that parse go missing, or
+    /// the input fail to parse, no error says why. Tokens are read one ahead
+    /// of the chart to show the oracle.
+    pub fn with_oracle(mut self, oracle: impl Fn(usize, &str, Option<&str>) -> bool + 'static)
+            -> Self {
+        self.oracle = Some(Box::new(oracle));
+        self
+    }
+
+    // Asks the oracle about `head` once, `verdicts` are for position `pos`
+    fn predictable<'p>(&self, verdicts: &mut HashMap<&'p str, bool>, head: &'p str,
+                       pos: usize, token: Option<&str>) -> bool {
+        match &self.oracle {
+            Some(oracle) => *verdicts.entry(head).or_insert_with(|| oracle(pos, head, token)),
+            None => true,
+        }
+    }
+
+    /// Fail parses of inputs longer than `limit` tokens, to guard against
+    /// untrusted input. There's no limit by default.
+    pub fn max_tokens(mut self, limit: usize) -> Self {
+        self.max_tokens = Some(limit);
+        self
+    }
+
+    /// Fail parses once the chart has over `limit` items (spans scanned or
+    /// completed), ambiguous grammars can grow it cubically on the input.
+    /// There's no limit by default.
+    pub fn max_items(mut self, limit: usize) -> Self {
+        self.max_items = Some(limit);
+        self
+    }
+
+    /// Empty coverage report for this parser's rules, see `RuleCoverage::record`
+    pub fn coverage(&self) -> RuleCoverage {
+        RuleCoverage::new(&self.grammar)
     }
 
     /// Build new `Completion` items based on `trigger` item having completed.
     /// When an item is completed it advances all items in the same starting
     /// StateSet whose next symbol matches its rule name.
     fn completions<'r>(
-        starting_stateset: impl Iterator<Item=&'r Rc<Span>> + 'r,
+        &'r self,
+        starting_stateset: &'r StateSet,
         trigger: &'r Rc<Span>,
         complete_pos: usize,
-    ) -> Box<dyn Iterator<Item=Span> + 'r>
+    ) -> impl Iterator<Item=Span> + 'r
     {
         assert!(trigger.complete(), "Incomplete `trigger` used for completions");
-        Box::new(starting_stateset.filter(move |span| {
-            match span.next_symbol() {
-                Some(Symbol::NonTerm(name)) => name == &trigger.rule.head,
-                _ => false
-            }
-        }).map(move |span| Span::extend(SpanSource::Completion(span.clone(), trigger.clone()), complete_pos)))
+        starting_stateset.waiting_on(&self.grammar.rules, trigger.start, move |s| match s {
+            Symbol::NonTerm(name) => name == &trigger.rule.head,
+            _ => false
+        }).map(move |span| Span::extend(SpanSource::Completion(span, trigger.clone()), complete_pos))
     }
 
     /// Build new `Scan` items for items in the current stateset whose next
     /// symbol is a Terminal that matches the input lexeme ahead in the stream.
     fn scans<'r>(
-        current_stateset: impl Iterator<Item=&'r Rc<Span>> + 'r,
-        lexeme: &'r str,
+        &'r self,
+        current_stateset: &'r StateSet,
+        lexeme: Rc<str>,
         end: usize,
     ) -> impl Iterator<Item=Rc<Span>> + 'r
     {
-        current_stateset.filter(move |span| 
-            // check span's next symbol is a temrinal that scans lexeme
-            span.next_symbol().is_some_and(|s| s.matches(lexeme))
-        ).map(move |span| Rc::new(Span::extend(SpanSource::Scan(span.clone(), lexeme.to_string()), end)))
+        // check span's next symbol is a temrinal that scans lexeme
+        let scanned = lexeme.clone();
+        current_stateset.waiting_on(&self.grammar.rules, end - 1, move |s| s.matches(&scanned))
+            .map(move |span| Rc::new(Span::extend(SpanSource::Scan(span, lexeme.clone()), end)))
+    }
+
+    /// Reusable scratch space to parse many inputs, see `ParseContext`.
+    pub fn parse_context(&self) -> ParseContext<'_> {
+        // Side table to lookup rules to predict for each Symbol
+        let mut rules_by_head: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (idx, rule) in self.grammar.rules.iter().enumerate() {
+            rules_by_head.entry(rule.head.as_str()).or_default().push(idx);
+        }
+        ParseContext{
+            parser: self,
+            rules_by_head,
+            statesets: Vec::new(),
+            spare: Vec::new(),
+            new_predictions: Vec::new(),
+            new_items: Vec::new(),
+            cancel: None,
+        }
     }
 
-    pub fn parse<T>(&self, mut tokenizer: T) -> Result<ParseTrees, String>
+    pub fn parse<T>(&self, tokenizer: T) -> Result<ParseTrees, String>
             where T: Iterator, T::Item: Debug + AsRef<str> {
+        self.parse_context().parse(tokenizer)
+    }
 
-        // Populate S0, add items for each rule matching the start symbol
-        let s0: HashSet<_> = self.grammar.rules.iter()
-            .filter(|rule| rule.head == self.grammar.start)
-            .map(|rule| Rc::new(Span::new(rule, 0)))
-            .collect();
+    /// Same as `parse` but gives up with `Err(CANCELLED)` once `check`
+    /// returns true, it's called every `CANCEL_CHECK_EVERY` chart operations.
+    /// Eg: `|| Instant::now() > deadline` to stop parses taking too long.
+    pub fn parse_cancellable<T>(&self, tokenizer: T, check: impl Fn() -> bool)
+            -> Result<ParseTrees, String>
+            where T: Iterator, T::Item: Debug + AsRef<str> {
+        self.parse_context().cancel_when(check).parse(tokenizer)
+    }
+
+    /// Same as `parse` taking anything that iterates tokens, eg: a `Vec`, an
+    /// array or a slice of tokens, besides tokenizers.
+    pub fn parse_tokens<T>(&self, tokens: T) -> Result<ParseTrees, String>
+            where T: IntoIterator, T::Item: Debug + AsRef<str> {
+        self.parse(tokens.into_iter())
+    }
+
+    /// See `ParseContext::parse_prefix`, the default is to parse whole inputs.
+    pub fn parse_prefix<T>(&self, tokenizer: T) -> Result<(ParseTrees, usize), String>
+            where T: Iterator, T::Item: Debug + AsRef<str> {
+        self.parse_context().parse_prefix(tokenizer)
+    }
+
+    /// Parse `tokens[start_pos..]` as if the tokens before it were already
+    /// consumed, the trees' Spans keep their positions in the whole input.
+    /// Along with `parse_prefix` it helps finding parses anywhere in a stream.
+    pub fn parse_at_position(&self, tokens: &[String], start_pos: usize)
+            -> Result<ParseTrees, String> {
+        if start_pos > tokens.len() {
+            return Err(format!("Start position {} past the end of input ({} tokens)",
+                               start_pos, tokens.len()));
+        }
+        let mut context = self.parse_context();
+        let chart = context.initial_chart(start_pos);
+        context.parse_from_chart(chart, tokens[start_pos..].iter(), None, false)
+            .map(|(trees, _)| trees)
+    }
+
+    /// Same as `parse` but also counts chart items created by each rule.
+    pub fn parse_with_stats<T>(&self, tokenizer: T) -> Result<(ParseTrees, ParseStats), String>
+            where T: Iterator, T::Item: Debug + AsRef<str> {
+        let mut stats = ParseStats::default();
+        let (trees, _) = self.parse_context().parse_impl(tokenizer, Some(&mut stats), false)?;
+        Ok((trees, stats))
+    }
 
-        let mut statesets = vec![s0];
+    /// Same as `parse_with_stats` but the stats also hold a dump of the chart
+    /// and are returned even when the parse fails, to debug grammars.
+    pub fn parse_chart<T>(&self, tokenizer: T) -> (Result<ParseTrees, String>, ParseStats)
+            where T: Iterator, T::Item: Debug + AsRef<str> {
+        let mut stats = ParseStats{record_chart: true, ..ParseStats::default()};
+        let trees = self.parse_context().parse_impl(tokenizer, Some(&mut stats), false);
+        (trees.map(|(trees, _)| trees), stats)
+    }
+
+    /// Parse a stream of lexer results, stops at the first lexer error.
+    /// Tokens are consumed as the parse goes, no need to collect them first.
+    pub fn parse_fallible<T, E>(&self, tokens: T) -> Result<ParseTrees, ParseOrLexError<E>>
+            where T: Iterator<Item=Result<String, E>> {
+        let mut lex_error = None;
+        let tokens = tokens.enumerate().map_while(|(idx, token)| match token {
+            Ok(token) => Some(token),
+            Err(err) => {
+                lex_error = Some((idx, err));
+                None
+            }
+        });
+        let parsed = self.parse_context().parse_impl(tokens, None, false);
+        // parser saw a truncated stream, the lexer error takes precedence
+        if let Some((idx, err)) = lex_error {
+            return Err(ParseOrLexError::Lex(idx, err));
+        }
+        parsed.map(|(trees, _)| trees).map_err(ParseOrLexError::Parse)
+    }
+}
+
+/// Scratch space for parsing many (small) inputs with the same parser.
+/// The chart's allocations are kept between inputs instead of building a
+/// new one on every `parse`. Parse results don't borrow from the context.
+pub struct ParseContext<'p> {
+    parser: &'p EarleyParser,
+    rules_by_head: HashMap<&'p str, Vec<usize>>,
+    statesets: Vec<StateSet>,
+    // Cleared StateSets ready for reuse
+    spare: Vec<StateSet>,
+    new_predictions: Vec<usize>,
+    new_items: Vec<Span>,
+    cancel: Option<Box<dyn Fn() -> bool + 'p>>,
+}
+
+/// Error of parses and evaluations stopped by their cancellation check
+pub const CANCELLED: &str = "Cancelled";
+/// Chart operations (predictions and items processed) between checks
+pub const CANCEL_CHECK_EVERY: usize = 256;
+
+impl<'p> ParseContext<'p> {
+    /// Stop parses with `Err(CANCELLED)` once `check` returns true, see
+    /// `EarleyParser::parse_cancellable`. The context can be used again.
+    pub fn cancel_when(mut self, check: impl Fn() -> bool + 'p) -> Self {
+        self.cancel = Some(Box::new(check));
+        self
+    }
+
+    pub fn parse<T>(&mut self, tokenizer: T) -> Result<ParseTrees, String>
+            where T: Iterator, T::Item: Debug + AsRef<str> {
+        self.parse_impl(tokenizer, None, false).map(|(trees, _)| trees)
+    }
+
+    /// Parse the longest prefix of the input that derives the start symbol.
+    /// Returns its parse trees and the number of tokens in it. Stops pulling
+    /// tokens (after the first one that can't be scanned) once it can't grow.
+    pub fn parse_prefix<T>(&mut self, tokenizer: T) -> Result<(ParseTrees, usize), String>
+            where T: Iterator, T::Item: Debug + AsRef<str> {
+        self.parse_impl(tokenizer, None, true)
+    }
+
+    fn parse_impl<T>(&mut self, tokenizer: T, stats: Option<&mut ParseStats>,
+                     prefix: bool) -> Result<(ParseTrees, usize), String>
+            where T: Iterator, T::Item: Debug + AsRef<str> {
+        let chart = self.initial_chart(0);
+        self.parse_from_chart(chart, tokenizer, stats, prefix)
+    }
+
+    // Chart with empty StateSets up to `origin` (tokens already consumed),
+    // the last one gets the start symbol's rules once parsing begins.
+    fn initial_chart(&mut self, origin: usize) -> Vec<StateSet> {
+        let mut statesets = std::mem::take(&mut self.statesets);
+        for _ in 0..=origin {
+            statesets.push(self.spare.pop().unwrap_or_default());
+        }
+        statesets
+    }
+
+    // Grow the chart from its last StateSet. Returns trees and number of
+    // tokens consumed. With `prefix` completing the start symbol at any
+    // position is a parse.
+    fn parse_from_chart<T>(&mut self, mut statesets: Vec<StateSet>, tokenizer: T,
+                           mut stats: Option<&mut ParseStats>, prefix: bool)
+            -> Result<(ParseTrees, usize), String>
+            where T: Iterator, T::Item: Debug + AsRef<str> {
+        let parser = self.parser;
+        let rules = &parser.grammar.rules;
+        let predict = |name: &str| self.rules_by_head.get(name).into_iter().flatten().cloned();
+        let mut tokenizer = tokenizer.peekable();
+        // oracle verdicts by head at the current position
+        let mut verdicts = HashMap::new();
+        let mut new_predictions = std::mem::take(&mut self.new_predictions);
+        let mut new_items = std::mem::take(&mut self.new_items);
+        let mut longest_prefix = None;
+        let origin = statesets.len() - 1;
+        let mut operations = 0;
+        let mut chart_items = 0;
+        let over_items = |items: usize| match parser.max_items {
+            Some(limit) if items > limit => Err(format!("Parse Error: chart over {} items", limit)),
+            _ => Ok(()),
+        };
+        let cancel = self.cancel.as_deref();
+        let mut cancelled = || {
+            operations += 1;
+            operations % CANCEL_CHECK_EVERY == 0 && cancel.is_some_and(|check| check())
+        };
 
         // New statesets are generated from input stream (Scans)
-        for idx in 0.. {
+        for idx in origin.. {
+            verdicts.clear();
+            let lookahead = match parser.oracle {
+                Some(_) => tokenizer.peek().map(|token| token.as_ref().to_string()),
+                None => None,
+            };
+            if idx == origin {
+                for rule in predict(&parser.grammar.start) {
+                    if parser.predictable(&mut verdicts, &rules[rule].head, idx,
+                                          lookahead.as_deref()) {
+                        if let Some(stats) = &mut stats {
+                            stats.predicted(&Span::new(&rules[rule], idx));
+                        }
+                        statesets[idx].add_prediction(rule);
+                    }
+                }
+            }
             // Predict/Complete until no new Spans are added to the StateSet
             // Instead of looping we could pre-populate completions of nullable symbols
             loop {
-                let new_items: Vec<_> = statesets[idx].iter().flat_map(|trigger| {
+                let stateset = &statesets[idx];
+                // Predicted rules can trigger other predictions or complete if empty
+                for prediction in &stateset.predicted {
+                    if cancelled() {
+                        return Err(CANCELLED.to_string());
+                    }
+                    let rule = &rules[prediction.rule];
+                    match rule.spec.first().map(|s| &**s) {
+                        Some(Symbol::NonTerm(next)) => new_predictions.extend(predict(next)),
+                        None => {
+                            let trigger = prediction.span
+                                .get_or_init(|| Rc::new(Span::new(rule, idx)));
+                            new_items.extend(parser.completions(stateset, trigger, idx));
+                        }
+                        _ => (),
+                    }
+                }
+                for trigger in &stateset.items {
+                    if cancelled() {
+                        return Err(CANCELLED.to_string());
+                    }
                     let next_sym = trigger.next_symbol();
                     if let Some(Symbol::NonTerm(next_terminal)) = next_sym {
                         // Prediction: Build new items from `next_terminal` of some Symbol
-                        Box::new(self.grammar.rules.iter().filter(|rule| rule.head == *next_terminal)
-                            .map(move |rule| Span::new(rule, idx)))
+                        new_predictions.extend(predict(next_terminal));
                     } else if trigger.complete() {
                         assert!(next_sym.is_none(), "Expected next symbol to be None");
-                        EarleyParser::completions(statesets[trigger.start].iter(), trigger, idx)
+                        let origin = &statesets[trigger.start];
+                        match origin.leo.get(&trigger.rule.head) {
+                            Some(link) => new_items.push(Span::leo(link, trigger.clone(), idx)),
+                            None => new_items.extend(parser.completions(origin, trigger, idx)),
+                        }
                     } else {
                         // Scan items populate next stateset only when done with current state
                         assert!(matches!(next_sym, Some(&Symbol::Term(_, _))));
-                        Box::new(std::iter::empty())
                     }
-                }).collect();
+                }
                 let stateset = statesets.get_mut(idx).unwrap();
                 let prev_len = stateset.len();
+                for rule in new_predictions.drain(..) {
+                    if !stateset.predicted_rules.contains(&rule) &&
+                            parser.predictable(&mut verdicts, &rules[rule].head, idx,
+                                               lookahead.as_deref()) {
+                        if let Some(stats) = &mut stats {
+                            stats.predicted(&Span::new(&rules[rule], idx));
+                        }
+                        stateset.add_prediction(rule);
+                    }
+                }
                 // Add new items to the current stateset merging existing ones
-                for new_item in new_items {
-                    if let Some(existent) = stateset.get(&new_item) {
+                for new_item in new_items.drain(..) {
+                    if let Some(existent) = stateset.items.get(&new_item) {
                         existent.merge_sources(new_item);
                     } else {
-                        stateset.insert(Rc::new(new_item));
+                        // Extended items are all completions, scans go to the next set
+                        if let Some(stats) = &mut stats {
+                            stats.completed(&new_item);
+                        }
+                        stateset.items.insert(Rc::new(new_item));
+                        chart_items += 1;
                     }
                 }
+                over_items(chart_items)?;
                 // do precitions/completions until expansions are exhausted
                 if prev_len == stateset.len() {
                     break;
                 }
             }
+            if prefix {
+                let parses = statesets[idx].complete_parses(&parser.grammar, origin, idx);
+                if !parses.is_empty() {
+                    longest_prefix = Some((parses, idx - origin));
+                }
+            }
             // Build Si+1 with items in the current state that accept the next token
             if let Some(lexeme) = tokenizer.next() {
-                statesets.push(EarleyParser::scans(
-                    statesets[idx].iter(), lexeme.as_ref(), idx + 1).collect());
+                if let Some(limit) = parser.max_tokens.filter(|limit| idx - origin >= *limit) {
+                    return Err(format!("Parse Error: input over {} tokens", limit));
+                }
+                // all Scans at this position share the lexeme
+                let lexeme: Rc<str> = lexeme.as_ref().into();
+                let mut next = self.spare.pop().unwrap_or_default();
+                next.items.extend(parser.scans(&statesets[idx], lexeme, idx + 1));
+                chart_items += next.items.len();
+                over_items(chart_items)?;
+                statesets.push(next);
+                if let Some(stats) = &mut stats {
+                    statesets[idx + 1].items.iter().for_each(|item| stats.scanned(item));
+                    stats.stateset_done(statesets[idx].len(), || statesets[idx].dump(rules, idx));
+                }
+                let (earlier, current) = statesets.split_at_mut(idx);
+                current[0].leo = current[0].leo_links(rules, idx, earlier);
+                statesets[idx].retain_waiting(rules);
+                // no item can progress, the prefix can't grow
+                if prefix && statesets[idx + 1].items.is_empty() {
+                    break;
+                }
             } else {
                 break;
             }
         }
 
-        // debug StateSets
+        // debug StateSets (all but the last one only keep waiting items)
         if cfg!(feature="debug") {
             for (idx, stateset) in statesets.iter().enumerate() {
                 eprintln!("=== StateSet {} ===", idx);
-                stateset.iter().inspect(|item| {
+                for prediction in &stateset.predicted {
+                    eprintln!("{:?} -- SRC: ", Span::new(&rules[prediction.rule], idx));
+                }
+                stateset.items.iter().inspect(|item| {
                     let src = item.sources().iter()
                         .map(|bp| format!("{:?}", bp))
                         .collect::<Vec<_>>().join(", ");
@@ -120,16 +647,29 @@ impl EarleyParser {
             }
         }
 
-        // Check that at least one item is a. complete, b. starts at the idx 0,
-        // and c. the name of the rule matches the starting symbol.
-        // It spans the whole input because we search at the last stateset
-        let parse_trees: Vec<_> = statesets.pop()
-            .expect("No Statesets (even s0)")
-            .iter()
-            .filter(|item| item.start == 0 && item.complete() &&
-                           item.rule.head == self.grammar.start)
-            .cloned()
-            .collect();
+        if let Some(stats) = &mut stats {
+            let last = &statesets[statesets.len() - 1];
+            stats.stateset_done(last.len(), || last.dump(rules, statesets.len() - 1));
+            stats.expected = last.expected_terminals(rules);
+        }
+
+        let (parse_trees, consumed) = match longest_prefix {
+            Some(longest) => longest,
+            None if prefix => (Vec::new(), 0),
+            None => {
+                let end = statesets.len() - 1;
+                (statesets[end].complete_parses(&parser.grammar, origin, end), end - origin)
+            }
+        };
+
+        // Hand allocations back for the next parse, trees hold on to their Spans.
+        // Swap buffers rather than moving StateSets over, no need to realloc.
+        statesets.iter_mut().for_each(StateSet::clear);
+        statesets.append(&mut self.spare);
+        self.statesets = std::mem::replace(&mut self.spare, statesets);
+        self.new_predictions = new_predictions;
+        self.new_items = new_items;
+
         if parse_trees.is_empty() {
             return Err("Parse Error: No Rule completes".to_string());
         }
@@ -139,6 +679,6 @@ impl EarleyParser {
                 eprintln!("{}", t.stringify(0));
             }
         }
-        Ok(ParseTrees(parse_trees))
+        Ok((ParseTrees(parse_trees), consumed))
     }
 }
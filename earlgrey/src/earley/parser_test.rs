@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 use super::grammar::{GrammarBuilder, Grammar};
 use super::parser::EarleyParser;
 use super::trees::EarleyForest;
@@ -123,6 +121,59 @@ mod math {
         // Test evaluation of the unique parse tree.
         assert_eq!(evaler.eval(&pout).unwrap(), expected_tree);
         assert_eq!(evaler.eval_recursive(&pout).unwrap(), expected_tree);
+        assert_eq!(evaler.eval_bottom_up(&pout).unwrap(), expected_tree);
+    }
+
+    #[test]
+    fn bottom_up_order() {
+        use super::super::parser::EarleyParser;
+        use super::super::trees::EarleyForest;
+        use std::cell::RefCell;
+        let grammar = grammar_math();
+        let p = EarleyParser::new(grammar.clone());
+        let pout = p.parse("1 + 2 * 3 + ( 4 )".split_whitespace()).unwrap();
+        // log Number reductions as they happen
+        let order = RefCell::new(Vec::new());
+        let mut evaler = EarleyForest::new(|_, tok| tok.parse::<f64>().unwrap_or(0.0));
+        evaler.action("Sum -> Sum [+-] Mul", |n| n[0] + n[2]);
+        evaler.action("Sum -> Mul", |n| n[0]);
+        evaler.action("Mul -> Mul [*/] Pow", |n| n[0] * n[2]);
+        evaler.action("Mul -> Pow", |n| n[0]);
+        evaler.action("Pow -> Num [^] Pow", |n| n[0].powf(n[2]));
+        evaler.action("Pow -> Num", |n| n[0]);
+        evaler.action("Num -> ( Sum )", |n| n[1]);
+        evaler.action("Num -> Number", |n| { order.borrow_mut().push(n[0]); n[0] });
+        assert_eq!(evaler.eval_bottom_up(&pout).unwrap(), 11.0);
+        assert_eq!(*order.borrow(), vec![1.0, 2.0, 3.0, 4.0]);
+        order.borrow_mut().clear();
+        assert_eq!(evaler.eval(&pout).unwrap(), 11.0);
+        assert_eq!(*order.borrow(), vec![4.0, 3.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn span_info() {
+        use super::super::parser::EarleyParser;
+        use super::super::trees::{EarleyForest, SpanInfo};
+        let grammar = grammar_math();
+        let p = EarleyParser::new(grammar.clone());
+        let pout = p.parse("1 + ( 2 * 3 )".split_whitespace()).unwrap();
+        let mut evaler = EarleyForest::new(|_, tok| tok.to_string());
+        for rule in ["Sum -> Mul", "Mul -> Pow", "Pow -> Num", "Num -> Number"] {
+            evaler.action(rule, |n| n[0].clone());
+        }
+        let at = |s: SpanInfo| format!("@{}..{}", s.start_token, s.end_token);
+        evaler.span_action("Sum -> Sum [+-] Mul", move |n, s| format!("[{} + {}]{}", n[0], n[2], at(s)));
+        evaler.span_action("Mul -> Mul [*/] Pow", move |n, s| format!("[{} * {}]{}", n[0], n[2], at(s)));
+        evaler.span_action("Num -> ( Sum )", move |n, s| format!("({}){}", n[1], at(s)));
+        // the root covers the whole input
+        assert_eq!(evaler.eval_with_span_info(&pout).unwrap(), "[1 + ([2 * 3]@3..6)@2..7]@0..7");
+        // other evaluators don't run span actions
+        assert_eq!(evaler.eval(&pout).unwrap_err(), "Missing Action: Mul -> Mul [*/] Pow");
+        evaler.action("Sum -> Sum [+-] Mul", |n| format!("{}+{}", n[0], n[2]));
+        evaler.action("Mul -> Mul [*/] Pow", |n| format!("{}*{}", n[0], n[2]));
+        evaler.action("Num -> ( Sum )", |n| n[1].clone());
+        assert_eq!(evaler.eval(&pout).unwrap(), "1+2*3");
+        assert_eq!(evaler.eval_with_span_info(&pout).unwrap(), "[1 + ([2 * 3]@3..6)@2..7]@0..7");
     }
 }
 
@@ -172,6 +223,124 @@ fn grammar_ambiguous() {
     check_trees(&trees2, expected_trees);
 }
 
+#[test]
+fn parse_tokens() {
+    let grammar = GrammarBuilder::default()
+      .nonterm("E")
+      .terminal("n", |n| n.chars().all(|c| c.is_ascii_digit()))
+      .literal("+")
+      .rule("E", &["E", "+", "E"])
+      .rule("E", &["n"])
+      .into_grammar("E").unwrap();
+    let p = EarleyParser::new(grammar.clone());
+    let evaler = tree_evaler(grammar);
+    let tokens: Vec<String> = vec!["1".into(), "+".into(), "2".into(), "+".into(), "3".into()];
+    let parsed = [
+        p.parse(lexers::DelimTokenizer::new("1 + 2 + 3".chars(), " ", true)).unwrap(),
+        p.parse_tokens(tokens.clone()).unwrap(),
+        p.parse_tokens(&tokens[..]).unwrap(),
+        p.parse_tokens(["1", "+", "2", "+", "3"]).unwrap(),
+    ];
+    let expected = evaler.eval_all(&parsed[0]).unwrap();
+    assert_eq!(expected.len(), 2);
+    for trees in &parsed {
+        assert_eq!(trees.to_string(), "2 derivations from 1 root, ambiguous");
+        assert_eq!(evaler.eval_all(trees).unwrap(), expected);
+    }
+}
+
+#[test]
+fn parse_with_oracle() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    let grammar = || GrammarBuilder::default()
+      .nonterm("S")
+      .nonterm("Date")
+      .nonterm("Num")
+      .terminal("month", |m| ["jan", "feb", "mar"].contains(&m))
+      .terminal("n", |n| n.chars().all(|c| c.is_ascii_digit()))
+      .rule("S", &["Date"])
+      .rule("S", &["Num"])
+      .rule("S", &["S", "S"])
+      .rule("Date", &["month", "n"])
+      .rule("Num", &["n"])
+      .into_grammar("S").unwrap();
+    let tokens = ["3", "jan", "5", "7"];
+    let (plain, plain_stats) = EarleyParser::new(grammar()).parse_with_stats(tokens.iter()).unwrap();
+    // dates can only start at months
+    let asked = Rc::new(RefCell::new(Vec::new()));
+    let log = asked.clone();
+    let parser = EarleyParser::new(grammar()).with_oracle(move |pos, head, token| {
+        log.borrow_mut().push((pos, head.to_string(), token.map(String::from)));
+        head != "Date" || token.is_some_and(|t| ["jan", "feb", "mar"].contains(&t))
+    });
+    let (pruned, pruned_stats) = parser.parse_with_stats(tokens.iter()).unwrap();
+    assert_eq!(format!("{:?}", pruned), format!("{:?}", plain));
+    assert!(pruned_stats.total().predictions < plain_stats.total().predictions);
+    let asked = asked.borrow();
+    assert!(asked.contains(&(0, "S".to_string(), Some("3".to_string()))));
+    assert!(asked.contains(&(1, "Date".to_string(), Some("jan".to_string()))));
+    assert!(asked.contains(&(4, "S".to_string(), None)));
+    // once per nonterminal and position, S has 3 rules
+    let mut unique = asked.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), asked.len());
+    // an oracle pruning what a parse needs loses it
+    let parser = EarleyParser::new(grammar()).with_oracle(|_, head, _| head != "Num");
+    assert!(parser.parse(tokens.iter()).is_err());
+    assert!(parser.parse(["feb", "2"].iter()).is_ok());
+}
+
+#[test]
+fn parse_eof_marker() {
+    // a list only makes a statement when the input ends after it
+    let grammar = GrammarBuilder::default()
+      .nonterm("S")
+      .nonterm("L")
+      .terminal("n", |n| n.chars().all(|c| c.is_ascii_digit()))
+      .literal("$")
+      .rule("S", &["L", "$"])
+      .rule("L", &["L", "n"])
+      .rule("L", &["n"])
+      .into_grammar("S").unwrap();
+    let p = EarleyParser::new(grammar);
+    let tokens = |input: &'static str| lexers::DelimTokenizer::new(input.chars(), " ", true);
+    let trees = p.parse(tokens("1 2 3").eof("$")).unwrap();
+    assert_eq!(trees.to_string(), "1 derivation from 1 root, unambiguous");
+    assert!(p.parse(tokens("1 2 3")).is_err());
+    assert!(p.parse(tokens("").eof("$")).is_err());
+}
+
+#[test]
+fn reserved_keywords() {
+    // Stmt -> if Ident | Ident Ident, 'if' is also an Ident
+    let builder = || GrammarBuilder::default()
+      .nonterm("Stmt")
+      .literal("if")
+      .terminal("Ident", |n| n.chars().all(|c| c.is_ascii_lowercase()))
+      .rule("Stmt", &["if", "Ident"])
+      .rule("Stmt", &["Ident", "Ident"]);
+    let grammar = builder().into_grammar("Stmt").unwrap();
+    let pout = EarleyParser::new(grammar.clone()).parse("if x".split_whitespace()).unwrap();
+    check_trees(&tree_evaler(grammar).eval_all(&pout).unwrap(), vec![
+        r#"Node("Stmt -> if Ident", [Leaf("if", "if"), Leaf("Ident", "x")])"#,
+        r#"Node("Stmt -> Ident Ident", [Leaf("Ident", "if"), Leaf("Ident", "x")])"#,
+    ]);
+
+    let grammar = builder().reserved("Ident", &["if", "while"]).into_grammar("Stmt").unwrap();
+    let parser = EarleyParser::new(grammar.clone());
+    let pout = parser.parse("if x".split_whitespace()).unwrap();
+    check_trees(&tree_evaler(grammar).eval_all(&pout).unwrap(), vec![
+        r#"Node("Stmt -> if Ident", [Leaf("if", "if"), Leaf("Ident", "x")])"#,
+    ]);
+    assert!(parser.parse("x if".split_whitespace()).is_err());
+    assert!(parser.parse("x y".split_whitespace()).is_ok());
+
+    let grammar = builder().reserved("Stmt", &["if"]).into_grammar("Stmt");
+    assert_eq!(grammar.unwrap_err(), "Reserved words need a Term: Stmt");
+}
+
 #[test]
 fn earley_corner_case() {
     // Earley's corner case. We should only get 2 trees.
@@ -330,6 +499,108 @@ fn math_ambiguous_catalan() {
     assert_eq!(ef.eval_all(&pout).unwrap().len(), 42);
 }
 
+#[test]
+fn cancellation() {
+    use super::parser::CANCELLED;
+    use std::cell::Cell;
+    // E -> E E | a, cubic on its input and with Catalan many trees
+    let grammar = GrammarBuilder::default()
+      .nonterm("E")
+      .literal("a")
+      .rule("E", &["E", "E"])
+      .rule("E", &["a"])
+      .into_grammar("E")
+      .expect("Bad grammar");
+    let p = EarleyParser::new(grammar.clone());
+    let input = vec!["a"; 300];
+    // trip on the 5th check, no work goes on after that
+    let checks = Cell::new(0);
+    let check = || { checks.set(checks.get() + 1); checks.get() >= 5 };
+    assert_eq!(p.parse_cancellable(input.iter(), check).unwrap_err(), CANCELLED);
+    assert_eq!(checks.get(), 5);
+    // a context can parse again after a cancellation
+    let mut context = p.parse_context().cancel_when(|| checks.get() < 10);
+    assert!(context.parse(input.iter()).is_err());
+    assert!(context.parse(["a", "a"].iter()).is_ok());
+
+    let pout = p.parse(vec!["a"; 14].iter()).unwrap();
+    let evaled = Cell::new(0);
+    let ef = tree_evaler(grammar)
+        .cancel_when(|| { evaled.set(evaled.get() + 1); evaled.get() > 100 });
+    assert_eq!(ef.eval_all(&pout).unwrap_err(), CANCELLED);
+    assert_eq!(evaled.get(), 101);
+}
+
+#[test]
+fn input_limits() {
+    // E -> E E | a, the chart grows cubically on the input
+    let grammar = || GrammarBuilder::default()
+      .nonterm("E")
+      .literal("a")
+      .rule("E", &["E", "E"])
+      .rule("E", &["a"])
+      .into_grammar("E")
+      .expect("Bad grammar");
+    let p = EarleyParser::new(grammar()).max_tokens(10);
+    assert!(p.parse(["a"; 10].iter()).is_ok());
+    assert_eq!(p.parse(["a"; 11].iter()).unwrap_err(), "Parse Error: input over 10 tokens");
+    // the error comes before reading the whole input
+    let mut read = 0;
+    let tokens = std::iter::repeat("a").inspect(|_| read += 1);
+    assert!(p.parse(tokens).is_err());
+    assert_eq!(read, 11);
+
+    let unlimited = EarleyParser::new(grammar());
+    let (_, stats) = unlimited.parse_with_stats(vec!["a"; 20].iter()).unwrap();
+    let items = stats.total().scans + stats.total().completions;
+    let p = EarleyParser::new(grammar()).max_items(items);
+    assert!(p.parse(vec!["a"; 20].iter()).is_ok());
+    assert_eq!(p.parse(vec!["a"; 21].iter()).unwrap_err(),
+               format!("Parse Error: chart over {} items", items));
+    // a context can parse again after hitting the limit
+    let mut context = p.parse_context();
+    assert!(context.parse(vec!["a"; 30].iter()).is_err());
+    assert!(context.parse(["a"; 3].iter()).is_ok());
+}
+
+#[test]
+fn complexity_estimate() {
+    let estimate = |rules: &[(&str, &[&str])], n| {
+        let mut builder = GrammarBuilder::default().literal("a").literal("+");
+        for (head, _) in rules {
+            builder.nonterm_try(head);
+        }
+        for (head, spec) in rules {
+            builder.rule_try(head, spec);
+        }
+        let grammar = builder.into_grammar(rules[0].0).expect("Bad grammar");
+        let bound = EarleyParser::complexity_estimate(&grammar, n);
+        assert_eq!(bound.worst_case, "O(n³)");
+        (bound.expected_case, bound.note)
+    };
+    let (expected, note) = estimate(&[("E", &["E", "+", "E"]), ("E", &["a"])], 100);
+    assert_eq!(expected, "O(n³)");
+    assert_eq!(note, "rules that are left and right recursive make the grammar ambiguous: \
+                      E -> E + E, about 1000000 chart operations for 100 tokens (up to 1000000)");
+    // recursion through other rules and nullable symbols counts
+    let (expected, note) = estimate(&[("S", &["T", "S", "N"]), ("T", &["S"]), ("T", &["a"]),
+                                      ("N", &[])], 10);
+    assert_eq!(expected, "O(n³)");
+    assert!(note.contains(": S -> T S N,"), "{}", note);
+    let (expected, note) = estimate(&[("A", &["B"]), ("B", &["A", "N"]), ("B", &["a"]),
+                                      ("N", &[])], 10);
+    assert_eq!(expected, "O(n³)");
+    assert!(note.starts_with("derivation cycles through A, B make"), "{}", note);
+    // one sided recursion
+    let (expected, note) = estimate(&[("S", &["S", "+", "T"]), ("S", &["T"]),
+                                      ("T", &["a", "T"]), ("T", &["a"])], 10);
+    assert_eq!(expected, "O(n²)");
+    assert_eq!(note, "no ambiguous rule found, deterministic grammars parse in O(n), \
+                      about 100 chart operations for 10 tokens (up to 1000)");
+    let (expected, _) = estimate(&[("S", &["a", "T"]), ("T", &["+"]), ("T", &[])], 10);
+    assert_eq!(expected, "O(n)");
+}
+
 #[test]
 fn trigger_has_multiple_bp() {
     // E -> E + n | n + E | n
@@ -351,9 +622,280 @@ fn trigger_has_multiple_bp() {
     assert_eq!(ef.eval_all(&pout).unwrap().len(), 8);
 }
 
+#[test]
+fn parse_stats() {
+    use super::stats::RuleStats;
+    // S -> S + n | n
+    let grammar = GrammarBuilder::default()
+      .nonterm("S")
+      .terminal("+", |n| n == "+")
+      .terminal("n", |n| n == "n")
+      .rule("S", &["S", "+", "n"])
+      .rule("S", &["n"])
+      .into_grammar("S")
+      .expect("Bad grammar");
+    let p = EarleyParser::new(grammar);
+    let (_, stats) = p.parse_with_stats("n + n".split_whitespace()).unwrap();
+    // S0: S -> . S + n, S -> . n  (predictions)
+    // S1: S -> n .  (scan), S -> S . + n  (completion)
+    // S2: S -> S + . n  (scan)
+    // S3: S -> S + n .  (scan), S -> S . + n  (completion)
+    assert_eq!(stats.rules["S -> S + n"],
+               RuleStats{predictions: 1, scans: 2, completions: 2});
+    assert_eq!(stats.rules["S -> n"],
+               RuleStats{predictions: 1, scans: 1, completions: 0});
+    assert_eq!(stats.total().items(), 7);
+    assert_eq!(stats.stateset_sizes, vec![2, 2, 1, 2]);
+    let table = stats.to_string();
+    assert!(table.find("S -> S + n").unwrap() < table.find("S -> n ").unwrap());
+}
+
+#[test]
+fn parse_chart() {
+    let grammar = GrammarBuilder::default()
+      .nonterm("S")
+      .literal("+")
+      .literal("n")
+      .rule("S", &["S", "+", "n"])
+      .rule("S", &["n"])
+      .into_grammar("S")
+      .expect("Bad grammar");
+    let p = EarleyParser::new(grammar);
+    let (trees, stats) = p.parse_chart("n +".split_whitespace());
+    assert!(trees.is_err());
+    assert_eq!(stats.chart[2], vec!["(0 - 2) S -> S + \u{b7} n #bp: 1"]);
+    assert_eq!(stats.expected, vec!["n"]);
+    assert_eq!(stats.dead_at(), None);
+    let (trees, stats) = p.parse_chart("n + + n".split_whitespace());
+    assert!(trees.is_err());
+    assert_eq!(stats.dead_at(), Some(2));
+    let (trees, stats) = p.parse_chart("n".split_whitespace());
+    assert!(trees.is_ok());
+    assert_eq!(stats.chart.len(), 2);
+    assert_eq!(stats.expected, vec!["+"]);
+    // only parse_chart dumps the chart
+    assert!(p.parse_with_stats("n".split_whitespace()).unwrap().1.chart.is_empty());
+}
+
+#[test]
+fn right_recursion_linear() {
+    // A -> x A | x
+    let grammar = GrammarBuilder::default()
+      .nonterm("A")
+      .literal("x")
+      .rule("A", &["x", "A"])
+      .rule("A", &["x"])
+      .into_grammar("A")
+      .expect("Bad grammar");
+    let p = EarleyParser::new(grammar);
+    let mut ev = EarleyForest::new(|_, _| 1);
+    ev.action("A -> x A", |n| n[0] + n[1]);
+    ev.action("A -> x", |n| n[0]);
+    let mut items = Vec::new();
+    for n in [500, 1000, 2000] {
+        let input = vec!["x"; n];
+        let (trees, stats) = p.parse_with_stats(input.iter()).unwrap();
+        assert_eq!(trees.derivations(), 1);
+        // skipped completions are still in the tree
+        assert_eq!(ev.eval(&trees), Ok(n));
+        items.push(stats.total().items());
+    }
+    // chart grows linearly, completions don't cascade over all recursion levels
+    assert!(items[1] <= 2 * items[0] + 10 && items[2] <= 2 * items[1] + 10, "{:?}", items);
+}
+
+#[test]
+fn right_recursion_shared_completions() {
+    // S -> y A, A -> P B, P -> x | x x, B -> x | x x
+    // Completions of B from 2 different positions yield the same A
+    let grammar = GrammarBuilder::default()
+      .nonterm("S")
+      .nonterm("A")
+      .nonterm("P")
+      .nonterm("B")
+      .literal("x")
+      .literal("y")
+      .rule("S", &["y", "A"])
+      .rule("A", &["P", "B"])
+      .rule("P", &["x"])
+      .rule("P", &["x", "x"])
+      .rule("B", &["x"])
+      .rule("B", &["x", "x"])
+      .into_grammar("S")
+      .expect("Bad grammar");
+    let p = EarleyParser::new(grammar.clone());
+    let trees = p.parse("y x x x".split_whitespace()).unwrap();
+    assert_eq!(format!("{:?}", trees), r#"ParseTrees(["S -> y A"])"#);
+    assert_eq!(trees.derivations(), 2);
+    let root = trees.0[0].sources();
+    assert_eq!(root.len(), 1);
+    let super::spans::SpanSource::Completion(_, a) = &root[0] else { panic!("Expected A") };
+    assert_eq!(a.sources().len(), 2);
+    check_trees(&tree_evaler(grammar).eval_all(&trees).unwrap(), vec![
+        r#"Node("S -> y A", [Leaf("y", "y"), Node("A -> P B", [Node("P -> x", [Leaf("x", "x")]), Node("B -> x x", [Leaf("x", "x"), Leaf("x", "x")])])])"#,
+        r#"Node("S -> y A", [Leaf("y", "y"), Node("A -> P B", [Node("P -> x x", [Leaf("x", "x"), Leaf("x", "x")]), Node("B -> x", [Leaf("x", "x")])])])"#,
+    ]);
+}
+
+#[test]
+fn rule_coverage() {
+    // E -> E + E | E * E | ( E ) | n
+    let grammar = GrammarBuilder::default()
+      .nonterm("E")
+      .literal("+")
+      .literal("*")
+      .literal("(")
+      .literal(")")
+      .terminal("n", |n| n.chars().all(|c| c.is_ascii_digit()))
+      .rule("E", &["E", "+", "E"])
+      .rule("E", &["E", "*", "E"])
+      .rule("E", &["(", "E", ")"])
+      .rule("E", &["n"])
+      .into_grammar("E")
+      .expect("Bad grammar");
+    let p = EarleyParser::new(grammar);
+    let mut coverage = p.coverage();
+    assert_eq!(coverage.covered(), Vec::<&str>::new());
+    coverage.record(&p.parse("1 + 2".split_whitespace()).unwrap());
+    assert_eq!(coverage.covered(), vec!["E -> E + E", "E -> n"]);
+    // predicted or partially matched rules don't count
+    coverage.record(&p.parse("1 + 2 + 3".split_whitespace()).unwrap());
+    assert_eq!(coverage.uncovered(), vec!["E -> E * E", "E -> ( E )"]);
+    assert_eq!(coverage.hits("E -> E + E"), 2);
+    // both groupings of an ambiguous parse count
+    coverage.record(&p.parse("1 + 2 * 3".split_whitespace()).unwrap());
+    assert_eq!(coverage.uncovered(), vec!["E -> ( E )"]);
+    assert_eq!((coverage.hits("E -> n"), coverage.hits("E -> E * E")), (3, 1));
+}
+
+#[test]
+fn missing_action_default() {
+    #[derive(Clone, Debug, PartialEq)]
+    enum Node { Passthrough(String), Text(String), Assign(String, String) }
+    let grammar = GrammarBuilder::default()
+      .nonterm("Stmt")
+      .nonterm("Let")
+      .nonterm("Semi")
+      .nonterm("Id")
+      .nonterm("Num")
+      .literal("let")
+      .literal("=")
+      .literal(";")
+      .terminal("id", |id| id.chars().all(|c| c.is_alphabetic()))
+      .terminal("n", |n| n.chars().all(|c| c.is_ascii_digit()))
+      .rule("Stmt", &["Let", "Id", "=", "Num", "Semi"])
+      .rule("Let", &["let"])
+      .rule("Semi", &[";"])
+      .rule("Id", &["id"])
+      .rule("Num", &["n"])
+      .into_grammar("Stmt")
+      .expect("Bad grammar");
+    let parser = EarleyParser::new(grammar);
+    let trees = parser.parse("let x = 5 ;".split_whitespace()).unwrap();
+    let mut ev = EarleyForest::new(|_, token| Node::Text(token.to_string()));
+    let text = |node: &Node| match node {
+        Node::Text(text) => text.clone(),
+        other => format!("{:?}", other),
+    };
+    ev.action("Stmt -> Let Id = Num Semi", move |n| {
+        assert_eq!(n[0], Node::Passthrough("Let -> let".to_string()));
+        assert_eq!(n[4], Node::Passthrough("Semi -> ;".to_string()));
+        Node::Assign(text(&n[1]), text(&n[3]))
+    });
+    ev.action("Id -> id", |mut n| n.swap_remove(0));
+    ev.action("Num -> n", |mut n| n.swap_remove(0));
+    assert_eq!(ev.eval(&trees), Err("Missing Action: Semi -> ;".to_string()));
+    let ev = ev.with_missing_action(|rule| Node::Passthrough(rule.to_string()));
+    let expected = Node::Assign("x".to_string(), "5".to_string());
+    assert_eq!(ev.eval(&trees), Ok(expected.clone()));
+    assert_eq!(ev.eval_recursive(&trees), Ok(expected));
+}
+
+#[test]
+fn validate_actions() {
+    let grammar = GrammarBuilder::default()
+      .nonterm("expr")
+      .nonterm("term")
+      .literal("+")
+      .terminal("n", |n| n.chars().all(|c| c.is_ascii_digit()))
+      .rule("expr", &["expr", "+", "term"])
+      .rule("expr", &["term"])
+      .rule("term", &["n"])
+      .into_grammar("expr")
+      .expect("Bad grammar");
+    let mut ev = EarleyForest::new(|_, token| token.parse::<f64>().unwrap_or(0.0));
+    ev.action("expr -> term", |n| n[0]);
+    ev.action("foo -> bar", |n| n[0]);
+    ev.span_action("term -> n", |n, _| n[0]);
+    ev.span_action("expr -> expr - term", |n, _| n[0] - n[2]);
+    assert_eq!(ev.validate_actions_against_grammar(&grammar), vec![
+        "Missing action for: expr -> expr + term",
+        "Only a span action for: term -> n",
+        "Unknown rule registered: expr -> expr - term",
+        "Unknown rule registered: foo -> bar"]);
+    ev.action("expr -> expr + term", |n| n[0] + n[2]);
+    ev.action("term -> n", |n| n[0]);
+    assert_eq!(ev.validate_actions_against_grammar(&grammar).len(), 2);
+    // rules pushed on a built grammar can repeat, they're reported once
+    let mut grammar = grammar;
+    grammar.rules.push(grammar.rules[0].clone());
+    let ev = EarleyForest::new(|_, token| token.parse::<f64>().unwrap_or(0.0));
+    assert_eq!(ev.validate_actions_against_grammar(&grammar).len(), 3);
+    // a fallback covers rules without actions, not spurious ones
+    let mut ev = EarleyForest::new(|_, _| 0.0);
+    ev.action("term -> n ", |n| n[0]);
+    ev.fallback_action(|_, _| 0.0);
+    assert_eq!(ev.validate_actions_against_grammar(&grammar), vec![
+        "Unknown rule registered: term -> n "]);
+}
+
+#[test]
+fn reduce_arity_mismatch() {
+    use super::parser::ParseTrees;
+    use super::spans::{Span, SpanSource};
+    use std::rc::Rc;
+    let grammar = GrammarBuilder::default()
+      .nonterm("S")
+      .nonterm("A")
+      .literal("x")
+      .literal("y")
+      .literal("z")
+      .rule("S", &["x", "A"])
+      .rule("A", &["y", "z"])
+      .into_grammar("S")
+      .expect("Bad grammar");
+    let mut ev = EarleyForest::new(|_, token| token.to_string());
+    ev.action("S -> x A", |n| format!("({} {})", n[0], n[1]));
+    ev.action("A -> y z", |n| format!("({} {})", n[0], n[1]));
+    // the grammar changed ("S -> x A w" became "S -> x A"), the action didn't
+    let mut stale = EarleyForest::new(|_, token| token.to_string());
+    stale.action("S -> x A w", |n| format!("({} {} {})", n[0], n[1], n[2]));
+    stale.action("A -> y z", |n| format!("({} {})", n[0], n[1]));
+    let trees = EarleyParser::new(grammar.clone()).parse("x y z".split_whitespace()).unwrap();
+    assert_eq!(ev.eval(&trees), Ok("(x (y z))".to_string()));
+    assert_eq!(stale.eval(&trees), Err("Missing Action: S -> x A".to_string()));
+    assert_eq!(stale.eval_bottom_up(&trees), Err("Missing Action: S -> x A".to_string()));
+    // a forest where "A -> y z" completes "S -> x A" after only "y", its
+    // argument would go to S rather than A
+    let scan = |span: Span, token: &str| {
+        let end = span.end + 1;
+        Span::extend(SpanSource::Scan(Rc::new(span), token.into()), end)
+    };
+    let s = scan(Span::new(&grammar.rules[0], 0), "x");
+    let a = scan(Span::new(&grammar.rules[1], 1), "y");
+    let end = a.end;
+    let root = Span::extend(SpanSource::Completion(Rc::new(s), Rc::new(a)), end);
+    let trees = ParseTrees(vec![Rc::new(root)]);
+    let error = "Rule S -> x A reduced with 1 arguments, expected 2".to_string();
+    assert_eq!(ev.eval(&trees), Err(error.clone()));
+    assert_eq!(ev.eval_all(&trees), Err(error));
+    assert_eq!(ev.eval_bottom_up(&trees),
+               Err("Rule A -> y z reduced with 1 arguments, expected 2".to_string()));
+}
+
 mod small_math {
     use super::super::grammar::{Grammar, GrammarBuilder};
-    use super::super::parser::EarleyParser;
+    use super::super::parser::{EarleyParser, ParseTrees};
     use super::super::trees::EarleyForest;
     use super::check_trees;
 
@@ -390,6 +932,198 @@ mod small_math {
         assert!(trees.contains(&14.0));
     }
 
+    #[test]
+    fn eval_first() {
+        use std::cell::Cell;
+        let reductions = Cell::new(0);
+        let mut ev = EarleyForest::new(|symbol, token| {
+            match symbol {"n" => token.parse().unwrap(), _ => 0}
+        });
+        ev.action("E -> E + E", |nodes: Vec<i64>| { reductions.set(reductions.get() + 1); nodes[0] + nodes[2] });
+        ev.action("E -> E * E", |nodes| { reductions.set(reductions.get() + 1); nodes[0] * nodes[2] });
+        ev.action("E -> n", |nodes| { reductions.set(reductions.get() + 1); nodes[0] });
+        // 15 operators group in 9694845 ways, only one tree is evaluated
+        let input = "1 + 2 * 3 + 4 * 5 + 6 * 7 + 8 * 9 + 1 * 2 + 3 * 4 + 5 * 6 + 7".split_whitespace();
+        let ps = EarleyParser::new(small_math()).parse(input).unwrap();
+        let first = ev.eval_first(&ps).unwrap();
+        assert_eq!(reductions.get(), 31);
+        assert_eq!(ev.eval_first(&ps).unwrap(), first);
+        // short input to check it's one of all the trees
+        let input = "3 + 4 * 2".split_whitespace();
+        let ps = EarleyParser::new(small_math()).parse(input).unwrap();
+        assert!(ev.eval_all(&ps).unwrap().contains(&ev.eval_first(&ps).unwrap()));
+        // eval is eval_first
+        assert_eq!(ev.eval(&ps), ev.eval_first(&ps));
+        let empty = ParseTrees(Vec::new());
+        assert!(ev.eval(&empty).is_err());
+        assert!(ev.eval_bottom_up(&empty).is_err());
+    }
+
+    #[test]
+    fn eval_min_cost() {
+        use std::collections::HashMap;
+        // E -> E + E | E E | n n | n, juxtaposition multiplies, "n n" is a 2 digit number
+        let grammar = GrammarBuilder::default()
+          .nonterm("E")
+          .terminal("+", |n| n == "+")
+          .terminal("n", |n| "1234567890".contains(n))
+          .rule("E", &["E", "+", "E"])
+          .rule("E", &["E", "E"])
+          .rule("E", &["n", "n"])
+          .rule("E", &["n"])
+          .into_grammar("E")
+          .expect("Bad grammar");
+        let mut ev = EarleyForest::new(|symbol, token| {
+            match symbol {"n" => token.parse().unwrap(), _ => 0}
+        });
+        ev.action("E -> E + E", |nodes: Vec<i64>| nodes[0] + nodes[2]);
+        ev.action("E -> E E", |nodes| nodes[0] * nodes[1]);
+        ev.action("E -> n n", |nodes| nodes[0] * 10 + nodes[1]);
+        ev.action("E -> n", |nodes| nodes[0]);
+        let ps = EarleyParser::new(grammar).parse("1 2 + 3".split_whitespace()).unwrap();
+        let all = ev.eval_all(&ps).unwrap();
+        assert!(all.contains(&15) && all.contains(&5));
+        let costs = |rules: &[(&str, i32)]| -> HashMap<String, i32> {
+            rules.iter().map(|(rule, cost)| (rule.to_string(), *cost)).collect()
+        };
+        assert_eq!(ev.eval_min_cost(&ps, &costs(&[("E -> E E", 1)])), Ok(15));
+        assert_eq!(ev.eval_min_cost(&ps, &costs(&[("E -> n n", 1)])), Ok(5));
+        // costs add up over the tree, 12 + 3 takes 3 + 1 and 1 2 + 3 three times 1
+        assert_eq!(ev.eval_min_cost(&ps, &costs(&[("E -> n n", 3), ("E -> n", 1)])), Ok(5));
+        assert_eq!(ev.eval_min_cost(&ps, &costs(&[("E -> n n", 1), ("E -> n", 1)])), Ok(15));
+        // on ties it's the first tree
+        assert_eq!(ev.eval_min_cost(&ps, &HashMap::new()), ev.eval_first(&ps));
+        assert_eq!(ev.eval_min_cost(&ParseTrees(Vec::new()), &HashMap::new()).err(),
+                   Some("No parse trees to evaluate".to_string()));
+    }
+
+    #[test]
+    fn eval_dedup() {
+        let mut ev = EarleyForest::new(|symbol, token| {
+            match symbol {"n" => token.parse().unwrap(), _ => 0}
+        });
+        ev.action("E -> E + E", |nodes: Vec<i64>| nodes[0] + nodes[2]);
+        ev.action("E -> E * E", |nodes| nodes[0] * nodes[2]);
+        ev.action("E -> n", |nodes| nodes[0]);
+        // (1 + 2) + 3 and 1 + (2 + 3) both sum 6
+        let input = "1 + 2 + 3".split_whitespace();
+        let ps = EarleyParser::new(small_math()).parse(input).unwrap();
+        assert_eq!(ev.eval_all_recursive(&ps).unwrap(), vec![6, 6]);
+        assert_eq!(ev.eval_all_with_dedup(&ps).unwrap(), vec![6]);
+        // different groupings of + and * produce distinct results
+        let input = "3 + 4 * 2".split_whitespace();
+        let ps = EarleyParser::new(small_math()).parse(input).unwrap();
+        let mut trees = ev.eval_all_with_dedup(&ps).unwrap();
+        trees.sort();
+        assert_eq!(trees, vec![11, 14]);
+    }
+
+    #[test]
+    fn reused_context() {
+        let mut ev = EarleyForest::new(|symbol, token| {
+            match symbol {"n" => token.parse().unwrap(), _ => 0}
+        });
+        ev.action("E -> E + E", |nodes: Vec<i64>| nodes[0] + nodes[2]);
+        ev.action("E -> E * E", |nodes| nodes[0] * nodes[2]);
+        ev.action("E -> n", |nodes| nodes[0]);
+        let eval = |parsed: Result<_, String>| parsed.and_then(|ps| {
+            let mut trees = ev.eval_all(&ps)?;
+            trees.sort();
+            Ok(trees)
+        });
+        let parser = EarleyParser::new(small_math());
+        let mut context = parser.parse_context();
+        for i in 0..10_000 {
+            // eg: 1 + 2 * 3, some end on an operator and fail to parse
+            let mut input = Vec::new();
+            for (idx, digit) in (i % 1000).to_string().chars().enumerate() {
+                input.push(digit.to_string());
+                input.push(if (i / 1000 >> idx) % 2 == 0 { "+" } else { "*" }.to_string());
+            }
+            if i % 7 != 0 {
+                input.pop();
+            }
+            assert_eq!(eval(context.parse(input.iter())), eval(parser.parse(input.iter())));
+        }
+    }
+
+    #[test]
+    fn bulk_actions() {
+        // E -> E op E | n, for 9 binary ops
+        let ops = ["+", "-", "*", "/", "%", "^", "max", "min", "avg"];
+        let mut builder = GrammarBuilder::default()
+          .nonterm("E")
+          .terminal("n", |n| n.chars().all(|c| c.is_ascii_digit()))
+          .rule("E", &["n"]);
+        for op in ops {
+            builder = builder.literal(op).rule("E", &["E", op, "E"]);
+        }
+        let parser = EarleyParser::new(builder.into_grammar("E").unwrap());
+        let mut ev = EarleyForest::new(|symbol, token| {
+            match symbol {"n" => token.parse().unwrap(), _ => 0}
+        });
+        ev.register_actions_from_map(crate::earley_actions!{
+            "E -> n" => |n: Vec<i64>| n[0],
+            "E -> E + E" => |n| n[0] + n[2],
+            "E -> E - E" => |n| n[0] - n[2],
+            "E -> E * E" => |n| n[0] * n[2],
+            "E -> E / E" => |n| n[0] / n[2],
+            "E -> E % E" => |n| n[0] % n[2],
+            "E -> E ^ E" => |n: Vec<i64>| n[0].pow(n[2] as u32),
+            "E -> E max E" => |n| std::cmp::max(n[0], n[2]),
+            "E -> E min E" => |n| std::cmp::min(n[0], n[2]),
+            "E -> E avg E" => |n| (n[0] + n[2]) / 2,
+        });
+        let eval = |input: &str| ev.eval(&parser.parse(input.split_whitespace()).unwrap());
+        let results: Vec<_> = ["7", "7 + 2", "7 - 2", "7 * 2", "7 / 2", "7 % 2",
+                               "7 ^ 2", "7 max 2", "7 min 2", "7 avg 2"]
+            .iter().map(|input| eval(input).unwrap()).collect();
+        assert_eq!(results, vec![7, 9, 5, 14, 3, 1, 49, 7, 2, 4]);
+    }
+
+    #[test]
+    fn parse_trees_summary() {
+        let parser = EarleyParser::new(small_math());
+        let parse = |input: &str| parser.parse(input.split_whitespace()).unwrap();
+        let trees = parse("3");
+        assert_eq!(trees.to_string(), "1 derivation from 1 root, unambiguous");
+        assert_eq!(format!("{:?}", trees), r#"ParseTrees(["E -> n"])"#);
+        assert_eq!(parse("3 + 4 * 2").to_string(), "2 derivations from 2 roots, ambiguous");
+        let trees = parse("1 + 2 + 3");
+        assert_eq!(trees.to_string(), "2 derivations from 1 root, ambiguous");
+        assert_eq!(format!("{:?}", trees), r#"ParseTrees(["E -> E + E"])"#);
+        // catalan number of groupings
+        assert_eq!(parse("1 + 2 + 3 + 4 + 5").derivations(), 14);
+    }
+
+    #[test]
+    fn eval_fallback() {
+        let mut ev = EarleyForest::new(|symbol, token| match symbol {
+            "n" => token.to_string(), _ => String::new()
+        });
+        ev.action("E -> n", |mut nodes| nodes.swap_remove(0));
+        let input = "3 + 4 * 2".split_whitespace();
+        let ps = EarleyParser::new(small_math()).parse(input).unwrap();
+        assert!(ev.eval_all(&ps).unwrap_err().starts_with("Missing Action: "));
+        assert!(ev.eval_all_recursive(&ps).is_err());
+        // rules without actions print their name and args
+        ev.fallback_action(|rule, nodes| format!("[{}]({})", rule, nodes.join(",")));
+        check_trees(&ev.eval_all_recursive(&ps).unwrap(), vec![
+            r#""[E -> E + E](3,,[E -> E * E](4,,2))""#,
+            r#""[E -> E * E]([E -> E + E](3,,4),,2)""#,
+        ]);
+        check_trees(&ev.eval_all(&ps).unwrap(), vec![
+            r#""[E -> E + E](3,,[E -> E * E](4,,2))""#,
+            r#""[E -> E * E]([E -> E + E](3,,4),,2)""#,
+        ]);
+        // registered actions still take precedence
+        ev.action("E -> E + E", |nodes| format!("({}+{})", nodes[0], nodes[2]));
+        check_trees(&ev.eval_all(&ps).unwrap(), vec![
+            r#""(3+[E -> E * E](4,,2))""#,
+            r#""[E -> E * E]((3+4),,2)""#,
+        ]);
+    }
+
     #[test]
     fn build_ast() {
         #[derive(Clone, Debug)]
@@ -482,6 +1216,86 @@ mod earley_recognizer {
         fail(&p, "1 +");
     }
 
+    #[test]
+    fn prefix_parse() {
+        let grammar = GrammarBuilder::default()
+          .nonterm("Sum")
+          .terminal("Number", |n| n.chars().all(|c| c.is_ascii_digit()))
+          .terminal("+", |n| n == "+")
+          .rule("Sum", &["Sum", "+", "Number"])
+          .rule("Sum", &["Number"])
+          .into_grammar("Sum")
+          .expect("Bad Grammar");
+        let p = EarleyParser::new(grammar);
+        fail(&p, "1 + 2 garbage");
+        let (trees, consumed) = p.parse_prefix("1 + 2 garbage".split_whitespace()).unwrap();
+        assert_eq!(consumed, 3);
+        assert_eq!(trees.0.len(), 1);
+        // the longest prefix wins, trailing operators aren't part of it
+        let consumed = |input: &str| p.parse_prefix(input.split_whitespace()).map(|(_, n)| n);
+        assert_eq!(consumed("1 + 2 + 3"), Ok(5));
+        assert_eq!(consumed("1 + 2 +"), Ok(3));
+        assert_eq!(consumed("1 1"), Ok(1));
+        assert!(consumed("+ 1").is_err());
+        // stops pulling tokens once the prefix can't grow
+        let mut tokens = "1 x 2 3".split_whitespace();
+        assert_eq!(p.parse_prefix(&mut tokens).unwrap().1, 1);
+        assert_eq!(tokens.collect::<Vec<_>>(), vec!["2", "3"]);
+    }
+
+    #[test]
+    fn parse_at_position() {
+        // Time -> Hour : Minute AmPm | Hour AmPm
+        let grammar = GrammarBuilder::default()
+          .nonterm("Time")
+          .terminal("Num", |n| n.parse::<u32>().is_ok_and(|n| n < 60))
+          .terminal(":", |n| n == ":")
+          .terminal("AmPm", |n| n == "am" || n == "pm")
+          .rule("Time", &["Num", ":", "Num", "AmPm"])
+          .rule("Time", &["Num", "AmPm"])
+          .into_grammar("Time")
+          .expect("Bad Grammar");
+        let p = EarleyParser::new(grammar);
+        let tokens: Vec<String> = "meet at 10 : 30 pm by the door"
+            .split_whitespace().map(str::to_string).collect();
+        // slide over all windows, "30 pm" is also a time
+        let found: Vec<_> = (0..tokens.len())
+            .flat_map(|start| (start..=tokens.len()).map(move |end| (start, end)))
+            .filter(|&(start, end)| p.parse_at_position(&tokens[..end], start).is_ok())
+            .collect();
+        assert_eq!(found, vec![(2, 6), (4, 6)]);
+        // spans keep their position within the whole input
+        let trees = p.parse_at_position(&tokens[..6], 2).unwrap();
+        assert_eq!(trees.0.len(), 1);
+        assert_eq!((trees.0[0].start, trees.0[0].end), (2, 6));
+        assert_eq!(p.parse_at_position(&tokens[..6], 7).unwrap_err(),
+                   "Start position 7 past the end of input (6 tokens)");
+    }
+
+    #[test]
+    fn lexer_errors() {
+        use super::super::ParseOrLexError;
+        let grammar = GrammarBuilder::default()
+            .nonterm("S")
+            .terminal("n", |n| n == "n")
+            .terminal("+", |n| n == "+")
+            .rule("S", &["S", "+", "n"])
+            .rule("S", &["n"])
+            .into_grammar("S")
+            .expect("Bad Grammar");
+        let p = EarleyParser::new(grammar);
+        let lexed = |input: &str| input.split_whitespace()
+            .map(|t| if t == "\"" { Err("unterminated string") } else { Ok(t.to_string()) })
+            .collect::<Vec<_>>();
+        // 'n +' would be a partial parse, the lexer error is reported instead
+        let err = p.parse_fallible(lexed("n + \" n").into_iter()).unwrap_err();
+        assert_eq!(err, ParseOrLexError::Lex(2, "unterminated string"));
+        assert_eq!(err.to_string(), "lex error at token 2: unterminated string");
+        assert!(p.parse_fallible(lexed("n + n").into_iter()).is_ok());
+        assert_eq!(p.parse_fallible(lexed("n +").into_iter()).unwrap_err(),
+                   ParseOrLexError::Parse("Parse Error: No Rule completes".to_string()));
+    }
+
     #[test]
     fn left_recurse() {
         // S -> S + N | N
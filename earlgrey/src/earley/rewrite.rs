@@ -0,0 +1,62 @@
+use super::trees::Subtree;
+
+type LeafPredicate<'a> = Box<dyn Fn(&str, &str) -> bool + 'a>;
+type RuleRename<'a> = Box<dyn Fn(&str) -> Option<String> + 'a>;
+
+// Normalize parse trees before handing them to an interpreter.
+// All transformations are applied in a single bottom-up pass:
+// - drop_leaves: remove terminals (eg: punctuation) matching (symbol, lexeme)
+// - collapse_unit_chains: replace nodes with a single child by that child
+//   so `expr -> term -> factor -> Number` becomes just the Number node
+// - map_rule: rename rules, returning None keeps the original name
+#[derive(Default)]
+pub struct TreeRewriter<'a> {
+    drop_leaves: Option<LeafPredicate<'a>>,
+    collapse_unit_chains: bool,
+    map_rule: Option<RuleRename<'a>>,
+}
+
+impl<'a> TreeRewriter<'a> {
+    pub fn drop_leaves(mut self, pred: impl Fn(&str, &str) -> bool + 'a) -> Self {
+        self.drop_leaves = Some(Box::new(pred));
+        self
+    }
+
+    pub fn collapse_unit_chains(mut self, collapse: bool) -> Self {
+        self.collapse_unit_chains = collapse;
+        self
+    }
+
+    pub fn map_rule(mut self, rename: impl Fn(&str) -> Option<String> + 'a) -> Self {
+        self.map_rule = Some(Box::new(rename));
+        self
+    }
+
+    fn dropped(&self, tree: &Subtree) -> bool {
+        match (tree, &self.drop_leaves) {
+            (Subtree::Node(symbol, lexeme), Some(pred)) => pred(symbol, lexeme),
+            _ => false,
+        }
+    }
+
+    pub fn rewrite(&self, tree: &Subtree) -> Subtree {
+        match tree {
+            Subtree::Node(_, _) => tree.clone(),
+            Subtree::SubT(rule, children) => {
+                // children are rewritten first, a collapsed chain may end
+                // in a leaf that needs dropping as well
+                let mut children: Vec<_> = children.iter()
+                    .map(|child| self.rewrite(child))
+                    .filter(|child| !self.dropped(child))
+                    .collect();
+                if self.collapse_unit_chains && children.len() == 1 {
+                    return children.swap_remove(0);
+                }
+                let rule = self.map_rule.as_ref()
+                    .and_then(|rename| rename(rule))
+                    .unwrap_or_else(|| rule.clone());
+                Subtree::SubT(rule, children)
+            }
+        }
+    }
+}
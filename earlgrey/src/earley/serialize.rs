@@ -0,0 +1,180 @@
+use super::grammar::{Grammar, GrammarBuilder};
+use std::collections::HashMap;
+
+const MAGIC: &[u8] = b"earlgrey-grammar-1";
+
+// Symbol kinds, literals match their own name so they need no predicate
+const NONTERM: u8 = 0;
+const TERMINAL: u8 = 1;
+const LITERAL: u8 = 2;
+
+fn write_u32(out: &mut Vec<u8>, n: usize) {
+    out.extend_from_slice(&(n as u32).to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_u32(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+struct Reader<'d>(&'d [u8]);
+
+impl<'d> Reader<'d> {
+    fn take(&mut self, n: usize) -> Result<&'d [u8], String> {
+        if self.0.len() < n {
+            return Err("Truncated grammar data".to_string());
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn str(&mut self) -> Result<&'d str, String> {
+        let len = self.u32()?;
+        std::str::from_utf8(self.take(len)?).map_err(|e| format!("Bad grammar data: {}", e))
+    }
+}
+
+impl Grammar {
+    /// Bytes to rebuild the grammar with `deserialize`, eg: to cache one
+    /// generated from a large EBNF spec on disk. Terminal predicates are
+    /// closures so only their names are kept, literals are restored as is.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut symbols = Vec::new();
+        let mut index = HashMap::new();
+        let heads = self.rules.iter().map(|rule| (rule.head.as_str(), NONTERM));
+        let specs = self.rules.iter().flat_map(|rule| rule.spec.iter()).map(|s| {
+            let kind = match s.is_terminal() {
+                true if self.literals.contains(s.name()) => LITERAL,
+                true => TERMINAL,
+                false => NONTERM,
+            };
+            (s.name(), kind)
+        });
+        for (name, kind) in heads.chain(specs) {
+            index.entry(name).or_insert_with(|| {
+                symbols.push((name, kind));
+                symbols.len() - 1
+            });
+        }
+        let mut out = MAGIC.to_vec();
+        write_str(&mut out, &self.start);
+        write_u32(&mut out, symbols.len());
+        for (name, kind) in &symbols {
+            out.push(*kind);
+            write_str(&mut out, name);
+        }
+        write_u32(&mut out, self.rules.len());
+        for rule in &self.rules {
+            write_u32(&mut out, index[rule.head.as_str()]);
+            write_u32(&mut out, rule.spec.len());
+            for symbol in &rule.spec {
+                write_u32(&mut out, index[symbol.name()]);
+            }
+        }
+        out
+    }
+
+    /// Rebuild a grammar from `serialize`'s bytes. `terminal` gives back
+    /// the predicate of each terminal by name (reserved words included, see
+    /// `GrammarBuilder::reserved`), it's an error if one is missing. Parsers
+    /// and semantic actions can't be saved, build an `EarleyParser` and an
+    /// `EarleyForest` for the grammar as usual.
+    pub fn deserialize(data: &[u8], terminal: impl Fn(&str) -> Option<Box<dyn Fn(&str) -> bool>>)
+            -> Result<Grammar, String> {
+        let mut data = Reader(data);
+        if data.take(MAGIC.len()).ok() != Some(MAGIC) {
+            return Err("Not grammar data".to_string());
+        }
+        let start = data.str()?;
+        let mut builder = GrammarBuilder::default();
+        let mut names = Vec::new();
+        for _ in 0..data.u32()? {
+            let kind = data.u8()?;
+            let name = data.str()?;
+            match kind {
+                NONTERM => builder.nonterm_try(name),
+                LITERAL => builder.literal_try(name),
+                TERMINAL => match terminal(name) {
+                    Some(pred) => builder.terminal_try(name, pred),
+                    None => return Err(format!("Missing terminal predicate: {}", name)),
+                },
+                _ => return Err(format!("Bad grammar data: symbol kind {}", kind)),
+            }
+            names.push(name);
+        }
+        let symbol = |idx: usize| names.get(idx).copied()
+            .ok_or_else(|| format!("Bad grammar data: symbol {}", idx));
+        for _ in 0..data.u32()? {
+            let head = symbol(data.u32()?)?;
+            let spec = (0..data.u32()?)
+                .map(|_| symbol(data.u32()?))
+                .collect::<Result<Vec<_>, _>>()?;
+            builder.rule_try(head, &spec);
+        }
+        builder.into_grammar(start)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::earley::{EarleyForest, EarleyParser, Grammar, GrammarBuilder};
+
+    type Predicate = Box<dyn Fn(&str) -> bool>;
+
+    fn number(n: &str) -> bool {
+        n.chars().all(|c| c.is_ascii_digit())
+    }
+
+    #[test]
+    fn grammar_round_trip() {
+        let grammar = GrammarBuilder::default()
+          .nonterm("expr")
+          .nonterm("term")
+          .literal("+")
+          .terminal("n", number)
+          .rule("expr", &["expr", "+", "term"])
+          .rule("expr", &["term"])
+          .rule("term", &["n"])
+          .rule("term", &[])
+          .into_grammar("expr")
+          .expect("Bad grammar");
+        let data = grammar.serialize();
+        let plug = |name: &str| -> Option<Predicate> {
+            match name {
+                "n" => Some(Box::new(number)),
+                _ => None,
+            }
+        };
+        let restored = Grammar::deserialize(&data, plug).unwrap();
+        assert_eq!(format!("{:?}", restored), format!("{:?}", grammar));
+        assert_eq!(restored.literals, grammar.literals);
+        assert_eq!(restored.serialize(), data);
+        // actions are registered again on a new forest
+        let mut ev = EarleyForest::new(|_, n| n.parse().unwrap_or(0));
+        ev.action("expr -> expr + term", |n| n[0] + n[2]);
+        ev.action("expr -> term", |n| n[0]);
+        ev.action("term -> n", |n| n[0]);
+        ev.action("term -> ", |_| 0);
+        let trees = EarleyParser::new(restored).parse("1 + 2 + + 3".split_whitespace()).unwrap();
+        assert_eq!(ev.eval(&trees), Ok(6));
+
+        assert_eq!(Grammar::deserialize(&data, |_| None).err(),
+                   Some("Missing terminal predicate: n".to_string()));
+        assert_eq!(Grammar::deserialize(&data[..data.len() - 1], plug).err(),
+                   Some("Truncated grammar data".to_string()));
+        assert_eq!(Grammar::deserialize(b"expr -> term", plug).err(),
+                   Some("Not grammar data".to_string()));
+    }
+}
@@ -1,6 +1,5 @@
-#![deny(warnings)]
-
 use super::grammar::{Rule, Symbol};
+use std::collections::HashSet;
 use std::{cell, fmt, hash};
 use std::rc::Rc;
 
@@ -8,7 +7,78 @@ use std::rc::Rc;
 #[derive(PartialEq,Eq,Hash,Debug,Clone)]
 pub enum SpanSource {
     Completion(Rc<Span>, Rc<Span>),
-    Scan(Rc<Span>, String),
+    // Lexemes are shared by all scans at the same position
+    Scan(Rc<Span>, Rc<str>),
+}
+
+/// Deterministic reduction path (Leo's optimization). `waiting` is the only
+/// Span of its StateSet waiting on some NonTerm `B`, and it's complete once
+/// `B` is. Completing `B` there completes the whole chain of links up to
+/// `top` (waiting on its last symbol too), only `top` goes in the chart.
+pub struct LeoLink {
+    pub waiting: Rc<Span>,
+    // link for the head of `waiting` in the StateSet where it started
+    pub next: Option<Rc<LeoLink>>,
+    pub top: Rc<Span>,
+}
+
+// Leo completions whose intermediate Spans weren't built yet
+struct Deferred {
+    sources: Vec<SpanSource>,
+    // (link of the trigger's origin, the trigger that completed the chain)
+    chains: Vec<(Rc<LeoLink>, Rc<Span>)>,
+}
+
+// Most Spans have a single source, keep it inline to save an allocation
+enum Sources {
+    One(SpanSource),
+    Many(Vec<SpanSource>),
+    Deferred(Box<Deferred>),
+}
+
+impl Sources {
+    fn as_slice(&self) -> &[SpanSource] {
+        match self {
+            Sources::One(source) => std::slice::from_ref(source),
+            Sources::Many(sources) => sources,
+            Sources::Deferred(deferred) => &deferred.sources,
+        }
+    }
+
+    fn push(&mut self, source: SpanSource) {
+        match self {
+            Sources::Many(sources) => sources.push(source),
+            Sources::Deferred(deferred) => deferred.sources.push(source),
+            Sources::One(_) => {
+                let prev = std::mem::replace(self, Sources::Many(Vec::with_capacity(2)));
+                if let (Sources::One(first), Sources::Many(sources)) = (prev, &mut *self) {
+                    sources.extend([first, source]);
+                }
+            }
+        }
+    }
+
+    fn defer(&mut self, link: Rc<LeoLink>, trigger: Rc<Span>) {
+        if !matches!(self, Sources::Deferred(_)) {
+            let sources = match std::mem::take(self) {
+                Sources::One(source) => vec![source],
+                Sources::Many(sources) => sources,
+                Sources::Deferred(_) => unreachable!(),
+            };
+            *self = Sources::Deferred(Box::new(Deferred{sources, chains: Vec::new()}));
+        }
+        let Sources::Deferred(deferred) = self else { unreachable!() };
+        // the chart is grown to a fixpoint, same completions come back
+        if !deferred.chains.iter().any(|(l, t)| Rc::ptr_eq(l, &link) && Rc::ptr_eq(t, &trigger)) {
+            deferred.chains.push((link, trigger));
+        }
+    }
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Sources::Many(Vec::new())
+    }
 }
 
 /// An Span is a partially matched `Rule`. `dot` shows the match progress.
@@ -22,7 +92,7 @@ pub struct Span {
     // of backpointers would invalidate other Spans already pointing to this one.
     // Those invalidated items wouldn't have the whole back-pointer list.
     /// backpointers leading to this item: (source-item, Scan/Completion)
-    backpointers: cell::RefCell<Vec<SpanSource>>,
+    backpointers: cell::RefCell<Sources>,
 }
 
 
@@ -57,14 +127,14 @@ impl Span {
             .map(|s| s.name()).collect::<Vec<_>>().join(" ");
         format!("({} - {}) {} -> {} \u{00b7} {} #bp: {}{}",
                self.start, self.end, self.rule.head, pre, post,
-               self.backpointers.borrow().len(),
+               self.sources().len(),
                self.stringify_bp(nest + 1))
     }
 
     fn stringify_bp(&self, nest: usize) -> String {
         let mut out = String::new();
         let pfx = "   ".repeat(nest);
-        for bp in self.backpointers.borrow().iter() {
+        for bp in self.sources().iter() {
             match bp {
                 SpanSource::Completion(a, b) => {
                     out += format!("\n{}Complete(\n{}   {}, \n{}   {}\n{})",
@@ -94,7 +164,7 @@ impl fmt::Debug for Span {
             .map(|s| s.name()).collect::<Vec<_>>().join(" ");
         write!(f, "({} - {}) {} -> {} \u{00b7} {} #bp: {}",
                self.start, self.end, self.rule.head, pre, post,
-               self.backpointers.borrow().len())
+               self.sources().len())
     }
 }
 
@@ -110,9 +180,52 @@ impl Span {
     }
 
     /// Scans or Completions that led to the creation of this Span.
-    /// Only ever borrowed non-mutable ref returned for public consumption
-    pub fn sources(&self) -> cell::Ref<Vec<SpanSource>> {
-        self.backpointers.borrow()
+    /// Only ever borrowed non-mutable ref returned for public consumption.
+    /// A slice, not a Vec: a single source is kept inline and Leo deferred
+    /// ones are unwound first. Span is crate private, callers are in here.
+    pub fn sources(&self) -> cell::Ref<'_, [SpanSource]> {
+        if matches!(*self.backpointers.borrow(), Sources::Deferred(_)) {
+            self.unwind_leo();
+        }
+        cell::Ref::map(self.backpointers.borrow(), Sources::as_slice)
+    }
+
+    // Build the Spans a Leo completion skipped, the same the chain of plain
+    // completions would have added to the chart. Done once the parse is over
+    // and only for Spans in some tree, they all end where this one does.
+    // Spans hash ignoring backpointers (the mutable part), ok as set keys.
+    #[allow(clippy::mutable_key_type)]
+    fn unwind_leo(&self) {
+        let Sources::Deferred(deferred) = std::mem::take(&mut *self.backpointers.borrow_mut())
+            else { return };
+        let Deferred{sources, chains} = *deferred;
+        let mut unwound = Sources::Many(sources);
+        // Chains can meet (eg: ambiguous inputs) or go through Spans created
+        // without Leo (the triggers), those get merged as the chart would.
+        let mut built: HashSet<Rc<Span>> = chains.iter().map(|(_, t)| t.clone()).collect();
+        for (mut link, mut span) in chains {
+            while let Some(next) = link.next.clone() {
+                let extended = Span::extend(
+                    SpanSource::Completion(link.waiting.clone(), span), self.end);
+                span = match built.get(&extended) {
+                    Some(existent) => {
+                        existent.merge_sources(extended);
+                        existent.clone()
+                    },
+                    None => {
+                        let extended = Rc::new(extended);
+                        built.insert(extended.clone());
+                        extended
+                    }
+                };
+                link = next;
+            }
+            let source = SpanSource::Completion(link.waiting.clone(), span);
+            if !unwound.as_slice().contains(&source) {
+                unwound.push(source);
+            }
+        }
+        *self.backpointers.borrow_mut() = unwound;
     }
 
     /// Merge other Span into this one moving over its backpointers
@@ -122,11 +235,19 @@ impl Span {
     pub fn merge_sources(&self, other: Span) {
         assert_eq!(*self, other, "Spans to merge should be Eq");
         let mut dest_bp = self.backpointers.borrow_mut();
-        for bp in other.backpointers.into_inner() {
-            if ! dest_bp.contains(&bp) {
+        let (other_bp, chains) = match other.backpointers.into_inner() {
+            Sources::One(source) => (vec![source], Vec::new()),
+            Sources::Many(sources) => (sources, Vec::new()),
+            Sources::Deferred(deferred) => (deferred.sources, deferred.chains),
+        };
+        for bp in other_bp {
+            if ! dest_bp.as_slice().contains(&bp) {
                 dest_bp.push(bp);
             }
         }
+        for (link, trigger) in chains {
+            dest_bp.defer(link, trigger);
+        }
     }
 
     pub fn new(rule: &Rc<Rule>, start: usize) -> Span {
@@ -135,7 +256,21 @@ impl Span {
             dot: 0,
             start,
             end: start,
-            backpointers: cell::RefCell::new(Vec::new()),
+            backpointers: cell::RefCell::default(),
+        }
+    }
+
+    /// Complete `link`'s top Span right away once `trigger` completes the
+    /// NonTerm it waits on. The skipped Spans are built when sources are read.
+    pub fn leo(link: &Rc<LeoLink>, trigger: Rc<Span>, end: usize) -> Span {
+        let mut sources = Sources::Many(Vec::new());
+        sources.defer(link.clone(), trigger);
+        Span{
+            rule: link.top.rule.clone(),
+            dot: link.top.dot + 1,
+            start: link.top.start,
+            end,
+            backpointers: cell::RefCell::new(sources),
         }
     }
 
@@ -149,7 +284,7 @@ impl Span {
             dot: source.dot + 1,
             start: source.start,
             end,
-            backpointers: cell::RefCell::new([extension].into()),
+            backpointers: cell::RefCell::new(Sources::One(extension)),
         }
     }
 }
@@ -191,7 +326,7 @@ mod tests {
     }
 
     fn item(rule: Rc<Rule>, dot: usize, start: usize, end: usize) -> Span {
-        Span{rule, dot, start, end, backpointers: RefCell::new(Vec::new())}
+        Span{rule, dot, start, end, backpointers: RefCell::default()}
     }
 
     #[test]
@@ -225,11 +360,11 @@ mod tests {
         let rule1 = gen_rule1();
         let source = Rc::new(item(rule1.clone(), 1, 0, 1));
         // Scan a '+' token
-        let scan = Span::extend(SpanSource::Scan(source.clone(), "+".to_string()), 2);
+        let scan = Span::extend(SpanSource::Scan(source.clone(), "+".into()), 2);
         assert_eq!(item(rule1.clone(), 2, 0, 2), scan);
         // Check scan item backpointers
         let scan_src = scan.sources();
-        assert!(scan_src.contains(&SpanSource::Scan(source, "+".to_string())));
+        assert!(scan_src.contains(&SpanSource::Scan(source, "+".into())));
         assert_eq!(scan_src.len(), 1);
     }
 
@@ -0,0 +1,157 @@
+use super::grammar::Grammar;
+use super::parser::ParseTrees;
+use super::spans::{Span, SpanSource};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+/// Chart items a single rule contributed, split by the operation that created them.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct RuleStats {
+    pub predictions: usize,
+    pub scans: usize,
+    pub completions: usize,
+}
+
+impl RuleStats {
+    pub fn items(&self) -> usize {
+        self.predictions + self.scans + self.completions
+    }
+}
+
+/// Bookkeeping of a parse to find out which rules dominate the chart.
+/// Only distinct items are counted, merging backpointers doesn't add items.
+#[derive(Default, Debug, Clone)]
+pub struct ParseStats {
+    // Keyed by rule name, eg: "S -> S + N"
+    pub rules: HashMap<String, RuleStats>,
+    // Number of items in each StateSet (ie: per input position)
+    pub stateset_sizes: Vec<usize>,
+    // Items of each StateSet once done, only filled by `parse_chart`
+    pub chart: Vec<Vec<String>>,
+    // Terminals that could be scanned after the last token
+    pub expected: Vec<String>,
+    pub(super) record_chart: bool,
+}
+
+impl ParseStats {
+    fn rule(&mut self, span: &Span) -> &mut RuleStats {
+        self.rules.entry(span.rule.to_string()).or_default()
+    }
+
+    pub(super) fn predicted(&mut self, span: &Span) {
+        self.rule(span).predictions += 1;
+    }
+
+    pub(super) fn scanned(&mut self, span: &Span) {
+        self.rule(span).scans += 1;
+    }
+
+    pub(super) fn completed(&mut self, span: &Span) {
+        self.rule(span).completions += 1;
+    }
+
+    pub(super) fn stateset_done(&mut self, size: usize, items: impl FnOnce() -> Vec<String>) {
+        self.stateset_sizes.push(size);
+        if self.record_chart {
+            self.chart.push(items());
+        }
+    }
+
+    /// Index of the first token no item could scan, the parse died there
+    pub fn dead_at(&self) -> Option<usize> {
+        self.stateset_sizes.iter().skip(1).position(|size| *size == 0)
+    }
+
+    pub fn total(&self) -> RuleStats {
+        self.rules.values().fold(RuleStats::default(), |acc, r| RuleStats{
+            predictions: acc.predictions + r.predictions,
+            scans: acc.scans + r.scans,
+            completions: acc.completions + r.completions,
+        })
+    }
+}
+
+// Table of rules sorted by the number of chart items they created
+impl fmt::Display for ParseStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rules: Vec<_> = self.rules.iter().collect();
+        rules.sort_by(|a, b| b.1.items().cmp(&a.1.items()).then(a.0.cmp(b.0)));
+        let width = rules.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max(4);
+        writeln!(f, "{:<width$} {:>8} {:>8} {:>8} {:>8}",
+                 "Rule", "Predict", "Scan", "Complete", "Items")?;
+        let total = self.total();
+        for (name, r) in rules.into_iter().chain([(&"Total".to_string(), &total)]) {
+            writeln!(f, "{:<width$} {:>8} {:>8} {:>8} {:>8}",
+                     name, r.predictions, r.scans, r.completions, r.items())?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{:<8} {:>8}", "StateSet", "Items")?;
+        for (idx, size) in self.stateset_sizes.iter().enumerate() {
+            writeln!(f, "{:<8} {:>8}", idx, size)?;
+        }
+        Ok(())
+    }
+}
+
+/// Rules of a grammar used by the parse trees of a test suite, to find dead
+/// rules. Record as many parses as needed, counts are aggregated.
+#[derive(Debug, Clone)]
+pub struct RuleCoverage {
+    // Every rule name in grammar order, eg: "S -> S + N"
+    rules: Vec<String>,
+    // Number of recorded parses each rule took part in
+    hits: HashMap<String, usize>,
+}
+
+impl RuleCoverage {
+    pub fn new(grammar: &Grammar) -> Self {
+        RuleCoverage{
+            rules: grammar.rules.iter().map(|rule| rule.to_string()).collect(),
+            hits: HashMap::new()}
+    }
+
+    /// Mark the rules completed by any derivation of `ptrees`. Every span
+    /// reachable from the roots belongs to some derivation, so all
+    /// alternatives of an ambiguous parse count.
+    pub fn record(&mut self, ptrees: &ParseTrees) {
+        let mut seen = HashSet::new();
+        let mut fired = HashSet::new();
+        let mut pending: Vec<Rc<Span>> = ptrees.0.clone();
+        while let Some(span) = pending.pop() {
+            if !seen.insert(Rc::as_ptr(&span)) {
+                continue;
+            }
+            if span.complete() {
+                fired.insert(span.rule.to_string());
+            }
+            for source in span.sources().iter() {
+                match source {
+                    SpanSource::Completion(src, trigger) => {
+                        pending.push(src.clone());
+                        pending.push(trigger.clone());
+                    },
+                    SpanSource::Scan(src, _) => pending.push(src.clone()),
+                }
+            }
+        }
+        for rule in fired {
+            *self.hits.entry(rule).or_default() += 1;
+        }
+    }
+
+    /// Number of recorded parses that used `rule`
+    pub fn hits(&self, rule: &str) -> usize {
+        self.hits.get(rule).cloned().unwrap_or(0)
+    }
+
+    /// Rules used by some recorded parse, in grammar order
+    pub fn covered(&self) -> Vec<&str> {
+        self.rules.iter().filter(|r| self.hits(r) > 0).map(|r| r.as_str()).collect()
+    }
+
+    /// Rules no recorded parse used, in grammar order
+    pub fn uncovered(&self) -> Vec<&str> {
+        self.rules.iter().filter(|r| self.hits(r) == 0).map(|r| r.as_str()).collect()
+    }
+}
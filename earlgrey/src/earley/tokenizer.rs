@@ -0,0 +1,197 @@
+use super::grammar::{Grammar, Symbol};
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+// Prefix tree of literal terminals to find the longest one at a position
+#[derive(Default)]
+struct Trie {
+    children: HashMap<char, Trie>,
+    is_literal: bool,
+}
+
+impl Trie {
+    fn insert(&mut self, literal: &str) {
+        let node = literal.chars().fold(self, |node, ch| node.children.entry(ch).or_default());
+        node.is_literal = true;
+    }
+
+    // Byte length of the longest literal that's a prefix of input
+    fn longest_match(&self, input: &str) -> Option<usize> {
+        let mut longest = None;
+        let mut node = self;
+        for (idx, ch) in input.char_indices() {
+            match node.children.get(&ch) {
+                Some(child) => node = child,
+                None => break,
+            }
+            if node.is_literal {
+                longest = Some(idx + ch.len_utf8());
+            }
+        }
+        longest
+    }
+}
+
+/// Tokenizer derived from a Grammar's terminals using maximal munch.
+/// Literal terminals are matched via a trie, other terminals are tried
+/// on every prefix up to the next whitespace taking the longest accepted.
+pub struct GrammarTokenizer {
+    literals: Trie,
+    // Non-literal terminals sorted by name so conflicts are deterministic
+    terminals: Vec<Rc<Symbol>>,
+    whitespace: Box<dyn Fn(char) -> bool>,
+}
+
+impl Grammar {
+    pub fn tokenizer(&self) -> GrammarTokenizer {
+        let mut literals = Trie::default();
+        for literal in &self.literals {
+            literals.insert(literal);
+        }
+        let terminals: BTreeMap<_, _> = self.rules.iter()
+            .flat_map(|rule| rule.spec.iter())
+            .filter(|s| s.is_terminal() && !self.literals.contains(s.name()))
+            .map(|s| (s.name(), s.clone()))
+            .collect();
+        GrammarTokenizer{
+            literals,
+            terminals: terminals.into_values().collect(),
+            whitespace: Box::new(char::is_whitespace),
+        }
+    }
+}
+
+impl GrammarTokenizer {
+    // Configure which chars are skipped between tokens
+    pub fn whitespace(mut self, ws: impl Fn(char) -> bool + 'static) -> Self {
+        self.whitespace = Box::new(ws);
+        self
+    }
+
+    pub fn tokenize<'a>(&'a self, input: &'a str) -> GrammarTokens<'a> {
+        GrammarTokens{tokenizer: self, input}
+    }
+
+    // Find the longest token at the start of input. Returns matching
+    // terminal names (more than 1 is a conflict) and the token length.
+    fn longest_match(&self, input: &str) -> (Vec<String>, usize) {
+        let literal_len = self.literals.longest_match(input).unwrap_or(0);
+        let word_len = input.find(|c| (self.whitespace)(c)).unwrap_or(input.len());
+        // char boundaries of candidate prefixes, longest first
+        let ends = input[..word_len].char_indices()
+            .map(|(idx, ch)| idx + ch.len_utf8()).rev();
+        for end in ends.take_while(|end| *end >= literal_len) {
+            let mut names: Vec<String> = self.terminals.iter()
+                .filter(|t| t.matches(&input[..end]))
+                .map(|t| t.name().to_string())
+                .collect();
+            if !names.is_empty() {
+                if end == literal_len {
+                    names.push(input[..end].to_string());
+                    names.sort();
+                }
+                return (names, end);
+            }
+        }
+        match literal_len {
+            0 => (Vec::new(), 0),
+            len => (vec![input[..len].to_string()], len),
+        }
+    }
+}
+
+// Yields (terminal-name, lexeme) pairs. Stops after the first error.
+pub struct GrammarTokens<'a> {
+    tokenizer: &'a GrammarTokenizer,
+    input: &'a str,
+}
+
+impl<'a> Iterator for GrammarTokens<'a> {
+    type Item = Result<(String, String), String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tokenizer = self.tokenizer;
+        self.input = self.input.trim_start_matches(|c| (tokenizer.whitespace)(c));
+        if self.input.is_empty() {
+            return None;
+        }
+        let (mut names, len) = tokenizer.longest_match(self.input);
+        let (lexeme, rest) = self.input.split_at(len);
+        let token = match names.len() {
+            0 => Err(format!("No terminal matches: {}", self.input)),
+            1 => Ok((names.swap_remove(0), lexeme.to_string())),
+            _ => Err(format!("Ambiguous token '{}' matches: {}", lexeme, names.join(", "))),
+        };
+        self.input = if token.is_ok() { rest } else { "" };
+        Some(token)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::super::grammar::GrammarBuilder;
+    use super::super::parser::EarleyParser;
+
+    fn tokens(builder: GrammarBuilder, input: &str) -> Vec<Result<(String, String), String>> {
+        builder.into_grammar("S").unwrap().tokenizer().tokenize(input).collect()
+    }
+
+    fn comparison() -> GrammarBuilder {
+        // S -> Ident Op Number
+        GrammarBuilder::default()
+          .nonterm("S")
+          .nonterm("Op")
+          .literal(">=")
+          .literal(">")
+          .literal("=")
+          .terminal("Ident", |n| n.chars().all(|c| c.is_ascii_alphabetic()))
+          .terminal("Number", |n| n.chars().all(|c| c.is_ascii_digit()))
+          .rule("S", &["Ident", "Op", "Number"])
+          .rule("Op", &[">="])
+          .rule("Op", &[">"])
+          .rule("Op", &["="])
+    }
+
+    fn ok(pairs: &[(&str, &str)]) -> Vec<Result<(String, String), String>> {
+        pairs.iter().map(|(n, l)| Ok((n.to_string(), l.to_string()))).collect()
+    }
+
+    #[test]
+    fn maximal_munch() {
+        assert_eq!(tokens(comparison(), "foo>=42"),
+                   ok(&[("Ident", "foo"), (">=", ">="), ("Number", "42")]));
+        assert_eq!(tokens(comparison(), "  foo > = 42 "),
+                   ok(&[("Ident", "foo"), (">", ">"), ("=", "="), ("Number", "42")]));
+        assert_eq!(tokens(comparison(), "x=>1"),
+                   ok(&[("Ident", "x"), ("=", "="), (">", ">"), ("Number", "1")]));
+        // tokens feed straight into the parser
+        let grammar = comparison().into_grammar("S").unwrap();
+        let lexemes: Vec<String> = grammar.tokenizer().tokenize("foo>=42")
+            .map(|t| t.unwrap().1).collect();
+        assert!(EarleyParser::new(grammar).parse(lexemes.iter()).is_ok());
+    }
+
+    #[test]
+    fn custom_whitespace() {
+        let grammar = comparison().into_grammar("S").unwrap();
+        let tokenizer = grammar.tokenizer().whitespace(|c| c == ',');
+        let lexemes: Vec<_> = tokenizer.tokenize("a,>,1")
+            .map(|t| t.unwrap().1).collect();
+        assert_eq!(lexemes, vec!["a", ">", "1"]);
+    }
+
+    #[test]
+    fn errors() {
+        // 'if' matches both a literal and the Ident terminal
+        let builder = comparison()
+          .literal("if")
+          .rule("S", &["if", "Ident"]);
+        assert_eq!(tokens(builder, "if x"), vec![
+            Err("Ambiguous token 'if' matches: Ident, if".to_string())]);
+        assert_eq!(tokens(comparison(), "a ? b"), vec![
+            Ok(("Ident".to_string(), "a".to_string())),
+            Err("No terminal matches: ? b".to_string())]);
+    }
+}
@@ -1,47 +1,337 @@
-#![deny(warnings)]
-
+use super::grammar::{Grammar, Rule};
 use super::spans::{Span, SpanSource};
-use super::parser::ParseTrees;
-use std::collections::HashMap;
+use super::parser::{ParseTrees, CANCELLED};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::rc::Rc;
 
+// Generic parse tree, labels every node with the symbol/rule that built it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Subtree {
+    // ("[+-]", "+") a scanned terminal and its lexeme
+    Node(String, String),
+    // ("E -> E [+-] E", [...]) a completed rule and its children
+    SubT(String, Vec<Subtree>),
+}
+
+impl Subtree {
+    // A rule matches its full spec "E -> E + E" or just its head "E"
+    fn matches(&self, rule: &str) -> bool {
+        match self {
+            Subtree::SubT(spec, _) => spec == rule ||
+                spec.strip_prefix(rule).is_some_and(|rest| rest.starts_with(" ->")),
+            Subtree::Node(_, _) => false,
+        }
+    }
+
+    /// All `SubT` nodes built by `rule` (eg: "E" or "E -> E + E") in pre-order
+    pub fn find_all(&self, rule: &str) -> Vec<&Subtree> {
+        let mut found = Vec::new();
+        let mut pending = vec![self];
+        while let Some(tree) = pending.pop() {
+            if tree.matches(rule) {
+                found.push(tree);
+            }
+            if let Subtree::SubT(_, children) = tree {
+                pending.extend(children.iter().rev());
+            }
+        }
+        found
+    }
+
+    /// Lexemes of the scanned terminals in input order (the tree's yield)
+    pub fn leaves(&self) -> Vec<&str> {
+        let mut leaves = Vec::new();
+        let mut pending = vec![self];
+        while let Some(tree) = pending.pop() {
+            match tree {
+                Subtree::Node(_, lexeme) => leaves.push(lexeme.as_str()),
+                Subtree::SubT(_, children) => pending.extend(children.iter().rev()),
+            }
+        }
+        leaves
+    }
+
+    /// First `SubT` node built by `rule` in a depth-first search
+    pub fn find_first(&self, rule: &str) -> Option<&Subtree> {
+        if self.matches(rule) {
+            return Some(self);
+        }
+        match self {
+            Subtree::SubT(_, children) => children.iter().find_map(|c| c.find_first(rule)),
+            Subtree::Node(_, _) => None,
+        }
+    }
+
+    /// Lisp S-expression of the tree, `("rule" children...)` for `SubT` and
+    /// `"lexeme"` for `Node`, eg: `("E -> E + E" ("E -> n" "1") "+" ("E -> n" "2"))`.
+    /// Strings are double quoted with `\` and `"` escaped, so they read back
+    /// as single tokens with a lisp tokenizer. Terminal names are left out,
+    /// they're the symbols of the parent's rule.
+    pub fn to_sexp(&self) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        match self {
+            Subtree::Node(_, lexeme) => quote(lexeme),
+            Subtree::SubT(rule, children) => {
+                let mut sexp = format!("({}", quote(rule));
+                for child in children {
+                    sexp.push(' ');
+                    sexp.push_str(&child.to_sexp());
+                }
+                sexp + ")"
+            },
+        }
+    }
+}
+
+/// Accumulate over every node of `tree` in pre-order (parents before their
+/// children, left to right), `f` gets the accumulator, the node and its depth
+/// (0 for `tree`), eg: `fold(&tree, 0, |n, node, _| n + 1)` counts nodes.
+pub fn fold<T>(tree: &Subtree, init: T, mut f: impl FnMut(T, &Subtree, usize) -> T) -> T {
+    let mut acc = init;
+    let mut pending = vec![(tree, 0)];
+    while let Some((node, depth)) = pending.pop() {
+        acc = f(acc, node, depth);
+        if let Subtree::SubT(_, children) = node {
+            pending.extend(children.iter().rev().map(|c| (c, depth + 1)));
+        }
+    }
+    acc
+}
+
+// One node per line, children indented below their rule. Lexemes are
+// quoted and escaped so each stays on its line, eg:
+// expr -> expr + Number
+//   expr -> Number
+//     Number "1"
+//   + "+"
+//   Number "2"
+impl fmt::Display for Subtree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut pending = vec![(0, self)];
+        let mut first = true;
+        while let Some((depth, tree)) = pending.pop() {
+            if !first {
+                writeln!(f)?;
+            }
+            first = false;
+            write!(f, "{:indent$}", "", indent = 2 * depth)?;
+            match tree {
+                Subtree::Node(symbol, lexeme) => write!(f, "{} {:?}", symbol, lexeme)?,
+                Subtree::SubT(rule, children) => {
+                    // epsilon rules end in "-> "
+                    write!(f, "{}", rule.trim_end())?;
+                    pending.extend(children.iter().rev().map(|c| (depth + 1, c)));
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Semantic action building an AST node from the nodes of a completed rule
+pub type SemAction<'a, ASTNode> = Box<dyn Fn(Vec<ASTNode>) -> ASTNode + 'a>;
+
+/// Tokens a reduction covers, `end_token` is one past the last. Epsilon
+/// rules cover none, `start_token == end_token`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanInfo {
+    pub start_token: usize,
+    pub end_token: usize,
+}
+
+type SpanAction<'a, ASTNode> = Box<dyn Fn(Vec<ASTNode>, SpanInfo) -> ASTNode + 'a>;
+
+type FallbackAction<'a, ASTNode> = Box<dyn Fn(&str, Vec<ASTNode>) -> ASTNode + 'a>;
+
+/// Build a map of semantic actions for `register_actions_from_map`, eg:
+/// `earley_actions!{"E -> E + E" => |n: Vec<i64>| n[0] + n[2], "E -> n" => |n| n[0]}`
+#[macro_export]
+macro_rules! earley_actions {
+    ($($rule:expr => $action:expr),* $(,)?) => {{
+        let mut actions = ::std::collections::HashMap::new();
+        $(actions.insert($rule.to_string(), $crate::boxed_action($action));)*
+        actions
+    }};
+}
+
+// Helps type inference of closures in earley_actions!
+#[doc(hidden)]
+pub fn boxed_action<'a, ASTNode>(action: impl Fn(Vec<ASTNode>) -> ASTNode + 'a)
+        -> SemAction<'a, ASTNode> {
+    Box::new(action)
+}
+
 pub struct EarleyForest<'a, ASTNode: Clone> {
     // Semantic actions to apply when a production is completed
-    actions: HashMap<String, Box<dyn Fn(Vec<ASTNode>) -> ASTNode + 'a>>,
+    actions: HashMap<String, SemAction<'a, ASTNode>>,
+    // Actions that also get the tokens their rule covers
+    span_actions: HashMap<String, SpanAction<'a, ASTNode>>,
     // How to lift a 'scanned' terminal into an AST node.
     terminal_parser: Box<dyn Fn(&str, &str) -> ASTNode + 'a>,
+    // Catch-all for rules without a registered action
+    fallback: Option<FallbackAction<'a, ASTNode>>,
+    // Stops eval_all when it returns true
+    cancel: Option<Box<dyn Fn() -> bool + 'a>>,
 }
 
 impl<'a, ASTNode: Clone> EarleyForest<'a, ASTNode> {
     pub fn new(terminal_parser: impl Fn(&str, &str) -> ASTNode + 'a) -> Self {
         EarleyForest{
             actions: HashMap::new(),
-            terminal_parser: Box::new(terminal_parser)}
+            span_actions: HashMap::new(),
+            terminal_parser: Box::new(terminal_parser),
+            fallback: None,
+            cancel: None}
     }
 
     // Register semantic actions to act when rules are matched
     pub fn action(&mut self, rule: &str, action: impl Fn(Vec<ASTNode>) -> ASTNode + 'a) {
         self.actions.insert(rule.to_string(), Box::new(action));
     }
+
+    // Register an action that also gets the tokens its rule covered, eg: to
+    // keep source locations in AST nodes. Only `eval_with_span_info` runs
+    // them, it prefers them over plain actions for the same rule.
+    pub fn span_action(&mut self, rule: &str,
+                       action: impl Fn(Vec<ASTNode>, SpanInfo) -> ASTNode + 'a) {
+        self.span_actions.insert(rule.to_string(), Box::new(action));
+    }
+
+    // Register many actions at once (see earley_actions!), replaces existing ones
+    pub fn register_actions_from_map(&mut self, actions: HashMap<String, SemAction<'a, ASTNode>>) {
+        self.actions.extend(actions);
+    }
+
+    // Build nodes for rules missing an action instead of failing evaluation.
+    // Receives the rule name and its args, allows adding actions incrementally.
+    pub fn fallback_action(&mut self, action: impl Fn(&str, Vec<ASTNode>) -> ASTNode + 'a) {
+        self.fallback = Some(Box::new(action));
+    }
+
+    // Same as fallback_action for rules without semantic significance, eg:
+    // grammars built from a DSL. `handler` gets the rule name, args are dropped.
+    pub fn with_missing_action(mut self, handler: impl Fn(&str) -> ASTNode + 'a) -> Self {
+        self.fallback_action(move |rule, _| handler(rule));
+        self
+    }
+
+    // Stop `eval_all` with `Err(CANCELLED)` once `check` returns true, it's
+    // called before evaluating each tree. Ambiguous grammars can have many.
+    pub fn cancel_when(mut self, check: impl Fn() -> bool + 'a) -> Self {
+        self.cancel = Some(Box::new(check));
+        self
+    }
+
+    // Check registered actions before evaluating anything: rules of `grammar`
+    // without an action (unless there's a fallback) and actions naming rules
+    // it doesn't have, eg: a typo in the rule string. Rules with only a
+    // span_action are reported too, evals without span info can't run them.
+    // Each problem is reported once even if the grammar repeats a rule.
+    pub fn validate_actions_against_grammar(&self, grammar: &Grammar) -> Vec<String> {
+        let mut rules: Vec<_> = Vec::new();
+        for rule in grammar.rules.iter().map(|rule| rule.to_string()) {
+            if !rules.contains(&rule) {
+                rules.push(rule);
+            }
+        }
+        let mut diagnostics = Vec::new();
+        if self.fallback.is_none() {
+            diagnostics.extend(rules.iter()
+                .filter(|rule| !self.actions.contains_key(*rule))
+                .map(|rule| match self.span_actions.contains_key(rule) {
+                    true => format!("Only a span action for: {}", rule),
+                    false => format!("Missing action for: {}", rule),
+                }));
+        }
+        let mut unknown: Vec<_> = self.actions.keys().chain(self.span_actions.keys())
+            .filter(|name| !rules.contains(name))
+            .collect();
+        unknown.sort();
+        unknown.dedup();
+        diagnostics.extend(unknown.into_iter().map(|name| format!("Unknown rule registered: {}", name)));
+        diagnostics
+    }
+
+    fn apply_action(&self, rulename: &str, args: Vec<ASTNode>, span: Option<SpanInfo>)
+            -> Result<ASTNode, String> {
+        if let Some((action, span)) = span.and_then(|span| Some((self.span_actions.get(rulename)?, span))) {
+            return Ok(action(args, span));
+        }
+        match (self.actions.get(rulename), &self.fallback) {
+            (Some(action), _) => Ok(action(args)),
+            (None, Some(fallback)) => Ok(fallback(rulename, args)),
+            (None, None) => Err(format!("Missing Action: {}", rulename)),
+        }
+    }
+}
+
+
+// A reduction gets one argument per symbol of its rule. Fewer or more means
+// the forest is malformed and arguments would go to the wrong actions.
+fn check_arity(rule: &Rule, args: usize) -> Result<(), String> {
+    match rule.spec.len() {
+        slots if slots == args => Ok(()),
+        slots => Err(format!("Rule {} reduced with {} arguments, expected {}", rule, args, slots)),
+    }
 }
 
+// Lowest cost below each span and the source it's reached from, costs of
+// complete spans include their own rule. Spans are visited children first
+// with an explicit stack so long inputs don't overflow. The chart has no
+// cycles but sources still being visited are skipped to be sure.
+fn min_costs(roots: &[Rc<Span>], costs: &HashMap<String, i32>)
+        -> HashMap<*const Span, (i64, usize)> {
+    let mut best: HashMap<*const Span, (i64, usize)> = HashMap::new();
+    let mut visiting = HashSet::new();
+    let mut pending: Vec<Rc<Span>> = roots.to_vec();
+    while let Some(span) = pending.last().cloned() {
+        let key = Rc::as_ptr(&span);
+        if best.contains_key(&key) {
+            pending.pop();
+            continue;
+        }
+        let sources = span.sources();
+        let parts = |source: &SpanSource| match source {
+            SpanSource::Completion(source, trigger) => vec![source.clone(), trigger.clone()],
+            SpanSource::Scan(source, _) => vec![source.clone()],
+        };
+        if visiting.insert(key) {
+            let missing = sources.iter().flat_map(parts)
+                .filter(|s| !best.contains_key(&Rc::as_ptr(s)) && !visiting.contains(&Rc::as_ptr(s)));
+            pending.extend(missing);
+            continue;
+        }
+        let (below, idx) = sources.iter().enumerate()
+            .filter_map(|(idx, source)| parts(source).iter()
+                .map(|s| best.get(&Rc::as_ptr(s)).map(|b| b.0))
+                .sum::<Option<i64>>()
+                .map(|cost| (cost, idx)))
+            .min()
+            .unwrap_or((0, 0));
+        let own = match span.complete() {
+            true => costs.get(&span.rule.to_string()).copied().unwrap_or(0) as i64,
+            false => 0,
+        };
+        best.insert(key, (below + own, idx));
+        pending.pop();
+    }
+    best
+}
 
 impl<'a, ASTNode: Clone> EarleyForest<'a, ASTNode> {
     fn reduce(&self, root: &Rc<Span>, args: Vec<ASTNode>)
             -> Result<Vec<ASTNode>, String> {
         // If span is not complete, reduce is a noop passthrough
         if !root.complete() { return Ok(args) }
+        check_arity(&root.rule, args.len())?;
         // Lookup semantic action to apply based on rule name
         let rulename = root.rule.to_string();
-        match self.actions.get(&rulename) {
-            None => Err(format!("Missing Action: {}", rulename)),
-            Some(action) => {
-                if cfg!(feature="debug") {
-                    eprintln!("Reduction: {}", rulename);
-                }
-                Ok(vec![action(args)])
-            }
+        let node = self.apply_action(&rulename, args, None)?;
+        if cfg!(feature="debug") {
+            eprintln!("Reduction: {}", rulename);
         }
+        Ok(vec![node])
     }
 
     // To write this helper draw a tree of the backpointers and see how they link.
@@ -72,7 +362,7 @@ impl<'a, ASTNode: Clone> EarleyForest<'a, ASTNode> {
     // for non-ambiguous grammars this retreieves the only possible parse
     pub fn eval_recursive(&self, ptrees: &ParseTrees) -> Result<ASTNode, String> {
         // walker will always return a Vec of size 1 because root.complete
-        Ok(self.walker(ptrees.0.first().expect("BUG: ParseTrees empty"))?
+        Ok(self.walker(ptrees.0.first().ok_or("No parse trees to evaluate")?)?
            .swap_remove(0))
     }
 
@@ -120,6 +410,28 @@ impl<'a, ASTNode: Clone> EarleyForest<'a, ASTNode> {
     }
 }
 
+impl<'a, ASTNode: Clone + Eq> EarleyForest<'a, ASTNode> {
+    /// Retrieves all parse trees dropping those that evaluate to the same
+    /// ASTNode (eg: `1 + 2 + 3` grouped either way yields the same sum).
+    /// Keeps the first occurrence of each. Dedup compares every pair so it's
+    /// O(n²) on the number of trees, avoid it on highly ambiguous grammars.
+    pub fn eval_all_with_dedup(&self, ptrees: &ParseTrees) -> Result<Vec<ASTNode>, String> {
+        let mut unique = Vec::new();
+        for tree in self.eval_all_recursive(ptrees)? {
+            if !unique.contains(&tree) {
+                unique.push(tree);
+            }
+        }
+        Ok(unique)
+    }
+}
+
+
+// A child in the derivation of a complete span
+enum Derived {
+    Nested(Rc<Span>),
+    Scanned(String, Rc<str>),
+}
 
 struct ForestIterator {
     // A stack of (span, current-source-idx). 
@@ -174,29 +486,33 @@ impl<'a, ASTNode: Clone> EarleyForest<'a, ASTNode> {
                       /   \
                   .[0-9]   "1"
     */
-    fn eval_one(&self, root: Rc<Span>, mut selector: impl FnMut(&Rc<Span>) -> usize) -> Result<ASTNode, String> {
+    fn eval_one(&self, root: Rc<Span>, mut selector: impl FnMut(&Rc<Span>) -> usize,
+                with_spans: bool) -> Result<ASTNode, String> {
         let mut args = Vec::new();
+        // Completed spans and how many args were there before their own
         let mut completions = Vec::new();
         let mut spans = vec![root];
 
         while let Some(cursor) = spans.pop() {
             // As Earley chart is unwound keep a record of semantic actions to apply
             if cursor.complete() {
-                completions.push(cursor.clone());
+                completions.push((cursor.clone(), args.len()));
             }
 
             // (Reachable) Spans with no sources mean we've unwound to the
             // begining of a production/rule. Apply the rule reducing args.
             if cursor.sources().len() == 0 {
-                let completed_rule = &completions.pop().expect("BUG: span rule never completed").rule;
+                let (completed, base) = completions.pop().expect("BUG: span rule never completed");
+                let completed_rule = &completed.rule;
+                debug_assert!(args.len() >= base, "BUG: {} args taken by another rule", completed_rule);
+                check_arity(completed_rule, args.len() - base)?;
                 assert_eq!(&cursor.rule, completed_rule);
                 // Get input AST nodes for this reduction. Stored reversed.
-                let num_rule_slots = completed_rule.spec.len();
-                let rule_args = args.split_off(args.len() - num_rule_slots).into_iter().rev().collect();
+                let rule_args = args.split_off(base).into_iter().rev().collect();
                 // Apply the reduction.
                 let rulename = completed_rule.to_string();
-                let action = self.actions.get(&rulename).ok_or(format!("Missing Action: {}", rulename))?;
-                args.push(action(rule_args));
+                let span = SpanInfo{start_token: completed.start, end_token: completed.end};
+                args.push(self.apply_action(&rulename, rule_args, Some(span).filter(|_| with_spans))?);
             } else {
                 let span_source_idx = selector(&cursor);
                 // Walk the chart following span sources (back-pointers) of the tree.
@@ -220,9 +536,92 @@ impl<'a, ASTNode: Clone> EarleyForest<'a, ASTNode> {
         Ok(args.pop().expect("BUG: mismatched reduce args"))
     }
 
+    /// Evaluate one tree, on ambiguous parses the one `eval_first` picks.
     pub fn eval(&self, ptrees: &ParseTrees) -> Result<ASTNode, String> {
-        let root = ptrees.0.first().expect("BUG: ParseTrees empty").clone();
-        self.eval_one(root, |_| 0)
+        self.eval_first(ptrees)
+    }
+
+    /// Same as `eval` but rules with a `span_action` get the tokens they
+    /// cover, the root's span is the whole input.
+    pub fn eval_with_span_info(&self, ptrees: &ParseTrees) -> Result<ASTNode, String> {
+        let root = ptrees.0.first().ok_or("No parse trees to evaluate")?.clone();
+        self.eval_one(root, |_| 0, true)
+    }
+
+    /// Evaluate a single tree of a possibly ambiguous parse, the others are
+    /// never built. Picks the first root and the first source (back-pointer)
+    /// of every span, ie: the first derivation the parser found. The same
+    /// input always gets the same tree.
+    pub fn eval_first(&self, ptrees: &ParseTrees) -> Result<ASTNode, String> {
+        let root = ptrees.0.first().ok_or("No parse trees to evaluate")?.clone();
+        self.eval_one(root, |_| 0, false)
+    }
+
+    /// Evaluate the single tree whose rules add up to the lowest cost, eg:
+    /// to disambiguate by preferring some readings. Rules are named as for
+    /// `action` and cost 0 when missing from `costs`. On ties the earlier
+    /// root or source wins, as with `eval_first`.
+    pub fn eval_min_cost(&self, ptrees: &ParseTrees, costs: &HashMap<String, i32>)
+            -> Result<ASTNode, String> {
+        let best = min_costs(&ptrees.0, costs);
+        let root = ptrees.0.iter()
+            .min_by_key(|root| best[&Rc::as_ptr(root)].0)
+            .ok_or("No parse trees to evaluate")?.clone();
+        self.eval_one(root, |span| best.get(&Rc::as_ptr(span)).map_or(0, |b| b.1), false)
+    }
+
+    /// Same result as `eval` but reductions are applied bottom-up and left to
+    /// right, children before parents and left siblings before right ones.
+    /// Useful when actions depend on side-effects of earlier reductions.
+    pub fn eval_bottom_up(&self, ptrees: &ParseTrees) -> Result<ASTNode, String> {
+        let root = ptrees.0.first().ok_or("No parse trees to evaluate")?;
+        let mut args = Vec::new();
+        // Post-order walk over the tree: spans pending reduction and their
+        // children yet to be evaluated (stored reversed), with how many args
+        // were there before their own
+        let mut pending = vec![(root.clone(), Self::children(root), 0)];
+        while let Some((_, children, _)) = pending.last_mut() {
+            match children.pop() {
+                Some(Derived::Nested(span)) => {
+                    let nested = Self::children(&span);
+                    pending.push((span, nested, args.len()));
+                },
+                Some(Derived::Scanned(symbol, lexeme)) =>
+                    args.push((self.terminal_parser)(&symbol, &lexeme)),
+                None => {
+                    let (span, _, base) = pending.pop().expect("BUG: no pending span");
+                    debug_assert!(args.len() >= base, "BUG: {} args taken by another rule", span.rule);
+                    check_arity(&span.rule, args.len() - base)?;
+                    let rule_args = args.split_off(base);
+                    args.push(self.apply_action(&span.rule.to_string(), rule_args, None)?);
+                },
+            }
+        }
+        assert_eq!(args.len(), 1);
+        Ok(args.pop().expect("BUG: mismatched reduce args"))
+    }
+
+    // Children of a complete span following first sources, reversed
+    fn children(root: &Rc<Span>) -> Vec<Derived> {
+        let mut children = Vec::new();
+        let mut cursor = root.clone();
+        loop {
+            let source = match cursor.sources().first() {
+                Some(SpanSource::Completion(source, trigger)) => {
+                    children.push(Derived::Nested(trigger.clone()));
+                    source.clone()
+                },
+                Some(SpanSource::Scan(source, trigger)) => {
+                    let symbol = source.next_symbol()
+                        .expect("BUG: missing scan trigger symbol").name();
+                    children.push(Derived::Scanned(symbol.to_string(), trigger.clone()));
+                    source.clone()
+                },
+                None => break,
+            };
+            cursor = source;
+        }
+        children
     }
 
     pub fn eval_all(&self, ptrees: &ParseTrees) -> Result<Vec<ASTNode>, String> {
@@ -231,7 +630,10 @@ impl<'a, ASTNode: Clone> EarleyForest<'a, ASTNode> {
             let mut fi = ForestIterator{source_idx: Vec::new()};
             let mut iterator_has_more_items = true;
             while iterator_has_more_items {
-                results.push(self.eval_one(root.clone(), |s| fi.source_index(s))?);
+                if self.cancel.as_ref().is_some_and(|check| check()) {
+                    return Err(CANCELLED.to_string());
+                }
+                results.push(self.eval_one(root.clone(), |s| fi.source_index(s), false)?);
                 iterator_has_more_items = fi.advance();
             }
         }
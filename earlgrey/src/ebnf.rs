@@ -1,10 +1,9 @@
-#![deny(warnings)]
-
-use super::ebnf_tokenizer::EbnfTokenizer;
+use super::ebnf_tokenizer::{CharClass, EbnfTokenizer};
 use crate::earley::{
     EarleyForest, EarleyParser, Grammar, GrammarBuilder
 };
 use std::cell::RefCell;
+use std::fmt;
 
 macro_rules! debug {
     ($($args:tt)*) => (if cfg!(feature="debug") { eprintln!($($args)*); })
@@ -27,18 +26,20 @@ macro_rules! pull {
 }
 
 // https://en.wikipedia.org/wiki/Extended_Backus%E2%80%93Naur_form
-fn ebnf_grammar() -> Grammar {
+pub(crate) fn ebnf_grammar() -> Grammar {
     GrammarBuilder::default()
       .terminal("<Id>", move |s|
                 s.chars().enumerate().all(|(i, c)|
                     i == 0 && c.is_alphabetic() ||
-                    i > 0 && (c.is_alphanumeric() || c == '_')))
+                    i > 0 && (c.is_alphanumeric() || c == '_' || c == '-')))
       .terminal("<Chars>", move |s| s.chars().all(|c| !c.is_control()))
+      // only produced by EbnfTokenizer with char_classes enabled
+      .terminal("<CharClass>", |s| s.len() > 2 && s.starts_with('[') && s.ends_with(']'))
       .terminal("@<Tag>", move |s|
                 s.chars().enumerate().all(|(i, c)|
                     i == 0 && c == '@' ||
                     i == 1 && c.is_alphabetic() ||
-                    i > 1 && (c.is_alphanumeric() || c == '_')))
+                    i > 1 && (c.is_alphanumeric() || c == '_' || c == '-')))
       .terminal(":=", |s| s == ":=")
       .terminal(";", |s| s == ";")
       .terminal("[", |s| s == "[")
@@ -63,6 +64,7 @@ fn ebnf_grammar() -> Grammar {
       .rule("<Variant>", &["<Variant>", "<Atom>"])
       .rule("<Variant>", &["<Atom>"])
       .rule("<Atom>", &["<Id>"])
+      .rule("<Atom>", &["<CharClass>"])
       .rule("<Atom>", &["'", "<Chars>", "'"])
       .rule("<Atom>", &["\"", "<Chars>", "\""])
       .rule("<Atom>", &["[", "<VariantList>", "]"])
@@ -90,9 +92,16 @@ fn ebnf_terminal_parser(
             },
             "<Chars>" => {
                 debug!("Adding terminal {:?}", token);
-                let tok = token.to_string();
-                user_grammar_builder.borrow_mut()
-                    .terminal_try(token, move |s| s == tok);
+                user_grammar_builder.borrow_mut().literal_try(token);
+            },
+            "<CharClass>" => {
+                debug!("Adding terminal {:?}", token);
+                // the tokenizer already validated the class
+                let class = CharClass::parse(token).expect("BUG: bad char class token");
+                user_grammar_builder.borrow_mut().terminal_try(token, move |s| {
+                    let mut chars = s.chars();
+                    chars.next().is_some_and(|c| class.matches(c)) && chars.next().is_none()
+                });
             },
             _ => ()
         }
@@ -230,10 +239,64 @@ fn ebnf_repeat_action<'a>(ev: &mut EarleyForest<'a, G>, gb: &'a RefCell<GrammarB
     });
 }
 
+// Why an EBNF grammar was rejected
+// Tokens are counted from 0, spans are chars of the grammar as [start, end).
+#[derive(Debug, Clone, PartialEq)]
+pub enum GrammarError {
+    // the text doesn't tokenize
+    Lex{token: usize, span: (usize, usize), message: String},
+    // it's not EBNF, the token is where the parse died, none if input ran out
+    Syntax{token: Option<usize>, span: Option<(usize, usize)>, message: String},
+    // the rules don't make a grammar, eg: the start symbol isn't defined
+    Build(String),
+}
+
+impl GrammarError {
+    pub fn span(&self) -> Option<(usize, usize)> {
+        match self {
+            GrammarError::Lex{span, ..} => Some(*span),
+            GrammarError::Syntax{span, ..} => *span,
+            GrammarError::Build(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GrammarError::Lex{token, message, ..} =>
+                write!(f, "lex error at token {}: {}", token, message),
+            GrammarError::Syntax{message, ..} | GrammarError::Build(message) =>
+                write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+#[cfg(feature = "diagnostics")]
+impl lexers::SourceError for GrammarError {
+    fn message(&self) -> String {
+        self.to_string()
+    }
+
+    fn span(&self) -> Option<(usize, usize)> {
+        GrammarError::span(self)
+    }
+}
+
+// For callers keeping String errors
+impl From<GrammarError> for String {
+    fn from(error: GrammarError) -> String {
+        error.to_string()
+    }
+}
+
 pub struct EbnfGrammarParser {
     start: String,
     grammar: String,
     grammar_builder: GrammarBuilder,
+    char_classes: bool,
 }
 
 impl EbnfGrammarParser {
@@ -242,10 +305,21 @@ impl EbnfGrammarParser {
         Self{
             start: start.to_string(),
             grammar: grammar.to_string(),
-            grammar_builder: GrammarBuilder::default()
+            grammar_builder: GrammarBuilder::default(),
+            char_classes: false,
         }
     }
 
+    // Allow inline terminals like [a-zA-Z_] matching a token that's a single
+    // char in the class. A '[' followed by whitespace is still an optional,
+    // write `[ x ]` for those. Tokens must be single chars to match, so a
+    // whitespace splitting tokenizer only works if input chars are spaced
+    // out, otherwise tokenize one char at a time: `input.chars().map(..)`.
+    pub fn char_classes(mut self, enable: bool) -> Self {
+        self.char_classes = enable;
+        self
+    }
+
     // Plug-in functions that parse Terminals before we build the grammar
     pub fn plug_terminal(mut self, name: &str, pred: impl Fn(&str) -> bool + 'static) -> Self {
         debug!("Adding terminal {:?}", name);
@@ -253,7 +327,14 @@ impl EbnfGrammarParser {
         self
     }
 
-    pub fn into_grammar(self) -> Result<Grammar, String> {
+    // Same as plug_terminal with a regex, see GrammarBuilder::symbol_regex
+    #[cfg(feature = "regex")]
+    pub fn plug_regex(mut self, name: &str, pattern: regex::Regex) -> Self {
+        self.grammar_builder = self.grammar_builder.symbol_regex(name, pattern);
+        self
+    }
+
+    pub fn into_grammar(self) -> Result<Grammar, GrammarError> {
         // Need to move grammar_builder into a refcell because ebnf
         // semantic actions need mutable access to add rules and symbols.
         // These grammar-builder changes are executed while the ebnf-parser
@@ -272,17 +353,60 @@ impl EbnfGrammarParser {
             ebnf_optional_action(&mut user_semanter, &grammar_builder);
             ebnf_repeat_action(&mut user_semanter, &grammar_builder);
             user_semanter.action("<Atom> -> <Id>", |mut n| n.remove(0));
+            user_semanter.action("<Atom> -> <CharClass>", |mut n| n.remove(0));
             user_semanter.action("<Atom> -> ' <Chars> '", |mut n| n.remove(1));
             user_semanter.action("<Atom> -> \" <Chars> \"", |mut n| n.remove(1));
 
+            // Tokenizer errors fail the parse instead of cutting the grammar short
+            let mut tokenizer = EbnfTokenizer::new(self.grammar.chars())
+                .char_classes(self.char_classes);
+            let (mut tokens, mut spans) = (Vec::new(), Vec::new());
+            while let Some(token) = tokenizer.next_result().map_err(|message|
+                    GrammarError::Lex{token: tokens.len(), span: tokenizer.span(), message})? {
+                tokens.push(token);
+                spans.push(tokenizer.span());
+            }
             // Create a parser for EBNF which we'll use to parse input grammar
-            let parsed_user_grammar = EarleyParser::new(ebnf_grammar())
-                .parse(EbnfTokenizer::new(self.grammar.chars()))?;
-            // 
-            if user_semanter.eval_all(&parsed_user_grammar)?.len() != 1 {
-                panic!("BUG: EBNF grammar shouldn't be ambiguous!");
+            let (parsed_user_grammar, stats) = EarleyParser::new(ebnf_grammar())
+                .parse_chart(tokens.iter());
+            let parsed_user_grammar = parsed_user_grammar.map_err(|message| {
+                let token = stats.dead_at();
+                GrammarError::Syntax{token, span: token.map(|idx| spans[idx]), message}
+            })?;
+            let parses = user_semanter.eval_all(&parsed_user_grammar)
+                .map_err(GrammarError::Build)?.len();
+            if parses != 1 {
+                return Err(GrammarError::Build(
+                    format!("BUG: EBNF grammar shouldn't be ambiguous, got {} parses", parses)));
             }
         }
-        grammar_builder.into_inner().into_grammar(&self.start)
+        grammar_builder.into_inner().into_grammar(&self.start).map_err(GrammarError::Build)
+    }
+}
+
+// An EarleyParser for an EBNF grammar whose terminals are all quoted, eg:
+// grammars from users or a fuzzer. Errors come from try_new, nothing fails
+// after it. See EbnfGrammarParser to plug terminals or pick the start.
+pub struct ParserBuilder {
+    grammar: Grammar,
+}
+
+impl ParserBuilder {
+    // The start symbol is the first rule's
+    pub fn try_new(grammar: &str) -> Result<ParserBuilder, GrammarError> {
+        let mut tokenizer = EbnfTokenizer::new(grammar.chars());
+        let start = tokenizer.next_result()
+            .map_err(|message| GrammarError::Lex{token: 0, span: tokenizer.span(), message})?
+            .unwrap_or_default();
+        let grammar = EbnfGrammarParser::new(grammar, &start).into_grammar()?;
+        Ok(ParserBuilder{grammar})
+    }
+
+    pub fn grammar(&self) -> &Grammar {
+        &self.grammar
+    }
+
+    pub fn into_parser(self) -> EarleyParser {
+        EarleyParser::new(self.grammar)
     }
 }
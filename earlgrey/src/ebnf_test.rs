@@ -1,43 +1,33 @@
-#![deny(warnings)]
-
-use super::ebnf::EbnfGrammarParser;
-use super::{Grammar, EarleyForest, EarleyParser};
-use std::fmt;
-
-#[derive(Debug,Clone,PartialEq)]
-pub enum Tree {
-    // ("[+-]", "+")
-    Leaf(String, String),
-    // ("E -> E [+-] E", [...])
-    Node(String, Vec<Tree>),
-}
-
-pub fn ast_parser<InputIter>(grammar: Grammar)
-    -> Result<impl Fn(InputIter) -> Result<Vec<Tree>, String>, String>
-        where InputIter: Iterator, InputIter::Item: AsRef<str> + std::fmt::Debug
-{
-    let mut tree_builder = EarleyForest::new(
-        |sym, tok| Tree::Leaf(sym.to_string(), tok.to_string()));
-
-    for rule in grammar.rules.iter().map(|r| r.to_string()) {
-        tree_builder.action(
-            &rule.clone(), move |nodes| Tree::Node(rule.clone(), nodes));
+use super::ebnf::{EbnfGrammarParser, GrammarError, ParserBuilder};
+use super::EarleyParser;
+use super::{fold, subtree_parser, Subtree, TreeRewriter};
+use super::testing::GrammarTester;
+
+// Generated rules are named <Uniq-N>, N depends on how the grammar is built
+fn unnumbered(rule: &str) -> String {
+    let mut out = String::new();
+    let mut rest = rule;
+    while let Some(idx) = rest.find("<Uniq-") {
+        out += &rest[..idx + "<Uniq".len()];
+        rest = &rest[idx + "<Uniq-".len()..];
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_digit());
     }
-
-    let parser = EarleyParser::new(grammar);
-    Ok(move |tokenizer| tree_builder.eval_all(&parser.parse(tokenizer)?))
+    out + rest
 }
 
-fn check_trees<T: fmt::Debug>(trees: &Vec<T>, expected: Vec<&str>) {
-    use std::collections::HashSet;
+// Each tree yields `leaves`, the set of trees has the `expected` shapes
+// (Subtree's Display one line per node)
+fn check_trees(trees: &[Subtree], leaves: &[&str], expected: &[&[&str]]) {
     assert_eq!(trees.len(), expected.len());
-    let mut expect = HashSet::<&str>::from_iter(expected);
-    for t in trees {
-        let debug_string = format!("{:?}", t);
-        eprintln!("Removing {}", debug_string);
-        assert!(expect.remove(debug_string.as_str()));
+    let mut expected: Vec<String> = expected.iter().map(|lines| lines.join("\n")).collect();
+    for tree in trees {
+        assert_eq!(tree.leaves(), leaves);
+        let shape = unnumbered(&tree.to_string());
+        eprintln!("Removing:\n{}", shape);
+        let idx = expected.iter().position(|e| *e == shape)
+            .unwrap_or_else(|| panic!("unexpected tree:\n{}", shape));
+        expected.remove(idx);
     }
-    assert_eq!(0, expect.len());
 }
 
 #[test]
@@ -45,10 +35,13 @@ fn minimal_parser() {
     let g = r#" Number := "0" ; "#;
     let grammar = EbnfGrammarParser::new(&g, "Number")
         .into_grammar().unwrap();
-    let parser = ast_parser(grammar).unwrap();
+    let parser = subtree_parser(grammar).unwrap();
 
     let trees = parser(["0"].iter()).unwrap();
-    check_trees(&trees, vec![r#"Node("Number -> 0", [Leaf("0", "0")])"#]);
+    check_trees(&trees, &["0"], &[&[
+        "Number -> 0",
+        r#"  0 "0""#,
+    ]]);
 }
 
 #[test]
@@ -61,20 +54,140 @@ fn arith_parser() {
     "#;
     let grammar = EbnfGrammarParser::new(&g, "expr")
         .into_grammar().unwrap();
-    let parser = ast_parser(grammar).unwrap();
+    let parser = subtree_parser(grammar).unwrap();
 
     let trees = parser("3 + 2 + 1".split_whitespace()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("expr -> expr + Number", ["#,
-                r#"Node("expr -> expr + Number", ["#,
-                    r#"Node("expr -> Number", ["#,
-                        r#"Node("Number -> 3", [Leaf("3", "3")])]), "#,
-                    r#"Leaf("+", "+"), "#,
-                    r#"Node("Number -> 2", [Leaf("2", "2")])]), "#,
-                r#"Leaf("+", "+"), "#,
-                r#"Node("Number -> 1", [Leaf("1", "1")])])"#)
-    ]);
+    check_trees(&trees, &["3", "+", "2", "+", "1"], &[&[
+        "expr -> expr + Number",
+        "  expr -> expr + Number",
+        "    expr -> Number",
+        "      Number -> 3",
+        r#"        3 "3""#,
+        r#"    + "+""#,
+        "    Number -> 2",
+        r#"      2 "2""#,
+        r#"  + "+""#,
+        "  Number -> 1",
+        r#"    1 "1""#,
+    ]]);
+}
+
+#[test]
+fn subtree_sexp() {
+    use lexers::{LispToken, LispTokenizer, SExpr};
+    let g = r#"
+        expr := Number
+              | expr "+" Number
+              | expr '"' Number
+              | "(" expr ")" ;
+
+        Number := "0" | "1" | "2" | "3" ;
+    "#;
+    let grammar = EbnfGrammarParser::new(g, "expr")
+        .into_grammar().unwrap();
+    let parser = subtree_parser(grammar).unwrap();
+    let trees = parser("( 3 + 2 ) \" 1".split_whitespace()).unwrap();
+    let sexp = trees[0].to_sexp();
+    assert_eq!(sexp, concat!(r#"("expr -> expr \" Number" ("expr -> ( expr )" "(" "#,
+        r#"("expr -> expr + Number" ("expr -> Number" ("Number -> 3" "3")) "+" "#,
+        r#"("Number -> 2" "2")) ")") "\"" ("Number -> 1" "1"))"#));
+
+    // read it back, terminal names are the symbols of their parent's rule
+    fn unquote(token: &LispToken) -> String {
+        match token {
+            LispToken::String(s) => s[1..s.len() - 1].replace("\\\"", "\"").replace("\\\\", "\\"),
+            other => panic!("Expected a string, got {:?}", other),
+        }
+    }
+    fn read(sexp: &SExpr) -> Subtree {
+        let SExpr::List(list) = sexp else { panic!("Expected a list, got {:?}", sexp) };
+        let SExpr::Atom(rule) = &list[0] else { panic!("Expected a rule, got {:?}", list[0]) };
+        let rule = unquote(rule);
+        let spec: Vec<_> = rule.split_once(" -> ").unwrap().1.split(' ').collect();
+        let children = list[1..].iter().enumerate().map(|(idx, child)| match child {
+            SExpr::Atom(lexeme) => Subtree::Node(spec[idx].to_string(), unquote(lexeme)),
+            nested => read(nested),
+        }).collect();
+        Subtree::SubT(rule, children)
+    }
+    let read_back: Vec<_> = LispTokenizer::new(sexp.chars()).into_sexpr_iterator()
+        .map(|sexp| read(&sexp.unwrap()))
+        .collect();
+    assert_eq!(format!("{:?}", read_back), format!("{:?}", trees));
+}
+
+#[test]
+fn arith_rewrite() {
+    let g = r#"
+        expr := Number
+              | expr "+" Number ;
+
+        Number := "0" | "1" | "2" | "3" ;
+    "#;
+    let grammar = EbnfGrammarParser::new(&g, "expr")
+        .into_grammar().unwrap();
+    let parser = subtree_parser(grammar).unwrap();
+    let trees = parser("3 + 2 + 1".split_whitespace()).unwrap();
+
+    let rewriter = TreeRewriter::default()
+        .drop_leaves(|symbol, _| symbol == "+")
+        .collapse_unit_chains(true)
+        .map_rule(|rule| match rule {
+            "expr -> expr + Number" => Some("add".to_string()),
+            _ => None,
+        });
+    let leaf = |n: &str| Subtree::Node(n.to_string(), n.to_string());
+    let add = |l, r| Subtree::SubT("add".to_string(), vec![l, r]);
+    assert_eq!(rewriter.rewrite(&trees[0]),
+               add(add(leaf("3"), leaf("2")), leaf("1")));
+
+    // without collapsing only the '+' leaves go away
+    let rewriter = TreeRewriter::default()
+        .drop_leaves(|symbol, _| symbol == "+");
+    check_trees(&[rewriter.rewrite(&trees[0])], &["3", "2", "1"], &[&[
+        "expr -> expr + Number",
+        "  expr -> expr + Number",
+        "    expr -> Number",
+        "      Number -> 3",
+        r#"        3 "3""#,
+        "    Number -> 2",
+        r#"      2 "2""#,
+        "  Number -> 1",
+        r#"    1 "1""#,
+    ]]);
+}
+
+#[test]
+fn rewrite_unit_chains() {
+    let g = r#"
+        expr := term ;
+        term := factor ;
+        factor := "(" expr ")" | Number ;
+        Number := "1" ;
+    "#;
+    let grammar = EbnfGrammarParser::new(&g, "expr")
+        .into_grammar().unwrap();
+    let parser = subtree_parser(grammar).unwrap();
+    let trees = parser("( ( 1 ) )".split_whitespace()).unwrap();
+
+    // parens collapse into unit chains once dropped
+    let rewriter = TreeRewriter::default()
+        .drop_leaves(|_, lexeme| lexeme == "(" || lexeme == ")")
+        .collapse_unit_chains(true);
+    assert_eq!(rewriter.rewrite(&trees[0]),
+               Subtree::Node("1".to_string(), "1".to_string()));
+
+    // renaming alone keeps the shape
+    let rewriter = TreeRewriter::default()
+        .map_rule(|rule| rule.split(" -> ").next().map(str::to_string));
+    let renamed = rewriter.rewrite(&parser("1".split_whitespace()).unwrap()[0]);
+    check_trees(&[renamed], &["1"], &[&[
+        "expr",
+        "  term",
+        "    factor",
+        "      Number",
+        r#"        1 "1""#,
+    ]]);
 }
 
 #[test]
@@ -85,21 +198,23 @@ fn repetition() {
     "#;
     let grammar = EbnfGrammarParser::new(&g, "arg")
         .into_grammar().unwrap();
-    let parser = ast_parser(grammar).unwrap();
+    let parser = subtree_parser(grammar).unwrap();
 
     let trees = parser("1 , 0 , 1".split_whitespace()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("arg -> b <Uniq-4>", ["#,
-                r#"Node("b -> 1", [Leaf("1", "1")]), "#,
-                r#"Node("<Uniq-4> -> , b <Uniq-4>", ["#,
-                    r#"Leaf(",", ","), "#,
-                    r#"Node("b -> 0", [Leaf("0", "0")]), "#,
-                    r#"Node("<Uniq-4> -> , b <Uniq-4>", ["#,
-                        r#"Leaf(",", ","), "#,
-                        r#"Node("b -> 1", [Leaf("1", "1")]), "#,
-                        r#"Node("<Uniq-4> -> ", [])])])])"#)
-    ]);
+    check_trees(&trees, &["1", ",", "0", ",", "1"], &[&[
+        "arg -> b <Uniq>",
+        "  b -> 1",
+        r#"    1 "1""#,
+        "  <Uniq> -> , b <Uniq>",
+        r#"    , ",""#,
+        "    b -> 0",
+        r#"      0 "0""#,
+        "    <Uniq> -> , b <Uniq>",
+        r#"      , ",""#,
+        "      b -> 1",
+        r#"        1 "1""#,
+        "      <Uniq> ->",
+    ]]);
 }
 
 #[test]
@@ -110,21 +225,23 @@ fn repetition_tagged() {
     "#;
     let grammar = EbnfGrammarParser::new(&g, "arg")
         .into_grammar().unwrap();
-    let parser = ast_parser(grammar).unwrap();
+    let parser = subtree_parser(grammar).unwrap();
 
     let trees = parser("1 , 0 , 1".split_whitespace()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("arg -> b @x", ["#,
-                r#"Node("b -> 1", [Leaf("1", "1")]), "#,
-                r#"Node("@x -> , b @x", ["#,
-                    r#"Leaf(",", ","), "#,
-                    r#"Node("b -> 0", [Leaf("0", "0")]), "#,
-                    r#"Node("@x -> , b @x", ["#,
-                        r#"Leaf(",", ","), "#,
-                        r#"Node("b -> 1", [Leaf("1", "1")]), "#,
-                        r#"Node("@x -> ", [])])])])"#)
-    ]);
+    check_trees(&trees, &["1", ",", "0", ",", "1"], &[&[
+        "arg -> b @x",
+        "  b -> 1",
+        r#"    1 "1""#,
+        "  @x -> , b @x",
+        r#"    , ",""#,
+        "    b -> 0",
+        r#"      0 "0""#,
+        "    @x -> , b @x",
+        r#"      , ",""#,
+        "      b -> 1",
+        r#"        1 "1""#,
+        "      @x ->",
+    ]]);
 }
 
 #[test]
@@ -135,23 +252,24 @@ fn option() {
     "#;
     let grammar = EbnfGrammarParser::new(&g, "complex")
         .into_grammar().unwrap();
-    let parser = ast_parser(grammar).unwrap();
+    let parser = subtree_parser(grammar).unwrap();
 
     let trees = parser(["1"].iter()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("complex -> d <Uniq-5>", ["#,
-                r#"Node("d -> 1", [Leaf("1", "1")]), "#,
-                r#"Node("<Uniq-5> -> ", [])])"#)
-    ]);
+    check_trees(&trees, &["1"], &[&[
+        "complex -> d <Uniq>",
+        "  d -> 1",
+        r#"    1 "1""#,
+        "  <Uniq> ->",
+    ]]);
 
     let trees = parser(["2", "i"].iter()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("complex -> d <Uniq-5>", ["#,
-                r#"Node("d -> 2", [Leaf("2", "2")]), "#,
-                r#"Node("<Uniq-5> -> i", [Leaf("i", "i")])])"#)
-    ]);
+    check_trees(&trees, &["2", "i"], &[&[
+        "complex -> d <Uniq>",
+        "  d -> 2",
+        r#"    2 "2""#,
+        "  <Uniq> -> i",
+        r#"    i "i""#,
+    ]]);
 
     assert!(parser(["2", "i", "i"].iter()).is_err());
 }
@@ -164,23 +282,24 @@ fn option_tagged() {
     "#;
     let grammar = EbnfGrammarParser::new(&g, "complex")
         .into_grammar().unwrap();
-    let parser = ast_parser(grammar).unwrap();
+    let parser = subtree_parser(grammar).unwrap();
 
     let trees = parser(["1"].iter()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("complex -> d @x", ["#,
-                r#"Node("d -> 1", [Leaf("1", "1")]), "#,
-                r#"Node("@x -> ", [])])"#)
-    ]);
+    check_trees(&trees, &["1"], &[&[
+        "complex -> d @x",
+        "  d -> 1",
+        r#"    1 "1""#,
+        "  @x ->",
+    ]]);
 
     let trees = parser(["2", "i"].iter()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("complex -> d @x", ["#,
-                r#"Node("d -> 2", [Leaf("2", "2")]), "#,
-                r#"Node("@x -> i", [Leaf("i", "i")])])"#)
-    ]);
+    check_trees(&trees, &["2", "i"], &[&[
+        "complex -> d @x",
+        "  d -> 2",
+        r#"    2 "2""#,
+        "  @x -> i",
+        r#"    i "i""#,
+    ]]);
 
     assert!(parser(["2", "i", "i"].iter()).is_err());
 }
@@ -192,23 +311,25 @@ fn grouping() {
     "#;
     let grammar = EbnfGrammarParser::new(&g, "row")
         .into_grammar().unwrap();
-    let parser = ast_parser(grammar).unwrap();
+    let parser = subtree_parser(grammar).unwrap();
 
     let trees = parser(["b", "1"].iter()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("row -> <Uniq-5> <Uniq-2>", ["#,
-                r#"Node("<Uniq-5> -> b", [Leaf("b", "b")]), "#,
-                r#"Node("<Uniq-2> -> 1", [Leaf("1", "1")])])"#)
-    ]);
+    check_trees(&trees, &["b", "1"], &[&[
+        "row -> <Uniq> <Uniq>",
+        "  <Uniq> -> b",
+        r#"    b "b""#,
+        "  <Uniq> -> 1",
+        r#"    1 "1""#,
+    ]]);
 
     let trees = parser(["a", "0"].iter()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("row -> <Uniq-5> <Uniq-2>", ["#,
-                r#"Node("<Uniq-5> -> a", [Leaf("a", "a")]), "#,
-                r#"Node("<Uniq-2> -> 0", [Leaf("0", "0")])])"#)
-    ]);
+    check_trees(&trees, &["a", "0"], &[&[
+        "row -> <Uniq> <Uniq>",
+        "  <Uniq> -> a",
+        r#"    a "a""#,
+        "  <Uniq> -> 0",
+        r#"    0 "0""#,
+    ]]);
 
     assert!(parser(["a", "b"].iter()).is_err());
     assert!(parser(["0", "1"].iter()).is_err());
@@ -221,23 +342,25 @@ fn grouping_tagged() {
     "#;
     let grammar = EbnfGrammarParser::new(&g, "row")
         .into_grammar().unwrap();
-    let parser = ast_parser(grammar).unwrap();
+    let parser = subtree_parser(grammar).unwrap();
 
     let trees = parser(["b", "1"].iter()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("row -> @x @y", ["#,
-                r#"Node("@x -> b", [Leaf("b", "b")]), "#,
-                r#"Node("@y -> 1", [Leaf("1", "1")])])"#)
-    ]);
+    check_trees(&trees, &["b", "1"], &[&[
+        "row -> @x @y",
+        "  @x -> b",
+        r#"    b "b""#,
+        "  @y -> 1",
+        r#"    1 "1""#,
+    ]]);
 
     let trees = parser(["a", "0"].iter()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("row -> @x @y", ["#,
-                r#"Node("@x -> a", [Leaf("a", "a")]), "#,
-                r#"Node("@y -> 0", [Leaf("0", "0")])])"#)
-    ]);
+    check_trees(&trees, &["a", "0"], &[&[
+        "row -> @x @y",
+        "  @x -> a",
+        r#"    a "a""#,
+        "  @y -> 0",
+        r#"    0 "0""#,
+    ]]);
 
     assert!(parser(["a", "b"].iter()).is_err());
     assert!(parser(["0", "1"].iter()).is_err());
@@ -250,42 +373,44 @@ fn mixed() {
     "#;
     let grammar = EbnfGrammarParser::new(&g, "row")
         .into_grammar().unwrap();
-    let parser = ast_parser(grammar).unwrap();
+    let parser = subtree_parser(grammar.clone()).unwrap();
 
     let trees = parser(["a", "0"].iter()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("row -> a <Uniq-6> <Uniq-4> <Uniq-1>", ["#,
-                r#"Leaf("a", "a"), "#,
-                r#"Node("<Uniq-6> -> ", []), "#,
-                r#"Node("<Uniq-4> -> 0", [Leaf("0", "0")]), "#,
-                r#"Node("<Uniq-1> -> ", [])])"#)
-    ]);
+    check_trees(&trees, &["a", "0"], &[&[
+        "row -> a <Uniq> <Uniq> <Uniq>",
+        r#"  a "a""#,
+        "  <Uniq> ->",
+        "  <Uniq> -> 0",
+        r#"    0 "0""#,
+        "  <Uniq> ->",
+    ]]);
 
     let trees = parser(["a", "b", "1"].iter()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("row -> a <Uniq-6> <Uniq-4> <Uniq-1>", ["#,
-                r#"Leaf("a", "a"), "#,
-                r#"Node("<Uniq-6> -> b", [Leaf("b", "b")]), "#,
-                r#"Node("<Uniq-4> -> 1", [Leaf("1", "1")]), "#,
-                r#"Node("<Uniq-1> -> ", [])])"#)
-    ]);
+    check_trees(&trees, &["a", "b", "1"], &[&[
+        "row -> a <Uniq> <Uniq> <Uniq>",
+        r#"  a "a""#,
+        "  <Uniq> -> b",
+        r#"    b "b""#,
+        "  <Uniq> -> 1",
+        r#"    1 "1""#,
+        "  <Uniq> ->",
+    ]]);
 
     let trees = parser(["a", "1", "c"].iter()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("row -> a <Uniq-6> <Uniq-4> <Uniq-1>", ["#,
-                r#"Leaf("a", "a"), "#,
-                r#"Node("<Uniq-6> -> ", []), "#,
-                r#"Node("<Uniq-4> -> 1", [Leaf("1", "1")]), "#,
-                r#"Node("<Uniq-1> -> c", [Leaf("c", "c")])])"#)
-    ]);
-
-    assert!(parser(["a", "b"].iter()).is_err());
-    assert!(parser(["0", "1"].iter()).is_err());
-    assert!(parser(["a", "b", "0", "d"].iter()).is_err());
-    assert!(parser(["a", "b", "0"].iter()).is_ok());
+    check_trees(&trees, &["a", "1", "c"], &[&[
+        "row -> a <Uniq> <Uniq> <Uniq>",
+        r#"  a "a""#,
+        "  <Uniq> ->",
+        "  <Uniq> -> 1",
+        r#"    1 "1""#,
+        "  <Uniq> -> c",
+        r#"    c "c""#,
+    ]]);
+
+    GrammarTester::new(EarleyParser::new(grammar))
+        .rejects(&["a b", "0 1", "a b 0 d"])
+        .accepts(&["a b 0"])
+        .tree_yield("a b 0 c", &["a", "b", "0", "c"]);
 }
 
 #[test]
@@ -296,42 +421,44 @@ fn mixed_tagged() {
 
     let grammar = EbnfGrammarParser::new(&g, "row")
         .into_grammar().unwrap();
-    let parser = ast_parser(grammar).unwrap();
+    let parser = subtree_parser(grammar.clone()).unwrap();
 
     let trees = parser(["a", "0"].iter()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("row -> a @x @y @z", ["#,
-                r#"Leaf("a", "a"), "#,
-                r#"Node("@x -> ", []), "#,
-                r#"Node("@y -> 0", [Leaf("0", "0")]), "#,
-                r#"Node("@z -> ", [])])"#)
-    ]);
+    check_trees(&trees, &["a", "0"], &[&[
+        "row -> a @x @y @z",
+        r#"  a "a""#,
+        "  @x ->",
+        "  @y -> 0",
+        r#"    0 "0""#,
+        "  @z ->",
+    ]]);
 
     let trees = parser(["a", "b", "1"].iter()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("row -> a @x @y @z", ["#,
-                r#"Leaf("a", "a"), "#,
-                r#"Node("@x -> b", [Leaf("b", "b")]), "#,
-                r#"Node("@y -> 1", [Leaf("1", "1")]), "#,
-                r#"Node("@z -> ", [])])"#)
-    ]);
+    check_trees(&trees, &["a", "b", "1"], &[&[
+        "row -> a @x @y @z",
+        r#"  a "a""#,
+        "  @x -> b",
+        r#"    b "b""#,
+        "  @y -> 1",
+        r#"    1 "1""#,
+        "  @z ->",
+    ]]);
 
     let trees = parser(["a", "1", "c"].iter()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("row -> a @x @y @z", ["#,
-                r#"Leaf("a", "a"), "#,
-                r#"Node("@x -> ", []), "#,
-                r#"Node("@y -> 1", [Leaf("1", "1")]), "#,
-                r#"Node("@z -> c", [Leaf("c", "c")])])"#)
-    ]);
-
-    assert!(parser(["a", "b"].iter()).is_err());
-    assert!(parser(["0", "1"].iter()).is_err());
-    assert!(parser(["a", "b", "0", "d"].iter()).is_err());
-    assert!(parser(["a", "b", "0"].iter()).is_ok());
+    check_trees(&trees, &["a", "1", "c"], &[&[
+        "row -> a @x @y @z",
+        r#"  a "a""#,
+        "  @x ->",
+        "  @y -> 1",
+        r#"    1 "1""#,
+        "  @z -> c",
+        r#"    c "c""#,
+    ]]);
+
+    GrammarTester::new(EarleyParser::new(grammar))
+        .rejects(&["a b", "0 1", "a b 0 d"])
+        .accepts(&["a b 0"])
+        .tree_yield("a b 0 c", &["a", "b", "0", "c"]);
 }
 
 #[test]
@@ -345,14 +472,275 @@ fn plug_terminal() {
         .plug_terminal("Number", |i| i8::from_str(i).is_ok())
         .into_grammar().unwrap();
 
-    let parser = ast_parser(grammar).unwrap();
+    let parser = subtree_parser(grammar).unwrap();
 
     let trees = parser(["3", "+", "1"].iter()).unwrap();
-    check_trees(&trees, vec![
-        concat!(
-            r#"Node("expr -> expr + Number", ["#,
-                r#"Node("expr -> Number", [Leaf("Number", "3")]), "#,
-                r#"Leaf("+", "+"), "#,
-                r#"Leaf("Number", "1")])"#)
+    check_trees(&trees, &["3", "+", "1"], &[&[
+        "expr -> expr + Number",
+        "  expr -> Number",
+        r#"    Number "3""#,
+        r#"  + "+""#,
+        r#"  Number "1""#,
+    ]]);
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn plug_regex() {
+    let grammar = EbnfGrammarParser::new(r#"expr := Number | expr "+" Number ;"#, "expr")
+        .plug_regex("Number", regex::Regex::new(r"^[0-9]+$").unwrap())
+        .into_grammar().unwrap();
+    let parser = EarleyParser::new(grammar);
+    assert!(parser.parse(["12", "+", "3"].iter()).is_ok());
+    assert!(parser.parse(["12", "+", "x"].iter()).is_err());
+}
+
+#[test]
+fn char_class_terminals() {
+    let g = r#"
+        ident := [a-z_] { [a-z0-9_] } ;
+        pair := ident [ "=" [0-9] ] ;
+    "#;
+    let grammar = EbnfGrammarParser::new(g, "ident")
+        .char_classes(true)
+        .into_grammar().unwrap();
+    let single = |token: &str| vec![token.to_string()];
+    GrammarTester::new(EarleyParser::new(grammar.clone()))
+        .tokenizer(move |input| single(input))
+        .accepts(&["a", "_"])
+        .rejects(&["0", "A", "ab", ""]);
+    // one char per token
+    GrammarTester::new(EarleyParser::new(grammar))
+        .tokenizer(|input| input.chars().map(String::from).collect())
+        .accepts(&["x", "snake_case2"])
+        .rejects(&["2x", "Camel"]);
+    let grammar = EbnfGrammarParser::new(g, "pair")
+        .char_classes(true)
+        .into_grammar().unwrap();
+    GrammarTester::new(EarleyParser::new(grammar))
+        .accepts(&["a", "a = 1"])
+        .rejects(&["a = b", "a = 12"]);
+    // without the mode brackets are optionals, ["z"] is z or nothing
+    assert!(EbnfGrammarParser::new(g, "ident").into_grammar().is_err());
+    let grammar = EbnfGrammarParser::new(r#"a := ["z"] ;"#, "a").into_grammar().unwrap();
+    GrammarTester::new(EarleyParser::new(grammar)).accepts(&["", "z"]);
+    assert!(EbnfGrammarParser::new("a := [z-a] ;", "a").char_classes(true)
+        .into_grammar().is_err());
+}
+
+#[test]
+fn bad_grammars() {
+    // tokenizer errors used to be dropped, accepting the grammar up to them
+    let error = |g: &str| EbnfGrammarParser::new(g, "a").into_grammar().unwrap_err().to_string();
+    assert_eq!(error("a := 'x' ; $"), "lex error at token 6: Unexpected char: $");
+    assert_eq!(error("a := 'x' ; b := \"y"),
+               "lex error at token 8: Unfinished string missing close quote");
+    assert_eq!(error("a := 'x' | ' :"), "lex error at token 6: Unfinished string missing close quote");
+    assert_eq!(error("a := x-;"), "lex error at token 2: Unexpected char: -");
+    let corpus = ["", ";", "a :=", "a := ;", "a := [ ] ;", "a := ( | ) ;", "a := b ; b := a ;",
+                  "a := a | 'x' ;", "a := {} @t ;", "a := '' ;", "a := \"\\\" ;", ":=:=", "a := [a-z ;"];
+    for g in corpus {
+        for char_classes in [false, true] {
+            if let Ok(grammar) = EbnfGrammarParser::new(g, "a").char_classes(char_classes).into_grammar() {
+                let _ = EarleyParser::new(grammar).parse(["x", "", "a"].iter());
+            }
+        }
+    }
+    assert!(EbnfGrammarParser::new("a := 'x' ; # the end", "a").into_grammar().is_ok());
+}
+
+#[test]
+fn grammar_errors() {
+    let error = |g: &str| EbnfGrammarParser::new(g, "a").into_grammar().unwrap_err();
+    assert_eq!(error("a := x-;"), GrammarError::Lex{
+        token: 2, span: (5, 7), message: "Unexpected char: -".to_string()});
+    // parse errors say which token killed the parse
+    assert!(matches!(error("a := := 'x' ;"),
+                     GrammarError::Syntax{token: Some(2), span: Some((5, 7)), ..}));
+    assert!(matches!(error("a := 'x'"), GrammarError::Syntax{token: None, span: None, ..}));
+    // quoted strings are 3 tokens, the quotes and what's inside
+    assert_eq!(error("a := 'x' 'y' ; ;").span(), Some((15, 16)));
+    assert_eq!(error("a := 'xy' ;\n b := ) ;").span(), Some((18, 19)));
+    assert_eq!(error("b := 'x' ;"), GrammarError::Build("Missing start Symbol: a".to_string()));
+}
+
+#[cfg(feature = "diagnostics")]
+#[test]
+fn render_grammar_errors() {
+    let grammar = "a := 'x' ;\nb := a | := ;";
+    let error = EbnfGrammarParser::new(grammar, "a").into_grammar().unwrap_err();
+    assert_eq!(lexers::render(&error, grammar), concat!(
+        "error: Parse Error: No Rule completes\n",
+        " --> 2:10\n",
+        "  |\n",
+        "2 | b := a | := ;\n",
+        "  |          ^^\n"));
+}
+
+#[test]
+fn parser_builder() {
+    let builder = ParserBuilder::try_new("list := item | list ',' item ; item := 'x' ;").unwrap();
+    assert_eq!(builder.grammar().start, "list");
+    GrammarTester::new(builder.into_parser())
+        .accepts(&["x", "x , x , x"])
+        .rejects(&["", "x ,"]);
+    assert!(matches!(ParserBuilder::try_new(""), Err(GrammarError::Syntax{token: None, ..})));
+    assert!(matches!(ParserBuilder::try_new("$"), Err(GrammarError::Lex{token: 0, ..})));
+}
+
+#[test]
+fn hyphenated_rule_names() {
+    let g = r#"
+        date := day-of-week named-month ;
+        day-of-week := "mon" | "tue" ;
+        named-month := "jan" | "feb" ;
+    "#;
+    let grammar = EbnfGrammarParser::new(g, "date").into_grammar().unwrap();
+    let parser = subtree_parser(grammar).unwrap();
+    check_trees(&parser(["tue", "jan"].iter()).unwrap(), &["tue", "jan"], &[&[
+        "date -> day-of-week named-month",
+        "  day-of-week -> tue",
+        r#"    tue "tue""#,
+        "  named-month -> jan",
+        r#"    jan "jan""#,
+    ]]);
+}
+
+#[test]
+fn rule_lookup() {
+    let g = r#"
+        expr := Number
+              | expr "+" Number
+              | expr "+" "(" expr ")" ;
+
+        Number := "0" | "1" | "2" | "3" ;
+    "#;
+    let grammar = EbnfGrammarParser::new(&g, "expr")
+        .into_grammar().unwrap();
+    let rule = grammar.rule_for("expr", &["expr", "+", "Number"]).unwrap();
+    assert_eq!(rule.to_string(), "expr -> expr + Number");
+    assert_eq!(grammar.rule_for("Number", &["2"]).unwrap().to_string(), "Number -> 2");
+    assert!(grammar.rule_for("expr", &["expr", "+"]).is_none());
+    assert!(grammar.rule_for("expr", &["Number", "+", "expr"]).is_none());
+    assert!(grammar.rule_for("Number", &["expr"]).is_none());
+    assert!(grammar.rule_for("nope", &[]).is_none());
+
+    let rules: Vec<_> = grammar.rules_starting_with("expr", "expr")
+        .map(|r| r.to_string()).collect();
+    assert_eq!(rules, ["expr -> expr + Number", "expr -> expr + ( expr )"]);
+    let rules: Vec<_> = grammar.rules_starting_with("expr", "Number")
+        .map(|r| r.to_string()).collect();
+    assert_eq!(rules, ["expr -> Number"]);
+    assert_eq!(grammar.rules_starting_with("Number", "expr").count(), 0);
+    assert_eq!(grammar.rules_starting_with("expr", "+").count(), 0);
+}
+
+#[test]
+fn reparameterize_start() {
+    let g = r#"
+        block := "{" { stmt } "}" ;
+        stmt := expr ";" ;
+        expr := Number | expr "+" Number ;
+    "#;
+    let grammar = EbnfGrammarParser::new(g, "block")
+        .plug_terminal("Number", |n| n.chars().all(|c| c.is_ascii_digit()))
+        .into_grammar().unwrap();
+    GrammarTester::new(EarleyParser::new(grammar.clone()))
+        .accepts(&["{ 1 + 2 ; 3 ; }"])
+        .rejects(&["1 + 2"]);
+    let expr = grammar.with_start("expr").unwrap();
+    assert_eq!(expr.start, "expr");
+    assert_eq!(expr.rules.len(), grammar.rules.len());
+    GrammarTester::new(EarleyParser::new(expr))
+        .accepts(&["1 + 2"])
+        .rejects(&["{ 1 + 2 ; }"]);
+    // start must be an existing NonTerm
+    assert_eq!(grammar.with_start("Number").unwrap_err(),
+               "Grammar start must be NonTerm: Number");
+    assert_eq!(grammar.with_start("nope").unwrap_err(), "Missing start Symbol: nope");
+}
+
+#[test]
+fn find_subtrees() {
+    let grammar = EbnfGrammarParser::new(r#"
+        expr   := term | expr "+" term ;
+        term   := factor | term "*" factor ;
+        factor := Number | "(" expr ")" ;
+    "#, "expr")
+        .plug_terminal("Number", |n| n.chars().all(|c| c.is_ascii_digit()))
+        .into_grammar().unwrap();
+    let parser = subtree_parser(grammar).unwrap();
+    let trees = parser("1 * ( 2 + 3 ) + 4".split_whitespace()).unwrap();
+    let tree = &trees[0];
+
+    // by full rule, in pre-order
+    let numbers: Vec<_> = tree.find_all("factor -> Number").into_iter()
+        .filter_map(|t| match t {
+            Subtree::SubT(_, children) => match &children[0] {
+                Subtree::Node(_, n) => Some(n.as_str()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    assert_eq!(numbers, vec!["1", "2", "3", "4"]);
+    // by head, partial names don't match
+    assert_eq!(tree.find_all("factor").len(), 5);
+    assert_eq!(tree.find_all("expr").len(), 4);
+    assert!(tree.find_all("exp").is_empty());
+    assert!(tree.find_all("Number").is_empty());
+
+    // depth first: the outer sum comes first, then the parenthesized one
+    assert_eq!(tree.find_first("expr -> expr + term"), Some(tree));
+    match tree.find_first("factor -> ( expr )") {
+        Some(Subtree::SubT(_, children)) =>
+            assert_eq!(children[1].find_all("term -> factor").len(), 2),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(tree.find_first("missing"), None);
+}
+
+#[test]
+fn fold_subtrees() {
+    let grammar = EbnfGrammarParser::new(r#"
+        expr   := term | expr "+" term ;
+        term   := factor | term "*" factor ;
+        factor := Number | "(" expr ")" ;
+    "#, "expr")
+        .plug_terminal("Number", |n| n.chars().all(|c| c.is_ascii_digit()))
+        .into_grammar().unwrap();
+    let parser = subtree_parser(grammar).unwrap();
+    let tree = &parser("1 * ( 2 + 3 )".split_whitespace()).unwrap()[0];
+
+    let depth = fold(tree, 0, |deepest, _, depth| deepest.max(depth));
+    assert_eq!(depth, 7);
+    let text = fold(tree, String::new(), |text, node, _| match node {
+        Subtree::Node(_, lexeme) => text + lexeme,
+        Subtree::SubT(_, _) => text,
+    });
+    assert_eq!(text, "1*(2+3)");
+    assert_eq!(text, tree.leaves().concat());
+    // parents come before their children
+    let rules = fold(tree, Vec::new(), |mut rules, node, depth| {
+        if let Subtree::SubT(rule, _) = node {
+            if depth < 2 { rules.push(rule.clone()); }
+        }
+        rules
+    });
+    assert_eq!(rules, vec!["expr -> term", "term -> term * factor"]);
+    let leaf = Subtree::Node("Number".to_string(), "1".to_string());
+    assert_eq!(fold(&leaf, 0, |n, _, _| n + 1), 1);
+}
+
+#[test]
+fn subtree_display() {
+    let node = |s: &str, l: &str| Subtree::Node(s.to_string(), l.to_string());
+    let tree = Subtree::SubT("s -> str <Uniq-1>".to_string(), vec![
+        node("str", "say \"hi\"\n"),
+        Subtree::SubT("<Uniq-1> -> ".to_string(), vec![]),
     ]);
+    // lexemes are escaped to keep one node per line, epsilon rules trimmed
+    assert_eq!(tree.to_string(), "s -> str <Uniq-1>\n  str \"say \\\"hi\\\"\\n\"\n  <Uniq-1> ->");
+    assert_eq!(tree.leaves(), vec!["say \"hi\"\n"]);
+    assert_eq!(node("a", "b").to_string(), "a \"b\"");
 }
@@ -1,31 +1,76 @@
 pub struct EbnfTokenizer<I: Iterator<Item=char>> {
     input: std::iter::Peekable<I>,
-    buff: Vec<String>,
+    // tokens read ahead and the chars they span
+    buff: Vec<(String, (usize, usize))>,
+    // '[' followed by a non-space starts a character class, eg: [a-z]
+    char_classes: bool,
+    // chars read so far and where the last token started and ended
+    pos: usize,
+    span: (usize, usize),
 }
 
 impl<I: Iterator<Item=char>> EbnfTokenizer<I> {
     pub fn new(input: I) -> Self {
-        Self { input: input.peekable(), buff: Vec::new() }
+        Self { input: input.peekable(), buff: Vec::new(), char_classes: false, pos: 0, span: (0, 0) }
     }
 
-    fn next_result(&mut self) -> Result<Option<String>, String> {
+    // Chars of the input the last token (or error) came from as [start, end)
+    pub(crate) fn span(&self) -> (usize, usize) {
+        self.span
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let next = self.input.next();
+        self.pos += usize::from(next.is_some());
+        self.span.1 = self.pos;
+        next
+    }
+
+    pub(crate) fn char_classes(mut self, enable: bool) -> Self {
+        self.char_classes = enable;
+        self
+    }
+
+    pub(crate) fn next_result(&mut self) -> Result<Option<String>, String> {
         if self.buff.len() > 0 {
-            return Ok(Some(self.buff.remove(0)));
+            let (token, span) = self.buff.remove(0);
+            self.span = span;
+            return Ok(Some(token));
         }
-        match self.input.next() {
+        self.span = (self.pos, self.pos);
+        match self.bump() {
+            // Character classes are a single token, validated here.
+            Some('[') if self.char_classes &&
+                         self.input.peek().is_some_and(|c| !c.is_whitespace()) => {
+                let mut class = "[".to_string();
+                let mut escaped = false;
+                while let Some(ch) = self.bump() {
+                    class.push(ch);
+                    match ch {
+                        '\\' if !escaped => escaped = true,
+                        ']' if !escaped => {
+                            CharClass::parse(&class)?;
+                            return Ok(Some(class));
+                        },
+                        '\n' => break,
+                        _ => escaped = false,
+                    }
+                }
+                Err("Unfinished character class missing ]".to_string())
+            },
             // Various single char tokens.
             Some(x) if "[]{}()|;".contains(x) => Ok(Some(x.to_string())),
             // Assignment operator.
-            Some(':') => match self.input.next() {
+            Some(':') => match self.bump() {
                 Some('=') => Ok(Some(":=".to_string())),
                 _ => Err("Incomplete := operator".to_string()),
             },
             // Tokenize Strings checking for escapes.
             Some(open) if open == '"' || open == '\'' => {
-                self.buff.push(open.to_string());
                 let mut quoted_string = String::new();
                 let mut escaped = false;
-                while let Some(ch) = self.input.next() {
+                let start = self.span.0;
+                while let Some(ch) = self.bump() {
                     // Swallow escape char '\' and prevent string closure
                     if ! escaped && ch == '\\' {
                         escaped = true;
@@ -35,33 +80,46 @@ impl<I: Iterator<Item=char>> EbnfTokenizer<I> {
                         quoted_string.push('\\')
                     }
                     // Found close token. Check it wasn't escaped.
+                    // Quotes are buffered once closed, an unfinished string
+                    // can't leave an open quote behind for the next call.
                     if ! escaped && open == ch {
-                        self.buff.push(quoted_string);
-                        self.buff.push(ch.to_string());
-                        return self.next_result();
+                        self.buff.push((quoted_string, (start + 1, self.pos - 1)));
+                        self.buff.push((ch.to_string(), (self.pos - 1, self.pos)));
+                        self.span = (start, start + 1);
+                        return Ok(Some(open.to_string()));
                     }
                     quoted_string.push(ch);
                     escaped = false;
                 }
                 Err("Unfinished string missing close quote".to_string())
             },
-            // Swallow comments until EOL.
+            // Swallow comments until EOL or the end of input.
             Some('#') => {
-                while let Some(nl) = self.input.next() {
+                while let Some(nl) = self.bump() {
                     if nl == '\n' {
                         return self.next_result();
                     }
                 }
-                Err("Unfinished comment missing EOL".to_string())
+                Ok(None)
             },
-            // Tags (starts with '@') and identifiers.
+            // Tags (starts with '@') and identifiers, eg: day-of-week.
             Some(x) if x.is_ascii_alphabetic() || x == '@' || x == '_' => {
                 let mut id = x.to_string();
-                while let Some(ch) = self.input.peek() {
-                    if ! ch.is_ascii_alphanumeric() && *ch != '_' {
+                while let Some(&ch) = self.input.peek() {
+                    if ch == '-' {
+                        // hyphens only go between name chars
+                        self.bump();
+                        match self.input.peek() {
+                            Some(c) if c.is_ascii_alphanumeric() || *c == '_' => id.push('-'),
+                            _ => return Err("Unexpected char: -".to_string()),
+                        }
+                        continue;
+                    }
+                    if ! ch.is_ascii_alphanumeric() && ch != '_' {
                         break;
                     }
-                    id.push(self.input.next().unwrap());
+                    id.push(ch);
+                    self.bump();
                 }
                 Ok(Some(id))
             },
@@ -71,7 +129,7 @@ impl<I: Iterator<Item=char>> EbnfTokenizer<I> {
                     if ! ws.is_whitespace() {
                         break;
                     }
-                    self.input.next(); // consume whitespace
+                    self.bump(); // consume whitespace
                 }
                 self.next_result()
             },
@@ -95,9 +153,62 @@ impl<I: Iterator<Item=char>> Iterator for EbnfTokenizer<I> {
     }
 }
 
+/// Set of chars from a class like `[a-zA-Z_]`, `[^0-9]` negates it. Use
+/// `\\` to escape `]`, `-` or `\\` itself.
+pub(crate) struct CharClass {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    pub(crate) fn parse(class: &str) -> Result<CharClass, String> {
+        let inner = class.strip_prefix('[').and_then(|c| c.strip_suffix(']'))
+            .ok_or_else(|| format!("Bad character class: {}", class))?;
+        let (negated, inner) = match inner.strip_prefix('^') {
+            Some(rest) => (true, rest),
+            None => (false, inner),
+        };
+        // chars paired with whether they were escaped
+        let mut chars = Vec::new();
+        let mut input = inner.chars();
+        while let Some(ch) = input.next() {
+            chars.push(match ch {
+                '\\' => (input.next().ok_or_else(|| format!("Bad character class: {}", class))?, true),
+                ch => (ch, false),
+            });
+        }
+        let mut ranges = Vec::new();
+        let mut idx = 0;
+        while idx < chars.len() {
+            match chars.get(idx + 1..idx + 3) {
+                Some([('-', false), (hi, _)]) => {
+                    let lo = chars[idx].0;
+                    if lo > *hi {
+                        return Err(format!("Bad range {}-{} in character class: {}", lo, hi, class));
+                    }
+                    ranges.push((lo, *hi));
+                    idx += 3;
+                },
+                _ => {
+                    ranges.push((chars[idx].0, chars[idx].0));
+                    idx += 1;
+                },
+            }
+        }
+        if ranges.is_empty() {
+            return Err(format!("Empty character class: {}", class));
+        }
+        Ok(CharClass{negated, ranges})
+    }
+
+    pub(crate) fn matches(&self, ch: char) -> bool {
+        self.ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&ch)) != self.negated
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::EbnfTokenizer;
+    use super::{CharClass, EbnfTokenizer};
 
     #[test]
     fn simple() {
@@ -116,4 +227,68 @@ mod tests {
             assert_eq!(token, expected[idx]);
         }
     }
+
+    #[test]
+    fn char_classes() {
+        let input = "id := [a-zA-Z_] { [a-z0-9\\]] } [ x ] ;";
+        let tokens: Vec<_> = EbnfTokenizer::new(input.chars()).char_classes(true).collect();
+        assert_eq!(tokens, vec!["id", ":=", "[a-zA-Z_]", "{", "[a-z0-9\\]]", "}",
+                                "[", "x", "]", ";"]);
+        // off by default
+        let tokens: Vec<_> = EbnfTokenizer::new("[a]".chars()).collect();
+        assert_eq!(tokens, vec!["[", "a", "]"]);
+        let error = |input: &str| EbnfTokenizer::new(input.chars()).char_classes(true)
+            .next_result().unwrap_err();
+        assert_eq!(error("[a-z"), "Unfinished character class missing ]");
+        assert_eq!(error("[a-\n]"), "Unfinished character class missing ]");
+        assert_eq!(error("[z-a]"), "Bad range z-a in character class: [z-a]");
+        assert_eq!(error("[^]"), "Empty character class: [^]");
+
+        let class = CharClass::parse("[a-c_\\-]").unwrap();
+        assert!("abc_-".chars().all(|c| class.matches(c)));
+        assert!(!"dA0\\".chars().any(|c| class.matches(c)));
+        let class = CharClass::parse("[^0-9]").unwrap();
+        assert!(class.matches('x') && !class.matches('5'));
+        // a trailing '-' is literal
+        assert!(CharClass::parse("[a-]").unwrap().matches('-'));
+    }
+
+    #[test]
+    fn unfinished_input() {
+        // nothing is left behind after an error
+        let mut tokenizer = EbnfTokenizer::new("a \"open".chars());
+        assert_eq!(tokenizer.next_result(), Ok(Some("a".to_string())));
+        assert_eq!(tokenizer.next_result(), Err("Unfinished string missing close quote".to_string()));
+        assert_eq!(tokenizer.next_result(), Ok(None));
+        // a comment can end the input
+        let tokens: Vec<_> = EbnfTokenizer::new("'x' # end".chars()).collect();
+        assert_eq!(tokens, vec!["'", "x", "'"]);
+    }
+
+    #[test]
+    fn hyphenated_names() {
+        let tokens: Vec<_> = EbnfTokenizer::new("day-of-week := @named-month ;".chars())
+            .collect();
+        assert_eq!(tokens, vec!["day-of-week", ":=", "@named-month", ";"]);
+        assert_eq!(EbnfTokenizer::new("-month".chars()).next_result(),
+                   Err("Unexpected char: -".to_string()));
+        assert_eq!(EbnfTokenizer::new("month- ".chars()).next_result(),
+                   Err("Unexpected char: -".to_string()));
+    }
+
+    #[test]
+    fn spans() {
+        let mut tokenizer = EbnfTokenizer::new("ab := 'x\\'y' # c\n | [ z ]".chars());
+        let mut spans = Vec::new();
+        while let Ok(Some(token)) = tokenizer.next_result() {
+            spans.push((token, tokenizer.span()));
+        }
+        let expected = [("ab", (0, 2)), (":=", (3, 5)), ("'", (6, 7)), ("x\\'y", (7, 11)),
+                        ("'", (11, 12)), ("|", (18, 19)), ("[", (20, 21)), ("z", (22, 23)),
+                        ("]", (24, 25))];
+        assert_eq!(spans, expected.map(|(token, span)| (token.to_string(), span)));
+        let mut tokenizer = EbnfTokenizer::new("a := b-;".chars());
+        assert!(tokenizer.by_ref().take(2).count() == 2 && tokenizer.next_result().is_err());
+        assert_eq!(tokenizer.span(), (5, 7));
+    }
 }
@@ -1,14 +1,22 @@
-#![deny(warnings)]
+#![cfg_attr(feature = "strict", deny(warnings))]
 
 mod earley;
-pub use earley::{EarleyParser, EarleyForest, Grammar, GrammarBuilder};
+pub use earley::{EarleyParser, EarleyForest, Grammar, GrammarBuilder, ParseContext, Rule};
+pub use earley::{ParseStats, RuleCoverage, RuleStats, SemAction, SpanInfo, Subtree, TreeRewriter};
+#[doc(hidden)]
+pub use earley::boxed_action;
+pub use earley::{ComplexityBound, GrammarTokenizer, GrammarTokens, ParseOrLexError, TokenCursor};
+pub use earley::{fold, CANCELLED, CANCEL_CHECK_EVERY};
 
 mod ebnf_tokenizer;
 mod ebnf;
-pub use ebnf::EbnfGrammarParser;
+pub use ebnf::{EbnfGrammarParser, GrammarError, ParserBuilder};
+
+pub mod testing;
+pub mod repl;
 
 mod parsers;
-pub use parsers::{sexpr_parser, Sexpr};
+pub use parsers::{sexpr_parser, subtree_parser, Sexpr};
 
 #[cfg(test)]
 mod ebnf_test;
@@ -1,6 +1,4 @@
-#![deny(warnings)]
-
-use crate::earley::{EarleyParser, EarleyForest, Grammar};
+use crate::earley::{EarleyParser, EarleyForest, Grammar, Subtree};
 use std::fmt::Debug;
 
 #[derive(Clone,Debug)]
@@ -61,3 +59,20 @@ pub fn sexpr_parser<InputIter>(grammar: Grammar)
     let parser = EarleyParser::new(grammar);
     Ok(move |tokenizer| tree_builder.eval_all(&parser.parse(tokenizer)?))
 }
+
+// Build a Subtree for every parse, nodes are labeled with the rule they match
+pub fn subtree_parser<InputIter>(grammar: Grammar)
+    -> Result<impl Fn(InputIter) -> Result<Vec<Subtree>, String>, String>
+        where InputIter: Iterator, InputIter::Item: AsRef<str> + std::fmt::Debug
+{
+    let mut tree_builder = EarleyForest::new(
+        |sym, tok| Subtree::Node(sym.to_string(), tok.to_string()));
+
+    for rule in grammar.rules.iter().map(|r| r.to_string()) {
+        tree_builder.action(
+            &rule.clone(), move |nodes| Subtree::SubT(rule.clone(), nodes));
+    }
+
+    let parser = EarleyParser::new(grammar);
+    Ok(move |tokenizer| tree_builder.eval_all(&parser.parse(tokenizer)?))
+}
@@ -0,0 +1,252 @@
+// Command handling for examples/earlgrey-repl.rs, kept here so it can be
+// tested without a terminal. Each input line is parsed with the grammar,
+// lines starting with ':' are commands, see `GrammarRepl::handle`.
+
+use crate::earley::{EarleyForest, EarleyParser, Grammar, Subtree};
+use crate::ebnf::{ebnf_grammar, EbnfGrammarParser};
+use crate::ebnf_tokenizer::EbnfTokenizer;
+use crate::testing::subtree_forest;
+use std::path::PathBuf;
+
+// Tokens of an EBNF source and the line (1 based) each is on. Rules can
+// span lines, strings and comments can't.
+fn grammar_tokens(source: &str) -> Result<Vec<(String, usize)>, String> {
+    let mut tokens = Vec::new();
+    for (idx, line) in source.lines().enumerate() {
+        let mut tokenizer = EbnfTokenizer::new(line.chars().chain(Some('\n')));
+        while let Some(token) = tokenizer.next_result()
+                .map_err(|e| format!("line {}: {}", idx + 1, e))? {
+            tokens.push((token, idx + 1));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Build the EBNF grammar in `source`, same as `EbnfGrammarParser` but
+/// errors point at the line they're at, eg: "line 3: unexpected \"|\"".
+pub fn load_grammar(source: &str, start: &str) -> Result<Grammar, String> {
+    let tokens = grammar_tokens(source)?;
+    let (parsed, stats) = EarleyParser::new(ebnf_grammar())
+        .parse_chart(tokens.iter().map(|(token, _)| token));
+    if parsed.is_err() {
+        return Err(match stats.dead_at() {
+            Some(idx) => format!("line {}: unexpected {:?}", tokens[idx].1, tokens[idx].0),
+            None => format!("line {}: incomplete grammar, expected one of: {}",
+                            tokens.last().map_or(1, |(_, line)| *line),
+                            stats.expected.join(" ")),
+        });
+    }
+    // the syntax is fine, what's left are errors like a missing start symbol
+    EbnfGrammarParser::new(source, start).into_grammar().map_err(String::from)
+}
+
+pub struct GrammarRepl {
+    // where `:reload` reads the grammar from
+    path: Option<PathBuf>,
+    start: String,
+    parser: EarleyParser,
+    forest: EarleyForest<'static, Subtree>,
+    // tokens of the last parsed input
+    last: Option<Vec<String>>,
+}
+
+impl GrammarRepl {
+    pub fn new(source: &str, start: &str) -> Result<Self, String> {
+        let grammar = load_grammar(source, start)?;
+        Ok(GrammarRepl{
+            path: None,
+            start: start.to_string(),
+            forest: subtree_forest(&grammar),
+            parser: EarleyParser::new(grammar),
+            last: None,
+        })
+    }
+
+    /// Load the grammar in `path`, it's read again on `:reload`
+    pub fn open(path: impl Into<PathBuf>, start: &str) -> Result<Self, String> {
+        let path = path.into();
+        let source = std::fs::read_to_string(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(GrammarRepl{path: Some(path), ..GrammarRepl::new(&source, start)?})
+    }
+
+    /// Run a command or parse an input line, returns what to print:
+    /// - `:reload` read the grammar file again, the old one stays on errors
+    /// - `:all` every tree of the last input
+    /// - `:chart` the Earley chart of the last input
+    /// - `:expect tokens..` terminals that can follow a prefix
+    /// - anything else is split on whitespace and parsed
+    pub fn handle(&mut self, line: &str) -> String {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            None => String::new(),
+            Some(":reload") => self.reload(),
+            Some(":all") => self.all(),
+            Some(":chart") => self.chart(),
+            Some(":expect") => self.expect(words.map(String::from).collect()),
+            Some(cmd) if cmd.starts_with(':') => format!(
+                "unknown command {}, try :reload :all :chart :expect", cmd),
+            Some(_) => self.parse(line.split_whitespace().map(String::from).collect()),
+        }
+    }
+
+    fn reload(&mut self) -> String {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => return "nothing to reload, the grammar wasn't read from a file".to_string(),
+        };
+        match GrammarRepl::open(path, &self.start) {
+            Ok(repl) => {
+                *self = repl;
+                format!("reloaded {} rules", self.parser.grammar.rules.len())
+            }
+            Err(e) => format!("grammar error, keeping the previous one: {}", e),
+        }
+    }
+
+    fn parse(&mut self, tokens: Vec<String>) -> String {
+        let (parsed, stats) = self.parser.parse_chart(tokens.iter());
+        let out = match parsed {
+            Ok(trees) => match self.forest.eval(&trees) {
+                Ok(tree) => format!("{}\n{}", trees, tree),
+                Err(e) => e,
+            },
+            Err(_) => match stats.dead_at() {
+                Some(idx) => format!(
+                    "parse error at token {} {:?}, expected one of: {}", idx, tokens[idx],
+                    self.parser.parse_chart(tokens[..idx].iter()).1.expected.join(" ")),
+                None => format!("incomplete input, expected one of: {}", stats.expected.join(" ")),
+            },
+        };
+        self.last = Some(tokens);
+        out
+    }
+
+    fn all(&self) -> String {
+        let tokens = match &self.last {
+            Some(tokens) => tokens,
+            None => return "no input yet".to_string(),
+        };
+        let trees = self.parser.parse(tokens.iter())
+            .and_then(|trees| self.forest.eval_all(&trees));
+        match trees {
+            Ok(trees) => trees.iter().enumerate()
+                .map(|(idx, tree)| format!("=== Tree {} ===\n{}", idx, tree))
+                .collect::<Vec<_>>().join("\n"),
+            Err(e) => e,
+        }
+    }
+
+    fn chart(&self) -> String {
+        let tokens = match &self.last {
+            Some(tokens) => tokens,
+            None => return "no input yet".to_string(),
+        };
+        let (_, stats) = self.parser.parse_chart(tokens.iter());
+        let mut out = Vec::new();
+        for (idx, items) in stats.chart.iter().enumerate() {
+            match idx {
+                0 => out.push("=== StateSet 0 ===".to_string()),
+                _ => out.push(format!("=== StateSet {} after {:?} ===", idx, tokens[idx - 1])),
+            }
+            out.extend(items.iter().cloned());
+        }
+        out.join("\n")
+    }
+
+    fn expect(&self, prefix: Vec<String>) -> String {
+        let (parsed, stats) = self.parser.parse_chart(prefix.iter());
+        if let Some(idx) = stats.dead_at() {
+            return format!("no completions, token {} {:?} doesn't parse", idx, prefix[idx]);
+        }
+        let complete = if parsed.is_ok() { " (the prefix is complete)" } else { "" };
+        match stats.expected.is_empty() {
+            true => format!("nothing can follow{}", complete),
+            false => format!("{}{}", stats.expected.join(" "), complete),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::{load_grammar, GrammarRepl};
+
+    const GRAMMAR: &str = "
+        # sums of ones and twos
+        S := S '+' N | N ;
+        N := 'one' | 'two' | 'one' 'one' ;
+    ";
+
+    #[test]
+    fn grammar_errors() {
+        assert!(load_grammar(GRAMMAR, "S").is_ok());
+        assert_eq!(load_grammar("S := 'a' ;\nT := | 'b' ;", "S").unwrap_err(),
+                   "line 2: unexpected \"|\"");
+        assert_eq!(load_grammar("S := 'a'\n  | 'b'", "S").unwrap_err(),
+                   "line 2: incomplete grammar, expected one of: \" ' ( ; <CharClass> <Id> [ { |");
+        assert_eq!(load_grammar("S := 'a ;", "S").unwrap_err(),
+                   "line 1: Unfinished string missing close quote");
+        assert_eq!(load_grammar("S := 'a' ;", "T").unwrap_err(), "Missing start Symbol: T");
+    }
+
+    #[test]
+    fn commands() {
+        let mut repl = GrammarRepl::new(GRAMMAR, "S").unwrap();
+        assert_eq!(repl.handle(":all"), "no input yet");
+        assert_eq!(repl.handle("two + one"), "1 derivation from 1 root, unambiguous\n\
+                                              S -> S + N\n  S -> N\n    N -> two\n      \
+                                              two \"two\"\n  + \"+\"\n  N -> one\n    \
+                                              one \"one\"");
+        assert_eq!(repl.handle("  "), "");
+        assert_eq!(repl.handle("one one one"), "parse error at token 2 \"one\", expected one of: +");
+        assert_eq!(repl.handle("two +"), "incomplete input, expected one of: one two");
+        assert_eq!(repl.handle(":chart"), "=== StateSet 0 ===\n\
+                                           (0 - 0) S ->  \u{b7} S + N #bp: 0\n\
+                                           (0 - 0) S ->  \u{b7} N #bp: 0\n\
+                                           (0 - 0) N ->  \u{b7} one #bp: 0\n\
+                                           (0 - 0) N ->  \u{b7} two #bp: 0\n\
+                                           (0 - 0) N ->  \u{b7} one one #bp: 0\n\
+                                           === StateSet 1 after \"two\" ===\n\
+                                           (0 - 1) N -> two \u{b7}  #bp: 1\n\
+                                           (0 - 1) S -> N \u{b7}  #bp: 1\n\
+                                           (0 - 1) S -> S \u{b7} + N #bp: 1\n\
+                                           === StateSet 2 after \"+\" ===\n\
+                                           (2 - 2) N ->  \u{b7} one #bp: 0\n\
+                                           (2 - 2) N ->  \u{b7} two #bp: 0\n\
+                                           (2 - 2) N ->  \u{b7} one one #bp: 0\n\
+                                           (0 - 2) S -> S + \u{b7} N #bp: 1");
+        assert_eq!(repl.handle(":expect one"), "+ one (the prefix is complete)");
+        assert_eq!(repl.handle(":expect"), "one two");
+        assert_eq!(repl.handle(":expect two two"), "no completions, token 1 \"two\" doesn't parse");
+        assert_eq!(repl.handle(":nope"), "unknown command :nope, try :reload :all :chart :expect");
+        assert_eq!(repl.handle(":reload"), "nothing to reload, the grammar wasn't read from a file");
+    }
+
+    #[test]
+    fn all_trees() {
+        let mut repl = GrammarRepl::new("S := S S | 'a' ;", "S").unwrap();
+        assert!(repl.handle("a a a").starts_with("2 derivations from 1 root, ambiguous\n"));
+        let all = repl.handle(":all");
+        assert_eq!(all.matches("=== Tree").count(), 2);
+        assert!(all.starts_with("=== Tree 0 ===\nS -> S S\n"));
+    }
+
+    #[test]
+    fn reload() {
+        let path = std::env::temp_dir().join(format!("earlgrey-repl-{}.ebnf", std::process::id()));
+        std::fs::write(&path, "S := 'a' ;").unwrap();
+        let mut repl = GrammarRepl::open(&path, "S").unwrap();
+        assert!(repl.handle("b").starts_with("parse error"));
+        std::fs::write(&path, "S := 'a' | 'b' ;").unwrap();
+        assert_eq!(repl.handle(":reload"), "reloaded 2 rules");
+        assert!(repl.handle("b").starts_with("1 derivation"));
+        std::fs::write(&path, "S := 'a' |\n| 'b' ;").unwrap();
+        assert_eq!(repl.handle(":reload"),
+                   "grammar error, keeping the previous one: line 2: unexpected \"|\"");
+        assert!(repl.handle("b").starts_with("1 derivation"));
+        std::fs::remove_file(&path).unwrap();
+        assert!(GrammarRepl::open(&path, "S").is_err());
+    }
+}
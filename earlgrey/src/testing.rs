@@ -0,0 +1,365 @@
+// Conformance harness for grammars: generate random sentences and check
+// the parser round-trips them, or assert tables of inputs a parser must
+// accept or reject. Meant to be called from test suites.
+
+use crate::earley::{EarleyForest, EarleyParser, Grammar, Subtree, Symbol};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+type Tokenizer = Box<dyn Fn(&str) -> Vec<String>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundtripFailure {
+    // Generated sentence that failed a check
+    pub sentence: Vec<String>,
+    // Smallest sentence found by removing tokens that still fails
+    pub minimized: Vec<String>,
+    pub reason: String,
+}
+
+impl fmt::Display for RoundtripFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\n  sentence: {}\n  minimized: {}",
+               self.reason, self.sentence.join(" "), self.minimized.join(" "))
+    }
+}
+
+// Small xorshift PRNG, keeps the crate free of dependencies
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed ^ 0x9e37_79b9_7f4a_7c15 | 1)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+// Past these limits derivations pick the rules that terminate the fastest.
+// Keeps sentences short, highly ambiguous grammars blow up otherwise.
+const MAX_DEPTH: usize = 8;
+const MAX_TOKENS: usize = 8;
+
+// None if some non-terminal in the spec has no known height (yet)
+fn rule_height(heights: &HashMap<String, usize>, spec: &[Rc<Symbol>]) -> Option<usize> {
+    spec.iter()
+        .map(|s| match &**s {
+            Symbol::Term(_, _) => Some(0),
+            Symbol::NonTerm(name) => heights.get(name).cloned(),
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(|h| 1 + h.into_iter().max().unwrap_or(0))
+}
+
+struct SentenceGen<'g> {
+    grammar: &'g Grammar,
+    samples: HashMap<String, Vec<String>>,
+    // Minimum derivation height for each non-terminal
+    heights: HashMap<String, usize>,
+}
+
+impl<'g> SentenceGen<'g> {
+    fn new(grammar: &'g Grammar, samples: &[(&str, &[&str])]) -> Result<Self, String> {
+        let mut samples: HashMap<String, Vec<String>> = samples.iter()
+            .map(|(term, lexemes)| (term.to_string(),
+                                    lexemes.iter().map(|l| l.to_string()).collect()))
+            .collect();
+        // Terminals that match their own name (eg: "+") need no samples
+        for symbol in grammar.rules.iter().flat_map(|r| r.spec.iter()) {
+            if symbol.is_terminal() && !samples.contains_key(symbol.name()) {
+                if !symbol.matches(symbol.name()) {
+                    return Err(format!("Missing samples for terminal: {}", symbol.name()));
+                }
+                samples.insert(symbol.name().to_string(), vec![symbol.name().to_string()]);
+            }
+        }
+        // Fixpoint: a rule's height is 1 + its tallest symbol
+        let mut heights = HashMap::new();
+        loop {
+            let mut changed = false;
+            for rule in &grammar.rules {
+                if let Some(h) = rule_height(&heights, &rule.spec) {
+                    if heights.get(&rule.head).is_none_or(|prev| h < *prev) {
+                        heights.insert(rule.head.clone(), h);
+                        changed = true;
+                    }
+                }
+            }
+            if !changed { break; }
+        }
+        if !heights.contains_key(&grammar.start) {
+            return Err(format!("Start symbol never terminates: {}", grammar.start));
+        }
+        Ok(SentenceGen{grammar, samples, heights})
+    }
+
+    fn generate(&self, rng: &mut Rng, symbol: &Symbol, depth: usize, out: &mut Vec<String>) {
+        if symbol.is_terminal() {
+            let samples = &self.samples[symbol.name()];
+            out.push(samples[rng.below(samples.len())].clone());
+            return;
+        }
+        let mut rules: Vec<_> = self.grammar.rules.iter()
+            .filter(|r| r.head == symbol.name())
+            .filter_map(|r| rule_height(&self.heights, &r.spec).map(|h| (r, h)))
+            .collect();
+        if depth >= MAX_DEPTH || out.len() >= MAX_TOKENS {
+            let min = rules.iter().map(|(_, h)| *h).min().unwrap_or(0);
+            rules.retain(|(_, h)| *h == min);
+        }
+        let (rule, _) = rules[rng.below(rules.len())];
+        for s in &rule.spec {
+            self.generate(rng, s, depth + 1, out);
+        }
+    }
+}
+
+#[derive(PartialEq)]
+enum Check { Parse, Yield, Eval }
+
+fn check(parser: &EarleyParser, forest: &EarleyForest<Subtree>, sentence: &[String])
+        -> Result<(), (Check, String)> {
+    let ptrees = parser.parse(sentence.iter()).map_err(|e| (Check::Parse, e))?;
+    let trees = forest.eval_all(&ptrees).map_err(|e| (Check::Eval, e))?;
+    for tree in &trees {
+        let leaves = tree.leaves();
+        if leaves != sentence {
+            return Err((Check::Yield, format!("Tree yields: {}", leaves.join(" "))));
+        }
+    }
+    let tree = forest.eval(&ptrees).map_err(|e| (Check::Eval, e))?;
+    if !trees.contains(&tree) {
+        return Err((Check::Eval, "eval result not among eval_all results".to_string()));
+    }
+    Ok(())
+}
+
+// Greedily drop tokens while the sentence keeps failing the same check
+fn shrink(parser: &EarleyParser, forest: &EarleyForest<Subtree>,
+          sentence: &[String], failed: &Check) -> Vec<String> {
+    let mut current = sentence.to_vec();
+    let mut idx = 0;
+    while idx < current.len() && current.len() > 1 {
+        let mut candidate = current.clone();
+        candidate.remove(idx);
+        match check(parser, forest, &candidate) {
+            Err((kind, _)) if kind == *failed => current = candidate,
+            _ => idx += 1,
+        }
+    }
+    current
+}
+
+// Builds a Subtree for each parse, same as `subtree_parser`
+pub(crate) fn subtree_forest(grammar: &Grammar) -> EarleyForest<'static, Subtree> {
+    let mut forest = EarleyForest::new(
+        |sym, tok| Subtree::Node(sym.to_string(), tok.to_string()));
+    for rule in grammar.rules.iter().map(|r| r.to_string()) {
+        forest.action(&rule.clone(), move |nodes| Subtree::SubT(rule.clone(), nodes));
+    }
+    forest
+}
+
+/// Generates `n` random sentences from `grammar` and checks that each parses,
+/// that every parse tree yields back the input tokens and that `eval` picks
+/// one of the trees `eval_all` returns. Terminals are filled in from
+/// `samples` (eg: `("Number", &["0", "42"])`), those matching their own name
+/// like `"+"` can be left out. Same `seed` generates the same sentences.
+pub fn roundtrip(grammar: Grammar, samples: &[(&str, &[&str])], n: usize, seed: u64)
+        -> Result<(), RoundtripFailure> {
+    let failure = |reason| RoundtripFailure{
+        sentence: Vec::new(), minimized: Vec::new(), reason};
+    let generator = SentenceGen::new(&grammar, samples).map_err(failure)?;
+    let start = Symbol::NonTerm(grammar.start.clone());
+    let forest = subtree_forest(&grammar);
+    let parser = EarleyParser::new(grammar.clone());
+
+    let mut rng = Rng::new(seed);
+    for _ in 0..n {
+        let mut sentence = Vec::new();
+        generator.generate(&mut rng, &start, 0, &mut sentence);
+        if let Err((kind, reason)) = check(&parser, &forest, &sentence) {
+            let minimized = shrink(&parser, &forest, &sentence, &kind);
+            return Err(RoundtripFailure{sentence, minimized, reason});
+        }
+    }
+    Ok(())
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+/// Table driven checks for a parser, each call panics listing every input
+/// that didn't behave, eg:
+/// `GrammarTester::new(parser).accepts(&["1 + 2"]).rejects(&["1 +"])`.
+/// Inputs are split on whitespace unless a `tokenizer` is given.
+pub struct GrammarTester {
+    parser: EarleyParser,
+    forest: EarleyForest<'static, Subtree>,
+    tokenize: Tokenizer,
+}
+
+impl GrammarTester {
+    pub fn new(parser: EarleyParser) -> Self {
+        let forest = subtree_forest(&parser.grammar);
+        GrammarTester{parser, forest, tokenize: Box::new(
+            |input| input.split_whitespace().map(|t| t.to_string()).collect())}
+    }
+
+    /// Splits inputs into tokens with `tokenize` for the following checks
+    pub fn tokenizer(mut self, tokenize: impl Fn(&str) -> Vec<String> + 'static) -> Self {
+        self.tokenize = Box::new(tokenize);
+        self
+    }
+
+    fn parse(&self, input: &str) -> Result<Vec<Subtree>, String> {
+        let tokens = (self.tokenize)(input);
+        let ptrees = self.parser.parse(tokens.iter())?;
+        self.forest.eval_all(&ptrees)
+    }
+
+    fn report(failures: Vec<String>) {
+        if !failures.is_empty() {
+            panic!("GrammarTester: {} failed\n{}", failures.len(), failures.join("\n"));
+        }
+    }
+
+    /// Every input must parse
+    pub fn accepts(self, inputs: &[&str]) -> Self {
+        Self::report(inputs.iter()
+            .filter_map(|input| self.parse(input).err()
+                .map(|e| format!("expected to accept {:?}: {}", input, e)))
+            .collect());
+        self
+    }
+
+    /// No input may parse
+    pub fn rejects(self, inputs: &[&str]) -> Self {
+        Self::report(inputs.iter()
+            .filter_map(|input| self.parse(input).ok()
+                .map(|trees| format!("expected to reject {:?}, parsed as:\n{}", input,
+                                     trees.iter().map(|t| t.to_string())
+                                         .collect::<Vec<_>>().join("\n--\n"))))
+            .collect());
+        self
+    }
+
+    /// `input` parses and every parse tree has `leaves` as its yield
+    pub fn tree_yield(self, input: &str, leaves: &[&str]) -> Self {
+        let failures = match self.parse(input) {
+            Err(e) => vec![format!("expected to accept {:?}: {}", input, e)],
+            Ok(trees) => trees.iter()
+                .filter(|tree| tree.leaves() != leaves)
+                .map(|tree| format!("yield of {:?}\n  expected: {:?}\n  got: {:?}\n{}",
+                                    input, leaves, tree.leaves(), tree))
+                .collect(),
+        };
+        Self::report(failures);
+        self
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::{roundtrip, GrammarTester};
+    use crate::earley::{EarleyParser, GrammarBuilder};
+    use crate::ebnf::{ebnf_grammar, EbnfGrammarParser};
+
+    #[test]
+    fn ebnf_meta_grammar() {
+        roundtrip(ebnf_grammar(), &[
+            ("<Id>", &["expr", "Number", "x_1"]),
+            ("<Chars>", &["+", "hello", "("]),
+            ("@<Tag>", &["@tag"]),
+            ("<CharClass>", &["[a-z]"]),
+        ], 200, 42).unwrap();
+    }
+
+    #[test]
+    fn arithmetic() {
+        let grammar = EbnfGrammarParser::new(r#"
+            expr   := term | expr "+" term | expr "-" term ;
+            term   := factor | term "*" factor ;
+            factor := Number | "(" expr ")" | "-" factor ;
+        "#, "expr")
+          .plug_terminal("Number", |n| n.chars().all(|c| c.is_ascii_digit()))
+          .into_grammar().unwrap();
+        roundtrip(grammar, &[("Number", &["0", "42"])], 200, 7).unwrap();
+        // ambiguous: E -> E + E | n
+        let grammar = GrammarBuilder::default()
+          .nonterm("E")
+          .literal("+")
+          .terminal("n", |n| n == "1")
+          .rule("E", &["E", "+", "E"])
+          .rule("E", &["n"])
+          .into_grammar("E").unwrap();
+        roundtrip(grammar, &[("n", &["1"])], 10, 1).unwrap();
+    }
+
+    #[test]
+    fn failures() {
+        let grammar = GrammarBuilder::default()
+          .nonterm("S")
+          .literal("a")
+          .terminal("Num", |n| n.chars().all(|c| c.is_ascii_digit()))
+          .rule("S", &["a", "Num", "a"])
+          .into_grammar("S").unwrap();
+        assert_eq!(roundtrip(grammar.clone(), &[], 1, 0).unwrap_err().reason,
+                   "Missing samples for terminal: Num");
+        // a bogus sample can't be parsed, any shorter sentence fails too
+        let failure = roundtrip(grammar, &[("Num", &["x"])], 1, 0).unwrap_err();
+        assert_eq!(failure.sentence, vec!["a", "x", "a"]);
+        assert_eq!(failure.minimized, vec!["a"]);
+        assert_eq!(failure.reason, "Parse Error: No Rule completes");
+    }
+
+    fn sums() -> EarleyParser {
+        EarleyParser::new(EbnfGrammarParser::new(r#"
+            expr := Number | expr "+" Number ;
+        "#, "expr")
+          .plug_terminal("Number", |n| n.chars().all(|c| c.is_ascii_digit()))
+          .into_grammar().unwrap())
+    }
+
+    #[test]
+    fn tester_tables() {
+        GrammarTester::new(sums())
+            .accepts(&["3 + 4", "1", "1 + 2 + 3"])
+            .rejects(&["+ 3", "3 +", "", "3 4"])
+            .tree_yield("3 + 4", &["3", "+", "4"]);
+        // one char per token
+        GrammarTester::new(sums())
+            .tokenizer(|input| input.chars().filter(|c| !c.is_whitespace())
+                                    .map(|c| c.to_string()).collect())
+            .accepts(&["3+4", "1 +2"])
+            .rejects(&["34"])
+            .tree_yield("1+2", &["1", "+", "2"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "GrammarTester: 2 failed\n\
+                               expected to accept \"3 +\": Parse Error: No Rule completes\n\
+                               expected to accept \"+\": Parse Error: No Rule completes")]
+    fn tester_accepts_failure() {
+        GrammarTester::new(sums()).accepts(&["3 +", "1", "+"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected to reject \"3\", parsed as:\nexpr -> Number\n  Number \"3\"")]
+    fn tester_rejects_failure() {
+        GrammarTester::new(sums()).rejects(&["3"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "yield of \"3\"\n  expected: [\"4\"]\n  got: [\"3\"]")]
+    fn tester_yield_failure() {
+        GrammarTester::new(sums()).tree_yield("3", &["4"]);
+    }
+}
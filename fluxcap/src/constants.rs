@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 pub fn weekday(d: &str) -> Option<u32> {
     match d {
         "sunday"    | "sundays"    | "sun" => Some(0),
@@ -56,9 +54,41 @@ pub fn short_ordinal(n: &str) -> Option<u32> {
     }
 }
 
+// Four digit year, 2019 => 2019. Unlike short_ordinal there's no suffix,
+// and signs or padding (+2019, 0999) aren't years.
+pub fn year(y: &str) -> Option<i32> {
+    if y.len() != 4 || !y.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let year = y.parse().ok()?;
+    (1000..2200).contains(&year).then_some(year)
+}
+
+// ISO-8601 calendar date, 2024-03-15 => (2024, 3, 15)
+pub fn iso_date(d: &str) -> Option<(i32, u32, u32)> {
+    let (year, month) = iso_month(d.get(..7)?)?;
+    let day = match &d[7..] {
+        day if day.len() == 3 && day.starts_with('-') => day[1..].parse().ok()?,
+        _ => return None
+    };
+    chrono::NaiveDate::from_ymd_opt(year, month, day).map(|_| (year, month, day))
+}
+
+// ISO-8601 year and month, 2024-03 => (2024, 3)
+pub fn iso_month(m: &str) -> Option<(i32, u32)> {
+    let digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    match m.split_once('-') {
+        Some((y, mo)) if y.len() == 4 && mo.len() == 2 && digits(y) && digits(mo) => {
+            let month = mo.parse().ok()?;
+            (1..=12).contains(&month).then(|| (y.parse().unwrap(), month))
+        }
+        _ => None
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{ordinal, short_ordinal};
+    use super::{iso_date, iso_month, ordinal, short_ordinal, year};
     #[test]
     fn test_short_ordinal() {
         assert_eq!(short_ordinal("22nd"), Some(22));
@@ -70,4 +100,23 @@ mod tests {
         assert_eq!(ordinal("twenty-fourth"), Some(24));
         assert_eq!(ordinal("twelveth"), Some(12));
     }
+    #[test]
+    fn test_year() {
+        assert_eq!(year("2019"), Some(2019));
+        assert_eq!(year("2019th"), None);
+        assert_eq!(short_ordinal("2019"), None);
+        assert_eq!(year("+201"), None);
+        assert_eq!(year("-2019"), None);
+        assert_eq!(year("0999"), None);
+        assert_eq!(year("2200"), None);
+    }
+    #[test]
+    fn test_iso_date() {
+        assert_eq!(iso_date("2024-03-15"), Some((2024, 3, 15)));
+        assert_eq!(iso_date("2024-02-30"), None);
+        assert_eq!(iso_date("2024-03-5"), None);
+        assert_eq!(iso_month("2024-03"), Some((2024, 3)));
+        assert_eq!(iso_month("2024-13"), None);
+        assert_eq!(iso_month("24-03"), None);
+    }
 }
@@ -0,0 +1,135 @@
+use kronos::{Grain, Range};
+use std::cmp::Ordering;
+use std::fmt;
+
+// A length of time, eg: "3 days" or "an hour and a half". Calendar grains
+// (months and longer) vary in length so they're kept apart from the rest,
+// which are an exact number of seconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TDuration {
+    pub months: i64,
+    pub seconds: i64,
+}
+
+// Whole units used to display durations, largest first
+const CALENDAR_UNITS: [(&str, u64); 2] = [("year", 12), ("month", 1)];
+const EXACT_UNITS: [(&str, u64); 5] = [
+    ("week", 604800), ("day", 86400), ("hour", 3600), ("minute", 60), ("second", 1)];
+
+impl TDuration {
+    pub fn new(count: i64, grain: Grain) -> TDuration {
+        let months = |months| TDuration{months: count * months, seconds: 0};
+        let seconds = |seconds| TDuration{months: 0, seconds: count * seconds};
+        match grain {
+            Grain::Second => seconds(1),
+            Grain::Minute => seconds(60),
+            Grain::Hour => seconds(3600),
+            Grain::Day => seconds(86400),
+            Grain::Week => seconds(604800),
+            Grain::Month => months(1),
+            Grain::Quarter => months(3),
+            Grain::Half => months(6),
+            Grain::Year => months(12),
+            Grain::Lustrum => months(60),
+            Grain::Decade => months(120),
+            Grain::Century => months(1200),
+            Grain::Millenium => months(12000),
+        }
+    }
+
+    // Move the range later by the duration, keeping its grain
+    pub fn shift(&self, range: &Range) -> Result<Range, String> {
+        Ok(Range{start: self.after(range.start)?, end: self.after(range.end)?, grain: range.grain})
+    }
+
+    // Make the range longer by the duration, its start stays
+    pub fn extend(&self, range: &Range) -> Result<Range, String> {
+        Ok(Range{start: range.start, end: self.after(range.end)?, grain: range.grain})
+    }
+
+    fn after(&self, t: chrono::NaiveDateTime) -> Result<chrono::NaiveDateTime, String> {
+        let too_long = || format!("Can't shift {} by {}", t, self);
+        let months = i32::try_from(self.months).map_err(|_| too_long())?;
+        let t = kronos::shift_datetime(t, Grain::Month, months);
+        // chrono::Duration::seconds panics past its range
+        if self.seconds.unsigned_abs() > chrono::Duration::max_value().num_seconds().unsigned_abs() {
+            return Err(too_long());
+        }
+        t.checked_add_signed(chrono::Duration::seconds(self.seconds)).ok_or_else(too_long)
+    }
+}
+
+impl std::ops::Add for TDuration {
+    type Output = TDuration;
+    fn add(self, other: TDuration) -> TDuration {
+        TDuration{months: self.months + other.months, seconds: self.seconds + other.seconds}
+    }
+}
+
+// Months don't have a fixed length, a duration is longer than another only
+// if neither of its parts is shorter. "a month" vs "30 days" don't compare.
+impl PartialOrd for TDuration {
+    fn partial_cmp(&self, other: &TDuration) -> Option<Ordering> {
+        match (self.months.cmp(&other.months), self.seconds.cmp(&other.seconds)) {
+            (months, seconds) if months == seconds => Some(months),
+            (Ordering::Equal, other) | (other, Ordering::Equal) => Some(other),
+            _ => None,
+        }
+    }
+}
+
+// "a day", "2 weeks", "an hour and a half", "a year 2 months 3 days". When
+// its parts have different signs each gets its own, eg: "a month minus a day".
+impl fmt::Display for TDuration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (months, seconds) = (self.months, self.seconds);
+        let minus = |n: i64| if n < 0 { "minus " } else { "" };
+        if months.signum() * seconds.signum() < 0 {
+            write!(f, "{}{} {} {}", minus(months), describe(months.unsigned_abs(), 0),
+                   if seconds < 0 { "minus" } else { "plus" }, describe(0, seconds.unsigned_abs()))
+        } else {
+            write!(f, "{}{}", minus(months.min(seconds)),
+                   describe(months.unsigned_abs(), seconds.unsigned_abs()))
+        }
+    }
+}
+
+// The length of a duration with non-negative parts
+fn describe(months: u64, seconds: u64) -> String {
+    if months == 0 && seconds == 0 {
+        return "0 seconds".to_string();
+    }
+    let count = |n: u64, unit: &str| match n {
+        1 if unit == "hour" => "an hour".to_string(),
+        1 => format!("a {}", unit),
+        n => format!("{} {}s", n, unit),
+    };
+    // n units and a half, when that's all there is
+    let halves = |amount: u64, units: &[(&str, u64)]| units.iter()
+        .find(|(_, size)| amount >= *size)
+        .filter(|(_, size)| size % 2 == 0 && amount % size == size / 2)
+        .map(|(unit, size)| format!("{} and a half", count(amount / size, unit)));
+    let half = match (months, seconds) {
+        (months, 0) => halves(months, &CALENDAR_UNITS),
+        (0, seconds) => halves(seconds, &EXACT_UNITS),
+        _ => None,
+    };
+    if let Some(half) = half {
+        return half;
+    }
+    let mut parts = Vec::new();
+    for (amount, units) in [(months, &CALENDAR_UNITS[..]), (seconds, &EXACT_UNITS[..])] {
+        let mut left = amount;
+        for (unit, size) in units {
+            // "10 days" rather than "a week 3 days"
+            if *unit == "week" && left % size >= 86400 && left % 86400 == 0 {
+                continue;
+            }
+            if left >= *size {
+                parts.push(count(left / size, unit));
+                left %= size;
+            }
+        }
+    }
+    parts.join(" ")
+}
@@ -1,10 +1,13 @@
-#![deny(warnings)]
+#![cfg_attr(feature = "strict", deny(warnings))]
 
 mod constants;
 mod time_parser;
 pub use time_parser::time_grammar;
 pub use time_parser::debug_time_expression;
 
+mod duration;
+pub use duration::TDuration;
+
 mod time_semantics;
 pub use time_semantics::{TimeMachine, TimeEl};
 
@@ -41,6 +41,7 @@ fn main() -> Result<(), String> {
                 println!("({:?}) {} - {}", r.grain,
                          r.start.format(fmt(r.grain)),
                          r.end.format(fmt(r.grain))),
+            fluxcap::TimeEl::Duration(duration) => println!("{}", duration),
             _ => println!("{:?}", r),
         }
     }
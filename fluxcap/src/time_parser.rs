@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 // https://github.com/wit-ai/duckling_old/blob/master/resources/languages/en/corpus/time.clj
 // https://github.com/wit-ai/duckling_old/blob/master/resources/languages/en/rules/time.clj
 
@@ -11,6 +9,7 @@ pub fn time_grammar() -> &'static str {
               | day_ordinal 'of' month
               | month day_ordinal
               | weekday day_ordinal
+              | weekday month
               | weekday day_ordinal 'of' month
               | weekday month day_ordinal
               | 'weekend' | 'weekends'
@@ -19,15 +18,27 @@ pub fn time_grammar() -> &'static str {
 
     sequence := named_seq | grain;
 
-    comp_seq := ordinal sequence 'of' ['the'] @opt_the comp_seq
-              | 'last' sequence 'of' ['the'] @opt_the comp_seq
+    # what's picked from a comp_seq, eg: "the (third thursday) of march"
+    nth_seq := ordinal sequence
+             | 'last' sequence
+             | '(' nth_seq ')'
+             ;
+
+    comp_seq := nth_seq 'of' ['the'] @opt_the comp_seq
               | sequence
+              | '(' comp_seq ')'
               ;
 
     comp_grain := small_int grain
                | 'a' grain
+               | 'an' grain
                | comp_grain 'and' small_int grain
                | comp_grain 'and' 'a' grain
+               | comp_grain 'and' 'an' grain
+               | comp_grain small_int grain
+               | comp_grain 'a' grain
+               | comp_grain 'an' grain
+               | '(' comp_grain ')'
                ;
 
     time := 'today'
@@ -37,6 +48,8 @@ pub fn time_grammar() -> &'static str {
           | named_seq
 
           | 'the' comp_seq
+          # the article can go in the group, eg: "(the third thursday) of march"
+          | '(' 'the' nth_seq ')' 'of' ['the'] @opt_the comp_seq
           | 'this' comp_seq
           | 'next' comp_seq
           | 'last' comp_seq
@@ -51,8 +64,11 @@ pub fn time_grammar() -> &'static str {
           | comp_grain 'ago'
           | 'in' comp_grain
 
+          | 'in' year
           | month year
           | month day_ordinal year
+          | iso_date
+          | iso_month
 
           | comp_grain 'after' time
           | comp_grain 'before' time
@@ -60,21 +76,30 @@ pub fn time_grammar() -> &'static str {
           | sequence 'until' time
           | sequence 'since' time
           | sequence 'between' time 'and' time
+
+          # grouping picks an interpretation, eg: "hours between (in 2
+          # days and 3 hours) and 1 day ago"
+          | '(' time ')'
           ;
+
+    # durations aren't times, "a day after 2 days" doesn't parse
+    time_or_duration := time | comp_grain ;
     "#
 }
 
-fn _grammar() -> Result<earlgrey::Grammar, String> {
+fn _grammar() -> Result<earlgrey::Grammar, earlgrey::GrammarError> {
     use std::str::FromStr;
     use crate::constants::*;
-    earlgrey::EbnfGrammarParser::new(time_grammar(), "time")
+    earlgrey::EbnfGrammarParser::new(time_grammar(), "time_or_duration")
         .plug_terminal("ordinal", |d| ordinal(d).or_else(|| short_ordinal(d)).is_some())
-        .plug_terminal("day_ordinal", |d| ordinal(d).or_else(|| short_ordinal(d)).is_some())
+        .plug_terminal("day_ordinal", |d| ordinal(d).or_else(|| short_ordinal(d))
+                       .is_some_and(|d| (1..=31).contains(&d)))
         .plug_terminal("weekday", |d| weekday(d).is_some())
         .plug_terminal("month", |d| month(d).is_some())
         .plug_terminal("grain", |g| kronos::Grain::from_str(g).is_ok())
-        .plug_terminal("year", |y| if let Ok(year) = i32::from_str(y)
-                       { year > 999 && year < 2200 } else { false })
+        .plug_terminal("year", |y| year(y).is_some())
+        .plug_terminal("iso_date", |d| iso_date(d).is_some())
+        .plug_terminal("iso_month", |m| iso_month(m).is_some())
         .plug_terminal("small_int", |u| if let Ok(u) = usize::from_str(u)
                        { u < 100 } else { false })
         .into_grammar()
@@ -92,5 +117,23 @@ pub fn debug_time_expression(time: &str) -> Result<Vec<earlgrey::Sexpr>, String>
         _grammar()
         .unwrap_or_else(|e| panic!("TimeMachine grammar BUG: {:?}", e))
     )?;
-    parser(time.split(&[' ', ','][..]).filter(|w| !w.is_empty()))
+    parser(tokenize(time))
+}
+
+// Words separated by spaces or commas, parens are tokens of their own:
+// "(in 2 days)" is "(", "in", "2", "days", ")"
+pub fn tokenize(time: &str) -> impl Iterator<Item = &str> {
+    time.split(&[' ', ','][..])
+        .flat_map(|word| {
+            let mut tokens = Vec::new();
+            let mut rest = word;
+            while let Some(idx) = rest.find(&['(', ')'][..]) {
+                tokens.push(&rest[..idx]);
+                tokens.push(&rest[idx..idx + 1]);
+                rest = &rest[idx + 1..];
+            }
+            tokens.push(rest);
+            tokens
+        })
+        .filter(|w| !w.is_empty())
 }
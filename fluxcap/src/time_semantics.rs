@@ -1,8 +1,7 @@
-#![deny(warnings)]
-
 type DateTime = chrono::NaiveDateTime;
 type Date = chrono::NaiveDate;
 
+use crate::duration::TDuration;
 use earlgrey::{EarleyForest, EarleyParser};
 use kronos as k;
 type Shim = kronos::Shim<'static>;
@@ -11,6 +10,7 @@ type Shim = kronos::Shim<'static>;
 pub enum TimeEl {
     Time(k::Range),
     Count(u32),
+    Duration(TDuration),
 }
 
 impl TimeEl {
@@ -26,10 +26,13 @@ impl TimeEl {
 #[derive(Clone)]
 enum TimeNode {
     Int(i32),
+    Ymd(Date),
     Grain(k::Grain),
     Shifts(Vec<(k::Grain, i32)>),
     Nop,
     Seq(Shim),
+    // the nth (from 1) or the last of a sequence, see nth_seq
+    Nth(Option<usize>, Shim),
     This(Shim),
     Next(Shim, usize),
     Last(Shim, usize),
@@ -38,6 +41,33 @@ enum TimeNode {
     Until(Shim, DateTime),
     Since(Shim, DateTime),
     Between(Shim, DateTime, DateTime),
+    Duration(TDuration),
+}
+
+// "an hour and a half" is the hour and half of it (30 minutes), not a half
+// (year) as shifts. Half a month is 15 days, half a quarter 1 month 15 days.
+fn add_shift(mut shifts: Vec<(k::Grain, i32)>, grain: k::Grain, n: i32) -> TimeNode {
+    use k::Grain::*;
+    let half = match shifts.last() {
+        Some(&(last, _)) if grain == Half && n == 1 => match last {
+            Second => vec![],
+            Minute => vec![(Second, 30)],
+            Hour => vec![(Minute, 30)],
+            Day => vec![(Hour, 12)],
+            Week => vec![(Hour, 84)],
+            Month => vec![(Day, 15)],
+            Quarter => vec![(Month, 1), (Day, 15)],
+            Half => vec![(Month, 3)],
+            Year => vec![(Month, 6)],
+            Lustrum => vec![(Month, 30)],
+            Decade => vec![(Year, 5)],
+            Century => vec![(Year, 50)],
+            Millenium => vec![(Year, 500)],
+        },
+        _ => vec![(grain, n)],
+    };
+    shifts.extend(half);
+    TimeNode::Shifts(shifts)
 }
 
 // Shift a sequence by multiple shifts
@@ -81,6 +111,13 @@ impl TimeNode {
             panic!("BUG")
         }
     }
+    fn ymd(&self) -> Date {
+        if let TimeNode::Ymd(x) = self {
+            *x
+        } else {
+            panic!("BUG")
+        }
+    }
     fn grain(&self) -> k::Grain {
         if let TimeNode::Grain(x) = self {
             *x
@@ -88,6 +125,15 @@ impl TimeNode {
             panic!("BUG")
         }
     }
+    // The comp_seq picking this nth_seq of `of`
+    fn nth_of(&self, of: Shim) -> TimeNode {
+        use kronos::*;
+        match self {
+            TimeNode::Nth(Some(n), seq) => s!(NthOf(*n, seq.clone(), of)),
+            TimeNode::Nth(None, seq) => s!(LastOf(1, seq.clone(), of)),
+            _ => panic!("BUG"),
+        }
+    }
     fn seq(&self) -> Shim {
         if let TimeNode::Seq(x) = self {
             x.clone()
@@ -133,6 +179,7 @@ impl TimeNode {
                     .take_while(|x| x.start < *tn && x.end <= *tn)
                     .count() as u32,
             ),
+            Duration(duration) => TimeEl::Duration(*duration),
             _ => unreachable!(),
         }
     }
@@ -149,8 +196,16 @@ fn terminal_eval() -> impl Fn(&str, &str) -> TimeNode {
         "weekday" => Int(weekday(lex).unwrap() as i32),
         "month" => Int(month(lex).unwrap() as i32),
         "grain" => Grain(k::Grain::from_str(lex).unwrap()),
-        "year" => Int(i32::from_str(lex).unwrap()),
+        "year" => Int(year(lex).unwrap()),
         "small_int" => Int(i32::from_str(lex).unwrap()),
+        "iso_date" => {
+            let (year, month, day) = iso_date(lex).unwrap();
+            Ymd(Date::from_ymd(year, month, day))
+        }
+        "iso_month" => {
+            let (year, month) = iso_month(lex).unwrap();
+            Ymd(Date::from_ymd(year, month, 1))
+        }
         _ => Nop,
     }
 }
@@ -166,6 +221,7 @@ fn evaler_sequence(ev: &mut EarleyForest<'_, TimeNode>) {
         ))
     });
     ev.action("named_seq -> weekday", |t| s!(Weekday(t[0].u32())));
+    // the month once a year, "june" while in june is the current one
     ev.action("named_seq -> month", |t| s!(Month(t[0].u32())));
     ev.action("named_seq -> day_ordinal of month", |t| {
         s!(NthOf(t[0].usize(), Grains(Grain::Day), Month(t[2].u32())))
@@ -179,6 +235,9 @@ fn evaler_sequence(ev: &mut EarleyForest<'_, TimeNode>) {
             NthOf(t[1].usize(), Grains(Grain::Day), Grains(Grain::Month))
         ))
     });
+    ev.action("named_seq -> weekday month", |t| {
+        s!(Intersect(Weekday(t[0].u32()), Month(t[1].u32())))
+    });
     ev.action("named_seq -> weekday day_ordinal of month", |t| {
         s!(Intersect(
             Weekday(t[0].u32()),
@@ -202,18 +261,16 @@ fn evaler_sequence(ev: &mut EarleyForest<'_, TimeNode>) {
 }
 
 fn evaler_comp_seq(ev: &mut EarleyForest<'_, TimeNode>) {
-    use kronos::*;
-
     ev.action("@opt_the -> the", |_| TimeNode::Nop);
     ev.action("@opt_the -> ", |_| TimeNode::Nop);
 
-    ev.action("comp_seq -> ordinal sequence of @opt_the comp_seq", |t| {
-        s!(NthOf(t[0].usize(), t[1].seq(), t[4].seq()))
-    });
-    ev.action("comp_seq -> last sequence of @opt_the comp_seq", |t| {
-        s!(LastOf(1, t[1].seq(), t[4].seq()))
-    });
+    ev.action("nth_seq -> ordinal sequence", |t| TimeNode::Nth(Some(t[0].usize()), t[1].seq()));
+    ev.action("nth_seq -> last sequence", |t| TimeNode::Nth(None, t[1].seq()));
+    ev.action("nth_seq -> ( nth_seq )", |mut t| t.remove(1));
+
+    ev.action("comp_seq -> nth_seq of @opt_the comp_seq", |t| t[0].nth_of(t[3].seq()));
     ev.action("comp_seq -> sequence", |mut t| t.remove(0));
+    ev.action("comp_seq -> ( comp_seq )", |mut t| t.remove(1));
 }
 
 fn evaler_comp_grain(ev: &mut EarleyForest<'_, TimeNode>) {
@@ -223,16 +280,30 @@ fn evaler_comp_grain(ev: &mut EarleyForest<'_, TimeNode>) {
     ev.action("comp_grain -> a grain", |t| {
         TimeNode::Shifts(vec![(t[1].grain(), 1)])
     });
+    ev.action("comp_grain -> an grain", |t| {
+        TimeNode::Shifts(vec![(t[1].grain(), 1)])
+    });
     ev.action("comp_grain -> comp_grain and small_int grain", |mut t| {
-        let mut shifts = t.remove(0).shifts();
-        shifts.push((t[2].grain(), t[1].i32()));
-        TimeNode::Shifts(shifts)
+        let shifts = t.remove(0).shifts();
+        add_shift(shifts, t[2].grain(), t[1].i32())
     });
-    ev.action("comp_grain -> comp_grain and a grain", |mut t| {
-        let mut shifts = t.remove(0).shifts();
-        shifts.push((t[2].grain(), 1));
-        TimeNode::Shifts(shifts)
+    for rule in ["comp_grain -> comp_grain and a grain", "comp_grain -> comp_grain and an grain"] {
+        ev.action(rule, |mut t| {
+            let shifts = t.remove(0).shifts();
+            add_shift(shifts, t[2].grain(), 1)
+        });
+    }
+    for rule in ["comp_grain -> comp_grain a grain", "comp_grain -> comp_grain an grain"] {
+        ev.action(rule, |mut t| {
+            let shifts = t.remove(0).shifts();
+            add_shift(shifts, t[1].grain(), 1)
+        });
+    }
+    ev.action("comp_grain -> comp_grain small_int grain", |mut t| {
+        let shifts = t.remove(0).shifts();
+        add_shift(shifts, t[1].grain(), t[0].i32())
     });
+    ev.action("comp_grain -> ( comp_grain )", |mut t| t.remove(1));
 }
 
 fn evaler_time(ev: &mut EarleyForest<'_, TimeNode>, reftime: DateTime) {
@@ -251,6 +322,9 @@ fn evaler_time(ev: &mut EarleyForest<'_, TimeNode>, reftime: DateTime) {
     ev.action("time -> named_seq", |t| This(t[0].seq()));
 
     ev.action("time -> the comp_seq", |t| This(t[1].seq()));
+    ev.action("time -> ( the nth_seq ) of @opt_the comp_seq", |t| {
+        This(t[2].nth_of(t[6].seq()).seq())
+    });
     ev.action("time -> this comp_seq", |t| This(t[1].seq()));
     ev.action("time -> next comp_seq", |t| Next(t[1].seq(), 0));
     ev.action("time -> last comp_seq", |t| Last(t[1].seq(), 0));
@@ -271,11 +345,26 @@ fn evaler_time(ev: &mut EarleyForest<'_, TimeNode>, reftime: DateTime) {
         Last(build_shifter(shifts, -1, k::Grain::Second), 0)
     });
 
+    ev.action("time_or_duration -> time", |mut t| t.remove(0));
+    ev.action("time_or_duration -> comp_grain", |mut t| {
+        let shifts = t.remove(0).shifts();
+        Duration(shifts.into_iter().fold(TDuration::default(), |total, (grain, n)| {
+            total + TDuration::new(i64::from(n), grain)
+        }))
+    });
+
     ev.action("time -> in comp_grain", |mut t| {
         let shifts = t.remove(1).shifts();
         Next(build_shifter(shifts, 1, k::Grain::Second), 0)
     });
 
+    ev.action("time -> in year", |t| {
+        RefNext(
+            Shim::new(Grains(k::Grain::Year)),
+            Date::from_ymd(t[1].i32(), 1, 1).and_hms(0, 0, 0),
+        )
+    });
+
     ev.action("time -> month year", |t| {
         RefNext(
             Shim::new(Grains(k::Grain::Month)),
@@ -290,6 +379,14 @@ fn evaler_time(ev: &mut EarleyForest<'_, TimeNode>, reftime: DateTime) {
         )
     });
 
+    ev.action("time -> iso_date", |t| {
+        RefNext(Shim::new(Grains(k::Grain::Day)), t[0].ymd().and_hms(0, 0, 0))
+    });
+
+    ev.action("time -> iso_month", |t| {
+        RefNext(Shim::new(Grains(k::Grain::Month)), t[0].ymd().and_hms(0, 0, 0))
+    });
+
     ev.action("time -> comp_grain after time", move |mut t| {
         let r = t.remove(2).eval(reftime).range();
         let shifts = t.remove(0).shifts();
@@ -317,6 +414,8 @@ fn evaler_time(ev: &mut EarleyForest<'_, TimeNode>, reftime: DateTime) {
         let t0 = t.remove(2).eval(reftime).range().start;
         Between(t.remove(0).seq(), t0, tn)
     });
+
+    ev.action("time -> ( time )", |mut t| t.remove(1));
 }
 
 pub struct TimeMachine<'a> {
@@ -341,7 +440,7 @@ impl<'a> TimeMachine<'a> {
     }
 
     pub fn eval(&self, time: &str) -> Result<Vec<TimeEl>, String> {
-        let mut tokenizer = time.split(&[' ', ','][..]).filter(|w| !w.is_empty());
+        let mut tokenizer = crate::time_parser::tokenize(time);
 
         let state = self
             .parser
@@ -1,7 +1,6 @@
-#![deny(warnings)]
-
 type DateTime = chrono::NaiveDateTime;
 
+use crate::duration::TDuration;
 use crate::time_semantics::{TimeEl, TimeMachine};
 use kronos::Grain as g;
 
@@ -83,6 +82,19 @@ fn t_intersect() -> Result<(), String> {
     assert_eq!(tm.eval("friday 18th")?, r(d(2016, 11, 18), d(2016, 11, 19), g::Day));
     assert_eq!(tm.eval("18th of june")?, r(d(2017, 6, 18), d(2017, 6, 19), g::Day));
     assert_eq!(tm.eval("feb 27th")?, r(d(2017, 2, 27), d(2017, 2, 28), g::Day));
+
+    // thursdays in march
+    use chrono::Datelike;
+    let tm = TimeMachine::new(d(2016, 9, 5));
+    assert_eq!(tm.eval("thursday march")?, r(d(2017, 3, 2), d(2017, 3, 3), g::Day));
+    assert_eq!(tm.eval("last thu march")?, r(d(2016, 3, 31), d(2016, 4, 1), g::Day));
+    for expr in ["next thursday march", "thursday march after next", "last thursday march"] {
+        match &tm.eval(expr)?[..] {
+            [TimeEl::Time(day)] => assert_eq!((day.start.month(), day.start.weekday()),
+                                              (3, chrono::Weekday::Thu), "{}", expr),
+            other => panic!("{} => {:?}", expr, other),
+        }
+    }
     Ok(())
 }
 
@@ -94,6 +106,30 @@ fn t_anchored() -> Result<(), String> {
     Ok(())
 }
 
+#[test]
+fn t_iso_dates() -> Result<(), String> {
+    let tm = TimeMachine::new(d(2016, 9, 5));
+    assert_eq!(tm.eval("2024-03-15")?, r(d(2024, 3, 15), d(2024, 3, 16), g::Day));
+    assert_eq!(tm.eval("2024-03")?, r(d(2024, 3, 1), d(2024, 4, 1), g::Month));
+    assert_eq!(tm.eval("2 days after 2024-02-28")?, r(d(2024, 3, 1), d(2024, 3, 2), g::Day));
+    assert!(tm.eval("2024-13-40").is_err());
+    assert!(tm.eval("2024-02-30").is_err());
+    Ok(())
+}
+
+#[test]
+fn t_years() -> Result<(), String> {
+    let tm = TimeMachine::new(d(2016, 9, 5));
+    assert_eq!(tm.eval("2019")?, r(d(2019, 1, 1), d(2020, 1, 1), g::Year));
+    assert_eq!(tm.eval("in 2019")?, r(d(2019, 1, 1), d(2020, 1, 1), g::Year));
+    assert_eq!(tm.eval("in 2012")?, r(d(2012, 1, 1), d(2013, 1, 1), g::Year));
+    // ordinals are days, not years
+    assert_eq!(tm.eval("the 19th")?, r(d(2016, 9, 19), d(2016, 9, 20), g::Day));
+    assert!(tm.eval("2019th").is_err());
+    assert!(tm.eval("in 02019").is_err());
+    Ok(())
+}
+
 #[test]
 fn t_timediff() -> Result<(), String> {
     let tm = TimeMachine::new(d(2016, 9, 5));
@@ -125,3 +161,195 @@ fn t_shifts() -> Result<(), String> {
     assert_eq!(tm.eval("in a year")?, r(d(2017, 10, 26), dttm(2017, 10, 26, 0, 0, 1), g::Second));
     Ok(())
 }
+
+#[test]
+fn t_durations() -> Result<(), String> {
+    let tm = TimeMachine::new(d(2016, 10, 26));
+    let duration = |expr| match &tm.eval(expr).unwrap()[..] {
+        [TimeEl::Duration(duration)] => *duration,
+        other => panic!("{} => {:?}", expr, other),
+    };
+    let minutes = |n| TDuration::new(n, g::Minute);
+    assert_eq!(duration("3 days"), TDuration::new(3, g::Day));
+    assert_eq!(duration("a day"), TDuration::new(1, g::Day));
+    assert_eq!(duration("an hour"), TDuration::new(1, g::Hour));
+    assert_eq!(duration("an hour and a half"), minutes(90));
+    assert_eq!(duration("2 hours 30 minutes"), minutes(150));
+    assert_eq!(duration("2 hours and 30 minutes"), minutes(150));
+    assert_eq!(duration("a year and a half"), TDuration::new(18, g::Month));
+    assert_eq!(duration("a month and a half"), TDuration::new(1, g::Month) + TDuration::new(15, g::Day));
+    assert_eq!(duration("a week 2 days and an hour"),
+               TDuration::new(9, g::Day) + TDuration::new(1, g::Hour));
+    // halves shift times too
+    assert_eq!(tm.eval("in an hour and a half")?,
+               r(dttm(2016, 10, 26, 1, 30, 0), dttm(2016, 10, 26, 1, 30, 1), g::Second));
+    // durations aren't times
+    assert!(tm.eval("a day after 2 days").is_err());
+    assert!(tm.eval("days until 3 days").is_err());
+
+    // longer than 2 weeks
+    let weeks = TDuration::new(2, g::Week);
+    assert!(duration("15 days") > weeks);
+    assert!(duration("a week and 6 days") < weeks);
+    assert!(duration("14 days") == weeks && duration("14 days") <= weeks);
+    assert_eq!(TDuration::new(1, g::Month).partial_cmp(&TDuration::new(30, g::Day)), None);
+    assert!(TDuration::new(1, g::Month) + TDuration::new(1, g::Day) > TDuration::new(1, g::Month));
+    Ok(())
+}
+
+#[test]
+fn t_duration_ranges() -> Result<(), String> {
+    let jan31 = kronos::Range{start: d(2016, 1, 31), end: d(2016, 2, 1), grain: g::Day};
+    let month = TDuration::new(1, g::Month);
+    // months past the end of february stop there
+    assert_eq!(month.shift(&jan31)?, kronos::Range{start: d(2016, 2, 29), end: d(2016, 3, 1), grain: g::Day});
+    assert_eq!(TDuration::new(36, g::Hour).shift(&jan31)?,
+               kronos::Range{start: dttm(2016, 2, 1, 12, 0, 0), end: dttm(2016, 2, 2, 12, 0, 0), grain: g::Day});
+    assert_eq!(TDuration::new(2, g::Day).extend(&jan31)?,
+               kronos::Range{start: d(2016, 1, 31), end: d(2016, 2, 3), grain: g::Day});
+    assert_eq!((month + TDuration::new(1, g::Day)).extend(&jan31)?,
+               kronos::Range{start: d(2016, 1, 31), end: d(2016, 3, 2), grain: g::Day});
+    // too long to shift by rather than wrapping around or panicking
+    assert!(TDuration::new(1 << 32, g::Month).shift(&jan31).is_err());
+    assert!(TDuration{months: 0, seconds: i64::MIN}.extend(&jan31).is_err());
+    Ok(())
+}
+
+#[test]
+fn t_duration_display() {
+    let show = |n, grain| TDuration::new(n, grain).to_string();
+    assert_eq!(show(3, g::Day), "3 days");
+    assert_eq!(show(1, g::Day), "a day");
+    assert_eq!(show(1, g::Hour), "an hour");
+    assert_eq!(show(90, g::Minute), "an hour and a half");
+    assert_eq!(show(150, g::Minute), "2 hours and a half");
+    assert_eq!(show(36, g::Hour), "a day and a half");
+    assert_eq!(show(14, g::Day), "2 weeks");
+    assert_eq!(show(10, g::Day), "10 days");
+    assert_eq!(show(18, g::Month), "a year and a half");
+    assert_eq!(show(24, g::Month), "2 years");
+    assert_eq!(show(2, g::Quarter), "6 months");
+    assert_eq!(show(135, g::Minute), "2 hours 15 minutes");
+    assert_eq!(show(0, g::Day), "0 seconds");
+    assert_eq!(show(-3, g::Day), "minus 3 days");
+    assert_eq!((TDuration::new(-1, g::Month) + TDuration::new(-1, g::Day)).to_string(),
+               "minus a month a day");
+    // each part with its own sign
+    assert_eq!((TDuration::new(1, g::Month) + TDuration::new(-1, g::Day)).to_string(),
+               "a month minus a day");
+    assert_eq!((TDuration::new(-2, g::Year) + TDuration::new(36, g::Hour)).to_string(),
+               "minus 2 years plus a day and a half");
+    assert_eq!(TDuration{months: i64::MIN, seconds: 0}.to_string(),
+               "minus 768614336404564650 years 8 months");
+    assert_eq!((TDuration::new(14, g::Month) + TDuration::new(49, g::Hour)).to_string(),
+               "a year 2 months 2 days an hour");
+    // displayed durations parse back
+    let tm = TimeMachine::new(d(2016, 10, 26));
+    for minutes in [1, 45, 90, 135, 1500, 20160] {
+        let duration = TDuration::new(minutes, g::Minute);
+        assert_eq!(tm.eval(&duration.to_string()).unwrap(), vec![TimeEl::Duration(duration)]);
+    }
+}
+
+#[test]
+fn t_grouping() -> Result<(), String> {
+    let tm = TimeMachine::new(d(2016, 10, 26));
+    // "and 3 hours" goes with either time
+    let phrase = "hours between in 2 days and 3 hours and 1 day after dec 25th";
+    let all = tm.eval(phrase)?;
+    assert_eq!(all.len(), 2);
+    assert!(all.contains(&TimeEl::Count(1419)) && all.contains(&TimeEl::Count(1413)));
+    assert_eq!(tm.eval("hours between in 2 days and (3 hours and 1 day after dec 25th)")?,
+               vec![TimeEl::Count(1419)]);
+    assert_eq!(tm.eval("hours between (in 2 days and 3 hours) and 1 day after dec 25th")?,
+               vec![TimeEl::Count(1413)]);
+    // parens needn't be spaced, and nest
+    assert_eq!(tm.eval("(the (3rd thursday of (march)))")?, tm.eval("the 3rd thursday of march")?);
+    // what's picked can be grouped too
+    assert_eq!(tm.eval("(the third thursday) of march")?, tm.eval("the third thursday of march")?);
+    assert_eq!(tm.eval("the (last day) of the (2nd week of may)")?,
+               tm.eval("the last day of the 2nd week of may")?);
+    assert_eq!(tm.eval("the ((third thursday)) of march")?, tm.eval("the third thursday of march")?);
+    assert_eq!(tm.eval("in (2 days)")?, tm.eval("in 2 days")?);
+    assert!(tm.eval("(in 2 days").is_err());
+    Ok(())
+}
+
+#[test]
+fn t_named_month() -> Result<(), String> {
+    // inside june "june" is this one, the next one once it's over
+    let tm = TimeMachine::new(d(2016, 6, 15));
+    assert_eq!(tm.eval("june")?, r(d(2016, 6, 1), d(2016, 7, 1), g::Month));
+    assert_eq!(tm.eval("may")?, r(d(2017, 5, 1), d(2017, 6, 1), g::Month));
+    assert_eq!(tm.eval("july")?, r(d(2016, 7, 1), d(2016, 8, 1), g::Month));
+    let tm = TimeMachine::new(d(2016, 7, 1));
+    assert_eq!(tm.eval("june")?, r(d(2017, 6, 1), d(2017, 7, 1), g::Month));
+    assert_eq!(tm.eval("the 3rd of june")?, r(d(2017, 6, 3), d(2017, 6, 4), g::Day));
+    // with a year it's that exact month, past or future
+    assert_eq!(tm.eval("june 2020")?, r(d(2020, 6, 1), d(2020, 7, 1), g::Month));
+    assert_eq!(tm.eval("june 1999")?, r(d(1999, 6, 1), d(1999, 7, 1), g::Month));
+    assert_eq!(tm.eval("feb 2016")?, r(d(2016, 2, 1), d(2016, 3, 1), g::Month));
+    assert_eq!(tm.eval("feb 2017")?, r(d(2017, 2, 1), d(2017, 3, 1), g::Month));
+    assert_eq!(tm.eval("feb 29th")?, r(d(2020, 2, 29), d(2020, 3, 1), g::Day));
+    Ok(())
+}
+
+// Whether rules of nonterminal `head` can start at `token`, to prune
+// predictions with EarleyParser::with_oracle. Knows the first tokens of
+// dates and durations, any other nonterminal can start anywhere.
+fn can_start(head: &str, token: Option<&str>) -> bool {
+    use std::str::FromStr;
+    use crate::constants::*;
+    let is = |words: &[&str]| token.is_some_and(|t| words.contains(&t));
+    let named_seq = || token.is_some_and(|t| ordinal(t).or_else(|| short_ordinal(t)).is_some() ||
+        weekday(t).is_some() || month(t).is_some() || year(t).is_some()) ||
+        is(&["weekend", "weekends"]);
+    let sequence = || named_seq() || token.is_some_and(|t| kronos::Grain::from_str(t).is_ok());
+    match head {
+        "named_seq" => named_seq(),
+        "sequence" => sequence(),
+        "comp_seq" => sequence() || is(&["last", "("]) ||
+            token.is_some_and(|t| ordinal(t).or_else(|| short_ordinal(t)).is_some()),
+        "comp_grain" => is(&["a", "an", "("]) ||
+            token.is_some_and(|t| t.parse::<usize>().is_ok_and(|n| n < 100)),
+        _ => true,
+    }
+}
+
+// cargo test --release -- --ignored --nocapture
+#[test]
+#[ignore]
+fn oracle_benchmark() {
+    use crate::time_parser::{time_parser, tokenize};
+    let inputs = [
+        "hours between in 2 days and 3 hours and 1 day after dec 25th",
+        "the 2nd day of the 3rd week of 1987",
+        "third tuesday of the month after next",
+        "2 weeks 3 days and 4 hours after the 3rd day of the 2nd week of may",
+        "days since the 1st monday of the 3rd week of the last month of 2012",
+    ];
+    let plain = time_parser();
+    let pruned = time_parser().with_oracle(|_, head, token| can_start(head, token));
+    let time = |parser: &earlgrey::EarleyParser| {
+        let started = std::time::Instant::now();
+        let mut derivations = Vec::new();
+        for _ in 0..200 {
+            derivations.clear();
+            for input in &inputs {
+                let trees = parser.parse(tokenize(input));
+                derivations.push(trees.map(|t| t.derivations()).unwrap_or(0));
+            }
+        }
+        (started.elapsed(), derivations)
+    };
+    let (plain_time, plain_trees) = time(&plain);
+    let (pruned_time, pruned_trees) = time(&pruned);
+    assert_eq!(plain_trees, pruned_trees);
+    let predictions = |parser: &earlgrey::EarleyParser| inputs.iter().map(|input| {
+        let (_, stats) = parser.parse_with_stats(tokenize(input)).unwrap();
+        stats.total().predictions
+    }).sum::<usize>();
+    println!("time grammar predictions: plain {} with oracle {}",
+             predictions(&plain), predictions(&pruned));
+    println!("time grammar parses: plain {:?} with oracle {:?}", plain_time, pruned_time);
+}
@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// Any grammar text is an error or a parser, and parsers take any input
+fuzz_target!(|data: &str| {
+    let (grammar, input) = data.split_once('\0').unwrap_or((data, ""));
+    if let Ok(builder) = earlgrey::ParserBuilder::try_new(grammar) {
+        let _ = builder.into_parser().parse(input.split_whitespace());
+    }
+});
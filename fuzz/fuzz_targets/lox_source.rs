@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// Scanning and parsing may fail but not panic
+fuzz_target!(|data: &str| {
+    let _ = lox::parse(data);
+});
@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use shunting::{MathContext, ShuntingParser};
+
+// Parse, print and evaluate, none of it may panic
+fuzz_target!(|data: &str| {
+    if let Ok(expr) = ShuntingParser::parse_str(data) {
+        let _ = expr.try_to_infix();
+        let _ = expr.try_to_latex(Default::default());
+        let _ = MathContext::new().eval(&expr);
+    }
+});
@@ -1,9 +1,10 @@
-#![deny(warnings)]
+#![cfg_attr(feature = "strict", deny(warnings))]
 
 mod types;
 pub use crate::types::{Grain, TimeSequence, Range, Season};
 
 mod utils;
+pub use crate::utils::shift_datetime;
 
 mod seq_named;
 pub use crate::seq_named::{Weekday, Month, Weekend, Year};
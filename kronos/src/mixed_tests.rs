@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 use crate::types::{DateTime, Date, Grain, Range, TimeSequence};
 
 use crate::seq_nthof::*;
@@ -78,3 +76,21 @@ fn test_every_nmonths_from_offset() {
     assert_eq!(every_3months_from_next_march_iter.next().unwrap(), Range{
         start: dt(2019, 3, 1), end: dt(2019, 4, 1), grain: Grain::Month});
 }
+
+#[test]
+fn test_future_until() {
+    // thursdays in september 2016
+    let thursdays = Weekday(4);
+    let sept = thursdays.future_until(&dt(2016, 9, 1), &dt(2016, 10, 1));
+    assert_eq!(sept.map(|r| r.start).collect::<Vec<_>>(),
+               vec![dt(2016, 9, 1), dt(2016, 9, 8), dt(2016, 9, 15),
+                    dt(2016, 9, 22), dt(2016, 9, 29)]);
+    // thursdays remaining this year from "now"
+    let now = dttm(2016, 9, 14, 12, 30, 0);
+    assert_eq!(thursdays.future_until(&now, &dt(2017, 1, 1)).count(), 16);
+    // a range straddling the bound is kept, nothing starting at it is
+    let weeks = Grains(Grain::Week);
+    assert_eq!(weeks.future_until(&dt(2016, 9, 14), &dt(2016, 9, 18)).count(), 1);
+    assert_eq!(weeks.future_until(&dt(2016, 9, 14), &dt(2016, 9, 11)).count(), 0);
+}
+
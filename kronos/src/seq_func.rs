@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 use crate::types::{DateTime, Range, Grain, TimeSequence};
 
 #[derive(Clone)]
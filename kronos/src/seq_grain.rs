@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 use crate::utils;
 use crate::types::{DateTime, Range, Grain, TimeSequence};
 
@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 use std::collections::VecDeque;
 use crate::types::{DateTime, Range, TimeSequence};
 
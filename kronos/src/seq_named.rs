@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 use crate::utils;
 use crate::types::{Date, DateTime, Duration, Range, Grain, TimeSequence};
 
@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 use crate::types::{DateTime, Range, TimeSequence};
 
 // Guard against impossible sequences, eg: 32nd day of the month
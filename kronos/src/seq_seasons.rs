@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 use crate::utils;
 use crate::types::{Grain, DateTime, Range, TimeSequence, Season};
 
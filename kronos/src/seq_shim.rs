@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 use crate::types::{DateTime, Range, TimeSequence};
 use std::rc::Rc;
 
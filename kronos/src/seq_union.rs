@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 use crate::types::{DateTime, Range, TimeSequence};
 
 // Alternates SeqA and SeqB depending on what happens first
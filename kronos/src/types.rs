@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 pub type DateTime = chrono::NaiveDateTime;
 pub type Date = chrono::NaiveDate;
 pub type Duration = chrono::Duration;
@@ -124,4 +122,12 @@ pub trait TimeSequence {
         Box::new(self._past_raw(&t0)
             .skip_while(move |range| range.end > t0))
     }
+
+    // Bounded "future", eg: all thursdays from t0 until the end of the year.
+    // Yields Ranges that start before `end`, those straddling it included.
+    fn future_until(&self, t0: &DateTime, end: &DateTime)
+            -> Box<dyn Iterator<Item=Range> + '_> {
+        let end = *end;
+        Box::new(self.future(t0).take_while(move |range| range.start < end))
+    }
 }
@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 use chrono::Timelike;
 use chrono::Datelike;
 use chrono::Weekday;
@@ -131,6 +129,7 @@ pub fn find_weekend(mut date: Date, future: bool) -> Date {
     date
 }
 
+// Move `d` by n grains, months past the end of the target month clamp to it
 pub fn shift_datetime(d: DateTime, granularity: Grain, n: i32) -> DateTime {
     use crate::types::Grain::*;
     let m = if n >= 0 {n as u32} else {(-n) as u32};
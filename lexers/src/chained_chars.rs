@@ -0,0 +1,288 @@
+use crate::scanner::Scanner;
+use std::fmt;
+
+// Where a char came from, eg: "lib.lox:3:14". Lines and columns start at 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourcePos {
+    pub source: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for SourcePos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.source, self.line, self.col)
+    }
+}
+
+struct Source {
+    label: String,
+    chars: Box<dyn Iterator<Item = char>>,
+    line: usize,
+    col: usize,
+    // lookahead handed back by push_source, not an include of its own
+    resumed: bool,
+}
+
+// Position of the char at `offset` (chars read), columns advance from there
+// until the next Mark. There's one after every newline and source switch.
+struct Mark {
+    offset: usize,
+    label: usize,
+    line: usize,
+    col: usize,
+}
+
+// Char source where sub-sources (eg: included files) can be pushed. Each is
+// read to exhaustion before resuming the one it was pushed from. Positions
+// of everything read are kept to report them on errors. Scan it with
+// Scanner::push_source and Scanner::token_pos.
+pub struct ChainedChars {
+    stack: Vec<Source>,
+    labels: Vec<String>,
+    marks: Vec<Mark>,
+    // chars read so far
+    offset: usize,
+    needs_mark: bool,
+    max_depth: usize,
+}
+
+impl ChainedChars {
+    pub fn new(label: &str, source: impl Iterator<Item = char> + 'static) -> Self {
+        ChainedChars {
+            stack: vec![Source {
+                label: label.to_string(),
+                chars: Box::new(source),
+                line: 1,
+                col: 1,
+                resumed: false,
+            }],
+            labels: Vec::new(),
+            marks: Vec::new(),
+            offset: 0,
+            needs_mark: true,
+            max_depth: 16,
+        }
+    }
+
+    // Limit of nested pushes, to stop include cycles not caught by label
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    // Number of sources pushed on top of the original one still being read
+    pub fn depth(&self) -> usize {
+        self.stack.iter().filter(|s| !s.resumed).count().saturating_sub(1)
+    }
+
+    // Read `source` before resuming the current one. Fails if a source with
+    // the same label is being read (an include cycle) or on too much nesting.
+    pub fn push(&mut self, label: &str, source: impl Iterator<Item = char> + 'static)
+            -> Result<(), String> {
+        let open: Vec<_> = self.stack.iter().filter(|s| !s.resumed)
+            .map(|s| s.label.as_str()).collect();
+        if open.contains(&label) {
+            return Err(format!("include cycle: {} -> {}", open.join(" -> "), label));
+        }
+        if self.depth() >= self.max_depth {
+            return Err(format!("include depth over {} including {}", self.max_depth, label));
+        }
+        self.stack.push(Source {
+            label: label.to_string(),
+            chars: Box::new(source),
+            line: 1,
+            col: 1,
+            resumed: false,
+        });
+        self.needs_mark = true;
+        Ok(())
+    }
+
+    // Where the char at `offset` (0 based, counting every char read) came from
+    pub fn pos_at(&self, offset: usize) -> Option<SourcePos> {
+        if offset >= self.offset {
+            return None;
+        }
+        let idx = self.marks.partition_point(|m| m.offset <= offset).checked_sub(1)?;
+        let mark = &self.marks[idx];
+        Some(SourcePos {
+            source: self.labels[mark.label].clone(),
+            line: mark.line,
+            col: mark.col + offset - mark.offset,
+        })
+    }
+
+    // Give back the last chars read, they're read again after what's pushed
+    // next. Their position is the one they were first read at.
+    fn unread(&mut self, chars: Vec<char>) {
+        if chars.is_empty() {
+            return;
+        }
+        let offset = self.offset - chars.len();
+        let pos = self.pos_at(offset).expect("ChainedChars: unread more than read");
+        self.marks.retain(|m| m.offset < offset);
+        self.offset = offset;
+        self.needs_mark = true;
+        self.stack.push(Source {
+            label: pos.source,
+            chars: Box::new(chars.into_iter()),
+            line: pos.line,
+            col: pos.col,
+            resumed: true,
+        });
+    }
+
+    fn label_idx(&mut self, label: &str) -> usize {
+        match self.labels.iter().position(|l| l == label) {
+            Some(idx) => idx,
+            None => {
+                self.labels.push(label.to_string());
+                self.labels.len() - 1
+            }
+        }
+    }
+}
+
+impl Iterator for ChainedChars {
+    type Item = char;
+    fn next(&mut self) -> Option<char> {
+        loop {
+            let source = self.stack.last_mut()?;
+            let ch = match source.chars.next() {
+                Some(ch) => ch,
+                None => {
+                    self.stack.pop();
+                    self.needs_mark = true;
+                    continue;
+                }
+            };
+            let (line, col) = (source.line, source.col);
+            match ch {
+                '\n' => (source.line, source.col) = (line + 1, 1),
+                _ => source.col += 1,
+            }
+            if self.needs_mark {
+                let label = self.stack.last().unwrap().label.clone();
+                let label = self.label_idx(&label);
+                self.marks.push(Mark { offset: self.offset, label, line, col });
+            }
+            self.needs_mark = ch == '\n';
+            self.offset += 1;
+            return Some(ch);
+        }
+    }
+}
+
+impl Scanner<ChainedChars> {
+    // Switch to reading `source` from the scanner's position, chars already
+    // buffered ahead of it are read after `source` is done.
+    pub fn push_source(&mut self, label: &str, source: impl Iterator<Item = char> + 'static)
+            -> Result<(), String> {
+        let lookahead = self.unbuffer();
+        let chars = self.source_mut();
+        chars.unread(lookahead);
+        chars.push(label, source)
+    }
+
+    // Where the token being scanned (what extract returns next) starts
+    pub fn token_pos(&mut self) -> Option<SourcePos> {
+        let offset = self.extracted();
+        if self.source_mut().pos_at(offset).is_none() {
+            // nothing buffered yet, read the token's first char
+            self.peek()?;
+        }
+        self.source_mut().pos_at(offset)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::{ChainedChars, SourcePos};
+    use crate::Scanner;
+
+    fn pos(source: &str, line: usize, col: usize) -> Option<SourcePos> {
+        Some(SourcePos { source: source.to_string(), line, col })
+    }
+
+    #[test]
+    fn push_sources() {
+        let main = "a\ninclude b\nc";
+        let mut chars = ChainedChars::new("main", main.chars());
+        assert_eq!(chars.by_ref().take(2).collect::<String>(), "a\n");
+        chars.push("sub", "x\ny".chars()).unwrap();
+        assert_eq!(chars.depth(), 1);
+        assert_eq!(chars.collect::<String>(), "x\nyinclude b\nc");
+
+        let mut chars = ChainedChars::new("main", "ab".chars());
+        chars.next();
+        chars.push("sub", "xy".chars()).unwrap();
+        let read: String = chars.by_ref().collect();
+        assert_eq!(read, "xyb");
+        assert_eq!(chars.pos_at(0), pos("main", 1, 1));
+        assert_eq!(chars.pos_at(2), pos("sub", 1, 2));
+        assert_eq!(chars.pos_at(3), pos("main", 1, 2));
+        assert_eq!(chars.pos_at(4), None);
+    }
+
+    // "include name;" switches to the named source
+    fn tokens(mut s: Scanner<ChainedChars>, files: &[(&str, &str)])
+            -> Result<Vec<(String, String)>, String> {
+        let mut out = Vec::new();
+        loop {
+            s.scan_whitespace();
+            let at = match s.token_pos() {
+                Some(at) => at,
+                None => return Ok(out),
+            };
+            let token = s.scan_identifier().or_else(|| s.scan_number())
+                .or_else(|| s.next().map(|c| { s.extract(); c.to_string() }))
+                .unwrap();
+            if token == "include" {
+                s.scan_whitespace();
+                let name = s.scan_identifier().unwrap();
+                s.accept(&';');
+                s.extract();
+                let source = files.iter().find(|f| f.0 == name).unwrap().1.to_string();
+                let chars: Vec<_> = source.chars().collect();
+                s.push_source(&name, chars.into_iter())?;
+                continue;
+            }
+            out.push((token, at.to_string()));
+        }
+    }
+
+    #[test]
+    fn scan_includes() {
+        let files = [("lib", "x\n  y include util;z"), ("util", "1\n2")];
+        let s = Scanner::new(ChainedChars::new("main", "a include lib; b\nc".chars()));
+        assert_eq!(tokens(s, &files).unwrap(), vec![
+            ("a".to_string(), "main:1:1".to_string()),
+            ("x".to_string(), "lib:1:1".to_string()),
+            ("y".to_string(), "lib:2:3".to_string()),
+            ("1".to_string(), "util:1:1".to_string()),
+            ("2".to_string(), "util:2:1".to_string()),
+            ("z".to_string(), "lib:2:18".to_string()),
+            ("b".to_string(), "main:1:16".to_string()),
+            ("c".to_string(), "main:2:1".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn include_limits() {
+        let files = [("a", "include b;"), ("b", "include a;")];
+        let s = Scanner::new(ChainedChars::new("main", "include a;".chars()));
+        assert_eq!(tokens(s, &files).unwrap_err(), "include cycle: main -> a -> b -> a");
+
+        let mut chars = ChainedChars::new("main", "".chars()).max_depth(2);
+        chars.push("1", "".chars()).unwrap();
+        chars.push("2", "".chars()).unwrap();
+        assert_eq!(chars.push("3", "".chars()).unwrap_err(), "include depth over 2 including 3");
+        // done sources no longer count
+        assert_eq!(chars.next(), None);
+        assert_eq!(chars.depth(), 0);
+        assert!(chars.push("1", "".chars()).is_ok());
+    }
+}
@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 use crate::scanner::Scanner;
 
 // A tokenizer that splits input on each delimiter
@@ -7,6 +5,12 @@ pub struct DelimTokenizer<I: Iterator<Item = char>> {
     src: Scanner<I>,
     delims: Vec<char>,
     remove: bool, // drop the delimiters ?
+    // keep quoted spans as a single token, see `quoted`
+    quoted: bool,
+    // why tokenization stopped before the end of input
+    error: Option<String>,
+    // marker emitted once after the last token, see `eof`
+    eof: Option<String>,
 }
 
 impl<I: Iterator<Item = char>> DelimTokenizer<I> {
@@ -15,25 +19,152 @@ impl<I: Iterator<Item = char>> DelimTokenizer<I> {
             src: Scanner::new(src),
             delims: delims.chars().collect(),
             remove,
+            quoted: false,
+            error: None,
+            eof: None,
         }
     }
-}
 
-impl<I: Iterator<Item = char>> Iterator for DelimTokenizer<I> {
-    type Item = String;
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.src.until_any(&self.delims) {
-            Some(self.src.extract_string())
+    // Tokens starting with a single or double quote run up to the matching
+    // one, delimiters included. Quotes are stripped and backslash escapes
+    // resolved, eg: `say "hello \"world\"" now` is say, hello "world", now
+    pub fn quoted(mut self) -> Self {
+        self.quoted = true;
+        self
+    }
+
+    // End the tokens with `marker`, eg: "$", for grammars with rules that
+    // must be followed by the end of input. Not emitted after an error.
+    pub fn eof(mut self, marker: &str) -> Self {
+        self.eof = Some(marker.to_string());
+        self
+    }
+
+    // Set when an unterminated quote ended tokenization
+    pub fn last_error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    // Same as `next` but an unterminated quote is an error instead of the
+    // end of tokens. Tokens end after it, same as at the end of input.
+    pub fn next_result(&mut self) -> Result<Option<String>, String> {
+        if self.error.is_some() {
+            Ok(None)
+        } else if let Some(q) = self.src.peek().filter(|c| self.quoted && ['"', '\''].contains(c)) {
+            match self.src.scan_quoted_string(q) {
+                Some(quoted) => Ok(Some(unquote(&quoted))),
+                None => Err(self.error.insert(format!("unterminated {} quote", q)).clone()),
+            }
+        } else if self.src.until_any(&self.delims) {
+            Ok(Some(self.src.extract_string()))
         } else if let Some(c) = self.src.accept_any(&self.delims) {
             self.src.extract(); // ignore
             if self.remove {
-                self.next()
+                self.next_result()
             } else {
-                Some(c.to_string())
+                Ok(Some(c.to_string()))
             }
         } else {
-            None
+            Ok(self.eof.take())
+        }
+    }
+}
+
+// The contents of a quoted token with backslash escapes resolved
+fn unquote(quoted: &str) -> String {
+    let mut unquoted = String::new();
+    let mut chars = quoted[1..quoted.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        unquoted.extend(if c == '\\' { chars.next() } else { Some(c) });
+    }
+    unquoted
+}
+
+#[cfg(feature = "regex")]
+impl DelimTokenizer<std::vec::IntoIter<char>> {
+    // Split input on each match of the regex, see RegexTokenizer
+    pub fn from_regex<'a>(input: &'a str, delimiter: &'a regex::Regex) -> RegexTokenizer<'a> {
+        RegexTokenizer::new(input, delimiter)
+    }
+}
+
+// Splits input on each match of a regex, eg: `\s*,\s*`, finding the next
+// match as tokens are read. Delimiters are dropped and so are empty tokens,
+// same as DelimTokenizer::new with remove, eg: `d,,e` is d, e.
+#[cfg(feature = "regex")]
+pub struct RegexTokenizer<'a> {
+    input: &'a str,
+    delimiter: &'a regex::Regex,
+    pos: usize, // bytes of input already split
+    // keep quoted spans as a single token, see `quoted`
+    quoted: bool,
+    // why tokenization stopped before the end of input
+    error: Option<String>,
+    // marker emitted once after the last token, see `eof`
+    eof: Option<String>,
+}
+
+#[cfg(feature = "regex")]
+impl<'a> RegexTokenizer<'a> {
+    pub fn new(input: &'a str, delimiter: &'a regex::Regex) -> Self {
+        RegexTokenizer{input, delimiter, pos: 0, quoted: false, error: None, eof: None}
+    }
+
+    // Same as DelimTokenizer::quoted, matches inside quotes don't split
+    pub fn quoted(mut self) -> Self {
+        self.quoted = true;
+        self
+    }
+
+    // Same as DelimTokenizer::eof
+    pub fn eof(mut self, marker: &str) -> Self {
+        self.eof = Some(marker.to_string());
+        self
+    }
+
+    // Set when an unterminated quote ended tokenization
+    pub fn last_error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    // Same as DelimTokenizer::next_result
+    pub fn next_result(&mut self) -> Result<Option<String>, String> {
+        while self.error.is_none() && self.pos < self.input.len() {
+            let rest = &self.input[self.pos..];
+            if let Some(q) = rest.chars().next().filter(|c| self.quoted && ['"', '\''].contains(c)) {
+                let Some(quoted) = Scanner::new(rest.chars()).scan_quoted_string(q) else {
+                    return Err(self.error.insert(format!("unterminated {} quote", q)).clone());
+                };
+                self.pos += quoted.len();
+                return Ok(Some(unquote(&quoted)));
+            }
+            // empty matches delimit nothing
+            let token = match self.delimiter.find_iter(rest).find(|m| !m.is_empty()) {
+                Some(m) => { self.pos += m.end(); &rest[..m.start()] },
+                None => { self.pos = self.input.len(); rest },
+            };
+            if !token.is_empty() {
+                return Ok(Some(token.to_string()));
+            }
         }
+        Ok(if self.error.is_none() { self.eof.take() } else { None })
+    }
+}
+
+#[cfg(feature = "regex")]
+impl Iterator for RegexTokenizer<'_> {
+    type Item = String;
+    // Tokens end at an unterminated quote, see last_error
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_result().ok().flatten()
+    }
+}
+
+impl<I: Iterator<Item = char>> Iterator for DelimTokenizer<I> {
+    type Item = String;
+    // Tokens end at an unterminated quote, see last_error
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_result().ok().flatten()
     }
 }
 
@@ -65,4 +196,67 @@ mod tests {
             assert_eq!(lx.next(), None);
         }
     }
+
+    #[test]
+    fn quoted() {
+        let tokens: Vec<_> = DelimTokenizer::new(r#"say "hello world" now"#.chars(), " ", true)
+            .quoted().collect();
+        assert_eq!(tokens, vec!["say", "hello world", "now"]);
+        let input = r#"'it''s' "a \"b\" \\c",'' x"#;
+        let tokens: Vec<_> = DelimTokenizer::new(input.chars(), " ,", true).quoted().collect();
+        assert_eq!(tokens, vec!["it", "s", r#"a "b" \c"#, "", "x"]);
+        // quotes are just characters unless asked for
+        let tokens: Vec<_> = DelimTokenizer::new(r#"say "hi there""#.chars(), " ", true).collect();
+        assert_eq!(tokens, vec!["say", "\"hi", "there\""]);
+        let mut lx = DelimTokenizer::new(r#"say "hello world"#.chars(), " ", true).quoted();
+        assert_eq!(lx.next().as_deref(), Some("say"));
+        assert_eq!(lx.next(), None);
+        assert_eq!(lx.last_error(), Some("unterminated \" quote".to_string()));
+        assert_eq!(lx.next(), None);
+        let mut lx = DelimTokenizer::new("a 'b c".chars(), " ", true).quoted();
+        assert_eq!(lx.next_result(), Ok(Some("a".to_string())));
+        assert_eq!(lx.next_result(), Err("unterminated ' quote".to_string()));
+        assert_eq!(lx.next_result(), Ok(None));
+    }
+
+    #[test]
+    fn eof() {
+        let tokens: Vec<_> = DelimTokenizer::new("1 + 2 ".chars(), " ", true).eof("$").collect();
+        assert_eq!(tokens, vec!["1", "+", "2", "$"]);
+        let mut lx = DelimTokenizer::new("".chars(), " ", true).eof("<EOF>");
+        assert_eq!(lx.next().as_deref(), Some("<EOF>"));
+        assert_eq!(lx.next(), None);
+        let tokens: Vec<_> = DelimTokenizer::new("a 'b".chars(), " ", true)
+            .quoted().eof("$").collect();
+        assert_eq!(tokens, vec!["a"]);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn from_regex() {
+        let spaces = regex::Regex::new(r"\s+").unwrap();
+        let tokens: Vec<_> = DelimTokenizer::from_regex("  this is\t\na  test ", &spaces).collect();
+        assert_eq!(tokens, vec!["this", "is", "a", "test"]);
+        let separators = regex::Regex::new(r"\s*[,;]\s*").unwrap();
+        let input = "a , b;c ;  d,,e ;";
+        let tokens: Vec<_> = DelimTokenizer::from_regex(input, &separators).collect();
+        assert_eq!(tokens, vec!["a", "b", "c", "d", "e"]);
+        assert_eq!(DelimTokenizer::from_regex("", &spaces).next(), None);
+        assert_eq!(DelimTokenizer::from_regex(" ; ", &separators).next(), None);
+        let tokens: Vec<_> = DelimTokenizer::from_regex("1 2 ", &spaces).eof("$").collect();
+        assert_eq!(tokens, vec!["1", "2", "$"]);
+        // empty matches can't split, `\s*` only splits on spaces
+        let maybe_spaces = regex::Regex::new(r"\s*").unwrap();
+        let tokens: Vec<_> = DelimTokenizer::from_regex("ab  c", &maybe_spaces).collect();
+        assert_eq!(tokens, vec!["ab", "c"]);
+        // delimiters inside quotes don't split
+        let input = r#"a, "b, c",'d' , "é\"""#;
+        let tokens: Vec<_> = DelimTokenizer::from_regex(input, &separators).quoted().collect();
+        assert_eq!(tokens, vec!["a", "b, c", "d", "é\""]);
+        let mut lx = DelimTokenizer::from_regex("a, 'b, c", &separators).quoted().eof("$");
+        assert_eq!(lx.next_result(), Ok(Some("a".to_string())));
+        assert_eq!(lx.next_result(), Err("unterminated ' quote".to_string()));
+        assert_eq!(lx.next_result(), Ok(None));
+        assert_eq!(lx.last_error(), Some("unterminated ' quote".to_string()));
+    }
 }
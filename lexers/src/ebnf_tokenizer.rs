@@ -1,31 +1,44 @@
-#![deny(warnings)]
-
+use crate::helpers::{is_ident_char, is_ident_start};
 use crate::scanner::Scanner;
+use std::collections::VecDeque;
 
 pub struct EbnfTokenizer<I: Iterator<Item = char>> {
     input: Scanner<I>,
-    lookahead: Vec<String>,
+    // tokens already scanned but not yet consumed, in order
+    lookahead: VecDeque<String>,
+    // why tokenization stopped before the end of input
+    error: Option<String>,
 }
 
 impl<I: Iterator<Item = char>> EbnfTokenizer<I> {
     pub fn new(source: I) -> Self {
         EbnfTokenizer {
             input: Scanner::new(source),
-            lookahead: Vec::new(),
+            lookahead: VecDeque::new(),
+            error: None,
         }
     }
 
+    // Set when a malformed token ended tokenization, eg: ':' without '='
+    pub fn last_error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
     pub fn scanner(source: I) -> Scanner<Self> {
         Scanner::new(Self::new(source))
     }
-}
 
-impl<I: Iterator<Item = char>> Iterator for EbnfTokenizer<I> {
-    type Item = String;
-    fn next(&mut self) -> Option<Self::Item> {
-        // used for accumulating string parts
-        if !self.lookahead.is_empty() {
-            return self.lookahead.pop();
+    // Peek the n-th (0-indexed) upcoming token without consuming it
+    pub fn lookahead(&mut self, n: usize) -> Option<&str> {
+        while self.lookahead.len() <= n && self.scan() {}
+        self.lookahead.get(n).map(String::as_str)
+    }
+
+    // Scan more input queueing tokens on the lookahead buffer.
+    // Returns false when there's no more tokens to be read.
+    fn scan(&mut self) -> bool {
+        if self.error.is_some() {
+            return false;
         }
         let s = &mut self.input;
         s.scan_whitespace();
@@ -35,45 +48,128 @@ impl<I: Iterator<Item = char>> Iterator for EbnfTokenizer<I> {
                 if nl == '\n' {
                     s.extract(); // ignore comment
                                  // discard comment and allow more by restarting
-                    return self.next();
+                    return self.scan();
                 }
             }
         }
-        if s.accept_any(&['[', ']', '{', '}', '(', ')', '|', ';'])
+        if s.accept_any_string(&["[", "]", "{", "}", "(", ")", "|", ";", ":=", "::="])
             .is_some()
         {
-            return Some(s.extract_string());
+            // BNF style '::=' is the same assignment
+            let token = s.extract_string().replace("::=", ":=");
+            self.lookahead.push_back(token);
+            return true;
         }
-        let backtrack = s.buffer_pos();
         if s.accept(&':').is_some() {
-            if s.accept(&'=').is_some() {
-                return Some(s.extract_string());
-            }
-            s.set_buffer_pos(backtrack);
+            self.error = Some(format!("expected ':=' or '::=', found {:?}", s.extract_string()));
+            return false;
         }
         let backtrack = s.buffer_pos();
         if let Some(q) = s.accept_any(&['"', '\'']) {
             while let Some(n) = s.next() {
                 if n == q {
-                    // store closing quote
-                    self.lookahead.push(n.to_string());
-                    // store string content
+                    // split into opening quote, string content, closing quote
                     let v = s.extract_string();
-                    self.lookahead.push(v[1..v.len() - 1].to_string());
-                    // return opening quote
-                    return Some(q.to_string());
+                    self.lookahead.push_back(q.to_string());
+                    self.lookahead.push_back(v[1..v.len() - 1].to_string());
+                    self.lookahead.push_back(n.to_string());
+                    return true;
                 }
             }
             s.set_buffer_pos(backtrack);
         }
-        let backtrack = s.buffer_pos();
-        s.accept(&'@');
-        // NOTE: scan_identifier limits the valid options
-        if let Some(id) = s.scan_identifier() {
-            return Some(id);
+        // '@' can only go first, it starts tags like @named-month
+        if s.accept_hyphenated(|c| c == '@' || is_ident_start(c), is_ident_char) {
+            let id = s.extract_string();
+            if id == "@" {
+                self.error = Some("expected a tag name after '@'".to_string());
+                return false;
+            }
+            self.lookahead.push_back(id);
+            return true;
+        }
+        false
+    }
+}
+
+impl<I: Iterator<Item = char>> Iterator for EbnfTokenizer<I> {
+    type Item = String;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.lookahead.is_empty() {
+            self.scan();
+        }
+        self.lookahead.pop_front()
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::EbnfTokenizer;
+
+    #[test]
+    fn ebnf_tokenizer() {
+        let input = r#"expr := term "+" @tag # comment
+                       | [ x ] ;"#;
+        let expected = vec!["expr", ":=", "term", "\"", "+", "\"", "@tag",
+                            "|", "[", "x", "]", ";"];
+        let tokens: Vec<String> = EbnfTokenizer::new(input.chars()).collect();
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn hyphenated_names() {
+        let tokens: Vec<String> = EbnfTokenizer::new("day-of-week := @named-month ;".chars())
+            .collect();
+        assert_eq!(tokens, vec!["day-of-week", ":=", "@named-month", ";"]);
+        // a leading hyphen doesn't start an identifier
+        assert_eq!(EbnfTokenizer::new("-month".chars()).next(), None);
+        let tokens: Vec<String> = EbnfTokenizer::new("rule_2 := @tag_1 x9 ;".chars()).collect();
+        assert_eq!(tokens, vec!["rule_2", ":=", "@tag_1", "x9", ";"]);
+        // nor does a digit
+        assert_eq!(EbnfTokenizer::new("2x".chars()).next(), None);
+        let mut lx = EbnfTokenizer::new("a := @ b ;".chars());
+        assert_eq!(lx.by_ref().collect::<Vec<_>>(), vec!["a", ":="]);
+        assert_eq!(lx.last_error(), Some("expected a tag name after '@'".to_string()));
+    }
+
+    #[test]
+    fn assignment() {
+        for input in ["a := b ;", "a ::= b ;", "a::=b;"] {
+            let mut lx = EbnfTokenizer::new(input.chars());
+            assert_eq!(lx.by_ref().collect::<Vec<_>>(), vec!["a", ":=", "b", ";"]);
+            assert_eq!(lx.last_error(), None);
+        }
+        for input in ["a : b ;", "a: b ;", "a :: b ;", "a :"] {
+            let mut lx = EbnfTokenizer::new(input.chars());
+            assert_eq!(lx.by_ref().collect::<Vec<_>>(), vec!["a"]);
+            assert!(lx.last_error().unwrap().starts_with("expected ':=' or '::=', found"), "{}", input);
+        }
+        let mut lx = EbnfTokenizer::new("a : b".chars());
+        lx.by_ref().count();
+        assert_eq!(lx.last_error().unwrap(), "expected ':=' or '::=', found \":\"");
+        // tokenization doesn't resume past the error
+        assert_eq!(lx.next(), None);
+        assert_eq!(lx.lookahead(0), None);
+    }
+
+    #[test]
+    fn lookahead() {
+        let mut lx = EbnfTokenizer::new(r#"a := "b" c ;"#.chars());
+        // peeking is stable and doesn't consume
+        assert_eq!(lx.lookahead(2), Some("\""));
+        assert_eq!(lx.lookahead(2), Some("\""));
+        assert_eq!(lx.lookahead(3), Some("b"));
+        assert_eq!(lx.lookahead(0), Some("a"));
+        assert_eq!(lx.next().as_deref(), Some("a"));
+        assert_eq!(lx.lookahead(0), Some(":="));
+        assert_eq!(lx.next().as_deref(), Some(":="));
+        // lookahead(0) agrees with next() across string splits
+        while let Some(peeked) = lx.lookahead(0).map(str::to_string) {
+            assert_eq!(lx.next(), Some(peeked));
         }
-        // backtrack possible '@'
-        s.set_buffer_pos(backtrack);
-        None
+        assert_eq!(lx.lookahead(5), None);
+        assert_eq!(lx.next(), None);
     }
 }
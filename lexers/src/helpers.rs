@@ -1,5 +1,4 @@
-#![deny(warnings)]
-
+use crate::math_tokenizer::MathToken;
 use crate::scanner::Scanner;
 
 static WHITE: &[char] = &[' ', '\n', '\r', '\t'];
@@ -8,17 +7,15 @@ static HEXDIGITS: &[char] = &[
     '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f', 'A', 'B', 'C',
     'D', 'E', 'F',
 ];
-static ALPHA: &[char] = &[
-    '_', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r',
-    's', 't', 'u', 'v', 'w', 'x', 'y', 'z', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K',
-    'L', 'M', 'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z',
-];
-static ALNUM: &[char] = &[
-    '_', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h',
-    'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', 'A',
-    'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R', 'S', 'T',
-    'U', 'V', 'W', 'X', 'Y', 'Z',
-];
+// [a-zA-Z_], what scan_identifier takes first
+pub(crate) fn is_ident_start(c: char) -> bool {
+    c == '_' || c.is_ascii_alphabetic()
+}
+
+// [a-zA-Z0-9_]
+pub(crate) fn is_ident_char(c: char) -> bool {
+    c == '_' || c.is_ascii_alphanumeric()
+}
 
 impl<I: Iterator<Item = char>> Scanner<I> {
     pub fn extract_string(&mut self) -> String {
@@ -30,6 +27,17 @@ impl<I: Iterator<Item = char>> Scanner<I> {
         Some(self.extract_string())
     }
 
+    // Skip a '#!...' first line of a script, up to but not including the
+    // new-line so line counts stay right. Only at the start of input.
+    pub fn skip_shebang(&mut self) -> bool {
+        if !self.at_start() || !self.accept_all("#!".chars()) {
+            return false;
+        }
+        self.until_any(&['\n']);
+        self.extract();
+        true
+    }
+
     // scan numbers like -?[0-9]+(\.[0-9]+)?([eE][+-][0-9]+)?
     pub fn scan_number(&mut self) -> Option<String> {
         let backtrack = self.buffer_pos();
@@ -59,6 +67,46 @@ impl<I: Iterator<Item = char>> Scanner<I> {
         Some(self.extract_string())
     }
 
+    // Digits optionally grouped by underscores like 5_000
+    fn skip_grouped_digits(&mut self) -> bool {
+        if !self.skip_all(DIGITS) {
+            return false;
+        }
+        loop {
+            let backtrack = self.buffer_pos();
+            if self.accept(&'_').is_none() || !self.skip_all(DIGITS) {
+                self.set_buffer_pos(backtrack);
+                return true;
+            }
+        }
+    }
+
+    // scan numbers like 123, -3.14 or 1.5e-10 into a MathToken::Number.
+    // Unlike scan_number either the integer or the fractional part can be
+    // missing (.5 or 5.) and digits can be grouped (5_000).
+    pub fn scan_number_token(&mut self) -> Option<MathToken> {
+        let backtrack = self.buffer_pos();
+        // optional sign
+        self.accept_any(&['+', '-']);
+        let integer = self.skip_grouped_digits();
+        let fraction = self.accept(&'.').is_some() && self.skip_grouped_digits();
+        // a lone '.' isn't a number
+        if !integer && !fraction {
+            self.set_buffer_pos(backtrack);
+            return None;
+        }
+        // check for exponent part
+        let backtrack = self.buffer_pos();
+        if self.accept_any(&['e', 'E']).is_some() {
+            self.accept_any(&['+', '-']); // exponent sign is optional
+            if !self.skip_all(DIGITS) {
+                self.set_buffer_pos(backtrack);
+            }
+        }
+        let lexeme: String = self.extract().into_iter().filter(|c| *c != '_').collect();
+        lexeme.parse().ok().map(MathToken::Number)
+    }
+
     pub fn scan_math_op(&mut self) -> Option<String> {
         const OPS: &[char] = &['+', '-', '*', '/', '%', '^', '!', '(', ')', ','];
         if self.accept_any(&['>', '=', '<']).is_some() {
@@ -68,6 +116,8 @@ impl<I: Iterator<Item = char>> Scanner<I> {
         } else if self.accept(&':').is_some() && self.accept(&'=').is_some() {
             // accept ':='. Set delayed to avoid immediate eval of rhs.
             Some(self.extract_string())
+        } else if self.accept_any_string(&["&&", "||", "!="]).is_some() {
+            Some(self.extract_string())
         } else if self.accept(&'*').is_some() {
             // accept '*', '**'
             self.accept(&'*');
@@ -79,6 +129,15 @@ impl<I: Iterator<Item = char>> Scanner<I> {
         }
     }
 
+    // Accept the longest of the fixed 'candidates' matching upcoming chars,
+    // eg: ":=" over ":". The scanner doesn't move if none matches.
+    pub fn accept_any_string<'a>(&mut self, candidates: &[&'a str]) -> Option<&'a str> {
+        let mut candidates = candidates.to_vec();
+        candidates.sort_by_key(|c| std::cmp::Reverse(c.len()));
+        candidates.into_iter()
+            .find(|c| !c.is_empty() && self.accept_all(c.chars()))
+    }
+
     // scan integers like 0x34 0b10101 0o657
     pub fn scan_integer(&mut self) -> Option<String> {
         let backtrack = self.buffer_pos();
@@ -113,11 +172,86 @@ impl<I: Iterator<Item = char>> Scanner<I> {
         None
     }
 
+    // scan a delimited block like (a (b) c) including its delimiters,
+    // when nested inner open/close pairs need to be balanced
+    pub fn accept_block(&mut self, open: char, close: char, nested: bool) -> Option<String> {
+        let backtrack = self.buffer_pos();
+        self.accept(&open)?;
+        let mut depth = 1;
+        while let Some(n) = self.next() {
+            if n == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(self.extract_string());
+                }
+            } else if nested && n == open {
+                depth += 1;
+            }
+        }
+        self.set_buffer_pos(backtrack);
+        None
+    }
+
+    // Advance over a char matching `start` then all matching `cont`
+    pub(crate) fn accept_identifier(&mut self, start: impl Fn(char) -> bool,
+                                    cont: impl Fn(char) -> bool) -> bool {
+        if !self.peek().is_some_and(start) {
+            return false;
+        }
+        self.next();
+        while self.peek().is_some_and(&cont) {
+            self.next();
+        }
+        true
+    }
+
+    // Inner hyphens need a `cont` char after them: "-a" or "a-" aren't taken
+    pub(crate) fn accept_hyphenated(&mut self, start: impl Fn(char) -> bool,
+                                    cont: impl Fn(char) -> bool) -> bool {
+        if !self.accept_identifier(start, &cont) {
+            return false;
+        }
+        loop {
+            let backtrack = self.buffer_pos();
+            if self.accept(&'-').is_none() {
+                break;
+            }
+            if !self.accept_identifier(&cont, &cont) {
+                self.set_buffer_pos(backtrack);
+                break;
+            }
+        }
+        true
+    }
+
+    // scan a char matching `start` followed by any matching `cont`, for
+    // identifiers beyond [a-zA-Z0-9_], eg: set-car! in Lisp
+    pub fn scan_identifier_with(&mut self, start: impl Fn(char) -> bool,
+                                cont: impl Fn(char) -> bool) -> Option<String> {
+        match self.accept_identifier(start, cont) {
+            true => Some(self.extract_string()),
+            false => None,
+        }
+    }
+
     // scan [a-zA-Z_][a-zA-Z0-9_]+
     pub fn scan_identifier(&mut self) -> Option<String> {
-        self.accept_any(ALPHA)?;
-        self.skip_all(ALNUM);
-        Some(self.extract_string())
+        self.scan_identifier_with(is_ident_start, is_ident_char)
+    }
+
+    // scan identifiers made of unicode letters, digits and '_', eg: año
+    pub fn scan_unicode_identifier(&mut self) -> Option<String> {
+        self.scan_identifier_with(|c| c == '_' || c.is_alphabetic(),
+                                  |c| c == '_' || c.is_alphanumeric())
+    }
+
+    // scan identifiers with inner hyphens, eg: day-of-week.
+    // A hyphen needs an [a-zA-Z0-9_] after it: "-a" or "a-" aren't taken.
+    pub fn scan_hyphenated_identifier(&mut self) -> Option<String> {
+        match self.accept_hyphenated(is_ident_start, is_ident_char) {
+            true => Some(self.extract_string()),
+            false => None,
+        }
     }
 
     // scan an optional prefix (unit multiplier) and unit
@@ -140,7 +274,13 @@ impl<I: Iterator<Item = char>> Scanner<I> {
             let pfx_backtrack = self.buffer_pos();
             if self.accept_all(prefix.chars()) {
                 for unit in BARE_UNITS {
+                    let unit_backtrack = self.buffer_pos();
                     if self.accept_all(unit.chars()) {
+                        // the unit has to end the word: "7 mod" isn't 7 m
+                        if self.peek().is_some_and(|c| c.is_alphanumeric()) {
+                            self.set_buffer_pos(unit_backtrack);
+                            continue;
+                        }
                         self.extract_string(); // ignore
                         return Some((prefix.to_string(), unit.to_string()))
                     }
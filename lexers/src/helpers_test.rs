@@ -1,3 +1,4 @@
+use crate::math_tokenizer::MathToken;
 use crate::scanner::Scanner;
 
 #[test]
@@ -36,11 +37,45 @@ fn scan_number() {
     }
 }
 
+#[test]
+fn scan_number_token() {
+    let tests = vec![
+        ("123", 123.0),
+        ("2.5", 2.5),
+        ("1.5e-10", 1.5e-10),
+        ("-42", -42.0),
+        ("+7", 7.0),
+        (".5", 0.5),
+        ("-.5", -0.5),
+        ("5.", 5.0),
+        ("5.e3", 5e3),
+        ("5e10", 5e10),
+        ("5E+2", 5e2),
+        ("5_000", 5000.0),
+        ("1_000_000.000_5", 1000000.0005),
+    ];
+    for (t, expected) in tests {
+        let result = Scanner::new(t.chars()).scan_number_token();
+        assert_eq!(result, Some(MathToken::Number(expected)), "{}", t);
+    }
+    // what isn't part of the number is left for the next token
+    for (t, expected, rest) in [("5_", 5.0, "_"), ("2e", 2.0, "e"), ("3._", 3.0, "_")] {
+        let mut s = Scanner::new(t.chars());
+        assert_eq!(s.scan_number_token(), Some(MathToken::Number(expected)));
+        assert_eq!(s.scan_identifier().as_deref(), Some(rest));
+    }
+    for t in [".", "-", "-.", "_5", "e5", ".e5"] {
+        let mut s = Scanner::new(t.chars());
+        assert_eq!(s.scan_number_token(), None, "{}", t);
+        assert_eq!(s.next(), t.chars().next());
+    }
+}
+
 #[test]
 fn scan_math_ops() {
     let tests = vec![
         "<", "<=", "=", "==", ">=", ">", "(", ")", ",", "*",
-        "**", "^", "!", "+", "-", "/", "%", ":=",
+        "**", "^", "!", "+", "-", "/", "%", ":=", "&&", "||", "!=",
     ];
     for t in tests.iter() {
         let result = Scanner::new(t.chars()).scan_math_op();
@@ -49,6 +84,11 @@ fn scan_math_ops() {
     // Negative tests
     let result = Scanner::new(":".chars()).scan_math_op();
     assert_eq!(result, None);
+    let result = Scanner::new("&".chars()).scan_math_op();
+    assert_eq!(result, None);
+    // '!' not followed by '=' is still factorial or not
+    let mut scanner = Scanner::new("!x".chars());
+    assert_eq!(scanner.scan_math_op(), Some("!".to_string()));
 }
 
 #[test]
@@ -60,6 +100,75 @@ fn scan_identifiers() {
     }
 }
 
+#[test]
+fn scan_identifiers_with() {
+    assert_eq!(Scanner::new("1abc".chars()).scan_identifier(), None);
+    // ascii only unless asked for
+    assert_eq!(Scanner::new("año".chars()).scan_identifier(), Some("a".to_string()));
+    assert_eq!(Scanner::new("año = 1".chars()).scan_unicode_identifier(), Some("año".to_string()));
+    assert_eq!(Scanner::new("λ_2x".chars()).scan_unicode_identifier(), Some("λ_2x".to_string()));
+    assert_eq!(Scanner::new("2λ".chars()).scan_unicode_identifier(), None);
+    let lisp = |c: char| c.is_alphanumeric() || "-?!*".contains(c);
+    let mut s = Scanner::new("set-car! x".chars());
+    assert_eq!(s.scan_identifier_with(|c| !c.is_ascii_digit() && lisp(c), lisp),
+               Some("set-car!".to_string()));
+    // nothing is consumed on a mismatch
+    let mut s = Scanner::new("9x".chars());
+    assert_eq!(s.scan_identifier_with(|c| c.is_alphabetic(), lisp), None);
+    assert_eq!(s.buffer_pos(), -1);
+}
+
+#[test]
+fn scan_hyphenated_identifiers() {
+    let tests = [("day-of-week", "day-of-week"), ("a-1_b", "a-1_b"),
+                     ("month-", "month"), ("a--b", "a"), ("x -y", "x")];
+    for (t, expected) in tests.iter() {
+        let result = Scanner::new(t.chars()).scan_hyphenated_identifier();
+        assert_eq!(Some(expected.to_string()), result);
+    }
+    assert_eq!(Scanner::new("-month".chars()).scan_hyphenated_identifier(), None);
+    // the plain variant stops at hyphens
+    assert_eq!(Scanner::new("day-of-week".chars()).scan_identifier(), Some("day".to_string()));
+}
+
+#[test]
+fn accept_any_string() {
+    let mut s = Scanner::new(":= : =:".chars());
+    // longest candidate wins regardless of order
+    assert_eq!(s.accept_any_string(&[":", ":=", "="]), Some(":="));
+    assert_eq!(s.extract_string(), ":=");
+    s.scan_whitespace();
+    assert_eq!(s.accept_any_string(&[":", ":="]), Some(":"));
+    assert_eq!(s.extract_string(), ":");
+    s.scan_whitespace();
+    // no match leaves position untouched
+    assert_eq!(s.accept_any_string(&[":=", "=="]), None);
+    assert_eq!(s.accept_any_string(&[]), None);
+    assert_eq!(s.buffer_pos(), -1);
+    assert_eq!(s.accept_any_string(&["=:"]), Some("=:"));
+    assert_eq!(s.accept_any_string(&[":"]), None);
+}
+
+#[test]
+fn skip_shebang() {
+    let mut s = Scanner::new("#!/usr/bin/env lox\nvar x;".chars());
+    assert!(s.skip_shebang());
+    assert_eq!(s.next(), Some('\n'));
+    assert!(!s.skip_shebang());
+    // only at the start of input
+    let mut s = Scanner::new(" #!/bin/sh".chars());
+    assert!(!s.skip_shebang());
+    s.scan_whitespace();
+    assert!(!s.skip_shebang());
+    assert_eq!(s.next(), Some('#'));
+    let mut s = Scanner::new("#not!".chars());
+    assert!(!s.skip_shebang());
+    assert_eq!(s.next(), Some('#'));
+    let mut s = Scanner::new("#!".chars());
+    assert!(s.skip_shebang());
+    assert_eq!(s.next(), None);
+}
+
 #[test]
 fn scan_string() {
     let tests = vec![
@@ -72,6 +181,26 @@ fn scan_string() {
     }
 }
 
+#[test]
+fn accept_block() {
+    let result = Scanner::new("(a (b) c) d".chars()).accept_block('(', ')', true);
+    assert_eq!(result, Some("(a (b) c)".to_string()));
+    let result = Scanner::new("(a (b) c) d".chars()).accept_block('(', ')', false);
+    assert_eq!(result, Some("(a (b)".to_string()));
+    let result = Scanner::new("[[x]]".chars()).accept_block('[', ']', true);
+    assert_eq!(result, Some("[[x]]".to_string()));
+    let result = Scanner::new("{}".chars()).accept_block('{', '}', true);
+    assert_eq!(result, Some("{}".to_string()));
+    // not at an open delimiter, or never closed: backtrack
+    let mut s = Scanner::new("x(y)".chars());
+    assert_eq!(s.accept_block('(', ')', true), None);
+    assert_eq!(s.buffer_pos(), -1);
+    let mut s = Scanner::new("(a (b)".chars());
+    assert_eq!(s.accept_block('(', ')', true), None);
+    assert_eq!(s.buffer_pos(), -1);
+    assert_eq!(s.accept_block('(', ')', false), Some("(a (b)".to_string()));
+}
+
 #[test]
 fn scan_units() {
     static PFX: &[&str] = &[
@@ -91,4 +220,9 @@ fn scan_units() {
             assert_eq!(result, Some((prefix.to_string(), unit_base.to_string())));
         }
     }
+    // a unit followed by more letters is a word
+    let mut s = Scanner::new("mod".chars());
+    assert_eq!(s.scan_unit(), None);
+    assert_eq!(s.buffer_pos(), -1);
+    assert_eq!(Scanner::new("ms*2".chars()).scan_unit(), Some(("m".to_string(), "s".to_string())));
 }
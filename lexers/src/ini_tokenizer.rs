@@ -0,0 +1,148 @@
+use crate::scanner::Scanner;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum IniToken {
+    Section(String),
+    Key(String),
+    Assign,
+    Value(String),
+    // Malformed input at (line, message)
+    Error(usize, String),
+}
+
+// Tokenizes INI style configs, ie: `[section]` headers and `key = value`
+// lines. Values run until end-of-line. Comments start with ';' or '#'.
+pub struct IniTokenizer<I: Iterator<Item = char>> {
+    src: Scanner<I>,
+    line: usize,
+    after_key: bool,
+    after_assign: bool,
+}
+
+impl<I: Iterator<Item = char>> IniTokenizer<I> {
+    pub fn new(source: I) -> Self {
+        IniTokenizer {
+            src: Scanner::new(source),
+            line: 1,
+            after_key: false,
+            after_assign: false,
+        }
+    }
+
+    pub fn scanner(source: I) -> Scanner<Self> {
+        Scanner::new(Self::new(source))
+    }
+
+    // skip whitespace keeping track of line numbers
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.src.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            if c == '\n' {
+                self.line += 1;
+            }
+            self.src.next();
+        }
+        self.src.extract(); // ignore
+    }
+
+    // consume the rest of the current line, excluding the '\n'
+    fn rest_of_line(&mut self) -> String {
+        self.src.until_any(&['\n']);
+        self.src.extract_string()
+    }
+
+    fn error(&mut self, msg: String) -> IniToken {
+        self.rest_of_line(); // recover on next line
+        IniToken::Error(self.line, msg)
+    }
+}
+
+impl<I: Iterator<Item = char>> Iterator for IniTokenizer<I> {
+    type Item = IniToken;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.after_key {
+            self.after_key = false;
+            self.after_assign = true;
+            self.src.accept(&'=');
+            self.src.extract(); // ignore
+            return Some(IniToken::Assign);
+        }
+        if self.after_assign {
+            // values may be empty, don't skip past end-of-line
+            self.after_assign = false;
+            self.src.skip_all(&[' ', '\t']);
+            self.src.extract(); // ignore
+            let value = self.rest_of_line();
+            return Some(IniToken::Value(value.trim_end().to_string()));
+        }
+        self.skip_whitespace();
+        let next = self.src.peek()?;
+        if next == ';' || next == '#' {
+            self.rest_of_line(); // ignore comment
+            return self.next();
+        }
+        if next == '[' {
+            self.src.until_any(&[']', '\n']);
+            if self.src.accept(&']').is_none() {
+                return Some(self.error("unterminated section header".to_string()));
+            }
+            let header = self.src.extract_string();
+            return Some(IniToken::Section(header[1..header.len() - 1].trim().to_string()));
+        }
+        if next == '=' {
+            return Some(self.error("missing key before '='".to_string()));
+        }
+        self.src.until_any(&['=', '\n']);
+        let key = self.src.extract_string().trim_end().to_string();
+        match self.src.peek() {
+            Some('=') => {
+                self.after_key = true;
+                Some(IniToken::Key(key))
+            }
+            _ => Some(IniToken::Error(self.line, format!("missing '=' after '{}'", key))),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::{IniToken, IniTokenizer};
+
+    #[test]
+    fn ini_tokenizer() {
+        use IniToken::*;
+        let input = "; global settings\n\
+                     [server]\n\
+                     host = example.com\n\
+                     # greeting shown on login\n\
+                     motd=hello there  \n\
+                     empty =\n\
+                     bogus line\n\
+                     [ db ]\n\
+                     = orphan\n\
+                     port = 5432";
+        let expected = vec![
+            Section("server".to_string()),
+            Key("host".to_string()), Assign, Value("example.com".to_string()),
+            Key("motd".to_string()), Assign, Value("hello there".to_string()),
+            Key("empty".to_string()), Assign, Value("".to_string()),
+            Error(7, "missing '=' after 'bogus line'".to_string()),
+            Section("db".to_string()),
+            Error(9, "missing key before '='".to_string()),
+            Key("port".to_string()), Assign, Value("5432".to_string()),
+        ];
+        let tokens: Vec<IniToken> = IniTokenizer::new(input.chars()).collect();
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn unterminated_section() {
+        let tokens: Vec<IniToken> = IniTokenizer::new("[oops\nk = v".chars()).collect();
+        assert_eq!(tokens[0], IniToken::Error(1, "unterminated section header".to_string()));
+        assert_eq!(tokens[1], IniToken::Key("k".to_string()));
+    }
+}
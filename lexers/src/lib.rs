@@ -1,22 +1,45 @@
-#![deny(warnings)]
+#![cfg_attr(feature = "strict", deny(warnings))]
 
 mod helpers;
 mod scanner;
 pub use crate::scanner::Scanner;
 
+mod read_chars;
+pub use crate::read_chars::ReadChars;
+
+mod chained_chars;
+pub use crate::chained_chars::{ChainedChars, SourcePos};
+
 mod ebnf_tokenizer;
 pub use crate::ebnf_tokenizer::EbnfTokenizer;
 
 mod math_tokenizer;
-pub use crate::math_tokenizer::{MathToken, MathTokenizer};
+pub use crate::math_tokenizer::{MathToken, MathTokenizer, NumberLocale};
 
 mod delim_tokenizer;
 pub use crate::delim_tokenizer::DelimTokenizer;
+#[cfg(feature = "regex")]
+pub use crate::delim_tokenizer::RegexTokenizer;
 
 mod lisp_tokenizer;
-pub use crate::lisp_tokenizer::{LispToken, LispTokenizer};
+pub use crate::lisp_tokenizer::{LispToken, LispTokenizer, SExpr, SExprIterator};
+
+mod ini_tokenizer;
+pub use crate::ini_tokenizer::{IniToken, IniTokenizer};
+
+mod suggest;
+pub use crate::suggest::{closest_name, did_you_mean, edit_distance};
+
+mod source_error;
+pub use crate::source_error::{line_span, render, Diagnostic, SourceError};
 
 #[cfg(test)]
 mod scanner_test;
 #[cfg(test)]
 mod helpers_test;
+#[cfg(test)]
+mod strict_test;
+
+// A benign warning for strict_test to build this crate with
+#[cfg(lexers_warn)]
+fn unused() {}
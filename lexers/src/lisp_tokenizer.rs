@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 use crate::scanner::Scanner;
 
 #[derive(Clone, PartialEq, Debug)]
@@ -10,6 +8,8 @@ pub enum LispToken {
     QuasiQuote,
     UnQuote,
     UnQSplice,
+    // #; comments out the next datum, SExprIterator skips it
+    DatumCommentPrefix,
     True,
     False,
     Symbol(String),
@@ -21,7 +21,9 @@ pub struct LispTokenizer<I: Iterator<Item = char>>(Scanner<I>);
 
 impl<I: Iterator<Item = char>> LispTokenizer<I> {
     pub fn new(source: I) -> Self {
-        LispTokenizer(Scanner::new(source))
+        let mut src = Scanner::new(source);
+        src.skip_shebang(); // allow running scripts directly
+        LispTokenizer(src)
     }
 
     pub fn scanner(source: I) -> Scanner<Self> {
@@ -54,9 +56,14 @@ impl<I: Iterator<Item = char>> Iterator for LispTokenizer<I> {
             self.0.extract(); // ignore
             return Some(token);
         }
-        if self.0.until_any(&[')', ' ', '\n', '\r', '\t']) {
+        if self.0.accept_all("#;".chars()) {
+            self.0.extract(); // ignore
+            return Some(LispToken::DatumCommentPrefix);
+        }
+        // symbols, numbers and booleans, anything up to a ')' or whitespace
+        let symbol_char = |c: char| ![')', ' ', '\n', '\r', '\t'].contains(&c);
+        if let Some(lexeme) = self.0.scan_identifier_with(symbol_char, symbol_char) {
             use std::str::FromStr;
-            let lexeme = self.0.extract_string();
             return match &lexeme[..] {
                 "#t" => Some(LispToken::True),
                 "#f" => Some(LispToken::False),
@@ -70,11 +77,87 @@ impl<I: Iterator<Item = char>> Iterator for LispTokenizer<I> {
     }
 }
 
+// A complete form read from the token stream. Quoting shorthands are
+// expanded to lists, eg: 'x => (Quote x)
+#[derive(Clone, PartialEq, Debug)]
+pub enum SExpr {
+    Atom(LispToken),
+    List(Vec<SExpr>),
+}
+
+// Lisp's read step, yields one top-level form at a time. Datums after a
+// DatumCommentPrefix (#;) are read and dropped, eg: (1 #; (2) 3) => (1 3)
+pub struct SExprIterator<I: Iterator<Item = char>>(LispTokenizer<I>);
+
+impl<I: Iterator<Item = char>> LispTokenizer<I> {
+    pub fn into_sexpr_iterator(self) -> SExprIterator<I> {
+        SExprIterator(self)
+    }
+}
+
+impl<I: Iterator<Item = char>> SExprIterator<I> {
+    // Read the datum following a #; comment and drop it, nested ones too
+    fn skip_datum(&mut self) -> Result<(), String> {
+        match self.0.next() {
+            Some(LispToken::DatumCommentPrefix) => {
+                self.skip_datum()?;
+                self.skip_datum()
+            }
+            Some(token) => self.read(token).map(|_| ()),
+            None => Err("missing expression after #;".to_string()),
+        }
+    }
+
+    fn read(&mut self, token: LispToken) -> Result<SExpr, String> {
+        match token {
+            LispToken::OParen => {
+                let mut list = Vec::new();
+                loop {
+                    match self.0.next() {
+                        Some(LispToken::CParen) => return Ok(SExpr::List(list)),
+                        Some(LispToken::DatumCommentPrefix) => self.skip_datum()?,
+                        Some(token) => list.push(self.read(token)?),
+                        None => return Err("unbalanced '(' missing ')'".to_string()),
+                    }
+                }
+            }
+            LispToken::CParen => Err("unexpected ')'".to_string()),
+            LispToken::DatumCommentPrefix => {
+                self.skip_datum()?;
+                match self.0.next() {
+                    Some(next) => self.read(next),
+                    None => Err("missing expression after #;".to_string()),
+                }
+            }
+            LispToken::Quote | LispToken::QuasiQuote |
+            LispToken::UnQuote | LispToken::UnQSplice => match self.0.next() {
+                Some(next) => Ok(SExpr::List(vec![SExpr::Atom(token), self.read(next)?])),
+                None => Err(format!("missing expression after {:?}", token)),
+            },
+            atom => Ok(SExpr::Atom(atom)),
+        }
+    }
+}
+
+impl<I: Iterator<Item = char>> Iterator for SExprIterator<I> {
+    type Item = Result<SExpr, String>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.0.next()? {
+                LispToken::DatumCommentPrefix => if let Err(err) = self.skip_datum() {
+                    return Some(Err(err));
+                },
+                token => return Some(self.read(token)),
+            }
+        }
+    }
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
 mod tests {
-    use super::{LispToken, LispTokenizer};
+    use super::{LispToken, LispTokenizer, SExpr};
 
     #[test]
     fn lisp_tokenizer() {
@@ -106,4 +189,64 @@ mod tests {
             assert_eq!(lx.next(), None);
         }
     }
+
+    #[test]
+    fn shebang() {
+        use LispToken::*;
+        let tokens: Vec<_> = LispTokenizer::new("#!/usr/bin/lisp\n(+ 3 4)".chars()).collect();
+        assert_eq!(tokens, vec![OParen, Symbol("+".to_string()), Number(3.0), Number(4.0), CParen]);
+    }
+
+    #[test]
+    fn symbols() {
+        use LispToken::*;
+        let tokens: Vec<_> = LispTokenizer::new("(set-car! x? λ -3 -)".chars()).collect();
+        assert_eq!(tokens, vec![OParen, Symbol("set-car!".to_string()), Symbol("x?".to_string()),
+                                Symbol("λ".to_string()), Number(-3.0), Symbol("-".to_string()),
+                                CParen]);
+    }
+
+    #[test]
+    fn sexpr_iterator() {
+        use LispToken::*;
+        use SExpr::{Atom, List};
+        let read = |input: &str| {
+            LispTokenizer::new(input.chars()).into_sexpr_iterator().collect::<Vec<_>>()
+        };
+        assert_eq!(read("(1 2 3)"), vec![
+            Ok(List(vec![Atom(Number(1.0)), Atom(Number(2.0)), Atom(Number(3.0))]))]);
+        assert_eq!(read("'x"), vec![
+            Ok(List(vec![Atom(Quote), Atom(Symbol("x".to_string()))]))]);
+        // one top-level form at a time
+        assert_eq!(read("(a (b)) #t `(,c)"), vec![
+            Ok(List(vec![Atom(Symbol("a".to_string())),
+                         List(vec![Atom(Symbol("b".to_string()))])])),
+            Ok(Atom(True)),
+            Ok(List(vec![Atom(QuasiQuote), List(vec![
+                List(vec![Atom(UnQuote), Atom(Symbol("c".to_string()))])])])),
+        ]);
+        assert_eq!(read("(1 (2)"), vec![Err("unbalanced '(' missing ')'".to_string())]);
+        assert_eq!(read(") 1"), vec![Err("unexpected ')'".to_string()), Ok(Atom(Number(1.0)))]);
+        assert_eq!(read("'"), vec![Err("missing expression after Quote".to_string())]);
+    }
+
+    #[test]
+    fn datum_comments() {
+        use LispToken::*;
+        use SExpr::{Atom, List};
+        let tokens: Vec<_> = LispTokenizer::new("(1 #; 2 3)".chars()).collect();
+        assert_eq!(tokens, vec![OParen, Number(1.0), DatumCommentPrefix, Number(2.0),
+                                Number(3.0), CParen]);
+        let read = |input: &str| {
+            LispTokenizer::new(input.chars()).into_sexpr_iterator().collect::<Vec<_>>()
+        };
+        assert_eq!(read("(1 #; 2 3)"), vec![Ok(List(vec![Atom(Number(1.0)), Atom(Number(3.0))]))]);
+        // the whole next datum goes, nested comments skip one datum each
+        assert_eq!(read("#;(a (b)) x #;#; y z"), vec![Ok(Atom(Symbol("x".to_string())))]);
+        assert_eq!(read("'#;a b"), vec![
+            Ok(List(vec![Atom(Quote), Atom(Symbol("b".to_string()))]))]);
+        assert_eq!(read("(#;)"), vec![Err("unexpected ')'".to_string())]);
+        assert_eq!(read("1 #;"), vec![Ok(Atom(Number(1.0))),
+                                      Err("missing expression after #;".to_string())]);
+    }
 }
@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 use crate::scanner::Scanner;
 
 #[derive(Clone, PartialEq, Debug)]
@@ -16,38 +14,151 @@ pub enum MathToken {
     Comma,
 }
 
+impl MathToken {
+    // Whether an operand can end with this token, then a - or ! after it
+    // is binary minus or factorial rather than prefix, eg: 3! - 1
+    pub fn ends_operand(&self) -> bool {
+        matches!(self, MathToken::Number(_) | MathToken::Variable(_) | MathToken::CParen) ||
+            matches!(self, MathToken::UOp(op) if op == "!")
+    }
+}
+
+// How numbers are written. With `decimal_comma` numbers look like "3,14"
+// and arguments are separated by ';' like in spreadsheets, so "max(1,5)"
+// is a single argument 1.5. With `thousands` digits can be grouped by the
+// separator that isn't the decimal one, eg: "1.234,56" or "1,234.56", every
+// group after the first must have 3 digits or the number is an Unknown token.
+// In the default decimal point mode that means "max(1,234)" is max(1234),
+// arguments must be separated by ", " with a space.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct NumberLocale {
+    pub decimal_comma: bool,
+    pub thousands: bool,
+}
+
 pub struct MathTokenizer<I: Iterator<Item = char>> {
     src: Scanner<I>,
     prev: Option<MathToken>,
+    locale: NumberLocale,
+    // extra operator symbols, read as BOp
+    symbols: Vec<String>,
+    // chars of the source the last token was read from
+    span: (usize, usize),
 }
 
 impl<I: Iterator<Item = char>> MathTokenizer<I> {
     pub fn new(source: I) -> Self {
+        Self::with_locale(source, NumberLocale::default())
+    }
+
+    pub fn with_locale(source: I, locale: NumberLocale) -> Self {
         MathTokenizer {
             src: Scanner::new(source),
             prev: None,
+            locale,
+            symbols: Vec::new(),
+            span: (0, 0),
         }
     }
 
+    // Also read `symbols` as operators (eg: "⊕" or "<>"), matched before
+    // the built-in ones, longest first
+    pub fn operators(mut self, symbols: Vec<String>) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    // Chars of the source the last token was read from as [start, end),
+    // without the whitespace around it
+    pub fn span(&self) -> (usize, usize) {
+        self.span
+    }
+
     pub fn scanner(source: I) -> Scanner<Self> {
         Scanner::new(Self::new(source))
     }
 
     // when would a minus be unary? we need to know the prev token
     fn makes_unary(prev: &Option<MathToken>) -> bool {
-        !matches!(*prev,
-            Some(MathToken::Number(_)) |
-            Some(MathToken::Variable(_)) |
-            Some(MathToken::CParen))
+        !prev.as_ref().is_some_and(MathToken::ends_operand)
+    }
+
+    fn skip_digits(&mut self, group: Option<char>) -> bool {
+        let mut advanced = false;
+        loop {
+            let backtrack = self.src.buffer_pos();
+            if group.is_some_and(|g| self.src.accept(&g).is_some()) && !advanced {
+                self.src.set_buffer_pos(backtrack);
+                return false;
+            }
+            match self.src.next() {
+                Some(c) if c.is_ascii_digit() => advanced = true,
+                _ => {
+                    self.src.set_buffer_pos(backtrack);
+                    return advanced;
+                }
+            }
+        }
+    }
+
+    // Like Scanner::scan_number but following the locale. The number is
+    // normalized to be read as f64, Err has the lexeme if groups are wrong.
+    fn scan_number(&mut self) -> Option<Result<String, String>> {
+        if self.locale == NumberLocale::default() {
+            return self.src.scan_number().map(Ok);
+        }
+        let (decimal, group) = match self.locale.decimal_comma {
+            true => (',', '.'),
+            false => ('.', ','),
+        };
+        if !self.skip_digits(self.locale.thousands.then_some(group)) {
+            return None;
+        }
+        let integer: String = self.src.view().iter().collect();
+        let backtrack = self.src.buffer_pos();
+        if self.src.accept(&decimal).is_some() && !self.skip_digits(None) {
+            self.src.set_buffer_pos(backtrack);
+        }
+        let backtrack = self.src.buffer_pos();
+        if self.src.accept_any(&['e', 'E']).is_some() {
+            self.src.accept_any(&['+', '-']);
+            if !self.skip_digits(None) {
+                self.src.set_buffer_pos(backtrack);
+            }
+        }
+        let lexeme = self.src.extract_string();
+        let mut groups = integer.split(group);
+        let first_ok = groups.next().is_some_and(|g| g.len() <= 3 || integer.len() == g.len());
+        if !first_ok || !groups.all(|g| g.len() == 3) {
+            return Some(Err(lexeme));
+        }
+        Some(Ok(lexeme.chars()
+            .filter(|c| *c != group)
+            .map(|c| if c == decimal { '.' } else { c })
+            .collect()))
     }
 
     fn get_token(&mut self) -> Option<MathToken> {
         self.src.scan_whitespace(); // discard whatever came before + and spaces
+        let start = self.src.extracted();
+        self.span = (start, start);
+        if self.locale.decimal_comma && self.src.accept(&';').is_some() {
+            self.src.extract();
+            return Some(MathToken::Comma);
+        }
+        let symbols: Vec<_> = self.symbols.iter().map(|s| s.as_str()).collect();
+        if self.src.accept_any_string(&symbols).is_some() {
+            return Some(MathToken::BOp(self.src.extract_string()));
+        }
         if let Some(op) = self.src.scan_math_op() {
             return match op.as_ref() {
                 "(" => Some(MathToken::OParen),
                 ")" => Some(MathToken::CParen),
+                // ',' only separates arguments with a decimal point
+                "," if self.locale.decimal_comma => Some(MathToken::Unknown(op)),
                 "," => Some(MathToken::Comma),
+                // prefix '!' is a logical not, postfix is factorial
+                "!" if Self::makes_unary(&self.prev) => Some(MathToken::UOp("not".to_string())),
                 "!" => Some(MathToken::UOp(op)),
                 "-" if Self::makes_unary(&self.prev) => Some(MathToken::UOp(op)),
                 _ => Some(MathToken::BOp(op)),
@@ -59,11 +170,22 @@ impl<I: Iterator<Item = char>> MathTokenizer<I> {
                 _ => Some(MathToken::Variable(id)),
             };
         }
-        if let Some(num) = self.src.scan_number() {
+        if let Some(num) = self.scan_number() {
+            let num = match num {
+                Ok(num) => num,
+                Err(lexeme) => return Some(MathToken::Unknown(lexeme)),
+            };
+            // units may follow a space, the number ends before it
+            self.span.1 = self.src.extracted();
             self.src.scan_whitespace(); // discard whatever came before + and spaces
             use std::str::FromStr;
-            let value = f64::from_str(&num).unwrap();
+            // the scanner also takes imaginary numbers, eg: 2i
+            let value = match f64::from_str(&num) {
+                Ok(value) => value,
+                Err(_) => return Some(MathToken::Unknown(num)),
+            };
             if let Some((prefix, unit)) = self.src.scan_unit() {
+                self.span.1 = self.src.extracted();
                 return Some(MathToken::Quantity(value, prefix, unit));
             }
             return Some(MathToken::Number(value));
@@ -79,6 +201,10 @@ impl<I: Iterator<Item = char>> Iterator for MathTokenizer<I> {
     type Item = MathToken;
     fn next(&mut self) -> Option<Self::Item> {
         let token = self.get_token();
+        // tokens other than numbers end where reading stopped
+        if self.span.0 == self.span.1 {
+            self.span.1 = self.src.extracted();
+        }
         self.prev = token.clone();
         token
     }
@@ -88,7 +214,7 @@ impl<I: Iterator<Item = char>> Iterator for MathTokenizer<I> {
 
 #[cfg(test)]
 mod tests {
-    use super::{MathToken::*, MathTokenizer};
+    use super::{MathToken::*, MathTokenizer, NumberLocale};
 
     #[test]
     fn basic_ops() {
@@ -182,6 +308,38 @@ mod tests {
         assert_eq!(lx.next(), None);
     }
 
+    #[test]
+    fn extra_operators() {
+        let lx = MathTokenizer::new("a<>b ⊕ -c <= d".chars())
+            .operators(vec!["⊕".to_string(), "<>".to_string(), "<".to_string()]);
+        assert_eq!(lx.collect::<Vec<_>>(), [
+            Variable("a".to_string()), BOp("<>".to_string()), Variable("b".to_string()),
+            BOp("⊕".to_string()), UOp("-".to_string()), Variable("c".to_string()),
+            BOp("<".to_string()), BOp("=".to_string()), Variable("d".to_string())]);
+        let lx = MathTokenizer::new("1 ⊕ 2".chars());
+        assert_eq!(lx.collect::<Vec<_>>(), [Number(1.0), Unknown("⊕".to_string()), Number(2.0)]);
+    }
+
+    #[test]
+    fn logical_ops() {
+        let lx = MathTokenizer::new("!x && 3! - 1 >= !(2)".chars());
+        let expect = [
+            UOp("not".to_string()),
+            Variable("x".to_string()),
+            BOp("&&".to_string()),
+            Number(3.0),
+            UOp("!".to_string()),
+            BOp("-".to_string()),
+            Number(1.0),
+            BOp(">=".to_string()),
+            UOp("not".to_string()),
+            OParen,
+            Number(2.0),
+            CParen,
+        ];
+        assert_eq!(lx.collect::<Vec<_>>(), expect);
+    }
+
     #[test]
     fn quantity() {
         let mut lx = MathTokenizer::new("30km / (10 s) * 20g * 3 GHz".chars());
@@ -202,4 +360,55 @@ mod tests {
         }
         assert_eq!(lx.next(), None);
     }
+
+    #[test]
+    fn imaginary_numbers() {
+        // the number scanner takes them but they aren't f64s
+        let tokens: Vec<_> = MathTokenizer::new("2i + 1".chars()).collect();
+        assert_eq!(tokens, [Unknown("2i".to_string()), BOp("+".to_string()), Number(1.0)]);
+    }
+
+    #[test]
+    fn locales() {
+        let tokens = |input: &str, decimal_comma, thousands| {
+            let locale = NumberLocale{decimal_comma, thousands};
+            MathTokenizer::with_locale(input.chars(), locale).collect::<Vec<_>>()
+        };
+        // spreadsheet style
+        assert_eq!(tokens("max(2,75; 2,5e1)", true, false), [
+            Function("max".to_string(), 0), OParen, Number(2.75), Comma, Number(25.0), CParen]);
+        assert_eq!(tokens("max(1,5)", true, false),
+                   [Function("max".to_string(), 0), OParen, Number(1.5), CParen]);
+        assert_eq!(tokens("1, 5", true, false), [Number(1.0), Unknown(",".to_string()), Number(5.0)]);
+        assert_eq!(tokens("1.234,56", true, true), [Number(1234.56)]);
+        assert_eq!(tokens("12.345.678 km", true, true),
+                   [Quantity(12345678.0, "k".to_string(), "m".to_string())]);
+        assert_eq!(tokens("1234,5", true, true), [Number(1234.5)]);
+        // bad groups
+        assert_eq!(tokens("1.23,4", true, true), [Unknown("1.23,4".to_string())]);
+        assert_eq!(tokens("1234.567", true, true), [Unknown("1234.567".to_string())]);
+        assert_eq!(tokens("1.2345", true, true), [Unknown("1.2345".to_string())]);
+        // decimal point with thousands
+        assert_eq!(tokens("1,234.5 + 7", false, true),
+                   [Number(1234.5), BOp("+".to_string()), Number(7.0)]);
+        assert_eq!(tokens("f(1,234, 5)", false, true),
+                   [Function("f".to_string(), 0), OParen, Number(1234.0), Comma, Number(5.0), CParen]);
+        assert_eq!(tokens("f(1,5)", false, true),
+                   [Function("f".to_string(), 0), OParen, Unknown("1,5".to_string()), CParen]);
+        // default: ',' separates and ';' is unknown
+        assert_eq!(tokens("f(1,5;", false, false), [
+            Function("f".to_string(), 0), OParen, Number(1.0), Comma, Number(5.0),
+            Unknown(";".to_string())]);
+    }
+
+    #[test]
+    fn spans() {
+        let mut lx = MathTokenizer::new("max(7 km, 2) +  x1".chars());
+        let mut spans = Vec::new();
+        while lx.next().is_some() {
+            spans.push(lx.span());
+        }
+        assert_eq!(spans, [(0, 3), (3, 4), (4, 8), (8, 9), (10, 11), (11, 12), (13, 14), (16, 18)]);
+        assert_eq!(lx.span(), (18, 18));
+    }
 }
@@ -0,0 +1,99 @@
+use std::io::{self, BufReader, Bytes, Read};
+
+// Decodes UTF-8 chars from a reader so tokenizers can stream large inputs.
+// Reading stops at the first IO error or invalid UTF-8, check `error` after.
+pub struct ReadChars<R: Read> {
+    bytes: Bytes<BufReader<R>>,
+    error: Option<io::Error>,
+}
+
+impl<R: Read> ReadChars<R> {
+    pub fn new(reader: R) -> Self {
+        ReadChars {
+            bytes: BufReader::new(reader).bytes(),
+            error: None,
+        }
+    }
+
+    // The error that ended the stream early, if any
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    fn decode(&mut self, first: u8) -> io::Result<char> {
+        let width = match first {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 0,
+        };
+        let mut buf = [first, 0, 0, 0];
+        for byte in buf.iter_mut().take(width).skip(1) {
+            *byte = match self.bytes.next() {
+                Some(b) => b?,
+                None => 0, // truncated, rejected below
+            };
+        }
+        std::str::from_utf8(&buf[..width])
+            .ok()
+            .and_then(|s| s.chars().next())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                                          "stream did not contain valid UTF-8"))
+    }
+}
+
+impl<R: Read> Iterator for ReadChars<R> {
+    type Item = char;
+    fn next(&mut self) -> Option<char> {
+        if self.error.is_some() {
+            return None;
+        }
+        let decoded = match self.bytes.next()? {
+            Ok(first) => self.decode(first),
+            Err(e) => Err(e),
+        };
+        decoded.map_err(|e| self.error = Some(e)).ok()
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::ReadChars;
+    use crate::Scanner;
+    use std::io::{self, Read};
+
+    #[test]
+    fn decode_chars() {
+        let input = "a µ°C Ω 😀\nz";
+        let chars: String = ReadChars::new(input.as_bytes()).collect();
+        assert_eq!(chars, input);
+        let mut s = Scanner::new(ReadChars::new("42 km".as_bytes()));
+        assert_eq!(s.scan_number(), Some("42".to_string()));
+    }
+
+    #[test]
+    fn read_errors() {
+        let mut chars = ReadChars::new(&[b'a', 0xff, b'b'][..]);
+        assert_eq!(chars.by_ref().collect::<String>(), "a");
+        assert_eq!(chars.error().unwrap().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(chars.next(), None);
+        // truncated multi-byte char
+        let mut chars = ReadChars::new(&"Ω".as_bytes()[..1]);
+        assert_eq!(chars.next(), None);
+        assert!(chars.error().is_some());
+
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk on fire"))
+            }
+        }
+        let mut chars = ReadChars::new(Failing);
+        assert_eq!(chars.next(), None);
+        assert_eq!(chars.error().unwrap().to_string(), "disk on fire");
+        assert!(ReadChars::new(&b""[..]).next().is_none());
+    }
+}
@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 pub struct Scanner<I: Iterator>
 where
     I::Item: Clone,
@@ -7,6 +5,8 @@ where
     src: I,
     buf: Vec<I::Item>,
     pos: isize,
+    // items handed out by extract, to know if we're at the start of input
+    extracted: usize,
 }
 
 // Scanners are Iterators
@@ -39,6 +39,7 @@ where
             src: source,
             buf: Vec::new(),
             pos: -1,
+            extracted: 0,
         }
     }
 
@@ -96,6 +97,26 @@ where
         &self.buf[..n]
     }
 
+    // True if nothing has been read or extracted yet
+    pub fn at_start(&self) -> bool {
+        self.pos == -1 && self.extracted == 0
+    }
+
+    // Items handed out by extract so far, eg: the offset of the next token
+    pub fn extracted(&self) -> usize {
+        self.extracted
+    }
+
+    // Drops the items buffered ahead of the current position returning them
+    pub(crate) fn unbuffer(&mut self) -> Vec<I::Item> {
+        let split_point = std::cmp::min(self.pos + 1, self.buf.len() as isize);
+        self.buf.split_off(split_point as usize)
+    }
+
+    pub(crate) fn source_mut(&mut self) -> &mut I {
+        &mut self.src
+    }
+
     // Consumes the buffer into a new token (which can be ignored)
     pub fn extract(&mut self) -> Vec<I::Item> {
         // Check where to shift buffer
@@ -106,6 +127,7 @@ where
         // Split buffer and keep the remainder
         let mut remaining = self.buf.split_off(split_point as usize);
         std::mem::swap(&mut self.buf, &mut remaining);
+        self.extracted += remaining.len();
         remaining
     }
 }
@@ -0,0 +1,162 @@
+// An error about some source text, so tools built on these crates can
+// show them all the same way, see `render`.
+pub trait SourceError {
+    fn message(&self) -> String;
+    // Offending chars of the source as [start, end), none when unknown
+    fn span(&self) -> Option<(usize, usize)> {
+        None
+    }
+    // Hints shown after the source, eg: did you mean
+    fn notes(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+// Errors that are only a message
+impl SourceError for String {
+    fn message(&self) -> String {
+        self.clone()
+    }
+}
+
+// An error put together by hand, eg: from one that only knows its line
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Option<(usize, usize)>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>) -> Self {
+        Diagnostic{message: message.into(), ..Diagnostic::default()}
+    }
+
+    pub fn with_span(mut self, span: Option<(usize, usize)>) -> Self {
+        self.span = span;
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+}
+
+impl SourceError for Diagnostic {
+    fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    fn span(&self) -> Option<(usize, usize)> {
+        self.span
+    }
+
+    fn notes(&self) -> Vec<String> {
+        self.notes.clone()
+    }
+}
+
+// Span of a whole line (1-based) of `source`, without its new-line
+pub fn line_span(source: &str, line: usize) -> Option<(usize, usize)> {
+    let mut start = 0;
+    for (number, text) in source.split('\n').enumerate() {
+        let len = text.chars().count();
+        if number + 1 == line {
+            return Some((start, start + len));
+        }
+        start += len + 1;
+    }
+    None
+}
+
+// The message, the line where the error starts and a caret underline of
+// the span up to the end of that line, then the notes, eg:
+// error: Bad token: $
+//  --> 1:5
+//   |
+// 1 | 2 + $ 3
+//   |     ^
+//   = note: ...
+pub fn render(error: &dyn SourceError, source: &str) -> String {
+    let mut out = format!("error: {}\n", error.message());
+    let mut gutter = 2;
+    if let Some((start, end)) = error.span() {
+        // find the line holding `start`, a span past the end points after the last char
+        let mut line_start = 0;
+        let mut located = None;
+        for (number, text) in source.split('\n').enumerate() {
+            let len = text.chars().count();
+            if start <= line_start + len {
+                located = Some((number + 1, text, start - line_start, len));
+                break;
+            }
+            line_start += len + 1;
+        }
+        if let Some((line, text, column, len)) = located {
+            let number = line.to_string();
+            gutter = number.len() + 1;
+            let carets = end.min(line_start + len).saturating_sub(start).max(1);
+            out += &format!("{:w$}--> {}:{}\n", "", line, column + 1, w = gutter - 1);
+            out += &format!("{:w$}|\n", "", w = gutter);
+            out += &format!("{} | {}\n", number, text.trim_end_matches('\r'));
+            out += &format!("{:w$}| {:c$}{}\n", "", "", "^".repeat(carets), w = gutter, c = column);
+        }
+    }
+    for note in error.notes() {
+        out += &format!("{:w$}= note: {}\n", "", note, w = gutter);
+    }
+    out
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::{line_span, render, Diagnostic};
+
+    #[test]
+    fn render_diagnostics() {
+        let source = "let a = 1;\nlet b = a +;\n";
+        let error = Diagnostic::new("Expected expression")
+            .with_span(Some((21, 22)))
+            .with_note("the right operand of + is missing");
+        assert_eq!(render(&error, source), concat!(
+            "error: Expected expression\n",
+            " --> 2:11\n",
+            "  |\n",
+            "2 | let b = a +;\n",
+            "  |           ^\n",
+            "  = note: the right operand of + is missing\n"));
+        // spans end with their line, an empty one still gets a caret
+        let error = Diagnostic::new("Unterminated").with_span(Some((8, 100)));
+        assert_eq!(render(&error, source), concat!(
+            "error: Unterminated\n",
+            " --> 1:9\n",
+            "  |\n",
+            "1 | let a = 1;\n",
+            "  |         ^^\n"));
+        let error = Diagnostic::new("Unexpected EOF").with_span(Some((24, 24)));
+        assert_eq!(render(&error, source), concat!(
+            "error: Unexpected EOF\n",
+            " --> 3:1\n",
+            "  |\n",
+            "3 | \n",
+            "  | ^\n"));
+        // without a span, or one outside the source, there's only the message
+        assert_eq!(render(&"Division by zero".to_string(), source), "error: Division by zero\n");
+        let error = Diagnostic::new("Lost").with_span(Some((99, 100))).with_note("somewhere");
+        assert_eq!(render(&error, source), "error: Lost\n  = note: somewhere\n");
+    }
+
+    #[test]
+    fn line_spans() {
+        let source = "ab\n\ncdé\n";
+        assert_eq!(line_span(source, 1), Some((0, 2)));
+        assert_eq!(line_span(source, 2), Some((3, 3)));
+        assert_eq!(line_span(source, 3), Some((4, 7)));
+        assert_eq!(line_span(source, 4), Some((8, 8)));
+        assert_eq!(line_span(source, 5), None);
+        assert_eq!(line_span(source, 0), None);
+    }
+}
@@ -0,0 +1,18 @@
+use std::process::Command;
+
+// Build this crate with a warning in it, it only fails with strict on
+#[test]
+fn strict_feature() {
+    let target = std::env::temp_dir().join(format!("lexers-strict-{}", std::process::id()));
+    let cargo = |extra: &[&str]| Command::new(std::env::var("CARGO").unwrap_or("cargo".to_string()))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["build", "--offline", "--lib", "--target-dir"]).arg(&target)
+        .args(extra).env("RUSTFLAGS", "--cfg lexers_warn").output().unwrap();
+    let lenient = cargo(&[]);
+    assert!(lenient.status.success());
+    assert!(String::from_utf8_lossy(&lenient.stderr).contains("function `unused` is never used"));
+    let strict = cargo(&["--features", "strict"]);
+    assert!(!strict.status.success());
+    assert!(String::from_utf8_lossy(&strict.stderr).contains("error: function `unused` is never used"));
+    std::fs::remove_dir_all(&target).unwrap();
+}
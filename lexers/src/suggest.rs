@@ -0,0 +1,81 @@
+// Levenshtein distance, number of char insertions/deletions/substitutions
+// to turn `a` into `b`
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // distances from the prefix of `a` seen so far to each prefix of `b`
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// Closest candidate to a misspelled `name` for "did you mean" hints.
+// Only names about a third of `name` away are offered, and never ones that
+// would replace it entirely. Ties go to the first candidate.
+pub fn closest_name<'a>(name: &str, candidates: impl IntoIterator<Item=&'a str>)
+        -> Option<&'a str> {
+    let len = name.chars().count();
+    let threshold = len.div_ceil(3).min(len.saturating_sub(1));
+    candidates.into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance > 0 && *distance <= threshold)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+// Suffix for unknown name errors naming the closest candidate, eg:
+// ", did you mean 'count'?". Empty when nothing is close enough.
+pub fn did_you_mean<'a>(name: &str, candidates: impl IntoIterator<Item=&'a str>) -> String {
+    // sorted so ties break the same whatever order a HashMap gives
+    let mut candidates: Vec<_> = candidates.into_iter().collect();
+    candidates.sort();
+    match closest_name(name, candidates) {
+        Some(close) => format!(", did you mean '{}'?", close),
+        None => String::new(),
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::{closest_name, did_you_mean, edit_distance};
+
+    #[test]
+    fn distances() {
+        let tests = [("", "", 0), ("abc", "", 3), ("", "ab", 2), ("sin", "sin", 0),
+                     ("sqtr", "sqrt", 2), ("kitten", "sitting", 3), ("cos", "coss", 1)];
+        for (a, b, expected) in tests {
+            assert_eq!(edit_distance(a, b), expected, "{} {}", a, b);
+            assert_eq!(edit_distance(b, a), expected, "{} {}", b, a);
+        }
+    }
+
+    #[test]
+    fn closest_names() {
+        let names = ["abs", "cos", "count", "max", "min", "x"];
+        assert_eq!(closest_name("coss", names), Some("cos"));
+        assert_eq!(closest_name("mix", names), Some("max"));
+        assert_eq!(closest_name("counter", names), Some("count"));
+        // exact matches and unrelated names get nothing
+        assert_eq!(closest_name("cos", names), None);
+        assert_eq!(closest_name("banana", names), None);
+        // one char names would be fully replaced
+        assert_eq!(closest_name("y", names), None);
+    }
+
+    #[test]
+    fn did_you_mean_hints() {
+        assert_eq!(did_you_mean("conut", ["max", "count"]), ", did you mean 'count'?");
+        assert_eq!(did_you_mean("banana", ["max", "count"]), "");
+        // ties go to the first name in sorted order
+        assert_eq!(did_you_mean("ab", ["ac", "aa"]), ", did you mean 'aa'?");
+    }
+}
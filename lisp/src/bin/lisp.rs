@@ -2,14 +2,15 @@
 fn main() {
     use std::rc::Rc;
     use lisp::{LispContext, Parser};
+    use lexers::render;
     let cx = Rc::new(LispContext::new());
     let mut rl = rustyline::Editor::<()>::new();
     while let Ok(input) = rl.readline("~> ") {
         rl.add_history_entry(&input);
         match Parser::parse_str(&input[..]) {
-            Err(e) => println!("Parse error: {:?}", e),
+            Err(e) => print!("{}", render(&e, &input)),
             Ok(exp) => match LispContext::eval(&exp, &cx) {
-                Err(e) => println!("Eval error: {:?}", e),
+                Err(e) => print!("{}", render(&e, &input)),
                 Ok(res) => println!("{}", res.to_string())
             }
         }
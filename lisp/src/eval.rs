@@ -1,6 +1,7 @@
 use crate::parser::{Parser, ParseError, LispExpr};
 use crate::procedure::Procedure;
 use crate::builtin::builtins;
+use lexers::SourceError;
 
 use std::collections::HashMap;
 use std::iter::FromIterator;
@@ -23,6 +24,26 @@ pub enum EvalErr {
     NotImplemented,
 }
 
+impl SourceError for EvalErr {
+    fn message(&self) -> String {
+        match self {
+            EvalErr::ParseError(err) => err.message(),
+            EvalErr::UnknownSym(sym) => format!("unknown symbol {}", sym),
+            EvalErr::UnknownFunction(fun) => format!("unknown function {}", fun),
+            EvalErr::NotCallable => "not callable".to_string(),
+            EvalErr::InvalidExpr => "invalid expression".to_string(),
+            EvalErr::NotImplemented => "not implemented".to_string(),
+        }
+    }
+
+    fn notes(&self) -> Vec<String> {
+        match self {
+            EvalErr::ParseError(err) => err.notes(),
+            _ => Vec::new(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct LispContext {
     syms: RefCell<HashMap<String, LispExpr>>,
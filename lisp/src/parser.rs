@@ -1,4 +1,4 @@
-use lexers::{Scanner, LispToken, LispTokenizer};
+use lexers::{Scanner, LispToken, LispTokenizer, SourceError};
 use crate::procedure::Procedure;
 use std::string;
 use std::rc::Rc;
@@ -50,6 +50,22 @@ impl string::ToString for LispExpr {
     }
 }
 
+impl SourceError for ParseError {
+    fn message(&self) -> String {
+        match self {
+            ParseError::UnexpectedCParen => "unexpected ')'".to_string(),
+            ParseError::UnexpectedEOF => "unexpected end of input".to_string(),
+            ParseError::NotImplemented => "not implemented".to_string(),
+        }
+    }
+
+    fn notes(&self) -> Vec<String> {
+        match self {
+            ParseError::UnexpectedEOF => vec!["a '(' is missing its ')'".to_string()],
+            _ => Vec::new(),
+        }
+    }
+}
 
 pub struct Parser;
 
@@ -73,6 +89,11 @@ impl Parser {
             Some(LispToken::OParen)     => {
                 let mut list = Vec::new();
                 while lex.peek() != Some(LispToken::CParen) { // even when != None
+                    if lex.peek() == Some(LispToken::DatumCommentPrefix) {
+                        lex.next();
+                        Parser::parse(lex)?; // commented out with #;
+                        continue;
+                    }
                     match Parser::parse(lex) {
                         Err(err) => return Err(err),
                         Ok(expr) => list.push(expr),
@@ -96,6 +117,10 @@ impl Parser {
             Some(LispToken::UnQSplice) => {
                 let expr = Parser::parse(lex)?;
                 Ok(LispExpr::UnQSplice(Box::new(expr)))
+            },
+            Some(LispToken::DatumCommentPrefix) => {
+                Parser::parse(lex)?; // the commented out datum
+                Parser::parse(lex)
             }
         }
     }
@@ -22,3 +22,24 @@ fn test_lisp1() {
     ]);
     assert_eq!(p.unwrap(), r);
 }
+
+#[test]
+fn datum_comments() {
+    let p = Parser::parse_str("(1 #; 2 3)");
+    assert_eq!(p.unwrap(), LispExpr::List(vec![LispExpr::Number(1.0), LispExpr::Number(3.0)]));
+    let p = Parser::parse_str("#; (a b) c");
+    assert_eq!(p.unwrap(), LispExpr::Symbol("c".to_string()));
+}
+
+#[test]
+fn render_errors() {
+    use crate::eval::LispContext;
+    use lexers::render;
+    let error = |input| match LispContext::eval_str(input) {
+        Err(err) => render(&err, input),
+        Ok(res) => panic!("{} evaluated to {:?}", input, res),
+    };
+    assert_eq!(error("(+ 1 2"), "error: unexpected end of input\n  = note: a '(' is missing its ')'\n");
+    assert_eq!(error(")"), "error: unexpected ')'\n");
+    assert_eq!(error("(+ 1 x)"), "error: unknown symbol x\n");
+}
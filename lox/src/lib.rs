@@ -0,0 +1,32 @@
+#![cfg_attr(feature = "strict", deny(warnings))]
+
+mod lox_scanner;
+pub use crate::lox_scanner::LoxScanner;
+
+mod lox_parser;
+pub use crate::lox_parser::{parse, LoxParser, ParseError, Stmt};
+
+mod lox_interpreter;
+pub use crate::lox_interpreter::LoxInterpreter;
+
+mod lox_environment;
+mod lox_format;
+mod lox_gc;
+mod lox_native;
+
+mod lox_resolver;
+pub use crate::lox_resolver::Resolver;
+
+mod lox_printer;
+pub use crate::lox_printer::dump_stmts;
+
+mod lox_folder;
+pub use crate::lox_folder::fold_stmts;
+
+mod lox_macros;
+pub use crate::lox_macros::debug_print;
+
+mod lox_compiler;
+
+mod lox_vm;
+pub use crate::lox_vm::LoxVm;
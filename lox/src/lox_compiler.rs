@@ -0,0 +1,541 @@
+use crate::lox_parser::{Expr, Stmt};
+use crate::lox_scanner::{Token, TT};
+use crate::lox_vm::Value;
+use std::rc::Rc;
+
+
+// Lower the AST to bytecode for LoxVm. Variables are resolved the same way
+// the Resolver does, locals become (depth, slot) pairs into the chain of
+// scopes, anything else is a global looked up by name. Only blocks that
+// declare something get a scope at run-time.
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+    Constant(u32),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetGlobal(u32),
+    SetGlobal(u32),
+    DefineGlobal(u32),
+    // a global `let`, SetGlobal fails on it until defined again
+    DefineImmutable(u32),
+    GetLocal(u16, u16),
+    SetLocal(u16, u16),
+    DefineLocal(u16),
+    // `$name` lookup through the scope chain at run-time
+    Dynamic,
+    Negate,
+    Not,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Equal,
+    NotEqual,
+    // concat the top n values into a string
+    Interpolate(u32),
+    // print the top n values space separated
+    Print(u32),
+    // fails if the top of the stack is falsy, popping it
+    Assert,
+    Jump(u32),
+    // leave the condition on the stack
+    JumpIfFalse(u32),
+    JumpIfTrue(u32),
+    JumpIfNotNil(u32),
+    PushScope(u32),
+    PopScope,
+    Closure(u32),
+    // checks the callee before its arguments are evaluated
+    Callee(u32),
+    // bind the string on top to the named method
+    GetMethod(u32),
+    Call(u32),
+    Return,
+    // raise a run-time error with constant's message
+    Fail(u32),
+}
+
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub code: Vec<Op>,
+    // line to report for each op, 0 keeps the last one
+    pub lines: Vec<usize>,
+    pub constants: Vec<Value>,
+    pub names: Vec<Rc<str>>,
+    pub functions: Vec<Rc<Function>>,
+    // slot names of the scopes PushScope creates
+    pub scopes: Vec<Rc<[Rc<str>]>>,
+    // slot names of the function's own scope (params first)
+    pub scope: Rc<[Rc<str>]>,
+}
+
+impl Function {
+    pub fn is_script(&self) -> bool {
+        self.name.is_empty()
+    }
+}
+
+struct Loop {
+    // scopes open when the loop started
+    scopes: usize,
+    breaks: Vec<usize>,
+}
+
+struct FunctionBuilder {
+    name: String,
+    params: Vec<String>,
+    code: Vec<Op>,
+    lines: Vec<usize>,
+    constants: Vec<Value>,
+    names: Vec<Rc<str>>,
+    functions: Vec<Rc<Function>>,
+    scopes: Vec<Rc<[Rc<str>]>>,
+    loops: Vec<Loop>,
+}
+
+pub struct Compiler {
+    // names declared in each open scope, across enclosing functions
+    scopes: Vec<Vec<String>>,
+    // (scope, slot) of open `let` locals, assigning to them fails
+    lets: Vec<(usize, u16)>,
+    functions: Vec<FunctionBuilder>,
+}
+
+// Declarations go into the enclosing block's scope, even if nested in an
+// if/while without a block of their own
+fn declares(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match stmt {
+        Stmt::Var(_, _) | Stmt::Let(_, _) | Stmt::Function(_, _, _) => true,
+        Stmt::If(_, then_branch, else_branch) =>
+            declares(std::slice::from_ref(then_branch)) ||
+            else_branch.as_ref().is_some_and(|e| declares(std::slice::from_ref(e))),
+        Stmt::While(_, body) | Stmt::DoWhile(body, _) => declares(std::slice::from_ref(body)),
+        _ => false,
+    })
+}
+
+pub fn compile(stmts: &[Stmt]) -> Rc<Function> {
+    let mut compiler = Compiler{scopes: Vec::new(), lets: Vec::new(), functions: Vec::new()};
+    compiler.begin_function(String::new(), Vec::new(), false);
+    for stmt in stmts {
+        compiler.stmt(stmt);
+    }
+    compiler.emit(Op::Nil, 0);
+    compiler.emit(Op::Return, 0);
+    compiler.end_function(false)
+}
+
+impl Compiler {
+    fn current(&mut self) -> &mut FunctionBuilder {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, op: Op, line: usize) -> usize {
+        let function = self.current();
+        function.code.push(op);
+        function.lines.push(line);
+        function.code.len() - 1
+    }
+
+    fn patch(&mut self, at: usize) {
+        let target = self.current().code.len() as u32;
+        let function = self.current();
+        function.code[at] = match function.code[at] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            Op::JumpIfTrue(_) => Op::JumpIfTrue(target),
+            Op::JumpIfNotNil(_) => Op::JumpIfNotNil(target),
+            other => unreachable!("LoxCompiler Bug! patching {:?}", other),
+        };
+    }
+
+    fn constant(&mut self, value: Value) -> u32 {
+        let function = self.current();
+        function.constants.push(value);
+        (function.constants.len() - 1) as u32
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        let function = self.current();
+        match function.names.iter().position(|n| &**n == name) {
+            Some(idx) => idx as u32,
+            None => {
+                function.names.push(name.into());
+                (function.names.len() - 1) as u32
+            }
+        }
+    }
+
+    fn fail(&mut self, msg: String) {
+        let msg = self.constant(Value::Str(msg.into()));
+        self.emit(Op::Fail(msg), 0);
+    }
+
+    fn begin_function(&mut self, name: String, params: Vec<String>, scoped: bool) {
+        if scoped {
+            self.scopes.push(params.clone());
+        }
+        self.functions.push(FunctionBuilder{
+            name, params,
+            code: Vec::new(),
+            lines: Vec::new(),
+            constants: Vec::new(),
+            names: Vec::new(),
+            functions: Vec::new(),
+            scopes: Vec::new(),
+            loops: Vec::new(),
+        });
+    }
+
+    fn end_function(&mut self, scoped: bool) -> Rc<Function> {
+        let scope = match scoped {
+            true => self.pop_scope(),
+            false => Vec::new(),
+        };
+        let function = self.functions.pop().unwrap();
+        Rc::new(Function{
+            name: function.name,
+            params: function.params,
+            code: function.code,
+            lines: function.lines,
+            constants: function.constants,
+            names: function.names,
+            functions: function.functions,
+            scopes: function.scopes,
+            scope: scope.iter().map(|n| n.as_str().into()).collect(),
+        })
+    }
+
+    // Slot for a new name in the innermost scope, None at the top-level
+    fn declare(&mut self, name: &str) -> Option<u16> {
+        let scope = self.scopes.last_mut()?;
+        scope.push(name.to_string());
+        Some((scope.len() - 1) as u16)
+    }
+
+    fn define(&mut self, name: &str, slot: Option<u16>) {
+        match slot {
+            Some(slot) => self.emit(Op::DefineLocal(slot), 0),
+            None => {
+                let name = self.name(name);
+                self.emit(Op::DefineGlobal(name), 0)
+            }
+        };
+    }
+
+    fn pop_scope(&mut self) -> Vec<String> {
+        let names = self.scopes.pop().unwrap();
+        let open = self.scopes.len();
+        self.lets.retain(|(scope, _)| *scope < open);
+        names
+    }
+
+    fn resolve(&self, name: &str) -> Option<(u16, u16)> {
+        self.scopes.iter().rev().enumerate().find_map(|(depth, scope)| {
+            scope.iter().rposition(|n| n == name).map(|slot| (depth as u16, slot as u16))
+        })
+    }
+
+    fn block(&mut self, stmts: &[Stmt], tail: bool) {
+        let scope = declares(stmts).then(|| {
+            let function = self.current();
+            function.scopes.push(Rc::new([]));
+            function.scopes.len() - 1
+        });
+        if let Some(idx) = scope {
+            self.emit(Op::PushScope(idx as u32), 0);
+            self.scopes.push(Vec::new());
+        }
+        match stmts.split_last() {
+            Some((last, init)) if tail => {
+                init.iter().for_each(|stmt| self.stmt(stmt));
+                self.tail(last);
+            },
+            None if tail => {
+                self.emit(Op::Nil, 0);
+                self.emit(Op::Return, 0);
+            },
+            _ => stmts.iter().for_each(|stmt| self.stmt(stmt)),
+        }
+        if let Some(idx) = scope {
+            let names = self.pop_scope();
+            self.current().scopes[idx] = names.iter().map(|n| n.as_str().into()).collect();
+            if !tail {
+                self.emit(Op::PopScope, 0);
+            }
+        }
+    }
+
+    // A function's value is that of its last statement (when an expression)
+    // unless it returns before. Statements in tail position return it.
+    fn tail(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expr(expr) => {
+                self.expr(expr);
+                self.emit(Op::Return, 0);
+            },
+            Stmt::Block(stmts) => self.block(stmts, true),
+            Stmt::If(cond, then_branch, else_branch) => {
+                self.expr(cond);
+                let else_jump = self.emit(Op::JumpIfFalse(0), 0);
+                self.emit(Op::Pop, 0);
+                self.tail(then_branch);
+                self.patch(else_jump);
+                self.emit(Op::Pop, 0);
+                match else_branch {
+                    Some(else_branch) => self.tail(else_branch),
+                    None => {
+                        self.emit(Op::Nil, 0);
+                        self.emit(Op::Return, 0);
+                    }
+                }
+            },
+            other => {
+                self.stmt(other);
+                self.emit(Op::Nil, 0);
+                self.emit(Op::Return, 0);
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Print(exprs) => {
+                exprs.iter().for_each(|expr| self.expr(expr));
+                self.emit(Op::Print(exprs.len() as u32), 0);
+            },
+            Stmt::Assert(expr, line) => {
+                self.expr(expr);
+                self.emit(Op::Assert, *line);
+            },
+            Stmt::Expr(expr) => {
+                self.expr(expr);
+                self.emit(Op::Pop, 0);
+            },
+            Stmt::Var(name, init) => {
+                // declared before the initializer like the Resolver does
+                let slot = self.declare(name);
+                self.expr(init);
+                self.define(name, slot);
+            },
+            Stmt::Let(name, init) => {
+                let slot = self.declare(name);
+                self.expr(init);
+                match slot {
+                    Some(slot) => {
+                        self.lets.push((self.scopes.len() - 1, slot));
+                        self.emit(Op::DefineLocal(slot), 0);
+                    },
+                    None => {
+                        let name = self.name(name);
+                        self.emit(Op::DefineImmutable(name), 0);
+                    },
+                }
+            },
+            Stmt::Block(stmts) => self.block(stmts, false),
+            Stmt::If(cond, then_branch, else_branch) => {
+                self.expr(cond);
+                let else_jump = self.emit(Op::JumpIfFalse(0), 0);
+                self.emit(Op::Pop, 0);
+                self.stmt(then_branch);
+                let end_jump = self.emit(Op::Jump(0), 0);
+                self.patch(else_jump);
+                self.emit(Op::Pop, 0);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+                self.patch(end_jump);
+            },
+            Stmt::While(cond, body) => {
+                let start = self.current().code.len() as u32;
+                let scopes = self.scopes.len();
+                self.current().loops.push(Loop{scopes, breaks: Vec::new()});
+                self.expr(cond);
+                let exit_jump = self.emit(Op::JumpIfFalse(0), 0);
+                self.emit(Op::Pop, 0);
+                self.stmt(body);
+                self.emit(Op::Jump(start), 0);
+                self.patch(exit_jump);
+                self.emit(Op::Pop, 0);
+                let breaks = self.current().loops.pop().unwrap().breaks;
+                breaks.into_iter().for_each(|at| self.patch(at));
+            },
+            Stmt::DoWhile(body, cond) => {
+                let start = self.current().code.len() as u32;
+                let scopes = self.scopes.len();
+                self.current().loops.push(Loop{scopes, breaks: Vec::new()});
+                self.stmt(body);
+                self.expr(cond);
+                let exit_jump = self.emit(Op::JumpIfFalse(0), 0);
+                self.emit(Op::Pop, 0);
+                self.emit(Op::Jump(start), 0);
+                self.patch(exit_jump);
+                self.emit(Op::Pop, 0);
+                let breaks = self.current().loops.pop().unwrap().breaks;
+                breaks.into_iter().for_each(|at| self.patch(at));
+            },
+            Stmt::Break(num_breaks) => {
+                let loops = self.current().loops.len();
+                if loops < *num_breaks {
+                    return self.fail(format!("can't break {} times, depth {}",
+                                             num_breaks, loops));
+                }
+                let target = loops - num_breaks;
+                let open = self.scopes.len() - self.current().loops[target].scopes;
+                for _ in 0..open {
+                    self.emit(Op::PopScope, 0);
+                }
+                let at = self.emit(Op::Jump(0), 0);
+                self.current().loops[target].breaks.push(at);
+            },
+            Stmt::Function(name, params, body) => {
+                let slot = self.declare(name);
+                self.begin_function(name.clone(), params.clone(), true);
+                match body.split_last() {
+                    Some((last, init)) => {
+                        init.iter().for_each(|stmt| self.stmt(stmt));
+                        self.tail(last);
+                    },
+                    None => {
+                        self.emit(Op::Nil, 0);
+                        self.emit(Op::Return, 0);
+                    }
+                }
+                let function = self.end_function(true);
+                let current = self.current();
+                current.functions.push(function);
+                let idx = (current.functions.len() - 1) as u32;
+                self.emit(Op::Closure(idx), 0);
+                self.define(name, slot);
+            },
+            Stmt::Return(expr) => {
+                if self.functions.len() < 2 {
+                    return self.fail("can't return outside of function".to_string());
+                }
+                self.expr(expr);
+                self.emit(Op::Return, 0);
+            },
+        }
+    }
+
+    fn var(&mut self, token: &Token, set: bool) {
+        let local = self.resolve(&token.lexeme);
+        // locals are known here, globals are checked by the vm
+        let immutable = local.is_some_and(|(depth, slot)|
+            self.lets.contains(&(self.scopes.len() - 1 - depth as usize, slot)));
+        if set && immutable {
+            let msg = format!("cannot assign to immutable '{}'", token.lexeme);
+            let msg = self.constant(Value::Str(msg.into()));
+            self.emit(Op::Fail(msg), token.line);
+            return;
+        }
+        let op = match (local, set) {
+            (Some((depth, slot)), false) => Op::GetLocal(depth, slot),
+            (Some((depth, slot)), true) => Op::SetLocal(depth, slot),
+            (None, false) => Op::GetGlobal(self.name(&token.lexeme)),
+            (None, true) => Op::SetGlobal(self.name(&token.lexeme)),
+        };
+        self.emit(op, token.line);
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Nil => { self.emit(Op::Nil, 0); },
+            Expr::Bool(true) => { self.emit(Op::True, 0); },
+            Expr::Bool(false) => { self.emit(Op::False, 0); },
+            Expr::Num(n) => {
+                let idx = self.constant(Value::Num(*n));
+                self.emit(Op::Constant(idx), 0);
+            },
+            Expr::Str(s) => {
+                let idx = self.constant(Value::Str(s.as_str().into()));
+                self.emit(Op::Constant(idx), 0);
+            },
+            Expr::Grouping(gexpr) => self.expr(gexpr),
+            Expr::Unary(op, uexpr) => {
+                self.expr(uexpr);
+                let code = match op.token {
+                    TT::MINUS => Op::Negate,
+                    TT::BANG => Op::Not,
+                    TT::DOLLAR => Op::Dynamic,
+                    _ => unreachable!("LoxCompiler: bad Unary op {:?}", op)
+                };
+                self.emit(code, op.line);
+            },
+            Expr::Binary(lhs, op, rhs) => {
+                self.expr(lhs);
+                self.expr(rhs);
+                let code = match op.token {
+                    TT::PLUS => Op::Add,
+                    TT::MINUS => Op::Subtract,
+                    TT::STAR => Op::Multiply,
+                    TT::SLASH => Op::Divide,
+                    TT::GT => Op::Greater,
+                    TT::GE => Op::GreaterEqual,
+                    TT::LT => Op::Less,
+                    TT::LE => Op::LessEqual,
+                    TT::EQ => Op::Equal,
+                    TT::NE => Op::NotEqual,
+                    _ => unreachable!("LoxCompiler: bad binop {:?}", op)
+                };
+                self.emit(code, op.line);
+            },
+            Expr::Logical(lhs, op, rhs) => {
+                self.expr(lhs);
+                let jump = match op.token {
+                    TT::OR => Op::JumpIfTrue(0),
+                    _ => Op::JumpIfFalse(0),
+                };
+                let end_jump = self.emit(jump, op.line);
+                self.emit(Op::Pop, 0);
+                self.expr(rhs);
+                self.patch(end_jump);
+            },
+            Expr::Coalesce(lhs, op, rhs) => {
+                self.expr(lhs);
+                let end_jump = self.emit(Op::JumpIfNotNil(0), op.line);
+                self.emit(Op::Pop, 0);
+                self.expr(rhs);
+                self.patch(end_jump);
+            },
+            Expr::Ternary(cond, then, other) => {
+                self.expr(cond);
+                let else_jump = self.emit(Op::JumpIfFalse(0), 0);
+                self.emit(Op::Pop, 0);
+                self.expr(then);
+                let end_jump = self.emit(Op::Jump(0), 0);
+                self.patch(else_jump);
+                self.emit(Op::Pop, 0);
+                self.expr(other);
+                self.patch(end_jump);
+            },
+            Expr::Var(token) => self.var(token, false),
+            Expr::Assign(token, aexpr) => {
+                self.expr(aexpr);
+                self.var(token, true);
+            },
+            Expr::Call(callee, args) => {
+                self.expr(callee);
+                self.emit(Op::Callee(args.len() as u32), 0);
+                args.iter().for_each(|arg| self.expr(arg));
+                self.emit(Op::Call(args.len() as u32), 0);
+            },
+            Expr::Get(obj, name) => {
+                self.expr(obj);
+                let name_idx = self.name(&name.lexeme);
+                self.emit(Op::GetMethod(name_idx), name.line);
+            },
+            Expr::Interpolation(parts) => {
+                parts.iter().for_each(|part| self.expr(part));
+                self.emit(Op::Interpolate(parts.len() as u32), 0);
+            },
+        }
+    }
+}
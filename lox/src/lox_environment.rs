@@ -1,22 +1,25 @@
-#![deny(warnings)]
-
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use crate::lox_gc::{GcHandle, Trace, Tracer};
 use crate::lox_interpreter::V;
-use std::cell::RefCell;
-use std::rc::Rc;
+use lexers::did_you_mean;
+
 
+// Bindings of a scope chain innermost first, see Environment::snapshot
+pub struct Snapshot(Vec<(HashMap<String, V>, HashSet<String>)>);
 
 pub struct Environment {
     values: HashMap<String, V>,
-    parent: Option<Rc<RefCell<Environment>>>,
+    // names bound with `let`, assigning to them fails
+    immutable: HashSet<String>,
+    parent: Option<GcHandle<Environment>>,
 }
 
 impl Environment {
-    pub fn new(parent: Option<Rc<RefCell<Environment>>>) -> Self {
-        Environment{values: HashMap::new(), parent}
+    pub fn new(parent: Option<GcHandle<Environment>>) -> Self {
+        Environment{values: HashMap::new(), immutable: HashSet::new(), parent}
     }
 
-    fn ancestor(&self, depth: usize) -> Option<Rc<RefCell<Environment>>> {
+    fn ancestor(&self, depth: usize) -> Option<GcHandle<Environment>> {
         let mut ancestor = self.parent.clone();
         for _ in 1..depth {
             ancestor = match ancestor {
@@ -27,17 +30,79 @@ impl Environment {
         ancestor
     }
 
+    // Number of enclosing scopes, 0 for the globals
+    pub fn depth(&self) -> usize {
+        match self.parent {
+            Some(ref enc) => 1 + enc.borrow().depth(),
+            None => 0,
+        }
+    }
+
+    // Bindings of this scope only, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item=(&str, &V)> {
+        self.values.iter().map(|(name, val)| (name.as_str(), val))
+    }
+
+    // Bindings of this scope and all enclosing ones, innermost first, with
+    // how many scopes up they are (the depth of get_at). Enclosing scopes
+    // can't stay borrowed so their values are cloned, see snapshot.
+    pub fn iter_all(&self) -> impl Iterator<Item=(String, V, usize)> {
+        let mut all: Vec<_> = self.iter()
+            .map(|(name, val)| (name.to_string(), val.clone(), 0))
+            .collect();
+        let mut parent = self.parent.clone();
+        let mut level = 1;
+        while let Some(env) = parent {
+            all.extend(env.borrow().iter().map(|(name, val)| (name.to_string(), val.clone(), level)));
+            parent = env.borrow().parent.clone();
+            level += 1;
+        }
+        all.into_iter()
+    }
+
+    // Names visible from this scope, shadowed ones included once
+    pub fn names(&self) -> Vec<String> {
+        let mut names = self.values.keys().cloned().collect::<Vec<_>>();
+        if let Some(ref enc) = self.parent {
+            names.extend(enc.borrow().names());
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
     pub fn define<S: Into<String>>(&mut self, name: S, val: V) {
-        self.values.insert(name.into(), val);
+        let name = name.into();
+        self.immutable.remove(&name);
+        self.values.insert(name, val);
     }
 
-    pub fn get(&self, name: &str) -> Result<V, String> {
-        if self.values.contains_key(name) {
-            return Ok(self.values.get(name).unwrap().clone());
-        } else if let Some(ref enc) = self.parent {
-            return enc.borrow().get(name);
+    // Same as define but later assignments fail, it can still be shadowed
+    // or defined again
+    pub fn define_immutable<S: Into<String>>(&mut self, name: S, val: V) {
+        let name = name.into();
+        self.immutable.insert(name.clone());
+        self.values.insert(name, val);
+    }
+
+    fn check_mutable(&self, name: &str) -> Result<(), String> {
+        match self.immutable.contains(name) {
+            true => Err(format!("cannot assign to immutable '{}'", name)),
+            false => Ok(()),
         }
-        Err(format!("Environment get - undefined entity '{}'", name))
+    }
+
+    fn find(&self, name: &str) -> Option<V> {
+        match self.values.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.parent.as_ref()?.borrow().find(name),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<V, String> {
+        self.find(name).ok_or_else(|| format!(
+            "Environment get - undefined entity '{}'{}",
+            name, did_you_mean(name, self.names().iter().map(|n| n.as_str()))))
     }
 
     pub fn get_at(&self, depth: usize, name: &str) -> Result<V, String> {
@@ -56,25 +121,37 @@ impl Environment {
     }
 
     pub fn assign(&mut self, name: String, val: V) -> Result<V, String> {
-        if self.values.contains_key(&name) {
-            self.values.insert(name, val.clone());
-            return Ok(val)
+        match self.assign_defined(&name, val)? {
+            Some(val) => Ok(val),
+            None => Err(format!("Environment assign - undefined entity '{}'{}",
+                                name, did_you_mean(&name, self.names().iter().map(|n| n.as_str())))),
+        }
+    }
+
+    // Assign where `name` is defined, None when it's nowhere
+    fn assign_defined(&mut self, name: &str, val: V) -> Result<Option<V>, String> {
+        if self.values.contains_key(name) {
+            self.check_mutable(name)?;
+            self.values.insert(name.to_string(), val.clone());
+            return Ok(Some(val))
         } else if let Some(ref mut enc) = self.parent {
-            return enc.borrow_mut().assign(name, val);
+            return enc.borrow_mut().assign_defined(name, val);
         }
-        Err(format!("Environment assign - undefined entity '{}'", name))
+        Ok(None)
     }
 
     pub fn assign_at(&mut self, depth: usize,
                      name: String, val: V) -> Result<V, String> {
         match depth > 0 {
             false => if self.values.contains_key(&name) {
+                self.check_mutable(&name)?;
                 self.values.insert(name, val.clone());
                 return Ok(val);
             },
             true => match self.ancestor(depth) {
                 None => panic!("Resolver Bug! wrong env depth {}", depth),
                 Some(env) => if env.borrow().values.contains_key(&name) {
+                    env.borrow().check_mutable(&name)?;
                     env.borrow_mut().values.insert(name, val.clone());
                     return Ok(val);
                 }
@@ -82,4 +159,55 @@ impl Environment {
         }
         Err(format!("Environment assign_at - undefined entity '{}'", name))
     }
+
+    // Copy of the bindings of this scope and all enclosing ones. Values are
+    // cheap to clone, functions and their closures are shared.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut scopes = vec![(self.values.clone(), self.immutable.clone())];
+        let mut parent = self.parent.clone();
+        while let Some(env) = parent {
+            scopes.push((env.borrow().values.clone(), env.borrow().immutable.clone()));
+            parent = env.borrow().parent.clone();
+        }
+        Snapshot(scopes)
+    }
+
+    // Roll back every scope in the chain to a snapshot of it, definitions
+    // made after the snapshot go away too
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), String> {
+        if snapshot.0.len() != self.depth() + 1 {
+            return Err(format!("Environment restore - snapshot of {} scopes, found {}",
+                               snapshot.0.len(), self.depth() + 1));
+        }
+        let mut scopes = snapshot.0.into_iter();
+        (self.values, self.immutable) = scopes.next().unwrap();
+        let mut parent = self.parent.clone();
+        for (values, immutable) in scopes {
+            let env = parent.expect("Environment restore - depth changed");
+            {
+                let mut env = env.borrow_mut();
+                env.values = values;
+                env.immutable = immutable;
+            }
+            parent = env.borrow().parent.clone();
+        }
+        Ok(())
+    }
+}
+
+impl Trace for Environment {
+    fn trace(&self, tracer: &mut Tracer) {
+        for value in self.values.values() {
+            value.trace(tracer);
+        }
+        if let Some(ref parent) = self.parent {
+            tracer.visit(parent);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.values.clear();
+        self.immutable.clear();
+        self.parent = None;
+    }
 }
@@ -0,0 +1,174 @@
+use crate::lox_scanner::TT;
+use crate::lox_parser::{Expr, Stmt};
+use std::rc::Rc;
+
+
+// Constant folding pre-pass, eg: `2 + 3 * 4` => 14
+// Only folds what can't fail at run-time, anything that would raise an error
+// (type mismatches) or is dubious (division by zero) is left for the
+// interpreter. Must run *before* the Resolver since it rebuilds Exprs.
+pub fn fold_stmts(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    stmts.into_iter().map(fold_stmt).collect()
+}
+
+fn fold_stmt(stmt: Stmt) -> Stmt {
+    match stmt {
+        Stmt::Print(exprs) => Stmt::Print(exprs.into_iter().map(fold_expr).collect()),
+        Stmt::Expr(expr) => Stmt::Expr(fold_expr(expr)),
+        Stmt::Var(name, init) => Stmt::Var(name, fold_expr(init)),
+        Stmt::Let(name, init) => Stmt::Let(name, fold_expr(init)),
+        Stmt::Block(stmts) => Stmt::Block(fold_stmts(stmts)),
+        Stmt::If(cond, then_branch, else_branch) => Stmt::If(
+            fold_expr(cond), Box::new(fold_stmt(*then_branch)),
+            else_branch.map(|else_b| Box::new(fold_stmt(*else_b)))),
+        Stmt::While(cond, body) =>
+            Stmt::While(fold_expr(cond), Box::new(fold_stmt(*body))),
+        Stmt::DoWhile(body, cond) =>
+            Stmt::DoWhile(Box::new(fold_stmt(*body)), fold_expr(cond)),
+        Stmt::Function(name, params, body) => match Rc::try_unwrap(body) {
+            Ok(body) => Stmt::Function(name, params, Rc::new(fold_stmts(body))),
+            // body is shared, can't rebuild it
+            Err(body) => Stmt::Function(name, params, body),
+        },
+        Stmt::Return(expr) => Stmt::Return(fold_expr(expr)),
+        Stmt::Assert(expr, line) => Stmt::Assert(fold_expr(expr), line),
+        Stmt::Break(scopes) => Stmt::Break(scopes),
+    }
+}
+
+fn is_literal(expr: &Expr) -> bool {
+    matches!(expr, Expr::Nil | Expr::Bool(_) | Expr::Num(_) | Expr::Str(_))
+}
+
+// same as V::is_truthy
+fn is_truthy(expr: &Expr) -> bool {
+    !matches!(expr, Expr::Nil | Expr::Bool(false))
+}
+
+// same as V's PartialEq, only called on literals
+fn literal_eq(lhs: &Expr, rhs: &Expr) -> bool {
+    match (lhs, rhs) {
+        (Expr::Nil, Expr::Nil) => true,
+        (Expr::Bool(a), Expr::Bool(b)) => a == b,
+        (Expr::Num(a), Expr::Num(b)) => a == b,
+        (Expr::Str(a), Expr::Str(b)) => a == b,
+        _ => false,
+    }
+}
+
+pub fn fold_expr(expr: Expr) -> Expr {
+    match expr {
+        Expr::Grouping(gexpr) => match fold_expr(*gexpr) {
+            literal if is_literal(&literal) => literal,
+            gexpr => Expr::Grouping(Box::new(gexpr)),
+        },
+        Expr::Unary(op, uexpr) => match (&op.token, fold_expr(*uexpr)) {
+            (TT::MINUS, Expr::Num(n)) => Expr::Num(-n),
+            (TT::BANG, literal) if is_literal(&literal) =>
+                Expr::Bool(!is_truthy(&literal)),
+            (_, uexpr) => Expr::Unary(op, Box::new(uexpr)),
+        },
+        Expr::Binary(lhs, op, rhs) => {
+            let (lhs, rhs) = (fold_expr(*lhs), fold_expr(*rhs));
+            match (&lhs, &op.token, &rhs) {
+                (Expr::Num(l), TT::PLUS, Expr::Num(r)) => Expr::Num(l + r),
+                (Expr::Num(l), TT::MINUS, Expr::Num(r)) => Expr::Num(l - r),
+                (Expr::Num(l), TT::STAR, Expr::Num(r)) => Expr::Num(l * r),
+                (Expr::Num(l), TT::SLASH, Expr::Num(r)) if *r != 0.0 =>
+                    Expr::Num(l / r),
+                (Expr::Str(l), TT::PLUS, Expr::Str(r)) =>
+                    Expr::Str(format!("{}{}", l, r)),
+                (Expr::Num(l), TT::GT, Expr::Num(r)) => Expr::Bool(l > r),
+                (Expr::Num(l), TT::GE, Expr::Num(r)) => Expr::Bool(l >= r),
+                (Expr::Num(l), TT::LT, Expr::Num(r)) => Expr::Bool(l < r),
+                (Expr::Num(l), TT::LE, Expr::Num(r)) => Expr::Bool(l <= r),
+                (l, TT::EQ, r) if is_literal(l) && is_literal(r) =>
+                    Expr::Bool(literal_eq(l, r)),
+                (l, TT::NE, r) if is_literal(l) && is_literal(r) =>
+                    Expr::Bool(!literal_eq(l, r)),
+                _ => Expr::Binary(Box::new(lhs), op, Box::new(rhs)),
+            }
+        },
+        // short-circuit on a literal lhs, the rhs is only kept if reachable
+        Expr::Logical(lhs, op, rhs) => {
+            let (lhs, rhs) = (fold_expr(*lhs), fold_expr(*rhs));
+            if !is_literal(&lhs) {
+                return Expr::Logical(Box::new(lhs), op, Box::new(rhs));
+            }
+            match (&op.token, is_truthy(&lhs)) {
+                (TT::OR, true) | (TT::AND, false) => lhs,
+                _ => rhs,
+            }
+        },
+        Expr::Coalesce(lhs, op, rhs) => {
+            let (lhs, rhs) = (fold_expr(*lhs), fold_expr(*rhs));
+            match lhs {
+                Expr::Nil => rhs,
+                lhs if is_literal(&lhs) => lhs,
+                lhs => Expr::Coalesce(Box::new(lhs), op, Box::new(rhs)),
+            }
+        },
+        // a literal condition picks the branch
+        Expr::Ternary(cond, then, other) => match fold_expr(*cond) {
+            cond if is_literal(&cond) =>
+                fold_expr(if is_truthy(&cond) { *then } else { *other }),
+            cond => Expr::Ternary(Box::new(cond), Box::new(fold_expr(*then)),
+                                  Box::new(fold_expr(*other))),
+        },
+        Expr::Assign(var, aexpr) => Expr::Assign(var, Box::new(fold_expr(*aexpr))),
+        Expr::Call(callee, args) => Expr::Call(
+            Box::new(fold_expr(*callee)), args.into_iter().map(fold_expr).collect()),
+        Expr::Get(obj, name) => Expr::Get(Box::new(fold_expr(*obj)), name),
+        Expr::Interpolation(parts) =>
+            Expr::Interpolation(parts.into_iter().map(fold_expr).collect()),
+        other => other,
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::lox_parser::LoxParser;
+    use crate::lox_printer::dump_stmts;
+    use crate::lox_scanner::LoxScanner;
+    use super::fold_stmts;
+
+    fn fold(source: &str) -> String {
+        let scanner = LoxScanner::scanner(source.chars());
+        dump_stmts(&fold_stmts(LoxParser::new(scanner).parse().unwrap()))
+    }
+
+    #[test]
+    fn folds_constants() {
+        assert_eq!(fold("2 + 3 * 4;"), "14");
+        assert_eq!(fold("(2 + 3) * 4;"), "20");
+        assert_eq!(fold("-(1 - 3) / 4;"), "0.5");
+        assert_eq!(fold("\"a\" + \"b\";"), "\"ab\"");
+        assert_eq!(fold("1 < 2 == !nil;"), "true");
+        assert_eq!(fold("nil or 1 + 1;"), "2");
+        assert_eq!(fold("false and f();"), "false");
+        assert_eq!(fold("nil ?? 1 + 1;"), "2");
+        assert_eq!(fold("false ?? f();"), "false");
+        assert_eq!(fold("1 < 2 ? x : f();"), "x");
+        assert_eq!(fold("nil ? x : 1 + 1;"), "2");
+        assert_eq!(fold("fun f() { return 2 * 3; }"), "(fun f () (return 6))");
+    }
+
+    #[test]
+    fn skips_non_constants() {
+        // variables aren't known until run-time
+        assert_eq!(fold("x + 2 * 3;"), "(+ x 6)");
+        assert_eq!(fold("x + 1 + 2;"), "(+ (+ x 1) 2)");
+        assert_eq!(fold("(x);"), "(group x)");
+        assert_eq!(fold("true and x;"), "x");
+        assert_eq!(fold("x ?? 1 + 1;"), "(?? x 2)");
+        assert_eq!(fold("x ? 1 + 1 : -2;"), "(? x 2 -2)");
+        // left for the interpreter to report/handle
+        assert_eq!(fold("1 / 0;"), "(/ 1 0)");
+        assert_eq!(fold("1 / (2 - 2);"), "(/ 1 0)");
+        assert_eq!(fold("-\"a\";"), "(- \"a\")");
+        assert_eq!(fold("1 < 2 < 3;"), "(< true 3)");
+        assert_eq!(fold("1 + \"a\";"), "(+ 1 \"a\")");
+    }
+}
@@ -0,0 +1,46 @@
+use std::fmt;
+
+// How values print, shared by LoxInterpreter and LoxVm so both backends agree
+
+// How numbers print, not left to f64's Display: integers up to 1e21 print
+// all their digits ("1e20" would be a surprise from "100000000000000000000"),
+// bigger ones use an exponent instead of 22+ digits, mostly noise. Negative
+// zero keeps its sign like the reference implementation, "-0". NaN, from
+// arithmetic on infinities, prints "nan". Division by zero doesn't get here,
+// `x / 0` (0 / 0 too) is an error value.
+pub fn format_num(n: f64) -> String {
+    match n {
+        _ if n.is_nan() => "nan".to_string(),
+        _ if n.is_infinite() => if n > 0.0 { "inf" } else { "-inf" }.to_string(),
+        _ if n == 0.0 && n.is_sign_negative() => "-0".to_string(),
+        _ if n.abs() >= 1e21 => format!("{:e}", n),
+        _ => format!("{}", n),
+    }
+}
+
+// What `print a, b;` writes: each value as displayed, space separated
+pub fn print_line(values: &[impl fmt::Display]) -> String {
+    values.iter().map(|value| value.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+// A print sink tests read back what was written to
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct Captured(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl Captured {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).to_string()
+    }
+}
+
+#[cfg(test)]
+impl std::io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+// Values that can hold GcHandles, eg: an Environment holding a function
+// whose closure is that same Environment. Reference counting never frees
+// such cycles, LoxInterpreter::gc does.
+pub trait Trace {
+    // Report every GcHandle held directly
+    fn trace(&self, tracer: &mut Tracer);
+    // Drop what's held to break cycles, the value is garbage
+    fn clear(&mut self);
+}
+
+type Erased = Rc<RefCell<dyn Trace>>;
+
+// A value shared through a plain Rc, eg: a function and its closure
+struct Shared {
+    addr: *const (),
+    refs: usize,
+    handles: Vec<Erased>,
+}
+
+// Collects the handles a value reports from Trace::trace
+pub struct Tracer {
+    handles: Vec<Erased>,
+    shared: Vec<Shared>,
+}
+
+impl Tracer {
+    pub fn visit<T: Trace + 'static>(&mut self, handle: &GcHandle<T>) {
+        self.handles.push(handle.0.clone());
+    }
+
+    // Handles reported by `trace` are held through `shared`. When it has
+    // more references than registered values hold, it's kept from outside
+    // and so are they.
+    pub fn visit_shared<T: ?Sized>(&mut self, shared: &Rc<T>, trace: impl FnOnce(&mut Tracer)) {
+        let start = self.handles.len();
+        trace(self);
+        let handles = self.handles.split_off(start);
+        self.shared.push(Shared{addr: Rc::as_ptr(shared) as *const (), refs: Rc::strong_count(shared), handles});
+    }
+}
+
+fn trace(value: &Erased) -> Tracer {
+    let mut tracer = Tracer{handles: Vec::new(), shared: Vec::new()};
+    value.borrow().trace(&mut tracer);
+    tracer
+}
+
+// Handles held directly or through shared values
+fn children(value: &Erased) -> Vec<Erased> {
+    let tracer = trace(value);
+    tracer.shared.into_iter().flat_map(|shared| shared.handles).chain(tracer.handles).collect()
+}
+
+fn addr(value: &Erased) -> *const () {
+    Rc::as_ptr(value) as *const ()
+}
+
+// Every GcHandle allocated, dead entries are pruned as it grows
+struct Registry {
+    entries: Vec<Weak<RefCell<dyn Trace>>>,
+    prune_at: usize,
+}
+
+thread_local! {
+    static REGISTRY: RefCell<Registry> = RefCell::new(Registry{entries: Vec::new(), prune_at: 64});
+}
+
+// Shared mutable value known to the collector, clones point to the same one
+pub struct GcHandle<T>(Rc<RefCell<T>>);
+
+impl<T: Trace + 'static> GcHandle<T> {
+    pub fn new(value: T) -> Self {
+        let handle = Rc::new(RefCell::new(value));
+        let erased: Erased = handle.clone();
+        REGISTRY.with(|registry| {
+            let mut registry = registry.borrow_mut();
+            if registry.entries.len() >= registry.prune_at {
+                registry.entries.retain(|entry| entry.strong_count() > 0);
+                registry.prune_at = 64.max(2 * registry.entries.len());
+            }
+            registry.entries.push(Rc::downgrade(&erased));
+        });
+        GcHandle(handle)
+    }
+}
+
+impl<T> GcHandle<T> {
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.0.borrow()
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.0.borrow_mut()
+    }
+}
+
+impl<T> Clone for GcHandle<T> {
+    fn clone(&self) -> Self {
+        GcHandle(self.0.clone())
+    }
+}
+
+// Mark what's reachable from `roots` and free the rest of the registry.
+// Values also held from outside (eg: a function kept by the embedder) count
+// as roots, they're found comparing reference counts against the references
+// from registered values. Returns the number of values freed.
+pub fn collect<'a, T: Trace + 'static>(roots: impl IntoIterator<Item = &'a GcHandle<T>>) -> usize {
+    let registered: Vec<Erased> = REGISTRY.with(|registry| registry.borrow().entries.iter()
+        .filter_map(|entry| entry.upgrade())
+        .collect());
+    // references to each value, shared ones counted once however many hold them
+    let mut internal = HashMap::<*const (), usize>::new();
+    let mut shared = HashMap::new();
+    for value in &registered {
+        let tracer = trace(value);
+        for child in &tracer.handles {
+            *internal.entry(addr(child)).or_default() += 1;
+        }
+        for value in tracer.shared {
+            shared.entry(value.addr).or_insert((value, 0)).1 += 1;
+        }
+    }
+    let mut pending: Vec<Erased> = roots.into_iter().map(|root| root.0.clone() as Erased).collect();
+    for (value, seen) in shared.into_values() {
+        for child in &value.handles {
+            *internal.entry(addr(child)).or_default() += 1;
+        }
+        if value.refs > seen {
+            pending.extend(value.handles);
+        }
+    }
+    // one reference is `registered`'s own
+    pending.extend(registered.iter().filter(|value|
+        Rc::strong_count(value) - 1 > internal.get(&addr(value)).cloned().unwrap_or(0)).cloned());
+    let mut marked = HashSet::new();
+    while let Some(value) = pending.pop() {
+        if marked.insert(addr(&value)) {
+            pending.extend(children(&value));
+        }
+    }
+    let garbage: Vec<_> = registered.into_iter()
+        .filter(|value| !marked.contains(&addr(value)))
+        .collect();
+    REGISTRY.with(|registry| registry.borrow_mut().entries
+        .retain(|entry| entry.upgrade().is_some_and(|value| marked.contains(&addr(&value)))));
+    for value in &garbage {
+        value.borrow_mut().clear();
+    }
+    garbage.len()
+}
@@ -1,12 +1,13 @@
-#![deny(warnings)]
-
 use crate::lox_scanner::{TT, Token};
 use crate::lox_parser::{Expr, Stmt};
-use crate::lox_environment::Environment;
-use crate::lox_native::native_fn_env;
-use std::cell::RefCell;
+use crate::lox_environment::{Environment, Snapshot};
+use crate::lox_format::{format_num, print_line};
+use crate::lox_gc::{self, GcHandle, Tracer};
+use crate::lox_native::{native_fn_env, str_method, BoundStrMethod};
+use crate::lox_macros::{self, Macros};
 use std::collections::HashMap;
 use std::fmt;
+use std::io::{self, Write};
 use std::rc::Rc;
 
 
@@ -14,6 +15,10 @@ pub trait Callable {
     fn call(&self, _: &mut LoxInterpreter, _: &[V]) -> ExecResult;
     fn arity(&self) -> usize;
     fn id(&self) -> String;
+    // As shown in stack traces
+    fn name(&self) -> String;
+    // Environments the callable keeps alive, see LoxInterpreter::gc
+    fn trace(&self, _: &mut Tracer) {}
 }
 
 #[derive(Clone)]
@@ -23,9 +28,17 @@ pub enum V {
     Bool(bool),
     Str(String),
     Callable(Rc<dyn Callable>),
+    // made by error(msg) or dividing by zero, operations on it give it back
+    // instead of failing, catch(value, handler) handles it
+    Error(String),
 }
 
 impl V {
+    pub fn trace(&self, tracer: &mut Tracer) {
+        if let V::Callable(callable) = self {
+            tracer.visit_shared(callable, |tracer| callable.trace(tracer));
+        }
+    }
     fn is_truthy(&self) -> bool {
         match self {
             V::Nil => false,
@@ -58,9 +71,10 @@ impl fmt::Debug for V {
         match self {
             V::Nil => write!(f, "nil"),
             V::Bool(ref b) => write!(f, "{}", b),
-            V::Num(ref n) => write!(f, "{}", n),
+            V::Num(n) => write!(f, "{}", format_num(*n)),
             V::Str(ref s) => write!(f, "\"{}\"", s),
             V::Callable(ref c) => write!(f, "\"{}\"", c.id()),
+            V::Error(ref msg) => write!(f, "<error {}>", msg),
         }
     }
 }
@@ -79,18 +93,33 @@ impl PartialEq for V {
             (&V::Bool(ref a), &V::Bool(ref b)) => a == b,
             (&V::Str(ref a), &V::Str(ref b)) => a == b,
             (&V::Callable(ref a), &V::Callable(ref b)) => a.id() == b.id(),
+            (V::Error(a), V::Error(b)) => a == b,
             _ => false,
         }
     }
 }
 
+// Ordering only makes sense between numbers. Chains like `1 < 2 < 3` end
+// up comparing a bool against a number, report it instead of coercing.
+fn compare(lhs: &V, op: &Token, rhs: &V) -> EvalResult {
+    match (lhs, rhs) {
+        (V::Num(l), V::Num(r)) => Ok(V::Bool(match op.token {
+            TT::GT => l > r,
+            TT::GE => l >= r,
+            TT::LT => l < r,
+            _ => l <= r,
+        })),
+        _ => Err(format!("can't compare {:?} {} {:?}", lhs, op.lexeme, rhs))
+    }
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 
 struct LoxFunction {
     name: String,
     params: Vec<String>,
     body: Rc<Vec<Stmt>>,
-    closure: Option<Rc<RefCell<Environment>>>,
+    closure: Option<GcHandle<Environment>>,
 }
 
 impl Callable for LoxFunction {
@@ -101,7 +130,7 @@ impl Callable for LoxFunction {
         }
         // keep track of return boundaries
         let retval = interp.exec_block(
-            &*self.body, Rc::new(RefCell::new(environ)),
+            &*self.body, GcHandle::new(environ),
             Nesting{func: true, loops: 0});
         interp.funreturn = false;
         retval
@@ -112,6 +141,14 @@ impl Callable for LoxFunction {
     fn id(&self) -> String {
         format!("<fn {}({})>", self.name, self.params.join(","))
     }
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(ref closure) = self.closure {
+            tracer.visit(closure);
+        }
+    }
 }
 
 ///////////////////////////////////////////////////////////////////////////////
@@ -119,6 +156,12 @@ impl Callable for LoxFunction {
 type EvalResult = Result<V, String>;
 pub type ExecResult = Result<V, String>;
 
+// An active call: the function and the line (in its caller) it was called at
+struct Frame {
+    function: String,
+    line: usize,
+}
+
 #[derive(Clone)]
 struct Nesting {
     func: bool,
@@ -126,26 +169,136 @@ struct Nesting {
 }
 
 pub struct LoxInterpreter {
-    globals: Rc<RefCell<Environment>>,
-    environ: Rc<RefCell<Environment>>,
+    globals: GcHandle<Environment>,
+    environ: GcHandle<Environment>,
     break_loops: usize,
     funreturn: bool,
     // keep a link to lookup variables
     locals: HashMap<usize, usize>,
+    frames: Vec<Frame>,
+    // line of the last token evaluated
+    line: usize,
+    // frames active when the last runtime error happened, innermost first
+    trace: Vec<String>,
+    macros: Macros,
+    // where print writes
+    out: Box<dyn Write>,
+}
+
+impl Default for LoxInterpreter {
+    fn default() -> Self {
+        LoxInterpreter::new()
+    }
 }
 
 impl LoxInterpreter {
     pub fn new() -> Self {
-        let globals = Rc::new(RefCell::new(native_fn_env()));
+        LoxInterpreter::with_output(Box::new(io::stdout()))
+    }
+
+    // Print to `out` instead of stdout
+    pub fn with_output(out: Box<dyn Write>) -> Self {
+        let globals = GcHandle::new(native_fn_env());
         LoxInterpreter{
             globals: globals.clone(),
             environ: globals,
             break_loops: 0,
             funreturn: false,
             locals: HashMap::new(),
+            frames: Vec::new(),
+            line: 0,
+            trace: Vec::new(),
+            macros: HashMap::new(),
+            out,
         }
     }
 
+    // Free environments unreachable from the current scope chain, eg: a
+    // block's scope kept alive by a function defined in it. Returns how
+    // many were freed.
+    pub fn gc(&mut self) -> usize {
+        lox_gc::collect([&self.globals, &self.environ])
+    }
+
+    // Snapshot of global bindings sorted by name
+    pub fn globals(&self) -> impl Iterator<Item=(String, V)> {
+        let mut globals: Vec<_> = self.globals.borrow().iter()
+            .map(|(name, val)| (name.to_string(), val.clone()))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals.into_iter()
+    }
+
+    // Bindings of the current scope chain, innermost first. Names hidden by
+    // an inner scope are marked as shadowed. Long values are truncated.
+    pub fn dump_env(&self) -> String {
+        const MAX_VALUE_LEN: usize = 40;
+        let show = |val: &V| {
+            let val = val.to_string();
+            match val.char_indices().nth(MAX_VALUE_LEN) {
+                Some((cut, _)) => format!("{}...", &val[..cut]),
+                None => val,
+            }
+        };
+        let mut seen = std::collections::HashSet::new();
+        let mut mark = |name: &str| if seen.insert(name.to_string()) { "" } else { " (shadowed)" };
+        let mut out = String::new();
+        let depth = self.environ.borrow().depth();
+        let mut scopes: Vec<_> = self.environ.borrow().iter_all()
+            .filter(|(_, _, level)| *level < depth)
+            .collect();
+        scopes.sort_by(|a, b| (a.2, &a.0).cmp(&(b.2, &b.0)));
+        for level in 0..depth {
+            out += &format!("scope {}:\n", depth - level);
+            for (name, val, _) in scopes.iter().filter(|(_, _, at)| *at == level) {
+                out += &format!("  {} = {}{}\n", name, show(val), mark(name));
+            }
+        }
+        out += "globals:\n";
+        for (name, val) in self.globals() {
+            out += &format!("  {} = {}{}\n", name, show(&val), mark(&name));
+        }
+        out
+    }
+
+    // Bindings visible from the current scope, to roll back with `restore`
+    pub fn snapshot(&self) -> Snapshot {
+        self.environ.borrow().snapshot()
+    }
+
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), String> {
+        self.environ.borrow_mut().restore(snapshot)
+    }
+
+    // Calls to `name` are replaced by what `transformer` builds out of the
+    // call's argument expressions, see `expand_macros`.
+    pub fn define_macro(&mut self, name: &str, transformer: impl Fn(Vec<Expr>) -> Expr + 'static) {
+        self.macros.insert(name.to_string(), Box::new(transformer));
+    }
+
+    // Expand macro calls, needs to run before resolving variables
+    pub fn expand_macros(&self, stmts: Vec<Stmt>) -> Vec<Stmt> {
+        lox_macros::expand_stmts(&self.macros, stmts)
+    }
+
+    // "[line N] in f()" frames of the last runtime error, ending in the script
+    pub fn stack_trace(&self) -> &[String] {
+        &self.trace
+    }
+
+    // Only the innermost error is traced, outer calls see it already done
+    fn capture_trace(&mut self) {
+        if !self.trace.is_empty() {
+            return;
+        }
+        let mut line = self.line;
+        for frame in self.frames.iter().rev() {
+            self.trace.push(format!("[line {}] in {}()", line, frame.function));
+            line = frame.line;
+        }
+        self.trace.push(format!("[line {}] in script", line));
+    }
+
     pub fn resolve(&mut self, expr: usize, depth: usize) {
         self.locals.insert(expr, depth);
     }
@@ -167,7 +320,9 @@ impl LoxInterpreter {
             Expr::Grouping(ref gexpr) => self.eval(&*gexpr),
             Expr::Unary(ref op, ref uexpr) => {
                 let uexpr = self.eval(uexpr)?;
+                self.line = op.line;
                 match op.token {
+                    _ if matches!(uexpr, V::Error(_)) => Ok(uexpr),
                     TT::MINUS => Ok(V::Num(-uexpr.num()?)),
                     TT::BANG => Ok(V::Bool(!uexpr.is_truthy())),
                     TT::DOLLAR => self.environ.borrow().get(uexpr.str()?),
@@ -177,7 +332,11 @@ impl LoxInterpreter {
             Expr::Binary(ref lhs, ref op, ref rhs) => {
                 let lhs = self.eval(lhs)?;
                 let rhs = self.eval(rhs)?;
+                self.line = op.line;
                 match op.token {
+                    _ if matches!(lhs, V::Error(_)) => Ok(lhs),
+                    _ if matches!(rhs, V::Error(_)) => Ok(rhs),
+                    TT::SLASH if rhs.num()? == 0.0 => Ok(V::Error("division by zero".to_string())),
                     TT::SLASH => Ok(V::Num(lhs.num()? / rhs.num()?)),
                     TT::STAR => Ok(V::Num(lhs.num()? * rhs.num()?)),
                     TT::MINUS => Ok(V::Num(lhs.num()? - rhs.num()?)),
@@ -191,10 +350,7 @@ impl LoxInterpreter {
                             Ok(V::Str(format!("{}{}", other, r))),
                         _ => Err(format!("can't {:?} + {:?}", lhs, rhs))
                     },
-                    TT::GT => Ok(V::Bool(lhs.num()? > rhs.num()?)),
-                    TT::GE => Ok(V::Bool(lhs.num()? >= rhs.num()?)),
-                    TT::LT => Ok(V::Bool(lhs.num()? < rhs.num()?)),
-                    TT::LE => Ok(V::Bool(lhs.num()? <= rhs.num()?)),
+                    TT::GT | TT::GE | TT::LT | TT::LE => compare(&lhs, op, &rhs),
                     TT::EQ => Ok(V::Bool(lhs == rhs)),
                     TT::NE => Ok(V::Bool(lhs != rhs)),
                     _ => unreachable!("LoxIntepreter: bad binop {:?} {:?} {:?}",
@@ -203,15 +359,32 @@ impl LoxInterpreter {
             },
             Expr::Logical(ref lhs, ref op, ref rhs) => {
                 let lhs = self.eval(lhs)?;
+                self.line = op.line;
                 match op.token {
                     TT::OR if lhs.is_truthy() => Ok(lhs),
                     TT::AND if !lhs.is_truthy() => Ok(lhs),
                     _ => self.eval(rhs)
                 }
             },
-            Expr::Var(ref var) => self.lookup_var(expr, var),
+            Expr::Coalesce(lhs, op, rhs) => {
+                let lhs = self.eval(lhs)?;
+                self.line = op.line;
+                match lhs {
+                    V::Nil => self.eval(rhs),
+                    lhs => Ok(lhs),
+                }
+            },
+            Expr::Ternary(cond, then, other) => match self.eval(cond)?.is_truthy() {
+                true => self.eval(then),
+                false => self.eval(other),
+            },
+            Expr::Var(ref var) => {
+                self.line = var.line;
+                self.lookup_var(expr, var)
+            },
             Expr::Assign(ref var, ref aexpr) => {
                 let value = self.eval(aexpr)?;
+                self.line = var.line;
                 if let Some(depth) = self.locals.get(&expr.id()) {
                     return self.environ.borrow_mut()
                         .assign_at(*depth, var.lexeme.clone(), value);
@@ -220,6 +393,7 @@ impl LoxInterpreter {
             },
             Expr::Call(ref callee, ref args) => {
                 let callee = self.eval(callee)?.call()?;
+                let line = self.line;
                 if callee.arity() != args.len() {
                     return Err(format!("wrong arity for {} expected {} not {}",
                                        callee.id(), callee.arity(), args.len()))
@@ -228,13 +402,48 @@ impl LoxInterpreter {
                 for arg in args {
                     arguments.push(self.eval(arg)?);
                 }
-                callee.call(self, &arguments)
-            }
+                self.line = line;
+                self.call(&callee, &arguments)
+            },
+            Expr::Get(ref obj, ref name) => {
+                let obj = self.eval(obj)?;
+                self.line = name.line;
+                match obj {
+                    V::Str(s) => Ok(V::Callable(Rc::new(
+                        BoundStrMethod::new(s, str_method(&name.lexeme)?)))),
+                    o => Err(format!("only strings have methods, found {:?}", o)),
+                }
+            },
+            // values are stringified like print does, except strings which
+            // are spliced in without quotes like `"a" + "b"` does
+            Expr::Interpolation(ref parts) => {
+                let mut out = String::new();
+                let mut error = None;
+                for part in parts {
+                    match self.eval(part)? {
+                        V::Str(s) => out += &s,
+                        err @ V::Error(_) => { error.get_or_insert(err); },
+                        other => out += &other.to_string(),
+                    }
+                }
+                Ok(error.unwrap_or(V::Str(out)))
+            },
         }
     }
 
+    // Call from the current line, natives calling back into Lox use it too
+    pub fn call(&mut self, callee: &Rc<dyn Callable>, args: &[V]) -> ExecResult {
+        self.frames.push(Frame{function: callee.name(), line: self.line});
+        let retval = callee.call(self, args);
+        if retval.is_err() {
+            self.capture_trace();
+        }
+        self.frames.pop();
+        retval
+    }
+
     fn exec_block(&mut self, statements: &[Stmt],
-                  env: Rc<RefCell<Environment>>,
+                  env: GcHandle<Environment>,
                   nesting: Nesting) -> ExecResult {
         let prev_env = self.environ.clone();
         self.environ = env;
@@ -253,8 +462,13 @@ impl LoxInterpreter {
     fn execute(&mut self, stmt: &Stmt, nesting: Nesting) -> ExecResult {
         match stmt {
             Stmt::Expr(ref expr) => self.eval(expr),
-            Stmt::Print(ref expr) => {
-                println!("{}", self.eval(expr)?);
+            Stmt::Print(ref exprs) => {
+                let mut values = Vec::new();
+                for expr in exprs {
+                    values.push(self.eval(expr)?);
+                }
+                writeln!(self.out, "{}", print_line(&values))
+                    .map_err(|e| format!("print failed: {}", e))?;
                 Ok(V::Nil)
             }
             Stmt::Var(ref name, ref init) => {
@@ -262,9 +476,14 @@ impl LoxInterpreter {
                 self.environ.borrow_mut().define(name.to_string(), value);
                 Ok(V::Nil)
             },
+            Stmt::Let(ref name, ref init) => {
+                let value = self.eval(init)?;
+                self.environ.borrow_mut().define_immutable(name.to_string(), value);
+                Ok(V::Nil)
+            },
             Stmt::Block(ref stmts) => {
                 let curenv = Environment::new(Some(self.environ.clone()));
-                self.exec_block(stmts, Rc::new(RefCell::new(curenv)), nesting)
+                self.exec_block(stmts, GcHandle::new(curenv), nesting)
             },
             Stmt::If(ref expr, ref then_branch, ref else_branch) => {
                 let condition = self.eval(expr)?;
@@ -287,8 +506,29 @@ impl LoxInterpreter {
                     if !condition.is_truthy() {
                         return Ok(V::Nil);
                     }
-                    self.execute(body, Nesting{
+                    let retval = self.execute(body, Nesting{
                         func: nesting.func, loops: nesting.loops+1})?;
+                    // returning from within the loop
+                    if self.funreturn {
+                        return Ok(retval);
+                    }
+                }
+            },
+            Stmt::DoWhile(ref body, ref condition) => {
+                loop {
+                    // the body runs before the condition is first checked
+                    let retval = self.execute(body, Nesting{
+                        func: nesting.func, loops: nesting.loops+1})?;
+                    if self.funreturn {
+                        return Ok(retval);
+                    }
+                    if self.break_loops > 0 {
+                        self.break_loops -= 1;
+                        return Ok(V::Nil);
+                    }
+                    if !self.eval(condition)?.is_truthy() {
+                        return Ok(V::Nil);
+                    }
                 }
             },
             Stmt::Break(num_breaks) => {
@@ -310,6 +550,13 @@ impl LoxInterpreter {
                     name.to_string(), V::Callable(Rc::new(function)));
                 Ok(V::Nil)
             },
+            Stmt::Assert(expr, line) => match self.eval(expr)?.is_truthy() {
+                true => Ok(V::Nil),
+                false => {
+                    self.line = *line;
+                    Err(format!("assertion failed at line {}", line))
+                }
+            },
             Stmt::Return(ref expr) => {
                 if !nesting.func {
                     return Err("can't return outside of function".to_string());
@@ -322,9 +569,449 @@ impl LoxInterpreter {
     }
 
     pub fn interpret(&mut self, statements: &[Stmt]) -> ExecResult {
+        // a previous run may have bailed out mid call
+        self.frames.clear();
+        self.trace.clear();
         for stmt in statements {
-            self.execute(stmt, Nesting{func: false, loops: 0})?;
+            if let Err(err) = self.execute(stmt, Nesting{func: false, loops: 0}) {
+                self.capture_trace();
+                return Err(err);
+            }
         }
         Ok(V::Nil)
     }
 }
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::lox_parser::LoxParser;
+    use crate::lox_resolver::Resolver;
+    use crate::lox_scanner::LoxScanner;
+    use crate::lox_environment::Environment;
+    use crate::lox_gc::GcHandle;
+    use super::{LoxInterpreter, V};
+
+    // run source and fetch the global variable 'r'
+    fn run(source: &str) -> Result<V, String> {
+        run_with(LoxInterpreter::new(), source)
+    }
+
+    fn run_with(mut interpreter: LoxInterpreter, source: &str) -> Result<V, String> {
+        let scanner = LoxScanner::scanner(source.chars());
+        let stmts = interpreter.expand_macros(LoxParser::new(scanner).parse().unwrap());
+        Resolver::new(&mut interpreter).resolve(&stmts)?;
+        interpreter.interpret(&stmts)?;
+        let result = interpreter.globals.borrow().get("r");
+        result
+    }
+
+    #[test]
+    fn dump_env() {
+        let scanner = LoxScanner::scanner(
+            "var a = 1; var s = \"0123456789012345678901234567890123456789xyz\";".chars());
+        let stmts = LoxParser::new(scanner).parse().unwrap();
+        let mut interpreter = LoxInterpreter::new();
+        interpreter.interpret(&stmts).unwrap();
+        assert_eq!(interpreter.globals().map(|(name, _)| name).collect::<Vec<_>>(),
+                   vec!["a", "catch", "clock", "error", "is_error", "s"]);
+        // enter a block shadowing 'a'
+        let mut block = Environment::new(Some(interpreter.globals.clone()));
+        block.define("a", V::Num(2.0));
+        block.define("b", V::Bool(true));
+        interpreter.environ = GcHandle::new(block);
+        assert_eq!(interpreter.environ.borrow().depth(), 1);
+        let mut outer: Vec<_> = interpreter.environ.borrow().iter_all()
+            .filter(|(_, _, level)| *level == 1).map(|(name, _, _)| name).collect();
+        outer.sort();
+        assert_eq!(outer, vec!["a", "catch", "clock", "error", "is_error", "s"]);
+        assert_eq!(interpreter.dump_env(), "scope 1:\n\
+                                            \x20 a = 2\n\
+                                            \x20 b = true\n\
+                                            globals:\n\
+                                            \x20 a = 1 (shadowed)\n\
+                                            \x20 catch = \"catch\"\n\
+                                            \x20 clock = \"clock\"\n\
+                                            \x20 error = \"error\"\n\
+                                            \x20 is_error = \"is_error\"\n\
+                                            \x20 s = \"012345678901234567890123456789012345678...\n");
+    }
+
+    #[test]
+    fn environment_iter() {
+        let mut globals = Environment::new(None);
+        globals.define("x", V::Num(1.0));
+        assert_eq!(globals.iter().collect::<Vec<_>>(), vec![("x", &V::Num(1.0))]);
+        let globals = GcHandle::new(globals);
+        let mut block = Environment::new(Some(globals.clone()));
+        block.define("x", V::Num(2.0));
+        block.define("y", V::Bool(true));
+        let mut inner: Vec<_> = block.iter().collect();
+        inner.sort_by_key(|(name, _)| *name);
+        assert_eq!(inner, vec![("x", &V::Num(2.0)), ("y", &V::Bool(true))]);
+        // shadowed bindings come after the ones hiding them
+        let mut all: Vec<_> = block.iter_all().collect();
+        all[..2].sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(all, vec![
+            ("x".to_string(), V::Num(2.0), 0),
+            ("y".to_string(), V::Bool(true), 0),
+            ("x".to_string(), V::Num(1.0), 1)]);
+        assert_eq!(block.names(), vec!["x", "y"]);
+        assert_eq!(Environment::new(None).iter_all().count(), 0);
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let mut interpreter = LoxInterpreter::new();
+        let run = |interpreter: &mut LoxInterpreter, source: &str| {
+            let stmts = LoxParser::new(LoxScanner::scanner(source.chars())).parse().unwrap();
+            Resolver::new(interpreter).resolve(&stmts).unwrap();
+            interpreter.interpret(&stmts)
+        };
+        run(&mut interpreter, "var a = 1; var s = \"x\";").unwrap();
+        let snapshot = interpreter.snapshot();
+        assert!(run(&mut interpreter, "a = 2; var r = 3; s = s + \"y\"; a + nil;").is_err());
+        interpreter.restore(snapshot).unwrap();
+        let native = |name: &str| (name.to_string(), interpreter.globals.borrow().get(name).unwrap());
+        assert_eq!(interpreter.globals().collect::<Vec<_>>(), vec![
+            ("a".to_string(), V::Num(1.0)),
+            native("catch"), native("clock"), native("error"), native("is_error"),
+            ("s".to_string(), V::Str("x".to_string()))]);
+
+        // nested scopes roll back all the way up to the globals
+        let mut block = Environment::new(Some(interpreter.globals.clone()));
+        block.define("a", V::Num(10.0));
+        let snapshot = block.snapshot();
+        block.assign("a".to_string(), V::Nil).unwrap();
+        block.define("b", V::Nil);
+        block.assign_at(1, "a".to_string(), V::Nil).unwrap();
+        block.restore(snapshot).unwrap();
+        assert_eq!(block.iter().map(|(name, _)| name).collect::<Vec<_>>(), vec!["a"]);
+        assert_eq!(block.get_at(0, "a"), Ok(V::Num(10.0)));
+        assert_eq!(block.get_at(1, "a"), Ok(V::Num(1.0)));
+        // snapshots only go back into the chain they came from
+        let snapshot = block.snapshot();
+        assert_eq!(interpreter.restore(snapshot),
+                   Err("Environment restore - snapshot of 2 scopes, found 1".to_string()));
+    }
+
+    #[test]
+    fn did_you_mean() {
+        assert_eq!(run("var count = 1; var r = conut;"), Err(
+            "Environment get - undefined entity 'conut', did you mean 'count'?".to_string()));
+        assert_eq!(run("fun f() { return clokc(); } var r = f();"), Err(
+            "Environment get - undefined entity 'clokc', did you mean 'clock'?".to_string()));
+        assert_eq!(run("var count = 1; var r = banana;"), Err(
+            "Environment get - undefined entity 'banana'".to_string()));
+        // names from enclosing scopes are offered too
+        let globals = GcHandle::new(Environment::new(None));
+        globals.borrow_mut().define("total", V::Nil);
+        let mut block = Environment::new(Some(globals));
+        block.define("totals", V::Nil);
+        assert_eq!(block.names(), vec!["total", "totals"]);
+        assert_eq!(block.get("totl"), Err(
+            "Environment get - undefined entity 'totl', did you mean 'total'?".to_string()));
+        block.define("counter", V::Nil);
+        assert_eq!(block.assign("countr".to_string(), V::Nil), Err(
+            "Environment assign - undefined entity 'countr', did you mean 'counter'?".to_string()));
+    }
+
+    #[test]
+    fn gc_cycles() {
+        // run source and collect, how many scopes were freed
+        fn exec(interpreter: &mut LoxInterpreter, source: &str) -> usize {
+            let scanner = LoxScanner::scanner(source.chars());
+            let stmts = LoxParser::new(scanner).parse().unwrap();
+            Resolver::new(interpreter).resolve(&stmts).unwrap();
+            interpreter.interpret(&stmts).unwrap();
+            interpreter.gc()
+        }
+        let mut interpreter = LoxInterpreter::new();
+        // the block's scope holds f, f's closure is the block's scope
+        assert_eq!(exec(&mut interpreter, "{ fun f() { return 1; } }"), 1);
+        assert_eq!(exec(&mut interpreter, "{ var a = 1; }"), 0);
+        // make's scope is a cycle too but it's reachable from the globals
+        assert_eq!(exec(&mut interpreter, "
+            fun make() { var n = 0; fun inc() { n = n + 1; return n; } return inc; }
+            var counter = make(); counter();
+            { fun g() {} { fun h() { return g; } } }"), 2);
+        assert_eq!(exec(&mut interpreter, "var r = counter();"), 0);
+        assert_eq!(interpreter.globals.borrow().get("r"), Ok(V::Num(2.0)));
+        // values held outside the interpreter keep their scopes alive
+        exec(&mut interpreter, "var kept; { fun f() { return 3; } kept = f; }");
+        let kept = interpreter.globals.borrow().get("kept").unwrap();
+        assert_eq!(exec(&mut interpreter, "kept = nil;"), 0);
+        match kept {
+            V::Callable(ref f) => assert_eq!(interpreter.call(f, &[]), Ok(V::Num(3.0))),
+            ref o => panic!("expected a function, found {:?}", o),
+        }
+        drop(kept);
+        assert_eq!(interpreter.gc(), 1);
+        // freed once the last reference goes
+        assert_eq!(exec(&mut interpreter, "var a; var b; { fun f() {} a = f; b = f; } a = nil;"), 0);
+        assert_eq!(exec(&mut interpreter, "b = nil;"), 1);
+    }
+
+    #[test]
+    fn coalesce() {
+        assert_eq!(run("var r = nil ?? 5;"), Ok(V::Num(5.0)));
+        assert_eq!(run("var r = false ?? 5;"), Ok(V::Bool(false)));
+        assert_eq!(run("var a; var r = a ?? nil ?? \"x\";"), Ok(V::Str("x".to_string())));
+        // binds looser than or, tighter than assignment
+        assert_eq!(run("var r; r = nil or nil ?? 1 or 2;"), Ok(V::Num(1.0)));
+        // the right side is only evaluated on nil
+        assert_eq!(run("var r = 0; fun f() { r = r + 1; return 2; }\n\
+                        var a = 1 ?? f(); a = nil ?? f();"), Ok(V::Num(1.0)));
+    }
+
+    #[test]
+    fn ternary() {
+        assert_eq!(run("var r = 1 < 2 ? \"yes\" : \"no\";"), Ok(V::Str("yes".to_string())));
+        assert_eq!(run("var r = nil ? 1 : 0 ? 2 : 3;"), Ok(V::Num(2.0)));
+        // nested and right associative: a ? b : (c ? d : e)
+        assert_eq!(run("fun sign(n) { return n > 0 ? 1 : n < 0 ? -1 : 0; }\n\
+                        var r = \"${sign(5)} ${sign(-2)} ${sign(0)}\";"),
+                   Ok(V::Str("1 -1 0".to_string())));
+        assert_eq!(run("var r = true ? false ? 1 : 2 : 3;"), Ok(V::Num(2.0)));
+        // as a function argument and looser than ?? and or
+        assert_eq!(run("fun add(a, b) { return a + b; }\n\
+                        var r = add(false or nil ?? true ? 10 : 20, 1 > 2 ? 100 : 5);"),
+                   Ok(V::Num(15.0)));
+        assert_eq!(run("var r; r = false ? 1 : 2;"), Ok(V::Num(2.0)));
+        // the branch not taken isn't evaluated
+        assert_eq!(run("var r = 0; fun f() { r = r + 1; return r; }\n\
+                        true ? f() : f(); false ? f() : nil;"), Ok(V::Num(1.0)));
+        assert!(LoxParser::new(LoxScanner::scanner("1 ? 2;".chars())).parse().is_err());
+    }
+
+    #[test]
+    fn let_bindings() {
+        assert_eq!(run("let a = 2; var r = a * 3;"), Ok(V::Num(6.0)));
+        assert_eq!(run("let r = 1; r = 2;"), Err("cannot assign to immutable 'r'".to_string()));
+        assert_eq!(run("var r; { let a = 1; fun f() { a = 2; } f(); }"),
+                   Err("cannot assign to immutable 'a'".to_string()));
+        // shadowing is fine, the outer binding stays as it was
+        assert_eq!(run("let a = 1; var r; { var a = 2; a = 3; r = a; } r = r * 10 + a;"),
+                   Ok(V::Num(31.0)));
+        assert_eq!(run("var a = 1; var r; { let a = 2; r = a; } a = 5; r = r + a;"), Ok(V::Num(7.0)));
+        // defining it again takes the new kind of binding
+        assert_eq!(run("let r = 1; var r = 2; r = 3;"), Ok(V::Num(3.0)));
+        assert!(LoxParser::new(LoxScanner::scanner("let a;".chars())).parse().is_err());
+    }
+
+    #[test]
+    fn do_while() {
+        // the body runs once even when the condition starts false
+        assert_eq!(run("var r = 0; do r = r + 1; while (false);"), Ok(V::Num(1.0)));
+        assert_eq!(run("var r = 0; do { r = r + 1; } while (r < 5);"), Ok(V::Num(5.0)));
+        // the condition is checked after the body, seeing its effects
+        assert_eq!(run("var r = 0; var n = 3; do { n = n - 1; r = r + 1; } while (n > 0);"),
+                   Ok(V::Num(3.0)));
+        assert_eq!(run("var r = 0; do { r = r + 1; if (r == 3) break; } while (true);"),
+                   Ok(V::Num(3.0)));
+        assert_eq!(run("var r = 0; while (true) { do { r = r + 1; break 2; } while (true); r = -1; }"),
+                   Ok(V::Num(1.0)));
+        assert_eq!(run("fun f() { do { return 7; } while (true); } var r = f();"), Ok(V::Num(7.0)));
+        // each run gets its own scope for closures to capture
+        assert_eq!(run("var a; var b; var n = 0;
+                        do { var i = n; fun get() { return i; } if (n == 0) a = get; else b = get;
+                             n = n + 1; } while (n < 2);
+                        var r = a() * 10 + b();"), Ok(V::Num(1.0)));
+        assert!(LoxParser::new(LoxScanner::scanner("do { } while (true)".chars())).parse().is_err());
+    }
+
+    #[test]
+    fn assert() {
+        assert_eq!(run("assert true; var r = 1;"), Ok(V::Num(1.0)));
+        assert_eq!(run("var r = 0;\nassert 1 == 2;\nr = 1;"),
+                   Err("assertion failed at line 2".to_string()));
+        assert_eq!(run("fun f(x) {\n  assert x > 0;\n  return x;\n}\nvar r = f(1) + f(2);"),
+                   Ok(V::Num(3.0)));
+        assert_eq!(run("var r = nil;\nassert r ?? 0;\nassert r;"),
+                   Err("assertion failed at line 3".to_string()));
+    }
+
+    #[test]
+    fn chained_comparison() {
+        // parses as (1 < 2) < 3, second comparison is true < 3
+        assert_eq!(run("var r = 1 < 2 < 3;"),
+                   Err("can't compare true < 3".to_string()));
+        assert_eq!(run("var r = 3 >= 2 > 1;"),
+                   Err("can't compare true > 1".to_string()));
+        // comparison binds tighter than equality
+        assert_eq!(run("var r = 1 < 2 == true;"), Ok(V::Bool(true)));
+        assert_eq!(run("var r = 2 <= 1 != true;"), Ok(V::Bool(true)));
+        assert_eq!(run("var r = (1 < 2) == (2 < 3);"), Ok(V::Bool(true)));
+    }
+
+    #[test]
+    fn string_methods() {
+        assert_eq!(run("var r = \"abc\".length();"), Ok(V::Num(3.0)));
+        assert_eq!(run("var r = \"µs\".length();"), Ok(V::Num(2.0)));
+        assert_eq!(run("var s = \"a,b\"; var r = (s + \",c\").upper().split(\",\", 2);"),
+                   Ok(V::Str("C".to_string())));
+        assert_eq!(run("var r = \"a,b\".split(\",\", 2);"), Ok(V::Nil));
+        // methods are values bound to their string
+        assert_eq!(run("var up = \"x\".upper; var r = up() + up;"),
+                   Ok(V::Str("X\"<method upper of \"x\">\"".to_string())));
+        assert_eq!(run("var r = \"a\".upper == \"a\".upper;"), Ok(V::Bool(true)));
+        assert_eq!(run("var r = \"abc\".lenght();"),
+                   Err("undefined method 'lenght' for string, did you mean 'length'?".to_string()));
+        assert_eq!(run("var r = 1; r.length();"),
+                   Err("only strings have methods, found 1".to_string()));
+        assert_eq!(run("var r = \"a\".split(\"\", 0);"),
+                   Err("split separator can't be empty".to_string()));
+        assert_eq!(run("var r = \"a\".upper(1);"),
+                   Err("wrong arity for <method upper of \"a\"> expected 0 not 1".to_string()));
+        assert!(LoxParser::new(LoxScanner::scanner("\"a\".;".chars())).parse().is_err());
+    }
+
+    #[test]
+    fn interpolation() {
+        assert_eq!(run("var x = 1; var y = 2; var r = \"x = ${x}, y = ${y * 2}\";"),
+                   Ok(V::Str("x = 1, y = 4".to_string())));
+        // nested strings and braces, strings are spliced in without quotes
+        assert_eq!(run("var s = \"in\"; var r = \"<${\"a ${s + \"!\"} b\"}>\";"),
+                   Ok(V::Str("<a in! b>".to_string())));
+        assert_eq!(run("fun f() { return nil; } var r = \"${f()}${true}\";"),
+                   Ok(V::Str("niltrue".to_string())));
+        // '$' without '{' and escaped '${' are literal
+        assert_eq!(run("var r = \"$5 \\${x}\";"), Ok(V::Str("$5 ${x}".to_string())));
+    }
+
+    #[test]
+    fn error_values() {
+        let handler = "fun h(e) { return \"caught: \" + e; }";
+        assert_eq!(run(&format!("{} var r = catch(1/0, h);", handler)),
+                   Ok(V::Str("caught: division by zero".to_string())));
+        assert_eq!(run(&format!("{} var r = catch(6/2, h);", handler)), Ok(V::Num(3.0)));
+        // errors go through operations without stopping the script
+        assert_eq!(run("var e = error(\"bad\"); var r = -(e * 2) + 1 < 3;"),
+                   Ok(V::Error("bad".to_string())));
+        assert_eq!(run("var r = \"x ${1/0}\";"), Ok(V::Error("division by zero".to_string())));
+        assert_eq!(run("var r = is_error(1/0) and !is_error(nil);"), Ok(V::Bool(true)));
+        assert_eq!(run("fun f(x) { return x; } var r = f(error(\"a\")) == error(\"a\");"),
+                   Ok(V::Error("a".to_string())));
+        assert_eq!(run("var r = \"\" + error(\"a\");"), Ok(V::Error("a".to_string())));
+        assert_eq!(V::Error("a".to_string()).to_string(), "<error a>");
+        // the handler runs like any call
+        assert_eq!(run("fun h(e) { return e - 1; } var r = catch(error(\"a\"), h);"),
+                   Err("expected V::Num, found \"a\"".to_string()));
+        assert_eq!(run("var r = catch(1, 2);"),
+                   Err("catch expects a handler taking 1 argument, found 2".to_string()));
+        assert_eq!(run("var r = error(1);"),
+                   Err("error expects a message string, found 1".to_string()));
+    }
+
+    #[test]
+    fn print_values() {
+        assert_eq!(crate::lox_format::print_line(&[V::Num(1.0), V::Num(2.0), V::Num(3.0)]), "1 2 3");
+        assert_eq!(crate::lox_format::print_line(&[V::Str("a".to_string())]), "\"a\"");
+        let out = crate::lox_format::Captured::default();
+        let interpreter = LoxInterpreter::with_output(Box::new(out.clone()));
+        assert_eq!(run_with(interpreter, "var r = nil; print 1, 2, 3;\nprint 4;"), Ok(V::Nil));
+        assert_eq!(out.text(), "1 2 3\n4\n");
+        // each value is evaluated in order, once
+        let out = crate::lox_format::Captured::default();
+        let interpreter = LoxInterpreter::with_output(Box::new(out.clone()));
+        assert_eq!(run_with(interpreter, "var r = \"\"; fun f(s) { r = r + s; return s; }\n\
+                                          print f(1), f(2), f(3);"), Ok(V::Str("123".to_string())));
+        assert_eq!(out.text(), "1 2 3\n");
+        assert!(LoxParser::new(LoxScanner::scanner("print 1,;".chars())).parse().is_err());
+    }
+
+    #[test]
+    fn nil_equality() {
+        // nil only equals nil, it's falsey but not false
+        assert_eq!(run("var r = nil == nil;"), Ok(V::Bool(true)));
+        assert_eq!(run("var r = nil == false;"), Ok(V::Bool(false)));
+        assert_eq!(run("var r = nil == 0;"), Ok(V::Bool(false)));
+        assert_eq!(run("var r = nil != \"\";"), Ok(V::Bool(true)));
+        assert_eq!(run("var r = !nil == !false;"), Ok(V::Bool(true)));
+        // print keeps nil and the empty string apart
+        assert_eq!(V::Nil.to_string(), "nil");
+        assert_eq!(V::Str(String::new()).to_string(), "\"\"");
+    }
+
+    #[test]
+    fn numeric_edge_cases() {
+        let div = Ok(V::Error("division by zero".to_string()));
+        assert_eq!(run("var r = 1 / 0;"), div);
+        assert_eq!(run("var r = -1 / 0;"), div);
+        assert_eq!(run("var r = 0 / 0;"), div);
+        assert_eq!(run("var r = 0 / -0;"), div);
+        // overflow is the way to infinity, and from there to nan
+        let nums = "var inf = 1; for (var i = 0; i < 400; i = i + 1) inf = inf * 10;\n\
+                    var nan = inf - inf;\n";
+        let matrix = [
+            ("nan == nan", V::Bool(false)),
+            ("nan != nan", V::Bool(true)),
+            ("nan < 1 or nan >= 1", V::Bool(false)),
+            ("nan == nil", V::Bool(false)),
+            ("nil == nil", V::Bool(true)),
+            ("inf == inf", V::Bool(true)),
+            ("-0 == 0", V::Bool(true)),
+            ("\"${inf} ${-inf} ${nan}\"", V::Str("inf -inf nan".to_string())),
+            ("\"${-0} ${0 * -1} ${-0 + 0}\"", V::Str("-0 -0 0".to_string())),
+            ("\"${100000000000000000000}\"", V::Str("100000000000000000000".to_string())),
+            ("\"${1000000000000000000000}\"", V::Str("1e21".to_string())),
+            ("\"${-1500000000000000000000}\"", V::Str("-1.5e21".to_string())),
+            ("\"${9007199254740993}\"", V::Str("9007199254740992".to_string())),
+            ("\"${2.5} ${1 / 4}\"", V::Str("2.5 0.25".to_string())),
+        ];
+        for (expr, expected) in matrix {
+            assert_eq!(run(&format!("{}var r = {};", nums, expr)), Ok(expected), "{}", expr);
+        }
+        assert_eq!(V::Num(-0.0).to_string(), "-0");
+        assert_eq!(V::Num(1e300).to_string(), "1e300");
+    }
+
+    #[test]
+    fn stack_trace() {
+        let source = "fun f(s) {\n  return s / 2;\n}\n\
+                      fun g(s) {\n  var x = 1;\n  return f(s) + x;\n}\n\
+                      fun h() { return g(\"a\"); }\n\
+                      var r = h();";
+        let scanner = LoxScanner::scanner(source.chars());
+        let stmts = LoxParser::new(scanner).parse().unwrap();
+        let mut interpreter = LoxInterpreter::new();
+        Resolver::new(&mut interpreter).resolve(&stmts).unwrap();
+        assert!(interpreter.interpret(&stmts).is_err());
+        assert_eq!(interpreter.stack_trace(), ["[line 2] in f()", "[line 6] in g()",
+                                               "[line 8] in h()", "[line 9] in script"]);
+        // returns unwind the stack, a new run starts clean
+        let scanner = LoxScanner::scanner("fun k() { return 1; }\nk();\nk() + nil;".chars());
+        let stmts = LoxParser::new(scanner).parse().unwrap();
+        assert!(interpreter.interpret(&stmts).is_err());
+        assert_eq!(interpreter.stack_trace(), ["[line 3] in script"]);
+    }
+
+    #[test]
+    fn macros() {
+        use crate::lox_macros::debug_print;
+        use crate::lox_parser::Expr;
+        use crate::lox_scanner::{Token, TT};
+        let mut interpreter = LoxInterpreter::new();
+        interpreter.define_macro("debug_print", debug_print);
+        assert_eq!(run_with(interpreter, "var x = 2; var r = debug_print(x + 1, x);"),
+                   Ok(V::Str("(+ x 1) = 3, x = 2".to_string())));
+
+        // require(cond) => cond or fail("Assertion failed"), `assert` is a keyword
+        let require = |mut args: Vec<Expr>| {
+            let token = |token, lexeme: &str| Token{line: 0, offset: 0, token, lexeme: lexeme.to_string()};
+            let fail = Expr::Call(Box::new(Expr::Var(token(TT::Id("fail".to_string()), "fail"))),
+                                  vec![Expr::Str("Assertion failed".to_string())]);
+            Expr::Logical(Box::new(args.remove(0)), token(TT::OR, "or"), Box::new(fail))
+        };
+        let source = "var r = nil; fun fail(msg) { r = msg; }\n\
+                      fun check(n) { require(n > 1); return n; }";
+        let mut interpreter = LoxInterpreter::new();
+        interpreter.define_macro("require", require);
+        assert_eq!(run_with(interpreter, &format!("{} check(2);", source)), Ok(V::Nil));
+        let mut interpreter = LoxInterpreter::new();
+        interpreter.define_macro("require", require);
+        assert_eq!(run_with(interpreter, &format!("{} check(1);", source)),
+                   Ok(V::Str("Assertion failed".to_string())));
+    }
+}
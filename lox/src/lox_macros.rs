@@ -0,0 +1,78 @@
+use crate::lox_parser::{Expr, Stmt};
+use crate::lox_printer::dump_expr;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+
+// Syntactic macros: calls to a macro name are replaced by whatever its
+// transformer builds from the (unevaluated) argument expressions. Arguments
+// are expanded first, the expansion itself isn't expanded again. Like the
+// folder this rebuilds Exprs so it must run *before* the Resolver.
+pub type Macro = Box<dyn Fn(Vec<Expr>) -> Expr>;
+pub type Macros = HashMap<String, Macro>;
+
+pub fn expand_stmts(macros: &Macros, stmts: Vec<Stmt>) -> Vec<Stmt> {
+    stmts.into_iter().map(|stmt| expand_stmt(macros, stmt)).collect()
+}
+
+fn expand_stmt(macros: &Macros, stmt: Stmt) -> Stmt {
+    let expand = |expr| expand_expr(macros, expr);
+    let expand_box = |stmt: Box<Stmt>| Box::new(expand_stmt(macros, *stmt));
+    match stmt {
+        Stmt::Print(exprs) => Stmt::Print(exprs.into_iter().map(expand).collect()),
+        Stmt::Expr(expr) => Stmt::Expr(expand(expr)),
+        Stmt::Var(name, init) => Stmt::Var(name, expand(init)),
+        Stmt::Let(name, init) => Stmt::Let(name, expand(init)),
+        Stmt::Block(stmts) => Stmt::Block(expand_stmts(macros, stmts)),
+        Stmt::If(cond, then_branch, else_branch) =>
+            Stmt::If(expand(cond), expand_box(then_branch), else_branch.map(expand_box)),
+        Stmt::While(cond, body) => Stmt::While(expand(cond), expand_box(body)),
+        Stmt::DoWhile(body, cond) => Stmt::DoWhile(expand_box(body), expand(cond)),
+        Stmt::Function(name, params, body) => match Rc::try_unwrap(body) {
+            Ok(body) => Stmt::Function(name, params, Rc::new(expand_stmts(macros, body))),
+            // body is shared, can't rebuild it
+            Err(body) => Stmt::Function(name, params, body),
+        },
+        Stmt::Return(expr) => Stmt::Return(expand(expr)),
+        Stmt::Assert(expr, line) => Stmt::Assert(expand(expr), line),
+        Stmt::Break(scopes) => Stmt::Break(scopes),
+    }
+}
+
+fn expand_expr(macros: &Macros, expr: Expr) -> Expr {
+    let expand = |expr: Box<Expr>| Box::new(expand_expr(macros, *expr));
+    let expand_all = |exprs: Vec<Expr>| -> Vec<Expr> {
+        exprs.into_iter().map(|expr| expand_expr(macros, expr)).collect()
+    };
+    match expr {
+        Expr::Call(callee, args) => {
+            let args = expand_all(args);
+            match *callee {
+                Expr::Var(ref name) if macros.contains_key(&name.lexeme) =>
+                    macros[&name.lexeme](args),
+                callee => Expr::Call(expand(Box::new(callee)), args),
+            }
+        },
+        Expr::Logical(lhs, op, rhs) => Expr::Logical(expand(lhs), op, expand(rhs)),
+        Expr::Coalesce(lhs, op, rhs) => Expr::Coalesce(expand(lhs), op, expand(rhs)),
+        Expr::Ternary(cond, then, other) => Expr::Ternary(expand(cond), expand(then), expand(other)),
+        Expr::Binary(lhs, op, rhs) => Expr::Binary(expand(lhs), op, expand(rhs)),
+        Expr::Unary(op, uexpr) => Expr::Unary(op, expand(uexpr)),
+        Expr::Grouping(gexpr) => Expr::Grouping(expand(gexpr)),
+        Expr::Assign(var, aexpr) => Expr::Assign(var, expand(aexpr)),
+        Expr::Get(obj, name) => Expr::Get(expand(obj), name),
+        Expr::Interpolation(parts) => Expr::Interpolation(expand_all(parts)),
+        other => other,
+    }
+}
+
+// debug_print(x + 1, y) => "(+ x 1) = 3, y = 2"
+pub fn debug_print(args: Vec<Expr>) -> Expr {
+    let mut parts = Vec::new();
+    for (idx, arg) in args.into_iter().enumerate() {
+        let sep = if idx == 0 { "" } else { ", " };
+        parts.push(Expr::Str(format!("{}{} = ", sep, dump_expr(&arg))));
+        parts.push(arg);
+    }
+    Expr::Interpolation(parts)
+}
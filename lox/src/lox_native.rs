@@ -1,23 +1,131 @@
-#![deny(warnings)]
-
 use crate::lox_environment::Environment;
 use crate::lox_interpreter::{Callable, LoxInterpreter, V};
+use lexers::did_you_mean;
 use std::rc::Rc;
 
 pub struct Clock;
 
+// Nanoseconds since the epoch
+pub fn clock_nanos() -> f64 {
+    (time::OffsetDateTime::now_utc() - time::OffsetDateTime::UNIX_EPOCH)
+        .whole_nanoseconds() as f64
+}
+
 impl Callable for Clock {
     fn call(&self, _: &mut LoxInterpreter, _: &[V]) -> Result<V, String> {
-        Ok(V::Num(
-            (time::OffsetDateTime::now_utc() - time::OffsetDateTime::UNIX_EPOCH)
-            .whole_nanoseconds() as f64))
+        Ok(V::Num(clock_nanos()))
     }
     fn arity(&self) -> usize { 0 }
     fn id(&self) -> String { "clock".to_string() }
+    fn name(&self) -> String { "clock".to_string() }
 }
 
+// Natives that are plain functions of their arguments
+#[derive(Clone, Copy)]
+struct NativeFn {
+    name: &'static str,
+    arity: usize,
+    call: fn(&mut LoxInterpreter, &[V]) -> Result<V, String>,
+}
+
+impl Callable for NativeFn {
+    fn call(&self, interp: &mut LoxInterpreter, args: &[V]) -> Result<V, String> {
+        (self.call)(interp, args)
+    }
+    fn arity(&self) -> usize { self.arity }
+    fn id(&self) -> String { self.name.to_string() }
+    fn name(&self) -> String { self.name.to_string() }
+}
+
+// Errors as values: error("msg") makes one, is_error(v) tests for it and
+// catch(v, handler) is handler("msg") for errors or v as is otherwise
+static ERROR_FNS: [NativeFn; 3] = [
+    NativeFn{name: "error", arity: 1, call: |_, args| match &args[0] {
+        V::Str(msg) => Ok(V::Error(msg.clone())),
+        o => Err(format!("error expects a message string, found {:?}", o)),
+    }},
+    NativeFn{name: "is_error", arity: 1, call: |_, args| Ok(V::Bool(matches!(args[0], V::Error(_))))},
+    NativeFn{name: "catch", arity: 2, call: |interp, args| match (&args[0], &args[1]) {
+        (V::Error(msg), V::Callable(handler)) if handler.arity() == 1 =>
+            interp.call(handler, &[V::Str(msg.clone())]),
+        (_, V::Callable(handler)) if handler.arity() == 1 => Ok(args[0].clone()),
+        (_, o) => Err(format!("catch expects a handler taking 1 argument, found {:?}", o)),
+    }},
+];
+
 pub fn native_fn_env() -> Environment {
     let mut environment = Environment::new(None);
     environment.define("clock", V::Callable(Rc::new(Clock)));
+    for native in &ERROR_FNS {
+        environment.define(native.name, V::Callable(Rc::new(*native)));
+    }
     environment
 }
+
+///////////////////////////////////////////////////////////////////////////////
+
+// Methods on strings, "abc".upper(). Shared by LoxInterpreter and LoxVm
+// which convert their values to and from Prim around the call.
+#[derive(Debug, PartialEq)]
+pub enum Prim {
+    Nil,
+    Num(f64),
+    Str(String),
+}
+
+pub struct StrMethod {
+    pub name: &'static str,
+    pub arity: usize,
+    pub call: fn(&str, &[Prim]) -> Result<Prim, String>,
+}
+
+// There are no lists so split takes the index of the part it returns,
+// nil when there are fewer parts: "a,b".split(",", 1) == "b"
+static STR_METHODS: [StrMethod; 3] = [
+    StrMethod{name: "length", arity: 0, call: |s, _| Ok(Prim::Num(s.chars().count() as f64))},
+    StrMethod{name: "upper", arity: 0, call: |s, _| Ok(Prim::Str(s.to_uppercase()))},
+    StrMethod{name: "split", arity: 2, call: |s, args| match args {
+        [Prim::Str(sep), Prim::Num(n)] if !sep.is_empty() => Ok(s.split(sep.as_str())
+            .nth(*n as usize).filter(|_| n.fract() == 0.0 && *n >= 0.0)
+            .map_or(Prim::Nil, |part| Prim::Str(part.to_string()))),
+        [Prim::Str(_), Prim::Num(_)] => Err("split separator can't be empty".to_string()),
+        _ => Err(format!("split expects a separator and an index, found {:?}", args)),
+    }},
+];
+
+pub fn str_method(name: &str) -> Result<&'static StrMethod, String> {
+    STR_METHODS.iter().find(|m| m.name == name).ok_or_else(|| format!(
+        "undefined method '{}' for string{}",
+        name, did_you_mean(name, STR_METHODS.iter().map(|m| m.name))))
+}
+
+// A string method with its receiver, what "abc".upper evaluates to
+pub struct BoundStrMethod {
+    receiver: String,
+    method: &'static StrMethod,
+}
+
+impl BoundStrMethod {
+    pub fn new(receiver: String, method: &'static StrMethod) -> Self {
+        BoundStrMethod{receiver, method}
+    }
+}
+
+impl Callable for BoundStrMethod {
+    fn call(&self, _: &mut LoxInterpreter, args: &[V]) -> Result<V, String> {
+        let args = args.iter().map(|arg| match arg {
+            V::Nil => Ok(Prim::Nil),
+            V::Num(n) => Ok(Prim::Num(*n)),
+            V::Str(s) => Ok(Prim::Str(s.clone())),
+            o => Err(format!("{} can't take {:?}", self.method.name, o)),
+        }).collect::<Result<Vec<_>, _>>()?;
+        Ok(match (self.method.call)(&self.receiver, &args)? {
+            Prim::Nil => V::Nil,
+            Prim::Num(n) => V::Num(n),
+            Prim::Str(s) => V::Str(s),
+        })
+    }
+    fn arity(&self) -> usize { self.method.arity }
+    fn id(&self) -> String { format!("<method {} of {:?}>", self.method.name, self.receiver) }
+    fn name(&self) -> String { self.method.name.to_string() }
+}
@@ -1,7 +1,6 @@
-#![deny(warnings)]
-
-use lexers::Scanner;
-use crate::lox_scanner::{Token, TT};
+use lexers::{Scanner, SourceError};
+use crate::lox_scanner::{self, StrPart, Token, TT};
+use std::fmt;
 use std::rc::Rc;
 
 
@@ -9,6 +8,10 @@ use std::rc::Rc;
 #[derive(Debug)]
 pub enum Expr {
     Logical(Box<Expr>, Token, Box<Expr>),
+    // a ?? b, b is only evaluated when a is nil
+    Coalesce(Box<Expr>, Token, Box<Expr>),
+    // cond ? then : else, only the taken branch is evaluated
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
     Binary(Box<Expr>, Token, Box<Expr>),
     Unary(Token, Box<Expr>),
     Nil,
@@ -19,6 +22,10 @@ pub enum Expr {
     Var(Token),
     Assign(Token, Box<Expr>),
     Call(Box<Expr>, Vec<Expr>),
+    // obj.name, only strings have (native) methods
+    Get(Box<Expr>, Token),
+    // "a ${b} c" as literal chunks (Expr::Str) and embedded expressions
+    Interpolation(Vec<Expr>),
 }
 
 impl Expr {
@@ -28,28 +35,72 @@ impl Expr {
 // NOTE: do _NOT_ define Clone because we use address of Expr as symtab id
 //       we need that address to stay the same for the Resolver
 pub enum Stmt {
-    Print(Expr),
+    // `print a, b;` prints values separated by a space
+    Print(Vec<Expr>),
     Expr(Expr),
     Var(String, Expr),
+    // same as Var but assigning to it later is an error
+    Let(String, Expr),
     Block(Vec<Stmt>),
     If(Expr, Box<Stmt>, Option<Box<Stmt>>),
     While(Expr, Box<Stmt>),
+    // `do body while (cond);` the condition is checked after each run
+    DoWhile(Box<Stmt>, Expr),
     Break(usize),
     Function(String, Vec<String>, Rc<Vec<Stmt>>),
     Return(Expr),
+    // fails with the line of the `assert` keyword when falsy
+    Assert(Expr, usize),
+}
+
+// A scanner or parser error and the chars of the source it's about as
+// [start, end), none when the source ended early
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Option<(usize, usize)>,
+}
+
+impl ParseError {
+    pub fn new<S: Into<String>>(message: S, span: Option<(usize, usize)>) -> Self {
+        ParseError{message: message.into(), span}
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl SourceError for ParseError {
+    fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    fn span(&self) -> Option<(usize, usize)> {
+        self.span
+    }
 }
 
-pub type ExprResult = Result<Expr, String>;
-pub type StmtResult = Result<Stmt, String>;
+pub type ExprResult = Result<Expr, ParseError>;
+pub type StmtResult = Result<Stmt, ParseError>;
+
+// Nested expressions and statements a parse recurses into, so deep input
+// (eg: thousands of parens) is an error instead of a stack overflow
+const MAX_DEPTH: usize = 64;
 
 pub struct LoxParser<I: Iterator<Item=Token>> {
     scanner: Scanner<I>,
     errors: bool,
+    depth: usize,
 }
 
 impl<I: Iterator<Item=Token>> LoxParser<I> {
     pub fn new(source: I) -> Self {
-        LoxParser{scanner: Scanner::new(source), errors: false}
+        LoxParser{scanner: Scanner::new(source), errors: false, depth: 0}
     }
 
     fn accept(&mut self, token_types: Vec<TT>) -> bool {
@@ -59,6 +110,7 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
                 TT::Str(_) => matches!(ttype, TT::Str(_)),
                 TT::Id(_) => matches!(ttype, TT::Id(_)),
                 TT::Num(_) => matches!(ttype, TT::Num(_)),
+                TT::Interpolation(_) => matches!(ttype, TT::Interpolation(_)),
                 other => other == ttype
             });
             if found { return true; }
@@ -68,7 +120,7 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
     }
 
     fn consume<S: AsRef<str>>(&mut self, token_types: Vec<TT>,
-                              err: S) -> Result<Token, String> {
+                              err: S) -> Result<Token, ParseError> {
         match self.accept(token_types) {
             true => Ok(self.scanner.extract().swap_remove(0)),
             false => {
@@ -78,15 +130,27 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
         }
     }
 
-    fn error<S: AsRef<str>>(&mut self, token: Option<Token>, msg: S) -> String {
+    fn error<S: AsRef<str>>(&mut self, token: Option<Token>, msg: S) -> ParseError {
         self.errors = true;
         match token {
-            Some(t) => format!("LoxParser error: {:?} at line {}, {}",
-                               t.lexeme, t.line, msg.as_ref()),
-            _ => format!("LoxParser error: EOF, {}", msg.as_ref()),
+            Some(t) => ParseError::new(format!("LoxParser error: {:?} at line {}, {}",
+                                               t.lexeme, t.line, msg.as_ref()), Some(t.span())),
+            _ => ParseError::new(format!("LoxParser error: EOF, {}", msg.as_ref()), None),
         }
     }
 
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, ParseError>)
+            -> Result<T, ParseError> {
+        if self.depth >= MAX_DEPTH {
+            let token = self.scanner.peek();
+            return Err(self.error(token, format!("nested deeper than {}", MAX_DEPTH)));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
     fn synchronize(&mut self) {
         while let Some(token) = self.scanner.next() {
             // if we hit a semicolon we're probably about to start a statement
@@ -100,8 +164,8 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
             if let Some(peek) = self.scanner.peek() {
                 use TT::*;
                 match peek.token {
-                    CLASS | FUN | VAR | FOR | IF |
-                    WHILE | PRINT | RETURN | BREAK
+                    CLASS | FUN | VAR | LET | FOR | IF |
+                    WHILE | DO | PRINT | RETURN | BREAK
                     => return, _ => ()
                 }
             }
@@ -115,6 +179,7 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
  *  program        := { declaration } EOF ;
  *
  *  declaration    := varDecl
+ *                  | letDecl
  *                  | funDecl
  *                  | statement ;
  *
@@ -123,30 +188,37 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
  *  parameters     := IDENTIFIER { "," IDENTIFIER } ;
  *
  *  varDecl        := "var" IDENTIFIER [ "=" expression ] ";" ;
+ *  letDecl        := "let" IDENTIFIER "=" expression ";" ;
  *
  *  statement      := exprStmt
  *                  | ifStmt
  *                  | printStmt
  *                  | whileStmt
+ *                  | doWhileStmt
  *                  | forStmt
  *                  | breakStmt
  *                  | returnStmt
+ *                  | assertStmt
  *                  | block ;
  *
  *  exprStmt       := expression ";" ;
  *  ifStmt         := "if" "(" expression ")" statement [ "else" statement ] ;
- *  printStmt      := "print" expression ";" ;
+ *  printStmt      := "print" expression ( "," expression )* ";" ;
  *  whileStmt      := "while" "(" expression ")" statement ;
+ *  doWhileStmt    := "do" statement "while" "(" expression ")" ";" ;
  *  forStmt        := "for" "(" varDecl | exprStmt | ";"
  *                            { expression } ";"
  *                            { expression } ")" statement ;
  *  breakStmt      := "break" [ NUMBER ] ";" ;
  *  returnStmt     "= "return" [ expression ] ";" ;
+ *  assertStmt     := "assert" expression ";" ;
  *  block          := "{" { declaration } "}" ;
  *
  *  expression     := assignment ;
  *  assignment     := identifier "=" assignment
- *                  | logic_or ;
+ *                  | ternary ;
+ *  ternary        := coalesce [ "?" ternary ":" ternary ] ;
+ *  coalesce       := logic_or { "??" logic_or } ;
  *  logic_or       := logic_and { "or" logic_and } ;
  *  logic_and      := equality { "and" equality } ;
  *  equality       := comparison { ( "!=" | "==" ) comparison } ;
@@ -155,7 +227,7 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
  *  multiplication := unary { ( "/" | "*" ) unary } ;
  *  unary          := ( "!" | "-" | "$" ) unary
  *                  | call_expr;
- *  call_expr      := primary { "(" [ arguments ] ")" } ; // hi precedence op()
+ *  call_expr      := primary { "(" [ arguments ] ")" | "." IDENTIFIER } ; // hi precedence op()
  *  arguments      := expression { "," expression } ;
  *  primary        := NUMBER | STRING | "false" | "true" | "nil"
  *                  | "(" expression ")"
@@ -164,7 +236,7 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
 
 impl<I: Iterator<Item=Token>> LoxParser<I> {
     fn assignment(&mut self) -> ExprResult {
-        let expr = self.logic_or()?;
+        let expr = self.ternary()?;
         if self.accept(vec![TT::ASSIGN]) {
             let maybe_bad = Some(self.scanner.extract().swap_remove(0));
             // recursively parse right-hand-side
@@ -179,7 +251,7 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
     }
 
     fn expression(&mut self) -> ExprResult {
-        self.assignment()
+        self.nested(Self::assignment)
     }
 
     fn logic_and(&mut self) -> ExprResult {
@@ -192,6 +264,29 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
         Ok(expr)
     }
 
+    // right associative: a ? b : c ? d : e is a ? b : (c ? d : e)
+    fn ternary(&mut self) -> ExprResult {
+        let cond = self.coalesce()?;
+        if !self.accept(vec![TT::QUESTION]) {
+            return Ok(cond);
+        }
+        self.scanner.extract();
+        let then = self.ternary()?;
+        self.consume(vec![TT::COLON], "expect ':' in ternary expression")?;
+        let other = self.ternary()?;
+        Ok(Expr::Ternary(Box::new(cond), Box::new(then), Box::new(other)))
+    }
+
+    fn coalesce(&mut self) -> ExprResult {
+        let mut expr = self.logic_or()?;
+        while self.accept(vec![TT::COALESCE]) {
+            let op = self.scanner.extract().swap_remove(0);
+            let rhs = self.logic_or()?;
+            expr = Expr::Coalesce(Box::new(expr), op, Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
     fn logic_or(&mut self) -> ExprResult {
         let mut expr = self.logic_and()?;
         while self.accept(vec![TT::OR]) {
@@ -202,6 +297,8 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
         Ok(expr)
     }
 
+    // equality and comparison are separate levels, both left associative:
+    // `1 < 2 == true` is (1 < 2) == true and `1 < 2 < 3` is (1 < 2) < 3
     fn equality(&mut self) -> ExprResult {
         let mut expr = self.comparison()?;
         while self.accept(vec![TT::EQ, TT::NE]) {
@@ -244,9 +341,13 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
 
     fn call_expr(&mut self) -> ExprResult {
         let mut primary = self.primary()?;
-        // if there's an OPAREN crawl thread the function Call chain
-        while self.accept(vec![TT::OPAREN]) {
-            self.scanner.extract(); // skip oparen
+        // if there's an OPAREN or DOT crawl thread the Call/Get chain
+        while self.accept(vec![TT::OPAREN, TT::DOT]) {
+            if self.scanner.extract()[0].token == TT::DOT {
+                let name = self.consume(vec![TT::Id("".to_string())], "expect name after '.'")?;
+                primary = Expr::Get(Box::new(primary), name);
+                continue;
+            }
             let mut arguments = Vec::new();
             if !self.accept(vec![TT::CPAREN]) { // 0-arg case
                 loop {
@@ -265,7 +366,7 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
     fn unary(&mut self) -> ExprResult {
         if self.accept(vec![TT::BANG, TT::MINUS, TT::DOLLAR]) {
             let op = self.scanner.extract().swap_remove(0);
-            let rhs = self.unary()?;
+            let rhs = self.nested(Self::unary)?;
             return Ok(Expr::Unary(op, Box::new(rhs)));
         }
         self.call_expr()
@@ -294,6 +395,18 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
                 o => panic!("LoxParser Bug! unexpected token: {:?}", o),
             });
         }
+        if self.accept(vec![TT::Interpolation(Vec::new())]) {
+            let token = self.scanner.extract().swap_remove(0);
+            let parts = match token.token {
+                TT::Interpolation(ref parts) => parts.clone(),
+                ref o => panic!("LoxParser Bug! unexpected token: {:?}", o),
+            };
+            let exprs = parts.into_iter().map(|part| match part {
+                StrPart::Lit(s) => Ok(Expr::Str(s)),
+                StrPart::Expr(tokens) => self.interpolated(&token, tokens),
+            }).collect::<Result<_, _>>()?;
+            return Ok(Expr::Interpolation(exprs));
+        }
         if self.accept(vec![TT::Id("".to_string())]) {
             return Ok(Expr::Var(self.scanner.extract().swap_remove(0)));
         }
@@ -307,10 +420,26 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
         Err(self.error(bad_token, "expected expression"))
     }
 
+    // Parse the tokens of a `${...}` part, they must form a single expression
+    fn interpolated(&mut self, token: &Token, tokens: Vec<Token>) -> ExprResult {
+        let mut parser = LoxParser::new(tokens.into_iter());
+        parser.depth = self.depth;
+        let expr = parser.expression().inspect_err(|_| self.errors = true)?;
+        if parser.scanner.peek().is_some() {
+            return Err(self.error(Some(token.clone()),
+                                  "expect a single expression inside '${}'"));
+        }
+        Ok(expr)
+    }
+
     fn print_stmt(&mut self) -> StmtResult {
-        let expr = self.expression()?;
+        let mut exprs = vec![self.expression()?];
+        while self.accept(vec![TT::COMMA]) {
+            self.scanner.extract(); // skip comma
+            exprs.push(self.expression()?);
+        }
         self.consume(vec![TT::SEMICOLON], "expect ';' after print expr")?;
-        Ok(Stmt::Print(expr))
+        Ok(Stmt::Print(exprs))
     }
 
     fn expr_stmt(&mut self) -> StmtResult {
@@ -319,11 +448,11 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
         Ok(Stmt::Expr(expr))
     }
 
-    fn block_stmt(&mut self) -> Result<Vec<Stmt>, String> {
+    fn block_stmt(&mut self) -> Result<Vec<Stmt>, ParseError> {
         let mut statements = Vec::new();
         while let Some(maybe_cbrace) = self.scanner.peek() {
             if maybe_cbrace.token == TT::CBRACE { break; }
-            statements.push(self.declaration()?);
+            statements.push(self.nested(Self::declaration)?);
         }
         self.consume(vec![TT::CBRACE], "expect '}' after block")?;
         Ok(statements)
@@ -333,10 +462,10 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
         self.consume(vec![TT::OPAREN], "expect '(' after 'if'")?;
         let condition = self.expression()?;
         self.consume(vec![TT::CPAREN], "expect ')' after 'if' condition")?;
-        let then_branch = self.statement()?;
+        let then_branch = self.nested(Self::statement)?;
         if self.accept(vec![TT::ELSE]) {
             self.scanner.extract(); // skip else
-            let else_branch = Some(Box::new(self.statement()?));
+            let else_branch = Some(Box::new(self.nested(Self::statement)?));
             return Ok(Stmt::If(condition, Box::new(then_branch), else_branch));
         }
         Ok(Stmt::If(condition, Box::new(then_branch), None))
@@ -346,10 +475,20 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
         self.consume(vec![TT::OPAREN], "expect '(' after 'while'")?;
         let condition = self.expression()?;
         self.consume(vec![TT::CPAREN], "expect ')' after 'if' condition")?;
-        let body = self.statement()?;
+        let body = self.nested(Self::statement)?;
         Ok(Stmt::While(condition, Box::new(body)))
     }
 
+    fn do_while_stmt(&mut self) -> StmtResult {
+        let body = self.statement()?;
+        self.consume(vec![TT::WHILE], "expect 'while' after 'do' body")?;
+        self.consume(vec![TT::OPAREN], "expect '(' after 'while'")?;
+        let condition = self.expression()?;
+        self.consume(vec![TT::CPAREN], "expect ')' after 'while' condition")?;
+        self.consume(vec![TT::SEMICOLON], "expect ';' after 'do' loop")?;
+        Ok(Stmt::DoWhile(Box::new(body), condition))
+    }
+
     fn for_stmt(&mut self) -> StmtResult {
         self.consume(vec![TT::OPAREN], "expect '(' after 'for'")?;
         let init = if self.accept(vec![TT::SEMICOLON]) {
@@ -375,8 +514,8 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
         self.consume(vec![TT::CPAREN], "expect ')' after 'for' clause")?;
         // desugar forStmt into WhileStmt
         let body = Stmt::While(condition, Box::new(match increment {
-            Some(inc) => Stmt::Block(vec![self.statement()?, Stmt::Expr(inc)]),
-            _ => self.statement()?
+            Some(inc) => Stmt::Block(vec![self.nested(Self::statement)?, Stmt::Expr(inc)]),
+            _ => self.nested(Self::statement)?
         }));
         Ok(match init {Some(init) => Stmt::Block(vec![init, body]), _ => body})
     }
@@ -393,6 +532,12 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
         Ok(Stmt::Break(scopes))
     }
 
+    fn assert_stmt(&mut self, line: usize) -> StmtResult {
+        let expr = self.expression()?;
+        self.consume(vec![TT::SEMICOLON], "expect ';' after assert expr")?;
+        Ok(Stmt::Assert(expr, line))
+    }
+
     fn return_stmt(&mut self) -> StmtResult {
         let expr = match self.scanner.peek() {
             Some(ref t) if t.token != TT::SEMICOLON => self.expression()?,
@@ -419,6 +564,10 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
             self.scanner.extract(); // skip while
             return self.while_stmt();
         }
+        if self.accept(vec![TT::DO]) {
+            self.scanner.extract(); // skip do
+            return self.do_while_stmt();
+        }
         if self.accept(vec![TT::FOR]) {
             self.scanner.extract(); // skip for
             return self.for_stmt();
@@ -431,6 +580,10 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
             self.scanner.extract(); // skip return
             return self.return_stmt();
         }
+        if self.accept(vec![TT::ASSERT]) {
+            let line = self.scanner.extract().swap_remove(0).line;
+            return self.assert_stmt(line);
+        }
         self.expr_stmt()
     }
 
@@ -446,6 +599,15 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
         Ok(Stmt::Var(name.lexeme, init))
     }
 
+    fn let_declaration(&mut self) -> StmtResult {
+        let name = self.consume(
+            vec![TT::Id("".to_string())], "expect variable name")?;
+        self.consume(vec![TT::ASSIGN], "expect '=' after 'let' name")?;
+        let init = self.expression()?;
+        self.consume(vec![TT::SEMICOLON], "expect ';' after variable decl")?;
+        Ok(Stmt::Let(name.lexeme, init))
+    }
+
     fn fun_declaration(&mut self, kind: &str) -> StmtResult {
         let name = self.consume(
             vec![TT::Id("".to_string())], format!("expect {} name", kind))?;
@@ -471,6 +633,10 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
             self.scanner.extract(); // skip var
             return self.var_declaration();
         }
+        if self.accept(vec![TT::LET]) {
+            self.scanner.extract(); // skip let
+            return self.let_declaration();
+        }
         if self.accept(vec![TT::FUN]) {
             self.scanner.extract(); // skip fun
             return self.fun_declaration("function");
@@ -478,7 +644,7 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
         self.statement()
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<String>> {
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<ParseError>> {
         let mut statements = Vec::new();
         let mut errors = Vec::new();
         while self.scanner.peek().is_some() {
@@ -493,3 +659,8 @@ impl<I: Iterator<Item=Token>> LoxParser<I> {
         }
     }
 }
+
+// Parse a whole program, scanner errors are reported before parsing
+pub fn parse(source: &str) -> Result<Vec<Stmt>, Vec<ParseError>> {
+    LoxParser::new(lox_scanner::scan(source)?.into_iter()).parse()
+}
@@ -0,0 +1,109 @@
+use crate::lox_parser::{Expr, Stmt};
+
+
+// Render parsed Lox as lisp-ish s-expressions, eg: `1 + 2 * 3` => (+ 1 (* 2 3))
+// Useful to check precedence/associativity without running the interpreter.
+pub fn dump_expr(expr: &Expr) -> String {
+    match expr {
+        Expr::Nil => "nil".to_string(),
+        Expr::Bool(b) => b.to_string(),
+        Expr::Num(n) => n.to_string(),
+        Expr::Str(s) => format!("\"{}\"", s),
+        Expr::Var(token) => token.lexeme.clone(),
+        Expr::Grouping(gexpr) => format!("(group {})", dump_expr(gexpr)),
+        Expr::Unary(op, uexpr) => format!("({} {})", op.lexeme, dump_expr(uexpr)),
+        Expr::Binary(lhs, op, rhs) | Expr::Logical(lhs, op, rhs) |
+        Expr::Coalesce(lhs, op, rhs) =>
+            format!("({} {} {})", op.lexeme, dump_expr(lhs), dump_expr(rhs)),
+        Expr::Ternary(cond, then, other) =>
+            format!("(? {} {} {})", dump_expr(cond), dump_expr(then), dump_expr(other)),
+        Expr::Assign(token, aexpr) =>
+            format!("(= {} {})", token.lexeme, dump_expr(aexpr)),
+        Expr::Call(callee, args) => {
+            let mut out = format!("(call {}", dump_expr(callee));
+            for arg in args {
+                out += &format!(" {}", dump_expr(arg));
+            }
+            out + ")"
+        },
+        Expr::Get(obj, name) => format!("(. {} {})", dump_expr(obj), name.lexeme),
+        Expr::Interpolation(parts) => {
+            let parts: Vec<String> = parts.iter().map(dump_expr).collect();
+            format!("(interp {})", parts.join(" "))
+        },
+    }
+}
+
+pub fn dump_stmt(stmt: &Stmt) -> String {
+    match stmt {
+        Stmt::Print(exprs) => format!("(print {})",
+                                      exprs.iter().map(dump_expr).collect::<Vec<_>>().join(" ")),
+        Stmt::Expr(expr) => dump_expr(expr),
+        Stmt::Var(name, init) => format!("(var {} {})", name, dump_expr(init)),
+        Stmt::Let(name, init) => format!("(let {} {})", name, dump_expr(init)),
+        Stmt::Block(stmts) => format!("(block{})", dump_body(stmts)),
+        Stmt::If(cond, then_branch, else_branch) => match else_branch {
+            Some(else_b) => format!("(if {} {} {})", dump_expr(cond),
+                                    dump_stmt(then_branch), dump_stmt(else_b)),
+            None => format!("(if {} {})", dump_expr(cond), dump_stmt(then_branch)),
+        },
+        Stmt::While(cond, body) =>
+            format!("(while {} {})", dump_expr(cond), dump_stmt(body)),
+        Stmt::DoWhile(body, cond) =>
+            format!("(do {} {})", dump_stmt(body), dump_expr(cond)),
+        Stmt::Break(scopes) => format!("(break {})", scopes),
+        Stmt::Function(name, params, body) =>
+            format!("(fun {} ({}){})", name, params.join(" "), dump_body(body)),
+        Stmt::Return(expr) => format!("(return {})", dump_expr(expr)),
+        Stmt::Assert(expr, _) => format!("(assert {})", dump_expr(expr)),
+    }
+}
+
+fn dump_body(stmts: &[Stmt]) -> String {
+    stmts.iter().map(|s| format!(" {}", dump_stmt(s))).collect()
+}
+
+// One top-level statement per line
+pub fn dump_stmts(stmts: &[Stmt]) -> String {
+    stmts.iter().map(dump_stmt).collect::<Vec<_>>().join("\n")
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::lox_parser::LoxParser;
+    use crate::lox_scanner::LoxScanner;
+    use super::dump_stmts;
+
+    fn dump(source: &str) -> String {
+        let scanner = LoxScanner::scanner(source.chars());
+        dump_stmts(&LoxParser::new(scanner).parse().unwrap())
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(dump("1 + 2 * 3;"), "(+ 1 (* 2 3))");
+        assert_eq!(dump("(1 + 2) * 3;"), "(* (group (+ 1 2)) 3)");
+        assert_eq!(dump("1 - 2 - 3;"), "(- (- 1 2) 3)");
+        assert_eq!(dump("-a or b and !c;"), "(or (- a) (and b (! c)))");
+        assert_eq!(dump("1 < 2 < 3;"), "(< (< 1 2) 3)");
+        assert_eq!(dump("1 < 2 == true;"), "(== (< 1 2) true)");
+        assert_eq!(dump("a == b != c;"), "(!= (== a b) c)");
+        assert_eq!(dump("x = a ?? b ? c : d ? e : f;"), "(= x (? (?? a b) c (? d e f)))");
+        assert_eq!(dump("\"a\".upper().split(s, 1);"), "(call (. (call (. \"a\" upper)) split) s 1)");
+    }
+
+    #[test]
+    fn statements() {
+        assert_eq!(dump("var x = f(1, 2);"), "(var x (call f 1 2))");
+        assert_eq!(dump("let x = 1;"), "(let x 1)");
+        assert_eq!(dump("fun f(a, b) { return a; }"), "(fun f (a b) (return a))");
+        assert_eq!(dump("assert a == 1;"), "(assert (== a 1))");
+        assert_eq!(dump("print a;"), "(print a)");
+        assert_eq!(dump("print 1, a = 2, \"x\";"), "(print 1 (= a 2) \"x\")");
+        assert_eq!(dump("while (x) { x = nil; break; }"),
+                   "(while x (block (= x nil) (break 1)))");
+        assert_eq!(dump("do x = x - 1; while (x > 0);"), "(do (= x (- x 1)) (> x 0))");
+    }
+}
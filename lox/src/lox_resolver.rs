@@ -1,5 +1,3 @@
-#![deny(warnings)]
-
 use crate::lox_parser::{Expr, Stmt};
 use crate::lox_interpreter::LoxInterpreter;
 use std::collections::HashMap;
@@ -13,7 +11,7 @@ pub struct Resolver<'a> {
 }
 
 impl<'a> Resolver<'a> {
-    pub fn new(interp: &'a mut LoxInterpreter) -> Resolver {
+    pub fn new(interp: &'a mut LoxInterpreter) -> Resolver<'a> {
         Resolver{interpreter: interp, scopes: Vec::new()}
     }
 
@@ -76,6 +74,15 @@ impl<'a> Resolver<'a> {
                 self.resolve_expr(left)?;
                 self.resolve_expr(right)
             },
+            Expr::Coalesce(left, _, right) => {
+                self.resolve_expr(left)?;
+                self.resolve_expr(right)
+            },
+            Expr::Ternary(cond, then, other) => {
+                self.resolve_expr(cond)?;
+                self.resolve_expr(then)?;
+                self.resolve_expr(other)
+            },
             &Expr::Binary(ref left, _, ref right) => {
                 self.resolve_expr(left)?;
                 self.resolve_expr(right)
@@ -103,14 +110,20 @@ impl<'a> Resolver<'a> {
                     .find(|arg| arg.is_err())
                     .unwrap_or(Ok(()))
             },
+            Expr::Get(obj, _) => self.resolve_expr(obj),
+            Expr::Interpolation(parts) => {
+                parts.iter().map(|part| self.resolve_expr(part))
+                    .find(|part| part.is_err())
+                    .unwrap_or(Ok(()))
+            },
         }
     }
 
     fn resolve_stmt(&mut self, stmt: &Stmt)  -> ResolveResult {
         match stmt {
-            Stmt::Print(ref expr) => self.resolve_expr(expr),
+            Stmt::Print(ref exprs) => exprs.iter().try_for_each(|expr| self.resolve_expr(expr)),
             Stmt::Expr(ref expr) => self.resolve_expr(expr),
-            Stmt::Var(ref name, ref init) => {
+            Stmt::Var(ref name, ref init) | Stmt::Let(ref name, ref init) => {
                 // split binding in declare/define to disallow self reference
                 self.declare(name.clone())?;
                 match init {
@@ -137,6 +150,10 @@ impl<'a> Resolver<'a> {
                 self.resolve_expr(cond)?;
                 self.resolve_stmt(body)
             },
+            Stmt::DoWhile(ref body, ref cond) => {
+                self.resolve_stmt(body)?;
+                self.resolve_expr(cond)
+            },
             Stmt::Break(_) => Ok(()),
             Stmt::Function(ref name, ref parameters, ref body) => {
                 self.declare(name.clone())?;
@@ -144,6 +161,7 @@ impl<'a> Resolver<'a> {
                 self.resolve_function(parameters, body)
             },
             Stmt::Return(ref expr) => self.resolve_expr(expr),
+            Stmt::Assert(expr, _) => self.resolve_expr(expr),
         }
     }
 }
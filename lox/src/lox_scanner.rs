@@ -1,39 +1,67 @@
-use lexers::Scanner;
+use lexers::{ReadChars, Scanner};
+use std::io::Read;
+use crate::lox_parser::ParseError;
 
 #[derive(Clone,Debug,PartialEq)]
 pub enum TT {
     // single char tokens
     OPAREN, CPAREN, OBRACE, CBRACE, COMMA, DOT,
     MINUS, PLUS, SEMICOLON, SLASH, STAR, DOLLAR,
-    BANG, ASSIGN, NE, EQ, GT, GE, LT, LE,
+    BANG, ASSIGN, NE, EQ, GT, GE, LT, LE, COALESCE, QUESTION, COLON,
     // literals
-    Id(String), Str(String), Num(f64),
+    Id(String), Str(String), Num(f64), Interpolation(Vec<StrPart>),
     // keywords
-    AND, CLASS, ELSE, FALSE, FUN, FOR, IF, NIL, OR, BREAK,
-    PRINT, RETURN, SUPER, THIS, TRUE, VAR, WHILE, EOF,
+    AND, CLASS, DO, ELSE, FALSE, FUN, FOR, IF, LET, NIL, OR, BREAK,
+    PRINT, RETURN, SUPER, THIS, TRUE, VAR, WHILE, ASSERT, EOF,
 }
 
-#[derive(Clone,Debug)]
+// Pieces of an interpolated string: "x = ${x + 1}"
+#[derive(Clone,Debug,PartialEq)]
+pub enum StrPart {
+    Lit(String),
+    Expr(Vec<Token>),
+}
+
+#[derive(Clone,Debug,PartialEq)]
 pub struct Token {
     pub line: usize,
+    // chars of the source before the token
+    pub offset: usize,
     pub token: TT,
     pub lexeme: String,
 }
 
+impl Token {
+    // Chars of the source the token was read from as [start, end)
+    pub fn span(&self) -> (usize, usize) {
+        (self.offset, self.offset + self.lexeme.chars().count())
+    }
+}
+
+// Interpolations scan their `${...}` with a new LoxScanner, nesting is
+// bounded to keep "${"${"${... from overflowing the stack
+const MAX_INTERPOLATION_DEPTH: usize = 16;
+
 pub struct LoxScanner<I: Iterator<Item=char>> {
     src: Scanner<I>,
     line: usize,
+    // offset of `src` in the source, interpolations are scanned on their own
+    base: usize,
+    // where the token being scanned starts
+    start: usize,
     errors: bool,
+    depth: usize,
+    // errors are collected here for `scan`, else printed as they're found
+    messages: Option<Vec<ParseError>>,
 }
 
 
 impl<I: Iterator<Item=char>> LoxScanner<I> {
     pub fn scanner(source: I) -> Scanner<Self> {
-        Scanner::new(
-            LoxScanner{
-                src: Scanner::new(source),
-                line: 1,
-                errors: false})
+        let mut src = Scanner::new(source);
+        src.skip_shebang(); // allow running scripts directly
+        Scanner::new(LoxScanner{src, line: 1, base: 0, start: 0, errors: false, depth: 0,
+                                messages: None})
     }
 
     fn tokenize(&mut self, literal: TT) -> Option<Token> {
@@ -41,35 +69,143 @@ impl<I: Iterator<Item=char>> LoxScanner<I> {
             TT::EOF => String::new(),
             _ => self.src.extract_string()
         };
-        let literal = match literal {
-            TT::Str(_) => TT::Str(lexeme.trim_matches('"').to_string()),
-            other => other
-        };
-        Some(Token{line: self.line, token: literal, lexeme})
+        Some(Token{line: self.line, offset: self.start, token: literal, lexeme})
+    }
+
+    // Chars of the source read so far
+    fn offset(&self) -> usize {
+        self.base + self.src.extracted() + (self.src.buffer_pos() + 1) as usize
     }
 
-    fn error<T: AsRef<str>>(&mut self, err: T) {
-        eprintln!("LoxScanner error: {}", err.as_ref());
+    fn error<T: AsRef<str>>(&mut self, err: T, span: (usize, usize)) {
+        let err = ParseError::new(format!("LoxScanner error: {}", err.as_ref()), Some(span));
+        match &mut self.messages {
+            Some(messages) => messages.push(err),
+            None => eprintln!("{}", err),
+        }
         self.errors = true;
     }
 
-    fn scan_restof_string(&mut self, q: char) -> bool {
+    // Scan a string after its opening quote, splitting out `${expr}` parts.
+    // `\$` escapes a literal '$', other escapes are kept as written.
+    fn scan_restof_string(&mut self) -> Result<Vec<StrPart>, (String, (usize, usize))> {
         let backtrack = self.src.buffer_pos();
         let orig_line = self.line;
-        while let Some(n) = self.src.next() {
-            if n == '\n' { self.line += 1; }
-            if n == '\\' { self.src.next(); continue; }
-            if n == q { return true; }
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        // a bad interpolation still ends with the string, scanning goes on
+        // after it instead of scanning the string's insides as code again
+        let mut bad_part = None;
+        let result = loop {
+            match self.src.next() {
+                None => break Err((format!("unterminated string at line {}", orig_line),
+                                  (self.start, self.start + 1))),
+                Some('"') => break Ok(()),
+                Some('\\') => match self.src.next() {
+                    Some('$') => literal.push('$'),
+                    Some(c) => { literal.push('\\'); literal.push(c); },
+                    None => (),
+                },
+                Some('$') if self.src.accept(&'{').is_some() => {
+                    let line = self.line;
+                    let base = self.offset();
+                    let open = (base - 2, base);
+                    let mut expr = String::new();
+                    if !self.copy_interpolated(&mut expr) {
+                        break Err((format!("unterminated '${{' at line {}", line), open));
+                    }
+                    let part = (base - 2, self.offset());
+                    if bad_part.is_some() {
+                        continue;
+                    }
+                    if self.depth >= MAX_INTERPOLATION_DEPTH {
+                        bad_part = Some((format!("interpolation nested too deep at line {}", line), part));
+                        continue;
+                    }
+                    let mut scanner = LoxScanner{
+                        src: Scanner::new(expr.chars()), line, base, start: base, errors: false,
+                        depth: self.depth + 1, messages: self.messages.as_ref().map(|_| Vec::new())};
+                    let tokens: Vec<Token> = scanner.by_ref().collect();
+                    if let (Some(messages), Some(inner)) = (&mut self.messages, scanner.messages) {
+                        messages.extend(inner);
+                    }
+                    if scanner.errors {
+                        bad_part = Some((format!("bad interpolation at line {}", line), part));
+                    } else if tokens.is_empty() {
+                        bad_part = Some((format!("empty interpolation at line {}", line), part));
+                    }
+                    if !literal.is_empty() {
+                        parts.push(StrPart::Lit(std::mem::take(&mut literal)));
+                    }
+                    parts.push(StrPart::Expr(tokens));
+                },
+                Some(c) => {
+                    if c == '\n' { self.line += 1; }
+                    literal.push(c);
+                },
+            }
+        };
+        if let Err(err) = result {
+            self.src.set_buffer_pos(backtrack);
+            self.line = orig_line;
+            return Err(err);
+        }
+        if let Some(err) = bad_part {
+            return Err(err);
+        }
+        if !literal.is_empty() || parts.is_empty() {
+            parts.push(StrPart::Lit(literal));
+        }
+        Ok(parts)
+    }
+
+    // Copy the source of an interpolated expression up to its closing '}'.
+    // Braces and nested strings (which may interpolate too) are balanced.
+    fn copy_interpolated(&mut self, out: &mut String) -> bool {
+        let mut depth = 0;
+        while let Some(c) = self.src.next() {
+            match c {
+                '}' if depth == 0 => return true,
+                '}' => depth -= 1,
+                '{' => depth += 1,
+                '\n' => self.line += 1,
+                _ => (),
+            }
+            out.push(c);
+            if c == '"' && !self.copy_nested_string(out) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn copy_nested_string(&mut self, out: &mut String) -> bool {
+        while let Some(c) = self.src.next() {
+            out.push(c);
+            match c {
+                '"' => return true,
+                '\n' => self.line += 1,
+                '\\' => match self.src.next() {
+                    Some(e) => out.push(e),
+                    None => return false,
+                },
+                '$' if self.src.accept(&'{').is_some() => {
+                    out.push('{');
+                    if !self.copy_interpolated(out) {
+                        return false;
+                    }
+                    out.push('}');
+                },
+                _ => (),
+            }
         }
-        self.src.set_buffer_pos(backtrack);
-        self.line = orig_line;
         false
     }
 
     fn id_or_keyword(&mut self, keyword: String) -> Option<Token> {
         let key2 = keyword.clone();
         let tok = |literal: TT| -> Option<Token> {
-            Some(Token{line: self.line, token: literal, lexeme: key2})
+            Some(Token{line: self.line, offset: self.start, token: literal, lexeme: key2})
         };
         match keyword.as_ref() {
             "and" => tok(TT::AND),
@@ -77,8 +213,10 @@ impl<I: Iterator<Item=char>> LoxScanner<I> {
             "else" => tok(TT::ELSE),
             "false" => tok(TT::FALSE),
             "fun" => tok(TT::FUN),
+            "do" => tok(TT::DO),
             "for" => tok(TT::FOR),
             "if" => tok(TT::IF),
+            "let" => tok(TT::LET),
             "nil" => tok(TT::NIL),
             "or" => tok(TT::OR),
             "break" => tok(TT::BREAK),
@@ -89,12 +227,14 @@ impl<I: Iterator<Item=char>> LoxScanner<I> {
             "true" => tok(TT::TRUE),
             "var" => tok(TT::VAR),
             "while" => tok(TT::WHILE),
-            _ => Some(Token{line: self.line,
+            "assert" => tok(TT::ASSERT),
+            _ => Some(Token{line: self.line, offset: self.start,
                       token: TT::Id(keyword.clone()), lexeme: keyword})
         }
     }
 
     fn scan_token(&mut self) -> Option<Token> {
+        self.start = self.offset();
         let token = match self.src.next() {
             Some('(') => self.tokenize(TT::OPAREN),
             Some(')') => self.tokenize(TT::CPAREN),
@@ -127,6 +267,12 @@ impl<I: Iterator<Item=char>> LoxScanner<I> {
             } else {
                 self.tokenize(TT::GT)
             },
+            Some('?') => if self.src.accept(&'?').is_some() {
+                self.tokenize(TT::COALESCE)
+            } else {
+                self.tokenize(TT::QUESTION)
+            },
+            Some(':') => self.tokenize(TT::COLON),
             Some('/') => if self.src.accept(&'/').is_some() {
                 // skip comment
                 self.src.until_any(&['\n']);
@@ -136,25 +282,36 @@ impl<I: Iterator<Item=char>> LoxScanner<I> {
             },
             Some(' ') | Some('\t') | Some('\r') => None,
             Some('\n') => { self.line += 1; None }, // track current line
-            Some('"') => match self.scan_restof_string('"') {
-                true => self.tokenize(TT::Str(String::new())),
-                false => { self.error("unterminated string"); None }
+            Some('"') => match self.scan_restof_string() {
+                Ok(parts) => match &parts[..] {
+                    [StrPart::Lit(s)] => self.tokenize(TT::Str(s.clone())),
+                    _ => self.tokenize(TT::Interpolation(parts)),
+                },
+                Err((err, span)) => { self.error(err, span); None }
             },
-            Some(d) if d.is_digit(10) => {
+            Some(d) if d.is_ascii_digit() => {
                 self.src.prev(); // hacky but works
-                let num = self.src.scan_number().unwrap();
-                use std::str::FromStr;
-                Some(Token{line: self.line,
-                     token: TT::Num(f64::from_str(&num).unwrap()), lexeme: num})
+                // scan_number also takes imaginary numbers, eg: 2i
+                let num = self.src.scan_number().unwrap_or_default();
+                match num.parse() {
+                    Ok(n) => Some(Token{line: self.line, offset: self.start,
+                                        token: TT::Num(n), lexeme: num}),
+                    Err(_) => {
+                        let err = format!("bad number '{}' at line {}", num, self.line);
+                        self.error(err, (self.start, self.offset()));
+                        None
+                    },
+                }
             },
-            Some(a) if a.is_alphabetic() => {
+            // scan_identifier only takes ascii, other letters are bad chars
+            Some(a) if a.is_ascii_alphabetic() => {
                 self.src.prev(); // hacky but works
-                let id = self.src.scan_identifier().unwrap();
+                let id = self.src.scan_identifier().unwrap_or_default();
                 self.id_or_keyword(id)
             },
             Some(c) => {
                 let err = format!("bad char '{}' at line {}", c, self.line);
-                self.error(err);
+                self.error(err, (self.start, self.offset()));
                 None
             },
             None => self.tokenize(TT::EOF)
@@ -164,6 +321,27 @@ impl<I: Iterator<Item=char>> LoxScanner<I> {
     }
 }
 
+// Scan large files without reading them into a String first. The adapter
+// is borrowed so read errors can be checked once scanning is done.
+impl<'a, R: Read> LoxScanner<&'a mut ReadChars<R>> {
+    pub fn from_reader(chars: &'a mut ReadChars<R>) -> Scanner<Self> {
+        Self::scanner(chars)
+    }
+}
+
+// All the tokens of `source` or every error found scanning it
+pub fn scan(source: &str) -> Result<Vec<Token>, Vec<ParseError>> {
+    let mut src = Scanner::new(source.chars());
+    src.skip_shebang();
+    let mut scanner = LoxScanner{src, line: 1, base: 0, start: 0, errors: false, depth: 0,
+                                 messages: Some(Vec::new())};
+    let tokens: Vec<_> = scanner.by_ref().collect();
+    match scanner.messages {
+        Some(messages) if !messages.is_empty() => Err(messages),
+        _ => Ok(tokens),
+    }
+}
+
 impl<I: Iterator<Item=char>> Iterator for LoxScanner<I> {
     type Item = Token;
     fn next(&mut self) -> Option<Self::Item> {
@@ -177,3 +355,125 @@ impl<I: Iterator<Item=char>> Iterator for LoxScanner<I> {
         }
     }
 }
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::{LoxScanner, Scanner, StrPart, TT};
+
+    fn tokens(source: &str) -> Vec<TT> {
+        LoxScanner::scanner(source.chars()).map(|t| t.token).collect()
+    }
+
+    #[test]
+    fn interpolated_string() {
+        assert_eq!(tokens("\"no parts\""), vec![TT::Str("no parts".to_string())]);
+        let parts = match tokens("\"a ${ {b} } c\"").swap_remove(0) {
+            TT::Interpolation(parts) => parts,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0], StrPart::Lit("a ".to_string()));
+        match &parts[1] {
+            StrPart::Expr(expr) => assert_eq!(
+                expr.iter().map(|t| t.token.clone()).collect::<Vec<_>>(),
+                vec![TT::OBRACE, TT::Id("b".to_string()), TT::CBRACE]),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(parts[2], StrPart::Lit(" c".to_string()));
+        // a '}' inside a nested string doesn't close the interpolation
+        assert!(matches!(&tokens("\"${\"}\"}\"")[0],
+                         TT::Interpolation(p) if p.len() == 1));
+    }
+
+    #[test]
+    fn shebang() {
+        let source = "#!/usr/bin/lox\nvar x = 1;";
+        let lines: Vec<_> = LoxScanner::scanner(source.chars()).map(|t| t.line).collect();
+        assert_eq!(lines, vec![2, 2, 2, 2, 2]);
+        assert_eq!(tokens(source), tokens("\nvar x = 1;"));
+        let stmts = crate::lox_parser::LoxParser::new(LoxScanner::scanner(source.chars()))
+            .parse().unwrap();
+        assert_eq!(stmts.len(), 1);
+        // '#!' anywhere else is still an error
+        let mut scanner = LoxScanner{
+            src: Scanner::new("var x;\n#!".chars()), line: 1, base: 0, start: 0, errors: false,
+            depth: 0, messages: None};
+        assert!(scanner.by_ref().count() > 0);
+        assert!(scanner.errors);
+    }
+
+    #[test]
+    fn from_reader() {
+        let source = "#!/usr/bin/lox\nvar s = \"a\nb ${1 + 2}\";\nprint s; // µ\n\"open";
+        let mut chars = lexers::ReadChars::new(source.as_bytes());
+        let streamed: Vec<_> = LoxScanner::from_reader(&mut chars).collect();
+        assert!(chars.error().is_none());
+        assert_eq!(streamed, LoxScanner::scanner(source.chars()).collect::<Vec<_>>());
+        assert_eq!(streamed.iter().find(|t| t.token == TT::PRINT).unwrap().line, 4);
+    }
+
+    #[test]
+    fn crash_regressions() {
+        use crate::lox_parser::parse;
+        let messages = |source: &str| parse(source).err()
+            .map(|errors| errors.iter().map(ToString::to_string).collect::<Vec<_>>());
+        // inputs that used to panic, overflow the stack or take exponential time
+        assert_eq!(messages("assert é;"), Some(vec!["LoxScanner error: bad char 'é' at line 1".to_string()]));
+        assert_eq!(messages("print 1if;"), Some(vec!["LoxScanner error: bad number '1i' at line 1".to_string()]));
+        let deep = format!("print {}1{};", "(".repeat(300), ")".repeat(300));
+        assert!(messages(&deep).unwrap()[0].ends_with("nested deeper than 64"));
+        assert!(parse(&format!("{}{}", "{".repeat(300), "}".repeat(300))).is_err());
+        assert!(parse(&format!("print {}1;", "-!".repeat(300))).is_err());
+        let mut nested = "1".to_string();
+        for _ in 0..200 {
+            nested = format!("\"${{{}}}\"", nested);
+        }
+        assert!(messages(&format!("print {};", nested)).unwrap()
+            .contains(&"LoxScanner error: interpolation nested too deep at line 1".to_string()));
+        assert!(parse(&format!("print {}", "\"${".repeat(3000))).is_err());
+        // a bad interpolation ends with its string
+        assert_eq!(messages("print \"a ${é} b\"; print 1;"), Some(vec![
+            "LoxScanner error: bad char 'é' at line 1".to_string(),
+            "LoxScanner error: bad interpolation at line 1".to_string()]));
+        assert_eq!(parse("#!/bin/lox\nprint 1 + 2;").map(|stmts| stmts.len()), Ok(1));
+    }
+
+    #[test]
+    fn unterminated_interpolation() {
+        let mut scanner = LoxScanner{
+            src: Scanner::new("\"${x\nvar y;".chars()), line: 1, base: 0, start: 0, errors: false,
+            depth: 0, messages: None};
+        assert!(scanner.by_ref().all(|t| !matches!(t.token, TT::Interpolation(_))));
+        assert!(scanner.errors);
+    }
+
+    #[test]
+    fn render_errors() {
+        use crate::lox_parser::{LoxParser, ParseError};
+        use lexers::render;
+        let source = "var a = 1;\nprint a +;\n";
+        let errors = LoxParser::new(LoxScanner::scanner(source.chars())).parse().err().unwrap();
+        assert_eq!(render(&errors[0], source), concat!(
+            "error: LoxParser error: \";\" at line 2, expected expression\n",
+            " --> 2:10\n",
+            "  |\n",
+            "2 | print a +;\n",
+            "  |          ^\n"));
+        // scanner errors inside interpolations point into the string
+        let source = "var b = 2;\nprint \"b is ${b é}\";\n";
+        let errors = crate::lox_parser::parse(source).err().unwrap();
+        assert_eq!(errors[0].span, Some((27, 28)));
+        assert_eq!(render(&errors[1], source), concat!(
+            "error: LoxScanner error: bad interpolation at line 2\n",
+            " --> 2:13\n",
+            "  |\n",
+            "2 | print \"b is ${b é}\";\n",
+            "  |             ^^^^^^\n"));
+        // errors at the end of the source only get the message
+        let error = LoxParser::new(LoxScanner::scanner("print 1".chars())).parse().err().unwrap();
+        assert_eq!(error[0], ParseError::new("LoxParser error: EOF, expect ';' after print expr", None));
+        assert_eq!(render(&error[0], source), format!("error: {}\n", error[0]));
+    }
+}
@@ -0,0 +1,768 @@
+use crate::lox_compiler::{self, Function, Op};
+use crate::lox_format::{format_num, print_line};
+use crate::lox_native::{clock_nanos, str_method, Prim, StrMethod};
+use crate::lox_parser::Stmt;
+use lexers::did_you_mean;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+
+// Stack based VM running what lox_compiler produces. Observable behavior
+// (output, errors, stack traces) matches LoxInterpreter's.
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Num(f64),
+    Bool(bool),
+    Str(Rc<str>),
+    Closure(Rc<Closure>),
+    Native(&'static Native),
+    Method(Rc<Bound>),
+    // same as V::Error, operations on it give it back
+    Error(Rc<str>),
+}
+
+pub struct Closure {
+    function: Rc<Function>,
+    env: Option<Rc<Env>>,
+}
+
+pub struct Native {
+    name: &'static str,
+    arity: usize,
+    call: fn(&[Value]) -> Result<Value, String>,
+}
+
+// A string method with its receiver
+pub struct Bound {
+    receiver: Rc<str>,
+    method: &'static StrMethod,
+}
+
+static CLOCK: Native = Native{
+    name: "clock", arity: 0, call: |_| Ok(Value::Num(clock_nanos()))};
+
+// Same as lox_native's ERROR_FNS. catch only checks its handler and gives
+// back non errors, calling the handler on errors is up to the VM.
+static ERROR_FNS: [Native; 3] = [
+    Native{name: "error", arity: 1, call: |args| match &args[0] {
+        Value::Str(msg) => Ok(Value::Error(msg.clone())),
+        o => Err(format!("error expects a message string, found {:?}", o)),
+    }},
+    Native{name: "is_error", arity: 1, call: |args| Ok(Value::Bool(matches!(args[0], Value::Error(_))))},
+    Native{name: "catch", arity: 2, call: |args| match args[1].arity() {
+        Some(1) => Ok(args[0].clone()),
+        _ => Err(format!("catch expects a handler taking 1 argument, found {:?}", args[1])),
+    }},
+];
+
+impl Value {
+    fn is_truthy(&self) -> bool {
+        match self {
+            Value::Nil => false,
+            Value::Bool(b) => *b,
+            _ => true
+        }
+    }
+    fn num(&self) -> Result<f64, String> {
+        match self {
+            Value::Num(n) => Ok(*n),
+            o => Err(format!("expected V::Num, found {:?}", o))
+        }
+    }
+    fn arity(&self) -> Option<usize> {
+        match self {
+            Value::Closure(c) => Some(c.function.params.len()),
+            Value::Native(n) => Some(n.arity),
+            Value::Method(m) => Some(m.method.arity),
+            _ => None,
+        }
+    }
+    // Same as Callable::id, identifies callables on comparisons
+    fn id(&self) -> Option<String> {
+        match self {
+            Value::Closure(c) => Some(format!(
+                "<fn {}({})>", c.function.name, c.function.params.join(","))),
+            Value::Native(n) => Some(n.name.to_string()),
+            Value::Method(m) => Some(format!("<method {} of {:?}>", m.method.name, m.receiver)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Num(n) => write!(f, "{}", format_num(*n)),
+            Value::Str(s) => write!(f, "\"{}\"", s),
+            Value::Error(msg) => write!(f, "<error {}>", msg),
+            callable => write!(f, "\"{}\"", callable.id().unwrap()),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Num(a), Value::Num(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Error(a), Value::Error(b)) => a == b,
+            (a, b) => a.id().is_some() && a.id() == b.id(),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+// Run-time scope, slots are None until their declaration runs
+pub struct Env {
+    slots: RefCell<Vec<Option<Value>>>,
+    names: Rc<[Rc<str>]>,
+    parent: Option<Rc<Env>>,
+}
+
+impl Env {
+    fn new(names: &Rc<[Rc<str>]>, parent: Option<Rc<Env>>) -> Rc<Env> {
+        Rc::new(Env{slots: RefCell::new(vec![None; names.len()]),
+                    names: names.clone(), parent})
+    }
+
+    fn ancestor(self: &Rc<Env>, depth: u16) -> &Rc<Env> {
+        let mut env = self;
+        for _ in 0..depth {
+            env = env.parent.as_ref().expect("LoxVm Bug! wrong env depth");
+        }
+        env
+    }
+}
+
+// Caller state saved on calls
+struct Frame {
+    function: Rc<Function>,
+    ip: usize,
+    env: Option<Rc<Env>>,
+    // where the callee sits on the stack
+    base: usize,
+    // line (in the caller) the call happened at
+    line: usize,
+}
+
+pub struct LoxVm {
+    globals: HashMap<Rc<str>, Value>,
+    // globals bound with `let`
+    immutable: HashSet<Rc<str>>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    // line of the callee for Callee/Call pairs
+    call_lines: Vec<usize>,
+    // line of the last op that reported one
+    line: usize,
+    trace: Vec<String>,
+    // what catch runs handlers from, see Op::Call
+    catch: Rc<Function>,
+    // where print writes
+    out: Box<dyn Write>,
+}
+
+impl Default for LoxVm {
+    fn default() -> Self {
+        LoxVm::new()
+    }
+}
+
+impl LoxVm {
+    pub fn new() -> Self {
+        LoxVm::with_output(Box::new(io::stdout()))
+    }
+
+    // Print to `out` instead of stdout
+    pub fn with_output(out: Box<dyn Write>) -> Self {
+        let mut globals = HashMap::new();
+        globals.insert("clock".into(), Value::Native(&CLOCK));
+        for native in &ERROR_FNS {
+            globals.insert(native.name.into(), Value::Native(native));
+        }
+        // the handler and message are on the stack, call it and return what it does
+        let catch = Function{
+            name: "catch".to_string(), params: Vec::new(),
+            code: vec![Op::Call(1), Op::Return], lines: vec![0, 0],
+            constants: Vec::new(), names: Vec::new(), functions: Vec::new(),
+            scopes: Vec::new(), scope: Rc::new([])};
+        LoxVm{
+            globals,
+            immutable: HashSet::new(),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            call_lines: Vec::new(),
+            line: 0,
+            trace: Vec::new(),
+            catch: Rc::new(catch),
+            out,
+        }
+    }
+
+    // Same as LoxInterpreter::stack_trace
+    pub fn stack_trace(&self) -> &[String] {
+        &self.trace
+    }
+
+    pub fn run_compiled(&mut self, statements: &[Stmt]) -> Result<(), String> {
+        // a previous run may have bailed out mid call
+        self.stack.clear();
+        self.frames.clear();
+        self.call_lines.clear();
+        self.trace.clear();
+        let script = lox_compiler::compile(statements);
+        self.run(script)
+    }
+
+    fn capture_trace(&mut self, current: &Function) {
+        let frame = |line, function: &Function| match function.is_script() {
+            true => format!("[line {}] in script", line),
+            false => format!("[line {}] in {}()", line, function.name),
+        };
+        let mut line = self.line;
+        self.trace.push(frame(line, current));
+        for caller in self.frames.iter().rev() {
+            line = caller.line;
+            self.trace.push(frame(line, &caller.function));
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("LoxVm Bug! empty stack")
+    }
+
+    fn peek(&self) -> &Value {
+        self.stack.last().expect("LoxVm Bug! empty stack")
+    }
+
+    fn global(&self, name: &str) -> Result<Value, String> {
+        self.globals.get(name).cloned()
+            .ok_or_else(|| format!("Environment get - undefined entity '{}'{}",
+                                   name, did_you_mean(name, self.globals.keys().map(|n| &**n))))
+    }
+
+    fn dynamic(&self, env: &Option<Rc<Env>>, name: &str) -> Result<Value, String> {
+        let mut env = env.as_ref();
+        while let Some(scope) = env {
+            let slots = scope.slots.borrow();
+            let found = scope.names.iter().zip(slots.iter())
+                .find_map(|(n, v)| (&**n == name).then_some(v.as_ref()).flatten());
+            if let Some(value) = found {
+                return Ok(value.clone());
+            }
+            env = scope.parent.as_ref();
+        }
+        self.global(name)
+    }
+
+    fn binary(&mut self, op: Op) -> Result<(), String> {
+        let rhs = self.pop();
+        let lhs = self.pop();
+        let compare = |lexeme| match (&lhs, &rhs) {
+            (Value::Num(l), Value::Num(r)) => Ok(Value::Bool(match op {
+                Op::Greater => l > r,
+                Op::GreaterEqual => l >= r,
+                Op::Less => l < r,
+                _ => l <= r,
+            })),
+            _ => Err(format!("can't compare {:?} {} {:?}", lhs, lexeme, rhs))
+        };
+        let result = match op {
+            _ if matches!(lhs, Value::Error(_)) => lhs,
+            _ if matches!(rhs, Value::Error(_)) => rhs,
+            Op::Add => match (&lhs, &rhs) {
+                (Value::Num(l), Value::Num(r)) => Value::Num(l + r),
+                (Value::Str(l), Value::Str(r)) => Value::Str(format!("{}{}", l, r).into()),
+                (Value::Str(l), other) => Value::Str(format!("{}{}", l, other).into()),
+                (other, Value::Str(r)) => Value::Str(format!("{}{}", other, r).into()),
+                _ => return Err(format!("can't {:?} + {:?}", lhs, rhs))
+            },
+            Op::Subtract => Value::Num(lhs.num()? - rhs.num()?),
+            Op::Multiply => Value::Num(lhs.num()? * rhs.num()?),
+            Op::Divide if rhs.num()? == 0.0 => Value::Error("division by zero".into()),
+            Op::Divide => Value::Num(lhs.num()? / rhs.num()?),
+            Op::Greater => compare(">")?,
+            Op::GreaterEqual => compare(">=")?,
+            Op::Less => compare("<")?,
+            Op::LessEqual => compare("<=")?,
+            Op::Equal => Value::Bool(lhs == rhs),
+            Op::NotEqual => Value::Bool(lhs != rhs),
+            _ => unreachable!("LoxVm: bad binop {:?}", op),
+        };
+        self.stack.push(result);
+        Ok(())
+    }
+
+    fn run(&mut self, script: Rc<Function>) -> Result<(), String> {
+        let mut function = script;
+        let mut ip = 0;
+        let mut env: Option<Rc<Env>> = None;
+
+        // on errors record the stack trace before bailing out
+        macro_rules! throw {
+            ($result:expr) => {
+                match $result {
+                    Ok(value) => value,
+                    Err(err) => {
+                        self.capture_trace(&function);
+                        return Err(err);
+                    }
+                }
+            };
+        }
+
+        loop {
+            let op = function.code[ip];
+            let line = function.lines[ip];
+            if line != 0 {
+                self.line = line;
+            }
+            ip += 1;
+            match op {
+                Op::Constant(idx) => self.stack.push(function.constants[idx as usize].clone()),
+                Op::Nil => self.stack.push(Value::Nil),
+                Op::True => self.stack.push(Value::Bool(true)),
+                Op::False => self.stack.push(Value::Bool(false)),
+                Op::Pop => { self.pop(); },
+                Op::GetGlobal(name) => {
+                    let value = throw!(self.global(&function.names[name as usize]));
+                    self.stack.push(value);
+                },
+                Op::SetGlobal(name) => {
+                    let name = &function.names[name as usize];
+                    let value = self.peek().clone();
+                    if self.immutable.contains(name) {
+                        throw!(Err(format!("cannot assign to immutable '{}'", name)))
+                    }
+                    match self.globals.get_mut(name) {
+                        Some(global) => *global = value,
+                        None => {
+                            let hint = did_you_mean(name, self.globals.keys().map(|n| &**n));
+                            throw!(Err(format!(
+                                "Environment assign - undefined entity '{}'{}", name, hint)))
+                        },
+                    }
+                },
+                Op::DefineGlobal(name) => {
+                    let value = self.pop();
+                    self.immutable.remove(&function.names[name as usize]);
+                    self.globals.insert(function.names[name as usize].clone(), value);
+                },
+                Op::DefineImmutable(name) => {
+                    let value = self.pop();
+                    self.immutable.insert(function.names[name as usize].clone());
+                    self.globals.insert(function.names[name as usize].clone(), value);
+                },
+                Op::GetLocal(depth, slot) => {
+                    let scope = env.as_ref().expect("LoxVm Bug! no scope").ancestor(depth);
+                    let value = scope.slots.borrow()[slot as usize].clone();
+                    match value {
+                        Some(value) => self.stack.push(value),
+                        None => throw!(Err(format!(
+                            "Environment get_at - undefined entity '{}' depth {}",
+                            scope.names[slot as usize], depth))),
+                    }
+                },
+                Op::SetLocal(depth, slot) => {
+                    let scope = env.as_ref().expect("LoxVm Bug! no scope").ancestor(depth);
+                    let value = self.peek().clone();
+                    match scope.slots.borrow_mut()[slot as usize].as_mut() {
+                        Some(local) => *local = value,
+                        None => throw!(Err(format!(
+                            "Environment assign_at - undefined entity '{}'",
+                            scope.names[slot as usize]))),
+                    }
+                },
+                Op::DefineLocal(slot) => {
+                    let value = self.pop();
+                    let scope = env.as_ref().expect("LoxVm Bug! no scope");
+                    scope.slots.borrow_mut()[slot as usize] = Some(value);
+                },
+                Op::Dynamic => {
+                    let value = match self.pop() {
+                        Value::Str(name) => throw!(self.dynamic(&env, &name)),
+                        err @ Value::Error(_) => err,
+                        o => throw!(Err(format!("expected V::Str, found {:?}", o))),
+                    };
+                    self.stack.push(value);
+                },
+                Op::Negate => {
+                    let value = match self.pop() {
+                        err @ Value::Error(_) => err,
+                        value => Value::Num(-throw!(value.num())),
+                    };
+                    self.stack.push(value);
+                },
+                Op::Not => {
+                    let value = match self.pop() {
+                        err @ Value::Error(_) => err,
+                        value => Value::Bool(!value.is_truthy()),
+                    };
+                    self.stack.push(value);
+                },
+                Op::Add | Op::Subtract | Op::Multiply | Op::Divide |
+                Op::Greater | Op::GreaterEqual | Op::Less | Op::LessEqual |
+                Op::Equal | Op::NotEqual => throw!(self.binary(op)),
+                Op::Interpolate(count) => {
+                    let parts = self.stack.split_off(self.stack.len() - count as usize);
+                    let mut out = String::new();
+                    let mut error = None;
+                    for part in parts {
+                        match part {
+                            Value::Str(s) => out += &s,
+                            err @ Value::Error(_) => { error.get_or_insert(err); },
+                            other => out += &other.to_string(),
+                        }
+                    }
+                    self.stack.push(error.unwrap_or(Value::Str(out.into())));
+                },
+                Op::Print(count) => {
+                    let values = self.stack.split_off(self.stack.len() - count as usize);
+                    throw!(writeln!(self.out, "{}", print_line(&values))
+                        .map_err(|e| format!("print failed: {}", e)))
+                },
+                Op::Assert => if !self.pop().is_truthy() {
+                    throw!(Err(format!("assertion failed at line {}", line)))
+                },
+                Op::Jump(target) => ip = target as usize,
+                Op::JumpIfFalse(target) => if !self.peek().is_truthy() {
+                    ip = target as usize;
+                },
+                Op::JumpIfTrue(target) => if self.peek().is_truthy() {
+                    ip = target as usize;
+                },
+                Op::JumpIfNotNil(target) => if !matches!(self.peek(), Value::Nil) {
+                    ip = target as usize;
+                },
+                Op::PushScope(idx) => env = Some(Env::new(&function.scopes[idx as usize], env)),
+                Op::PopScope => env = env.and_then(|scope| scope.parent.clone()),
+                Op::Closure(idx) => {
+                    let closure = Closure{
+                        function: function.functions[idx as usize].clone(), env: env.clone()};
+                    self.stack.push(Value::Closure(Rc::new(closure)));
+                },
+                Op::Callee(argc) => {
+                    let arity = match self.peek().arity() {
+                        Some(arity) => arity,
+                        None => throw!(Err(format!("expected V::Callable, found {:?}", self.peek()))),
+                    };
+                    if arity != argc as usize {
+                        throw!(Err(format!("wrong arity for {} expected {} not {}",
+                                           self.peek().id().unwrap(), arity, argc)));
+                    }
+                    self.call_lines.push(self.line);
+                },
+                Op::Call(argc) => {
+                    self.line = self.call_lines.pop().expect("LoxVm Bug! no call line");
+                    let base = self.stack.len() - argc as usize - 1;
+                    match self.stack[base].clone() {
+                        Value::Closure(closure) => {
+                            let callee_env = Env::new(&closure.function.scope, closure.env.clone());
+                            {
+                                let mut slots = callee_env.slots.borrow_mut();
+                                for (slot, arg) in self.stack.drain(base + 1..).enumerate() {
+                                    slots[slot] = Some(arg);
+                                }
+                            }
+                            let caller = std::mem::replace(&mut function, closure.function.clone());
+                            self.frames.push(Frame{
+                                function: caller, ip, env: env.replace(callee_env),
+                                base, line: self.line});
+                            ip = 0;
+                        },
+                        Value::Native(native) => {
+                            let result = (native.call)(&self.stack[base + 1..]);
+                            // the interpreter has a frame for natives too
+                            if result.is_err() {
+                                self.trace.push(format!("[line {}] in {}()", self.line, native.name));
+                            }
+                            match throw!(result) {
+                                // call the handler from a catch frame, like the
+                                // interpreter's catch native calling back
+                                Value::Error(msg) if native.name == "catch" => {
+                                    self.stack[base + 1] = self.pop();
+                                    self.stack.push(Value::Str(msg));
+                                    self.call_lines.push(self.line);
+                                    let caller = std::mem::replace(&mut function, self.catch.clone());
+                                    self.frames.push(Frame{
+                                        function: caller, ip, env: env.take(), base, line: self.line});
+                                    ip = 0;
+                                },
+                                result => {
+                                    self.stack.truncate(base);
+                                    self.stack.push(result);
+                                },
+                            }
+                        },
+                        Value::Method(bound) => {
+                            let result = self.stack.drain(base + 1..).map(|arg| match arg {
+                                Value::Nil => Ok(Prim::Nil),
+                                Value::Num(n) => Ok(Prim::Num(n)),
+                                Value::Str(s) => Ok(Prim::Str(s.to_string())),
+                                o => Err(format!("{} can't take {:?}", bound.method.name, o)),
+                            }).collect::<Result<Vec<_>, _>>()
+                                .and_then(|args| (bound.method.call)(&bound.receiver, &args));
+                            // the interpreter has a frame for natives too
+                            if result.is_err() {
+                                self.trace.push(format!("[line {}] in {}()",
+                                                        self.line, bound.method.name));
+                            }
+                            let result = throw!(result);
+                            self.stack.truncate(base);
+                            self.stack.push(match result {
+                                Prim::Nil => Value::Nil,
+                                Prim::Num(n) => Value::Num(n),
+                                Prim::Str(s) => Value::Str(s.into()),
+                            });
+                        },
+                        _ => unreachable!("LoxVm Bug! Callee didn't check"),
+                    }
+                },
+                Op::Return => {
+                    let retval = self.pop();
+                    let caller = match self.frames.pop() {
+                        Some(caller) => caller,
+                        None => return Ok(()),
+                    };
+                    self.stack.truncate(caller.base);
+                    self.stack.push(retval);
+                    function = caller.function;
+                    ip = caller.ip;
+                    env = caller.env;
+                },
+                Op::GetMethod(name) => match self.stack.pop() {
+                    Some(Value::Str(receiver)) => {
+                        let method = throw!(str_method(&function.names[name as usize]));
+                        self.stack.push(Value::Method(Rc::new(Bound{receiver, method})));
+                    },
+                    o => throw!(Err(format!("only strings have methods, found {:?}",
+                                            o.expect("LoxVm Bug! empty stack")))),
+                },
+                Op::Fail(msg) => match &function.constants[msg as usize] {
+                    Value::Str(msg) => throw!(Err(msg.to_string())),
+                    o => unreachable!("LoxVm Bug! bad error message {:?}", o),
+                },
+            }
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use crate::lox_format::Captured;
+    use crate::lox_interpreter::LoxInterpreter;
+    use crate::lox_parser::{LoxParser, Stmt};
+    use crate::lox_resolver::Resolver;
+    use crate::lox_scanner::LoxScanner;
+    use super::LoxVm;
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        LoxParser::new(LoxScanner::scanner(source.chars())).parse().unwrap()
+    }
+
+    // run source on both backends, global 'r' (or the error and stack trace)
+    // and what was printed
+    fn run_both(source: &str) -> [(String, String); 2] {
+        let stmts = parse(source);
+        let out = Captured::default();
+        let mut interpreter = LoxInterpreter::with_output(Box::new(out.clone()));
+        Resolver::new(&mut interpreter).resolve(&stmts).unwrap();
+        let walked = match interpreter.interpret(&stmts) {
+            Ok(_) => format!("{:?}", interpreter.globals()
+                .find(|(name, _)| name == "r").map(|(_, v)| v).ok_or("no r")),
+            Err(e) => format!("{} {:?}", e, interpreter.stack_trace()),
+        };
+        let vm_out = Captured::default();
+        let mut vm = LoxVm::with_output(Box::new(vm_out.clone()));
+        let compiled = match vm.run_compiled(&stmts) {
+            Ok(_) => format!("{:?}", vm.global("r").map_err(|_| "no r")),
+            Err(e) => format!("{} {:?}", e, vm.stack_trace()),
+        };
+        [(walked, out.text()), (compiled, vm_out.text())]
+    }
+
+    fn check(source: &str, expected: &str) {
+        let [walked, compiled] = run_both(source);
+        assert_eq!(walked, compiled, "{}", source);
+        assert_eq!(walked.0, expected, "{}", source);
+    }
+
+    fn check_output(source: &str, expected: &str) {
+        let [walked, compiled] = run_both(source);
+        assert_eq!(walked, compiled, "{}", source);
+        assert_eq!(walked.1, expected, "{}", source);
+    }
+
+    #[test]
+    fn same_as_interpreter() {
+        check("fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }\n\
+               var r = fib(15);", "Ok(610)");
+        check("fun counter() { var i = 0; fun inc() { i = i + 1; return i; } return inc; }\n\
+               var c = counter(); c(); var r = c() + c();", "Ok(5)");
+        // functions evaluate to their last expression statement
+        check("fun f(x) { if (x) { x + 1; } else { 0; } } var r = f(1) + f(false);", "Ok(2)");
+        check("fun f() { while (true) { return 3; } } var r = f();", "Ok(3)");
+        check("var r = 0; for (var y = 0; y < 10; y = y + 1)\n\
+               for (var x = 0; x < 10; x = x + 1) { if (x > 5 or y >= 3) break 2; r = r + 1; }",
+              "Ok(6)");
+        check("var r = 0; do r = r + 1; while (false);", "Ok(1)");
+        check("var r = 0; while (true) { do { var x = r; r = x + 1; if (r > 2) break 2; } while (true); }",
+              "Ok(3)");
+        check("var x = 2; var r = \"x=${x + 1}, ${\"s\"}\";", "Ok(\"x=3, s\")");
+        check("var r = \"a\"; { var a = 1; r = $r; }", "Ok(1)");
+        check("var r = clock == clock and \"a\" != nil;", "Ok(true)");
+        check("var r = 0; fun f() { r = r + 1; return nil; }\n\
+               var a = false ?? f(); a = nil ?? f() ?? r;", "Ok(1)");
+        check("var r = 0; fun f(n) { r = r + n; return n; }\n\
+               r = (r > 0 ? f(1) : f(2) ? f(3) : f(4)) + r;", "Ok(8)");
+        check("var s = \"a-b\".upper; var r = \"${s().split(\"-\", 1)}${s}\".length();",
+              "Ok(26)");
+        check("var r = \"a\".upper == \"a\".upper and \"a\".upper != \"b\".upper;", "Ok(true)");
+        check("var r = 0; fun f() { r = r + 1; return r; }\nprint f(), f(), \"s\";", "Ok(2)");
+        check("var r = \"${-0} ${1000000000000000000000} ${0.5}\";", "Ok(\"-0 1e21 0.5\")");
+    }
+
+    #[test]
+    fn same_let_errors() {
+        check("let r = 1; var a = r + 1;", "Ok(1)");
+        check("let r = 1;\nr = 2;", "cannot assign to immutable 'r' [\"[line 2] in script\"]");
+        check("var r = 1;\n{ let a = 2;\n  fun f() {\n    a = 3;\n  }\n  f(); }",
+              "cannot assign to immutable 'a' [\"[line 4] in f()\", \"[line 6] in script\"]");
+        check("let r = 1; { var r = 2; r = 3; } { let r = 4; } var x = 1; { let x = 2; } x = r;",
+              "Ok(1)");
+        check("let r = 1; var r = 2; r = 3;", "Ok(3)");
+    }
+
+    #[test]
+    fn same_errors() {
+        check("var r = 2;\nassert r == 2;\nfun f() {\n  assert r > 2;\n}\nf();",
+              "assertion failed at line 4 [\"[line 4] in f()\", \"[line 6] in script\"]");
+        check("fun f(s) {\n  return s / 2;\n}\nfun g() { return f(\"a\"); }\nvar r = g();",
+              "expected V::Num, found \"a\" [\"[line 2] in f()\", \"[line 4] in g()\", \
+               \"[line 5] in script\"]");
+        check("fun f(a) {}\nf();", "wrong arity for <fn f(a)> expected 1 not 0 \
+               [\"[line 2] in script\"]");
+        check("var r = 1;\nr + nil;", "can't 1 + nil [\"[line 2] in script\"]");
+        check("var r = \"abc\";\nr.upper().uper();",
+              "undefined method 'uper' for string, did you mean 'upper'? [\"[line 2] in script\"]");
+        check("fun f() {}\nvar r = f.length;",
+              "only strings have methods, found \"<fn f()>\" [\"[line 2] in script\"]");
+        check("var r = \"a\".split(1, 2);", "split expects a separator and an index, \
+               found [Num(1.0), Num(2.0)] [\"[line 1] in split()\", \"[line 1] in script\"]");
+        check("var r = undefined;",
+              "Environment get - undefined entity 'undefined' [\"[line 1] in script\"]");
+        check("var count = 1;\nvar r = conut + 1;",
+              "Environment get - undefined entity 'conut', did you mean 'count'? \
+               [\"[line 2] in script\"]");
+        check("var count = 1;\ncuont = 2;",
+              "Environment assign - undefined entity 'cuont', did you mean 'count'? \
+               [\"[line 2] in script\"]");
+    }
+
+    #[test]
+    fn same_output() {
+        check_output("print 1, \"a\", nil, 1 / 4, -0;\nprint \"${1 + 1}\";",
+                     "1 \"a\" nil 0.25 -0\n\"2\"\n");
+        check_output("fun f(n) { print n; if (n > 0) f(n - 1); } f(2);", "2\n1\n0\n");
+        check_output("for (var i = 0; i < 3; i = i + 1) { if (i == 2) break; print i; }\n\
+                      fun g() { return \"s\".upper; } print g()(), clock == clock;",
+                     "0\n1\n\"S\" true\n");
+        // what was printed before an error stays
+        check_output("print 1;\nprint 2 + nil;\nprint 3;", "1\n");
+    }
+
+    #[test]
+    fn same_error_values() {
+        check("var r = 1;\nr = r / 0;", "Ok(<error division by zero>)");
+        check("fun h(e) { return \"caught: \" + e; } var r = catch(1/0, h);",
+              "Ok(\"caught: division by zero\")");
+        check("fun h(e) { return 0; } var r = catch(6/2, h);", "Ok(3)");
+        check("var e = error(\"bad\"); var r = -(e * 2) + 1 < 3;", "Ok(<error bad>)");
+        check("var r = !error(\"a\") == $error(\"b\");", "Ok(<error a>)");
+        check("var n = 0; fun f() { n = n + 1; return n; }\n\
+               var r = \"${1/0} ${error(\"a\")} ${f()}\" + n;", "Ok(<error division by zero>)");
+        check("var r = is_error(1/0) and !is_error(nil) and is_error(\"\" + error(\"a\"));",
+              "Ok(true)");
+        check("fun f(x) { return x; } var r = f(error(\"a\")) == error(\"a\");", "Ok(<error a>)");
+        check("var r = catch(error(\"a\"), is_error) == false and catch(1, is_error) == 1;", "Ok(true)");
+        check("fun h(e) { return e - 1; } fun g(e) { return catch(error(e + \"!\"), h); }\n\
+               var r = catch(error(\"a\"), g);", "expected V::Num, found \"a!\" \
+               [\"[line 1] in h()\", \"[line 1] in catch()\", \"[line 1] in g()\", \
+               \"[line 2] in catch()\", \"[line 2] in script\"]");
+        check("print error(\"a\");\nfun h(e) {\n  return e - 1;\n}\nvar r = catch(error(\"a\"), h);",
+              "expected V::Num, found \"a\" [\"[line 3] in h()\", \"[line 5] in catch()\", \
+               \"[line 5] in script\"]");
+        check("var r = catch(1, 2);", "catch expects a handler taking 1 argument, found 2 \
+               [\"[line 1] in catch()\", \"[line 1] in script\"]");
+        check("var r = error(1);",
+              "error expects a message string, found 1 [\"[line 1] in error()\", \"[line 1] in script\"]");
+        check_output("print error(\"a\"), 1 / 0;", "<error a> <error division by zero>\n");
+    }
+
+    #[test]
+    fn print_values() {
+        check_output("print 1, 2, 3;", "1 2 3\n");
+        check_output("print 1;\nprint \"a b\";", "1\n\"a b\"\n");
+        check_output("var r = 1; print r, r + 1, \"${r}\", nil;", "1 2 \"1\" nil\n");
+    }
+
+    // Every script in src/tests prints the same and fails (or not) on both
+    // backends. Resolve errors stop either before it runs, as in main.
+    #[test]
+    fn same_on_fixture_scripts() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests");
+        let mut scripts: Vec<_> = std::fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+            .collect();
+        scripts.sort();
+        assert!(!scripts.is_empty());
+        for script in scripts {
+            let stmts = parse(&std::fs::read_to_string(&script).unwrap());
+            let out = Captured::default();
+            let mut interpreter = LoxInterpreter::with_output(Box::new(out.clone()));
+            let walked = Resolver::new(&mut interpreter).resolve(&stmts).map_err(|e| e.to_string())
+                .and_then(|_| interpreter.interpret(&stmts).map(|_| ()).map_err(|e| e.to_string()));
+            let vm_out = Captured::default();
+            let mut vm = LoxVm::with_output(Box::new(vm_out.clone()));
+            let compiled = Resolver::new(&mut LoxInterpreter::new()).resolve(&stmts).map_err(|e| e.to_string())
+                .and_then(|_| vm.run_compiled(&stmts));
+            assert_eq!(walked.is_ok(), compiled.is_ok(), "{:?}: {:?} vs {:?}", script, walked, compiled);
+            assert_eq!(out.text(), vm_out.text(), "{:?}", script);
+        }
+    }
+
+    // cargo test --release -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn benchmark() {
+        let stmts = parse("fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }\n\
+                           var r = fib(25);");
+        let mut interpreter = LoxInterpreter::new();
+        Resolver::new(&mut interpreter).resolve(&stmts).unwrap();
+        let started = std::time::Instant::now();
+        interpreter.interpret(&stmts).unwrap();
+        let walked = started.elapsed();
+        let started = std::time::Instant::now();
+        LoxVm::new().run_compiled(&stmts).unwrap();
+        let compiled = started.elapsed();
+        println!("fib(25) tree-walker: {:?} vm: {:?}", walked, compiled);
+    }
+}
@@ -1,62 +1,99 @@
-#![deny(warnings)]
+#![cfg_attr(feature = "strict", deny(warnings))]
 
+use std::cell::OnceCell;
 use std::env;
 use std::fs::File;
-use std::io::{self, Read, Write};
+use std::io::{self, Write};
 
-mod lox_scanner;
-mod lox_parser;
-mod lox_interpreter;
-mod lox_environment;
-mod lox_native;
-mod lox_resolver;
-
-use crate::lox_scanner::LoxScanner;
-use crate::lox_parser::LoxParser;
-use crate::lox_interpreter::LoxInterpreter;
-use crate::lox_resolver::Resolver;
+use lexers::{render, Diagnostic, ReadChars, SourceError};
+use lox::{LoxInterpreter, LoxParser, LoxScanner, LoxVm, ParseError, Resolver, Stmt};
 
 
 fn main() {
-    if env::args().len() > 2 {
-        eprintln!("usage: lox [script]");
+    // --tree dumps the parsed program instead of running it
+    let tree = env::args().any(|arg| arg == "--tree");
+    // --fold evaluates constant subexpressions before running
+    let fold = env::args().any(|arg| arg == "--fold");
+    // --vm runs compiled bytecode instead of walking the tree
+    let mut vm = env::args().any(|arg| arg == "--vm").then(LoxVm::new);
+    let args: Vec<String> = env::args()
+        .filter(|arg| arg != "--tree" && arg != "--fold" && arg != "--vm").collect();
+    if args.len() > 2 {
+        eprintln!("usage: lox [--tree] [--fold] [--vm] [script]");
         return;
     }
 
-    let run = |source: String, interpreter: &mut LoxInterpreter| {
-        let scanner = LoxScanner::scanner(source.chars());
-        let mut parser = LoxParser::new(scanner);
-        match parser.parse() {
+    // errors are shown with the line of the source they point at, it's only
+    // read from `source` once there's an error to show
+    let run = |parsed: Result<Vec<Stmt>, Vec<ParseError>>, source: &dyn Fn() -> String,
+               interpreter: &mut LoxInterpreter, vm: &mut Option<LoxVm>| {
+        let text = OnceCell::new();
+        let show = |error: &dyn SourceError| eprint!("{}", render(error, text.get_or_init(source)));
+        let report = |error: Diagnostic| show(&error);
+        let parsed = parsed
+            .map(|stmts| interpreter.expand_macros(stmts))
+            .map(|stmts| if fold { lox::fold_stmts(stmts) } else { stmts });
+        match parsed {
+            Ok(stmts) if tree => println!("{}", lox::dump_stmts(&stmts)),
             Ok(stmts) => {
                 match Resolver::new(interpreter).resolve(&stmts) {
-                    Ok(_) => if let Err(error) = interpreter.interpret(&stmts) {
-                        eprintln!("LoxInterpreter error: {}", error)
+                    Ok(_) => match vm {
+                        Some(vm) => if let Err(error) = vm.run_compiled(&stmts) {
+                            report(Diagnostic::new(format!("LoxInterpreter error: {}", error)));
+                            vm.stack_trace().iter().for_each(|f| eprintln!("{}", f));
+                        },
+                        None => if let Err(error) = interpreter.interpret(&stmts) {
+                            report(Diagnostic::new(format!("LoxInterpreter error: {}", error)));
+                            interpreter.stack_trace().iter().for_each(|f| eprintln!("{}", f));
+                        },
                     },
-                    Err(error) => eprintln!("Resolve error: {}", error)
+                    Err(error) => report(Diagnostic::new(format!("Resolve error: {}", error)))
                 }
             }
-            Err(errors) => for e in errors { eprintln!("{}", e); }
+            Err(errors) => for e in errors { show(&e); }
         }
     };
 
     let mut interpreter = LoxInterpreter::new();
-    if env::args().len() == 2 {
-        let sourcefile = env::args().nth(1).unwrap();
-        if let Ok(mut f) = File::open(&sourcefile) {
-            let mut source = String::new();
-            if f.read_to_string(&mut source).is_ok() {
-                return run(source, &mut interpreter);
-            }
+    interpreter.define_macro("debug_print", lox::debug_print);
+    if args.len() == 2 {
+        let sourcefile = &args[1];
+        let fail = |error: &dyn std::fmt::Display| -> ! {
+            eprintln!("lox: failed to read source file {}: {}", sourcefile, error);
+            std::process::exit(1);
+        };
+        let mut chars = ReadChars::new(File::open(sourcefile).unwrap_or_else(|e| fail(&e)));
+        let parsed = LoxParser::new(LoxScanner::from_reader(&mut chars)).parse();
+        // don't run what was read of a file that failed midway
+        if let Some(e) = chars.error() {
+            fail(&e);
         }
-        eprintln!("lox: failed to read source file {}", sourcefile);
-        std::process::exit(1);
+        // the file is read again only to show the lines errors are on
+        run(parsed, &|| std::fs::read_to_string(sourcefile).unwrap_or_default(), &mut interpreter, &mut vm);
     } else {
+        // bindings before the last input, for :undo
+        let mut undo = None;
         loop {
             let mut input = String::new();
             io::stdout().write_all(b"~> ").unwrap();
             io::stdout().flush().unwrap();
             match io::stdin().read_line(&mut input) {
-                Ok(_) => run(input, &mut interpreter),
+                // :env dumps the bindings in scope
+                Ok(_) if matches!(input.trim(), ":env" | ":undo" | ":gc") && vm.is_some() =>
+                    eprintln!("lox: {} needs the tree-walker, run without --vm", input.trim()),
+                Ok(_) if input.trim() == ":env" => print!("{}", interpreter.dump_env()),
+                // :gc frees scopes only kept alive by reference cycles
+                Ok(_) if input.trim() == ":gc" => println!("freed {}", interpreter.gc()),
+                // :undo rolls back bindings changed by the last input
+                Ok(_) if input.trim() == ":undo" => match undo.take() {
+                    Some(snapshot) => interpreter.restore(snapshot).unwrap(),
+                    None => eprintln!("lox: nothing to undo"),
+                },
+                Ok(_) => {
+                    undo = Some(interpreter.snapshot());
+                    let parsed = lox::parse(&input);
+                    run(parsed, &|| input.clone(), &mut interpreter, &mut vm)
+                },
                 Err(e) => eprintln!("lox read_line error: {:?}", e)
             }
         }
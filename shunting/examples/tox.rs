@@ -1,26 +1,27 @@
 mod repl {
-    use lexers::{MathToken, MathTokenizer};
+    use lexers::{render, MathToken, MathTokenizer};
     use shunting::{MathContext, ShuntingParser, MathOp};
 
     pub fn evalexpr(input: &str) {
         match ShuntingParser::parse_str(input) {
-            Err(e) => println!("Parse error: {:?}", e),
+            Err(e) => print!("{}", render(&e, input)),
             Ok(expr) => match MathContext::new().eval(&expr) {
-                Err(e) => println!("Eval error: {:?}", e),
+                Err(e) => print!("{}", render(&e, input)),
                 Ok(r) => println!("{} -> {}", expr, r),
             },
         };
     }
 
     pub fn parse_statement(cx: &MathContext, input: &str) {
-        let mut ml = MathTokenizer::scanner(input.chars());
-        let backtrack = ml.buffer_pos();
+        let mut ml = MathTokenizer::new(input.chars());
         if let (Some(MathToken::Variable(var)), Some(op)) = (ml.next(), ml.next()) {
             if op == MathToken::BOp(":=".to_string()) {
-                match ShuntingParser::parse(&mut ml) {
-                    Err(e) => println!("Parse error: {:?}", e),
+                // errors point into what's after the :=
+                let input: String = input.chars().skip(ml.span().1).collect();
+                match ShuntingParser::parse_str(&input) {
+                    Err(e) => print!("{}", render(&e, &input)),
                     Ok(expr) => match cx.compile(&expr) {
-                        Err(e) => println!("Compile error: {:?}", e),
+                        Err(e) => print!("{}", render(&e, &input)),
                         Ok(code) => cx.setvar(&var, code),
                     }
                 }
@@ -28,13 +29,15 @@ mod repl {
             }
         }
         // wasn't assignment... try evaluating expression
-        ml.set_buffer_pos(backtrack);
-        match ShuntingParser::parse(&mut ml) {
-            Err(e) => println!("Parse error: {:?}", e),
+        match ShuntingParser::parse_str(input) {
+            Err(e) => print!("{}", render(&e, input)),
             Ok(expr) => match cx.compile(&expr) {
-                Err(e) => println!("Compile error: {:?}", e),
+                Err(e) => print!("{}", render(&e, input)),
                 Ok(MathOp::Number(n)) => println!("{}", n),
-                Ok(x) => println!("{:?}", x.histogram::<15>(2000)),
+                Ok(x) => match x.histogram::<15>(2000) {
+                    Ok(histogram) => println!("{:?}", histogram),
+                    Err(e) => print!("{}", render(&e, input)),
+                },
             }
         };
     }
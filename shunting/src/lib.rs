@@ -1,11 +1,27 @@
+mod operators;
 mod parser;
+#[cfg(feature = "cache")]
+mod rpncache;
 mod rpneval;
+mod rpnnormalize;
 mod rpnprint;
+mod rpnrational;
 
-pub use crate::parser::{RPNExpr, ShuntingParser};
-pub use crate::rpneval::{MathOp, MathContext};
+pub use crate::operators::{Operator, OperatorTable};
+pub use crate::parser::{Assoc, RPNExpr, ShuntingError, ShuntingParser};
+pub use crate::rpneval::{EvalError, MathOp, MathContext};
+pub use crate::rpnprint::{FracStyle, LatexOptions, MulSymbol};
+pub use crate::rpnrational::Rational64;
 
+#[cfg(test)]
+mod operators_test;
 #[cfg(test)]
 mod parser_test;
+#[cfg(all(test, feature = "cache"))]
+mod rpncache_test;
 #[cfg(test)]
 mod rpneval_test;
+#[cfg(test)]
+mod rpnnormalize_test;
+#[cfg(test)]
+mod rpnprint_test;
@@ -0,0 +1,154 @@
+use crate::parser::Assoc;
+use lexers::MathToken;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Built-in operators: symbol, arity, precedence and associativity. Prefix !
+// is "not" to tell it from factorial, see MathTokenizer.
+// NOTE: This can't encode relations between all tokens, just Ops.
+// For example:
+// In https://github.com/rodolf0/natools/blob/master/libparser/parser.c#L56-L94
+// - unary-minus has to be < than Numbers and OParen
+// - but OParen has to be < than unary-minus too!
+// - At the same time, unary-minus has to be > than bin-ops (eg: +)
+// Spaced so registered operators can go in between.
+pub(crate) const BUILTIN: &[(&str, usize, usize, Assoc)] = &[
+    ("||", 2, 4, Assoc::Left),
+    ("&&", 2, 6, Assoc::Left),
+    ("==", 2, 10, Assoc::Left),
+    ("!=", 2, 10, Assoc::Left),
+    ("<", 2, 10, Assoc::Left),
+    ("<=", 2, 10, Assoc::Left),
+    (">", 2, 10, Assoc::Left),
+    (">=", 2, 10, Assoc::Left),
+    ("+", 2, 20, Assoc::Left),
+    ("-", 2, 20, Assoc::Left),
+    ("*", 2, 30, Assoc::Left),
+    ("/", 2, 30, Assoc::Left),
+    ("%", 2, 30, Assoc::Left),
+    ("^", 2, 40, Assoc::Right),
+    ("**", 2, 40, Assoc::Right),
+    ("-", 1, 50, Assoc::Right),
+    ("not", 1, 50, Assoc::Right),
+    ("!", 1, 60, Assoc::Left),
+];
+
+type OpEval = Rc<dyn Fn(&[f64]) -> f64>;
+
+#[derive(Clone)]
+pub struct Operator {
+    pub symbol: String,
+    pub arity: usize,
+    pub precedence: usize,
+    pub associativity: Assoc,
+    // None for built-ins MathContext evaluates itself, and for operators
+    // only the parser knows, see OperatorTable::define
+    eval: Option<OpEval>,
+    builtin: bool,
+}
+
+pub(crate) fn is_word(symbol: &str) -> bool {
+    symbol.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+// Operators a ShuntingParser knows, shared with the MathContext evaluating
+// what it parses and with the printers. Clones share the same table.
+#[derive(Clone)]
+pub struct OperatorTable(Rc<RefCell<Vec<Operator>>>);
+
+impl Default for OperatorTable {
+    fn default() -> Self {
+        OperatorTable::new()
+    }
+}
+
+impl OperatorTable {
+    // The built-in operators
+    pub fn new() -> Self {
+        OperatorTable(Rc::new(RefCell::new(BUILTIN.iter()
+            .map(|&(symbol, arity, precedence, associativity)| Operator {
+                symbol: symbol.to_string(), arity, precedence, associativity,
+                eval: None, builtin: true})
+            .collect())))
+    }
+
+    // Register an operator evaluated by `eval` on its `arity` operands.
+    // It replaces one with the same symbol and arity, eg: to make ^ left
+    // associative. Symbols that are words (eg: "mod") are read from
+    // identifiers, others are matched before built-in operators. Arity 1
+    // operators are prefix.
+    pub fn add_operator(&self, symbol: &str, precedence: usize, associativity: Assoc,
+                        arity: usize, eval: impl Fn(&[f64]) -> f64 + 'static)
+            -> Result<(), String> {
+        self.insert(symbol, precedence, associativity, arity, Some(Rc::new(eval)))
+    }
+
+    // Same as add_operator for an operator only parsed and printed,
+    // evaluating it fails with an unknown op error
+    pub fn define(&self, symbol: &str, precedence: usize, associativity: Assoc, arity: usize)
+            -> Result<(), String> {
+        self.insert(symbol, precedence, associativity, arity, None)
+    }
+
+    fn insert(&self, symbol: &str, precedence: usize, associativity: Assoc,
+              arity: usize, eval: Option<OpEval>) -> Result<(), String> {
+        if arity != 1 && arity != 2 {
+            return Err(format!("Operator {} has arity {}, expected 1 or 2", symbol, arity));
+        }
+        if symbol.is_empty() || symbol.chars().any(|c| c.is_whitespace() || "(),".contains(c)) {
+            return Err(format!("Bad operator symbol: {:?}", symbol));
+        }
+        let mut ops = self.0.borrow_mut();
+        ops.retain(|op| op.symbol != symbol || op.arity != arity);
+        ops.push(Operator {
+            symbol: symbol.to_string(), arity, precedence, associativity, eval, builtin: false});
+        Ok(())
+    }
+
+    // A table with the same operators that doesn't see later changes, nor
+    // is changed with the original
+    pub(crate) fn copy(&self) -> OperatorTable {
+        OperatorTable(Rc::new(RefCell::new(self.0.borrow().clone())))
+    }
+
+    pub fn get(&self, symbol: &str, arity: usize) -> Option<Operator> {
+        self.0.borrow().iter().find(|op| op.symbol == symbol && op.arity == arity).cloned()
+    }
+
+    pub fn precedence(&self, token: &MathToken) -> Result<(usize, Assoc), String> {
+        let op = match token {
+            MathToken::BOp(symbol) => self.get(symbol, 2),
+            MathToken::UOp(symbol) => self.get(symbol, 1),
+            _ => None,
+        };
+        op.map(|op| (op.precedence, op.associativity))
+            .ok_or_else(|| format!("Undefined precedence for {:?}", token))
+    }
+
+    // Result of a registered operator, None for built-ins
+    pub(crate) fn eval(&self, symbol: &str, args: &[f64]) -> Option<f64> {
+        let eval = self.get(symbol, args.len())?.eval?;
+        Some(eval(args))
+    }
+
+    // Whether a registered operator evaluates `symbol` with `arity` operands
+    pub(crate) fn evaluates(&self, symbol: &str, arity: usize) -> bool {
+        self.get(symbol, arity).is_some_and(|op| op.eval.is_some())
+    }
+
+    // Symbols of operators added to the built-in ones, replacements included
+    pub(crate) fn is_extra(&self, symbol: &str) -> bool {
+        self.0.borrow().iter().any(|op| op.symbol == symbol && !op.builtin)
+    }
+
+    // What the tokenizer needs to read as operators
+    pub(crate) fn symbols(&self) -> Vec<String> {
+        let mut symbols: Vec<_> = self.0.borrow().iter()
+            .map(|op| op.symbol.clone())
+            .filter(|symbol| self.is_extra(symbol) && !is_word(symbol))
+            .collect();
+        symbols.sort();
+        symbols.dedup();
+        symbols
+    }
+}
@@ -0,0 +1,74 @@
+use crate::operators::OperatorTable;
+use crate::parser::{Assoc, RPNExpr, ShuntingParser};
+use crate::rpneval::MathContext;
+use crate::rpnprint::LatexOptions;
+use lexers::MathToken;
+
+fn hypot(args: &[f64]) -> f64 {
+    args[0].hypot(args[1])
+}
+
+#[test]
+fn test_hypot_operator() {
+    let mut parser = ShuntingParser::new();
+    // between + and *, right associative
+    parser.add_operator("⊕", 25, Assoc::Right, 2, hypot).unwrap();
+    let (num, op) = (|n| MathToken::Number(n), |o: &str| MathToken::BOp(o.to_string()));
+    let expr = parser.parse_expr("3 ⊕ 4 ⊕ 5").unwrap();
+    assert_eq!(expr, RPNExpr::from(vec![num(3.0), num(4.0), num(5.0), op("⊕"), op("⊕")]));
+    assert_eq!(parser.parse_expr("3⊕4⊕5"), Ok(expr.clone()));
+    assert_eq!(parser.parse_expr("1 + 3 ⊕ 2 * 2"), Ok(RPNExpr::from(vec![
+        num(1.0), num(3.0), num(2.0), num(2.0), op("*"), op("⊕"), op("+")])));
+    // expressions carry the table they were parsed with
+    let cx = MathContext::new();
+    assert_eq!(cx.eval(&expr), Ok(50f64.sqrt()));
+    assert_eq!(cx.eval(&parser.parse_expr("(3 ⊕ 4) - 5").unwrap()), Ok(0.0));
+    assert_eq!(cx.compile(&expr).and_then(|mathop| mathop.eval()), Ok(50f64.sqrt()));
+
+    // printing groups with the same precedences, and parses back
+    for input in ["3 ⊕ 4 ⊕ 5", "(3 ⊕ 4) ⊕ 5", "-(1 + x ⊕ y) ⊕ 2 * z"] {
+        let expr = parser.parse_expr(input).unwrap();
+        let printed = expr.to_infix();
+        assert_eq!(printed, input);
+        assert_eq!(parser.parse_expr(&printed), Ok(expr));
+    }
+    let expr = parser.parse_expr("(3 ⊕ 4) ⊕ 5").unwrap();
+    assert_eq!(expr.to_latex_with_style(LatexOptions::default()),
+               "\\left(3 ⊕ 4\\right) ⊕ 5");
+    // the built-in table doesn't know it, it prints as the loosest left
+    // associative operator and doesn't evaluate
+    let unknown = RPNExpr::from(expr.0.clone());
+    assert_eq!(unknown.to_infix(), "3 ⊕ 4 ⊕ 5");
+    assert_eq!(cx.eval(&unknown), Err("Unknown BOp: ⊕".to_string()));
+    assert_eq!(ShuntingParser::parse_str("3 ⊕ 4").map_err(String::from), Err("Bad token: ⊕".to_string()));
+    assert_eq!(ShuntingParser::new().parse_expr("3 ⊕ 4").map_err(String::from), Err("Bad token: ⊕".to_string()));
+}
+
+#[test]
+fn test_operator_table() {
+    // without registering anything it's the same as ShuntingParser::parse_str
+    let parser = ShuntingParser::new();
+    for input in ["3+4*2/-(1-5)^2^3", "!x && 3! - 1 >= 2", "max(1, -x)"] {
+        assert_eq!(parser.parse_expr(input), ShuntingParser::parse_str(input));
+    }
+    // built-ins can be replaced
+    let operators = OperatorTable::new();
+    operators.add_operator("^", 40, Assoc::Left, 2, |args| args[0].powf(args[1])).unwrap();
+    let parser = ShuntingParser::with_operators(operators);
+    assert_eq!(MathContext::new().eval(&parser.parse_expr("2^3^2").unwrap()), Ok(64.0));
+    // words are read from identifiers, unary and binary under the same name
+    let mut parser = ShuntingParser::new();
+    parser.add_operator("mod", 30, Assoc::Left, 2, |args| args[0].rem_euclid(args[1])).unwrap();
+    parser.add_operator("√", 50, Assoc::Right, 1, |args| args[0].sqrt()).unwrap();
+    parser.add_operator("√", 30, Assoc::Left, 2, |args| args[1].powf(1.0 / args[0])).unwrap();
+    let eval = |input: &str| MathContext::new().eval(&parser.parse_expr(input)?);
+    assert_eq!(eval("-7 mod 3"), Ok(2.0));
+    assert_eq!(eval("x mod -2"), Err("Unknown Variable: x".to_string()));
+    assert_eq!(eval("√16 + 3 √ 8"), Ok(6.0));
+    assert_eq!(parser.parse_expr("√x mod 2").unwrap().to_infix(),
+               "√x mod 2");
+    assert_eq!(parser.add_operator("?:", 1, Assoc::Left, 3, |_| 0.0),
+               Err("Operator ?: has arity 3, expected 1 or 2".to_string()));
+    assert_eq!(parser.add_operator("a b", 1, Assoc::Left, 2, |_| 0.0),
+               Err("Bad operator symbol: \"a b\"".to_string()));
+}
@@ -1,105 +1,298 @@
-use lexers::{MathToken, MathTokenizer};
+use crate::operators::OperatorTable;
+use lexers::{MathToken, MathTokenizer, NumberLocale, SourceError};
+use std::fmt;
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum Assoc {
     Left,
     Right,
 }
 
-pub fn op_precedence(mt: &MathToken) -> Result<(usize, Assoc), String> {
-    // NOTE: This can't encode relations between all tokens, just Ops.
-    // For example:
-    // In https://github.com/rodolf0/natools/blob/master/libparser/parser.c#L56-L94
-    // - unary-minus has to be < than Numbers and OParen
-    // - but OParen has to be < than unary-minus too!
-    // - At the same time, unary-minus has to be > than bin-ops (eg: +)
-    Ok(match mt {
-        MathToken::BOp(o) if o == "+" => (2, Assoc::Left),
-        MathToken::BOp(o) if o == "-" => (2, Assoc::Left),
-        MathToken::BOp(o) if o == "*" => (3, Assoc::Left),
-        MathToken::BOp(o) if o == "/" => (3, Assoc::Left),
-        MathToken::BOp(o) if o == "%" => (3, Assoc::Left),
-        MathToken::BOp(o) if o == "^" || o == "**" => (4, Assoc::Right),
-        MathToken::UOp(o) if o == "-" => (5, Assoc::Right), // unary minus
-        MathToken::UOp(o) if o == "!" => (6, Assoc::Left), // factorial
-        _ => return Err(format!("Undefined precedence for {:?}", mt)),
-    })
+// Why an expression didn't parse
+#[derive(PartialEq, Debug, Clone)]
+pub struct ShuntingError {
+    pub message: String,
+    // chars of the source as [start, end) when known
+    pub span: Option<(usize, usize)>,
 }
 
-#[derive(PartialEq, Debug, Clone)]
-pub struct RPNExpr(pub Vec<MathToken>);
+impl ShuntingError {
+    fn new(message: impl Into<String>) -> Self {
+        ShuntingError{message: message.into(), span: None}
+    }
+
+    fn at(message: impl Into<String>, span: Option<(usize, usize)>) -> Self {
+        ShuntingError{message: message.into(), span}
+    }
+}
+
+// Chars of the source a token was read from, when it came from a string
+type Span = Option<(usize, usize)>;
+
+// The tokens of `tokenizer` along with their spans
+fn spanned<I: Iterator<Item = char>>(mut tokenizer: MathTokenizer<I>)
+        -> impl Iterator<Item = (MathToken, Span)> {
+    std::iter::from_fn(move || tokenizer.next().map(|token| (token, Some(tokenizer.span()))))
+}
+
+impl fmt::Display for ShuntingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ShuntingError {}
+
+// For callers keeping String errors, eg: when also evaluating
+impl From<ShuntingError> for String {
+    fn from(error: ShuntingError) -> String {
+        error.message
+    }
+}
+
+impl SourceError for ShuntingError {
+    fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    fn span(&self) -> Option<(usize, usize)> {
+        self.span
+    }
+}
+
+// Tokens in postfix order and the operators they were parsed with, which
+// the printers and MathContext look operators up in. Expressions compare
+// equal on their tokens alone.
+#[derive(Clone)]
+pub struct RPNExpr(pub Vec<MathToken>, pub OperatorTable);
+
+impl From<Vec<MathToken>> for RPNExpr {
+    // Tokens using only the built-in operators
+    fn from(tokens: Vec<MathToken>) -> Self {
+        RPNExpr(tokens, OperatorTable::new())
+    }
+}
+
+impl PartialEq for RPNExpr {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl fmt::Debug for RPNExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("RPNExpr").field(&self.0).finish()
+    }
+}
+
+impl RPNExpr {
+    // Same as ShuntingParser::parse with the operators added to `operators`,
+    // eg: `7 mod 3`. The tokenizer reads word operators as variables or
+    // functions and doesn't know a - or ! after them is prefix.
+    pub fn parse_with_extensions(tokens: impl Iterator<Item = MathToken>,
+                                 operators: &OperatorTable) -> Result<RPNExpr, ShuntingError> {
+        Self::parse_spanned(tokens.map(|token| (token, None)), operators)
+    }
+
+    fn parse_spanned(tokens: impl Iterator<Item = (MathToken, Span)>,
+                     operators: &OperatorTable) -> Result<RPNExpr, ShuntingError> {
+        let mut prev: Option<MathToken> = None;
+        let tokens = tokens.map(|(token, span)| {
+            let after_operand = prev.as_ref().is_some_and(MathToken::ends_operand);
+            let token = match token {
+                MathToken::Variable(ref name) | MathToken::Function(ref name, _) |
+                MathToken::Unknown(ref name) | MathToken::BOp(ref name)
+                        if operators.is_extra(name) =>
+                    match (operators.get(name, 1), operators.get(name, 2)) {
+                        (Some(_), Some(_)) if after_operand => MathToken::BOp(name.clone()),
+                        (Some(_), _) => MathToken::UOp(name.clone()),
+                        _ => MathToken::BOp(name.clone()),
+                    },
+                MathToken::BOp(ref op) if op == "-" && !after_operand => MathToken::UOp(op.clone()),
+                MathToken::UOp(ref op) if op == "!" && !after_operand =>
+                    MathToken::UOp("not".to_string()),
+                token => token,
+            };
+            prev = Some(token.clone());
+            (token, span)
+        });
+        let out = shunting_yard(tokens, |token| operators.precedence(token))?;
+        Ok(RPNExpr(out, operators.clone()))
+    }
+}
 
-pub struct ShuntingParser;
+// Parses with the operators in its OperatorTable, the associated functions
+// (eg: ShuntingParser::parse_str) use the built-in ones.
+#[derive(Clone, Default)]
+pub struct ShuntingParser {
+    operators: OperatorTable,
+}
 
 impl ShuntingParser {
-    pub fn parse_str(expr: &str) -> Result<RPNExpr, String> {
-        Self::parse(&mut MathTokenizer::new(expr.chars()))
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_operators(operators: OperatorTable) -> Self {
+        ShuntingParser{operators}
+    }
+
+    pub fn operators(&self) -> &OperatorTable {
+        &self.operators
+    }
+
+    // See OperatorTable::add_operator
+    pub fn add_operator(&mut self, symbol: &str, precedence: usize, associativity: Assoc,
+                        arity: usize, eval: impl Fn(&[f64]) -> f64 + 'static)
+            -> Result<(), String> {
+        self.operators.add_operator(symbol, precedence, associativity, arity, eval)
+    }
+
+    // Like ShuntingParser::parse_str with this parser's operators
+    pub fn parse_expr(&self, expr: &str) -> Result<RPNExpr, ShuntingError> {
+        let tokens = MathTokenizer::new(expr.chars()).operators(self.operators.symbols());
+        RPNExpr::parse_spanned(spanned(tokens), &self.operators)
+    }
+
+    // Errors point at the chars of `expr` they're about
+    pub fn parse_str(expr: &str) -> Result<RPNExpr, ShuntingError> {
+        Self::parse_tokenizer(MathTokenizer::new(expr.chars()))
+    }
+
+    // Numbers and argument separators as in `locale`, see NumberLocale
+    pub fn parse_str_locale(expr: &str, locale: NumberLocale) -> Result<RPNExpr, ShuntingError> {
+        Self::parse_tokenizer(MathTokenizer::with_locale(expr.chars(), locale))
+    }
+
+    // Tokens don't know where they came from, errors have no span
+    pub fn parse(lexer: &mut impl Iterator<Item = MathToken>) -> Result<RPNExpr, ShuntingError> {
+        let operators = OperatorTable::new();
+        let out = shunting_yard(lexer.map(|token| (token, None)),
+                                |token| operators.precedence(token))?;
+        Ok(RPNExpr(out, operators))
+    }
+
+    fn parse_tokenizer<I: Iterator<Item = char>>(tokenizer: MathTokenizer<I>)
+            -> Result<RPNExpr, ShuntingError> {
+        let operators = OperatorTable::new();
+        let out = shunting_yard(spanned(tokenizer), |token| operators.precedence(token))?;
+        Ok(RPNExpr(out, operators))
+    }
+}
+
+// The span covering both `a` and `b`
+fn join(a: Span, b: Span) -> Span {
+    match (a, b) {
+        (Some(a), Some(b)) => Some((a.0.min(b.0), a.1.max(b.1))),
+        (a, b) => a.or(b),
     }
+}
+
+const MAX_HEIGHT: usize = 256;
 
-    pub fn parse(lexer: &mut impl Iterator<Item = MathToken>) -> Result<RPNExpr, String> {
+fn shunting_yard(lexer: impl Iterator<Item = (MathToken, Span)>,
+                 precedence: impl Fn(&MathToken) -> Result<(usize, Assoc), String>)
+        -> Result<Vec<MathToken>, ShuntingError> {
         let mut out = Vec::new();
-        let mut stack = Vec::new();
+        // where the tokens in `out` came from
+        let mut spans = Vec::new();
+        let mut stack: Vec<(MathToken, Span)> = Vec::new();
         let mut arity = Vec::<usize>::new();
 
-        for token in lexer {
+        for (token, span) in lexer {
             match token {
-                MathToken::Number(_) => out.push(token),
-                MathToken::Variable(_) => out.push(token),
-                MathToken::OParen => stack.push(token),
+                MathToken::Number(_) => { out.push(token); spans.push(span); },
+                MathToken::Variable(_) => { out.push(token); spans.push(span); },
+                MathToken::OParen => stack.push((token, span)),
                 MathToken::Function(_, _) => {
-                    stack.push(token);
+                    stack.push((token, span));
                     arity.push(1);
                 }
                 MathToken::Comma | MathToken::CParen => {
                     // Flush stack to output queue until open paren
-                    loop {
+                    let paren = loop {
                         match stack.pop() {
                             // Only advance until we find the matching open paren
-                            Some(MathToken::OParen) => break,
-                            Some(any) => out.push(any),
-                            None => return Err("Missing Opening Paren".to_string()),
+                            Some((MathToken::OParen, paren)) => break paren,
+                            Some((any, at)) => { out.push(any); spans.push(at); },
+                            None => return Err(ShuntingError::at("Missing Opening Paren", span)),
                         }
-                    }
+                    };
                     if token == MathToken::Comma {
                         // Keep track of function arity based on number of commas
-                        stack.push(MathToken::OParen); // put back OParen if reading Comma
+                        stack.push((MathToken::OParen, paren)); // put back OParen if reading Comma
                         match arity.last_mut() {
                             Some(a) => *a += 1,
-                            None => return Err("Comma outside function arglist".to_string())
+                            None => return Err(ShuntingError::at("Comma outside function arglist", span))
                         }
-                    } else if let Some(MathToken::Function(fname, _)) = stack.last() {
+                    } else if let Some((MathToken::Function(fname, _), at)) = stack.last() {
                         // token is CParen. Popped everything up to OParen. Check fn call.
-                        out.push(MathToken::Function(fname.clone(), arity.pop().unwrap()));
+                        out.push(MathToken::Function(fname.clone(), arity.pop().unwrap_or(0)));
+                        spans.push(join(*at, span));
                         stack.pop(); // pop the function we just shifted out
                     }
                 }
                 MathToken::UOp(_) | MathToken::BOp(_) => {
-                    let (input_token_prec, input_token_assoc) = op_precedence(&token)?;
+                    let (input_token_prec, input_token_assoc) = precedence(&token)
+                        .map_err(|e| ShuntingError::at(e, span))?;
                     // Flush stack while its precedence is lower than input or reach OParen
-                    while let Some(stack_top) = stack.last() {
+                    while let Some((stack_top, at)) = stack.last() {
                         if stack_top == &MathToken::OParen {
                             break;
                         }
-                        let (stack_top_prec, _) = op_precedence(stack_top)?;
+                        let (stack_top_prec, _) = precedence(stack_top)
+                            .map_err(|e| ShuntingError::at(e, *at))?;
                         if stack_top_prec < input_token_prec || (
                             stack_top_prec == input_token_prec &&
                             input_token_assoc == Assoc::Right) {
                             break;
                         }
-                        out.push(stack.pop().unwrap());
+                        if let Some((top, at)) = stack.pop() {
+                            out.push(top);
+                            spans.push(at);
+                        }
                     }
-                    stack.push(token);
+                    stack.push((token, span));
                 }
-                MathToken::Quantity(_, _, _) => return Err("Can't handle quantities".to_string()),
-                MathToken::Unknown(lexeme) => return Err(format!("Bad token: {}", lexeme)),
+                MathToken::Quantity(_, _, _) =>
+                    return Err(ShuntingError::at("Can't handle quantities", span)),
+                MathToken::Unknown(lexeme) =>
+                    return Err(ShuntingError::at(format!("Bad token: {}", lexeme), span)),
             }
         }
-        while let Some(top) = stack.pop() {
+        while let Some((top, at)) = stack.pop() {
             match top {
-                MathToken::OParen => return Err("Missing Closing Paren".to_string()),
-                token => out.push(token),
+                MathToken::OParen => return Err(ShuntingError::at("Missing Closing Paren", at)),
+                token => { out.push(token); spans.push(at); },
             }
         }
-        Ok(RPNExpr(out))
-    }
+        // Operators short of operands (eg: "2 *", "max()") or operands left
+        // over (eg: "1 2") would only fail later when printing or evaluating.
+        // Printers recurse on the expression tree so its height is bounded.
+        // Subtrees are kept as their height and the source they span.
+        let mut heights: Vec<(usize, Span)> = Vec::new();
+        for (token, span) in out.iter().zip(spans) {
+            let pops = match token {
+                MathToken::Function(_, arity) => *arity,
+                MathToken::UOp(_) => 1,
+                MathToken::BOp(_) => 2,
+                _ => 0,
+            };
+            let start = heights.len().checked_sub(pops).ok_or_else(||
+                ShuntingError::at(format!("Missing operands for {:?}", token), span))?;
+            let (height, span) = heights.split_off(start).into_iter()
+                .fold((0, span), |(height, span), (h, at)| (height.max(h), join(span, at)));
+            if height + 1 > MAX_HEIGHT {
+                return Err(ShuntingError::at(
+                    format!("Expression nested deeper than {}", MAX_HEIGHT), span));
+            }
+            heights.push((height + 1, span));
+        }
+        match heights.len() {
+            0 => Err(ShuntingError::new("Empty expression")),
+            1 => Ok(out),
+            // point at the first value nothing used
+            n => Err(ShuntingError::at(
+                format!("Expression leaves {} values, expected 1", n), heights[1].1)),
+        }
 }
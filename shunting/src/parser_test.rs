@@ -1,5 +1,8 @@
-use crate::parser::{RPNExpr, ShuntingParser};
+use crate::operators::OperatorTable;
+use crate::parser::{Assoc, RPNExpr, ShuntingParser};
+use crate::rpneval::{MathContext, MathOp};
 use lexers::MathToken;
+use lexers::MathTokenizer;
 
 #[test]
 fn test_associativity() {
@@ -11,7 +14,7 @@ fn test_associativity() {
         MathToken::BOp("^".to_string()),
         MathToken::BOp("^".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("2*3*4");
     let expect = vec![
         MathToken::Number(2.0),
@@ -20,7 +23,7 @@ fn test_associativity() {
         MathToken::Number(4.0),
         MathToken::BOp("*".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
 }
 
 #[test]
@@ -33,7 +36,7 @@ fn test_precedence() {
         MathToken::BOp("*".to_string()),
         MathToken::BOp("+".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("2*3+4");
     let expect = vec![
         MathToken::Number(2.0),
@@ -42,7 +45,7 @@ fn test_precedence() {
         MathToken::Number(4.0),
         MathToken::BOp("+".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("2+3*4^5");
     let expect = vec![
         MathToken::Number(2.0),
@@ -53,7 +56,7 @@ fn test_precedence() {
         MathToken::BOp("*".to_string()),
         MathToken::BOp("+".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("2^3+4*5");
     let expect = vec![
         MathToken::Number(2.0),
@@ -64,7 +67,7 @@ fn test_precedence() {
         MathToken::BOp("*".to_string()),
         MathToken::BOp("+".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
 }
 
 #[test]
@@ -76,7 +79,7 @@ fn test_unary_ops() {
         MathToken::UOp("-".to_string()),
         MathToken::BOp("/".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("-2/1");
     let expect = vec![
         MathToken::Number(2.0),
@@ -84,14 +87,14 @@ fn test_unary_ops() {
         MathToken::Number(1.0),
         MathToken::BOp("/".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("-2!");
     let expect = vec![
         MathToken::Number(2.0),
         MathToken::UOp("!".to_string()),
         MathToken::UOp("-".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("-2^3");
     let expect = vec![
         MathToken::Number(2.0),
@@ -99,7 +102,7 @@ fn test_unary_ops() {
         MathToken::Number(3.0),
         MathToken::BOp("^".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("2^-3");
     let expect = vec![
         MathToken::Number(2.0),
@@ -107,7 +110,7 @@ fn test_unary_ops() {
         MathToken::UOp("-".to_string()),
         MathToken::BOp("^".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("2^3!");
     let expect = vec![
         MathToken::Number(2.0),
@@ -115,7 +118,7 @@ fn test_unary_ops() {
         MathToken::UOp("!".to_string()),
         MathToken::BOp("^".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("(-2)^3");
     let expect = vec![
         MathToken::Number(2.0),
@@ -123,7 +126,7 @@ fn test_unary_ops() {
         MathToken::Number(3.0),
         MathToken::BOp("^".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("-(1-5)");
     let expect = vec![
         MathToken::Number(1.0),
@@ -131,7 +134,7 @@ fn test_unary_ops() {
         MathToken::BOp("-".to_string()),
         MathToken::UOp("-".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
 }
 
 #[test]
@@ -144,7 +147,7 @@ fn test_parens() {
         MathToken::Number(4.0),
         MathToken::BOp("*".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("2*(3*4)");
     let expect = vec![
         MathToken::Number(2.0),
@@ -153,7 +156,7 @@ fn test_parens() {
         MathToken::BOp("*".to_string()),
         MathToken::BOp("*".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("(2^3)^4");
     let expect = vec![
         MathToken::Number(2.0),
@@ -162,7 +165,7 @@ fn test_parens() {
         MathToken::Number(4.0),
         MathToken::BOp("^".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("((2+3)*4)^5");
     let expect = vec![
         MathToken::Number(2.0),
@@ -173,7 +176,7 @@ fn test_parens() {
         MathToken::Number(5.0),
         MathToken::BOp("^".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
 }
 
 #[test]
@@ -195,7 +198,7 @@ fn test_mixed_ops() {
         MathToken::BOp("/".to_string()),
         MathToken::BOp("+".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("3.4e-2 * sin(x)/(7! % -4) * max(2, x)");
     let expect = vec![
         MathToken::Number(3.4e-2),
@@ -213,7 +216,7 @@ fn test_mixed_ops() {
         MathToken::Function("max".to_string(), 2),
         MathToken::BOp("*".to_string()),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("sqrt(-(1-x^2) / (1 + x^2))");
     let expect = vec![
         MathToken::Number(1.0),
@@ -230,23 +233,88 @@ fn test_mixed_ops() {
         MathToken::BOp("/".to_string()),
         MathToken::Function("sqrt".to_string(), 1),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
+}
+
+#[test]
+fn test_logical_ops() {
+    let (num, op) = (|n| MathToken::Number(n), |o: &str| MathToken::BOp(o.to_string()));
+    let not = || MathToken::UOp("not".to_string());
+    assert_eq!(ShuntingParser::parse_str("!0"), Ok(RPNExpr::from(vec![num(0.0), not()])));
+    assert_eq!(ShuntingParser::parse_str("!(1 > 2)"), Ok(RPNExpr::from(vec![
+        num(1.0), num(2.0), op(">"), not()])));
+    // ! binds tighter than comparisons, which bind tighter than && and ||
+    assert_eq!(ShuntingParser::parse_str("!1 > 2 && 3 <= 4 || 0"), Ok(RPNExpr::from(vec![
+        num(1.0), not(), num(2.0), op(">"), num(3.0), num(4.0), op("<="), op("&&"),
+        num(0.0), op("||")])));
+    assert_eq!(ShuntingParser::parse_str("!x && 1 + 1 == 2"), Ok(RPNExpr::from(vec![
+        MathToken::Variable("x".to_string()), not(), num(1.0), num(1.0), op("+"),
+        num(2.0), op("=="), op("&&")])));
+    // postfix is still factorial
+    assert_eq!(ShuntingParser::parse_str("!3! - 1"), Ok(RPNExpr::from(vec![
+        num(3.0), MathToken::UOp("!".to_string()), not(), num(1.0), op("-")])));
 }
 
 #[test]
 fn bad_parse() {
-    let rpn = ShuntingParser::parse_str("1-x^2)");
+    let rpn = ShuntingParser::parse_str("1-x^2)").map_err(String::from);
     assert_eq!(rpn, Err("Missing Opening Paren".to_string()));
-    let rpn = ShuntingParser::parse_str("max 4, 6, 4)");
+    let rpn = ShuntingParser::parse_str("max 4, 6, 4)").map_err(String::from);
     assert_eq!(rpn, Err("Missing Opening Paren".to_string()));
-    let rpn = ShuntingParser::parse_str("sqrt(-(1-x^2)");
+    let rpn = ShuntingParser::parse_str("sqrt(-(1-x^2)").map_err(String::from);
     assert_eq!(rpn, Err("Missing Closing Paren".to_string()));
-    let rpn = ShuntingParser::parse_str("(2, 3)");
+    let rpn = ShuntingParser::parse_str("(2, 3)").map_err(String::from);
     assert_eq!(rpn, Err("Comma outside function arglist".to_string()));
-    let rpn = ShuntingParser::parse_str("3 # 4");
+    let rpn = ShuntingParser::parse_str("3 # 4").map_err(String::from);
     assert_eq!(rpn, Err("Bad token: #".to_string()));
 }
 
+#[test]
+fn crash_regressions() {
+    // inputs that used to panic when parsed, printed or evaluated
+    let parse = |input: &str| ShuntingParser::parse_str(input).unwrap_err().message;
+    assert_eq!(parse("4^"), "Missing operands for BOp(\"^\")");
+    assert_eq!(parse("*"), "Missing operands for BOp(\"*\")");
+    assert_eq!(parse("max()"), "Missing operands for Function(\"max\", 1)");
+    assert_eq!(parse("1 2"), "Expression leaves 2 values, expected 1");
+    assert_eq!(parse(" "), "Empty expression");
+    assert_eq!(parse("1i 8"), "Bad token: 1i");
+    assert_eq!(parse(&format!("{}1", "-".repeat(300))), "Expression nested deeper than 256");
+    let corpus = ["(+)", "!!", "x^^^", "-0 !9 ", "1e<3^(8!2)", "13 ^^5x-", "9+-%6",
+                  "sqrt(-(1-x^2)", "normal(0, -1)", "uniform(1, 0)", "lognormal(0, 1e308*10)",
+                  "sin(normal(0, 1), 2)", "max(,)", "((((1))))", "-----1!"];
+    let cx = MathContext::new();
+    cx.setvar("x", MathOp::Number(2.0));
+    for input in corpus {
+        if let Ok(rpn) = ShuntingParser::parse_str(input) {
+            let _ = (rpn.to_string(), rpn.to_latex(), cx.eval(&rpn), rpn.normalize());
+            if let Ok(op) = cx.compile(&rpn) {
+                let _ = op.histogram::<4>(8);
+            }
+        }
+    }
+}
+
+#[test]
+fn error_spans() {
+    let span = |input: &str| ShuntingParser::parse_str(input).unwrap_err().span;
+    assert_eq!(span("2 + $ 3"), Some((4, 5)));
+    assert_eq!(span("1 + 2)"), Some((5, 6)));
+    assert_eq!(span("x + (1"), Some((4, 5)));
+    assert_eq!(span("3 -"), Some((2, 3)));
+    assert_eq!(span("1, 2"), Some((1, 2)));
+    assert_eq!(span("5 km"), Some((0, 4)));
+    // leftover values span their whole subexpression
+    assert_eq!(span("1 sin(x * 2)"), Some((2, 12)));
+    assert_eq!(span(""), None);
+    // tokens from an iterator don't know their source
+    let mut tokens = MathTokenizer::new("2 + $ 3".chars());
+    assert_eq!(ShuntingParser::parse(&mut tokens).unwrap_err().span, None);
+    let mut parser = ShuntingParser::new();
+    parser.add_operator("mod", 5, Assoc::Left, 2, |args| args[0] % args[1]).unwrap();
+    assert_eq!(parser.parse_expr("7 mod $").unwrap_err().span, Some((6, 7)));
+}
+
 #[test]
 fn test_functions() {
     let rpn = ShuntingParser::parse_str("sin(pi)");
@@ -254,14 +322,14 @@ fn test_functions() {
         MathToken::Variable("pi".to_string()),
         MathToken::Function("sin".to_string(), 1),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("max(2, x)");
     let expect = vec![
         MathToken::Number(2.0),
         MathToken::Variable("x".to_string()),
         MathToken::Function("max".to_string(), 2),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
     let rpn = ShuntingParser::parse_str("sum(i , 0, gcd(24, 8))");
     let expect = vec![
         MathToken::Variable("i".to_string()),
@@ -271,5 +339,30 @@ fn test_functions() {
         MathToken::Function("gcd".to_string(), 2),
         MathToken::Function("sum".to_string(), 3),
     ];
-    assert_eq!(rpn, Ok(RPNExpr(expect)));
+    assert_eq!(rpn, Ok(RPNExpr::from(expect)));
+}
+
+#[test]
+fn test_extensions() {
+    let ops = OperatorTable::new();
+    ops.define("mod", 25, Assoc::Left, 2).unwrap();
+    ops.define("not", 10, Assoc::Right, 1).unwrap();
+    let parse = |input: &str| RPNExpr::parse_with_extensions(MathTokenizer::new(input.chars()), &ops);
+    let (num, op) = (|n| MathToken::Number(n), |o: &str| MathToken::BOp(o.to_string()));
+    // tighter than + but looser than *
+    assert_eq!(parse("2 + 7 mod 3 * 2"), Ok(RPNExpr::from(vec![
+        num(2.0), num(7.0), num(3.0), num(2.0), op("*"), op("mod"), op("+")])));
+    assert_eq!(parse("7 mod 4 mod 3"), Ok(RPNExpr::from(vec![
+        num(7.0), num(4.0), op("mod"), num(3.0), op("mod")])));
+    assert_eq!(parse("7 mod (2 + 1)"), parse("7 mod(2 + 1)"));
+    assert_eq!(parse("7 mod -2"), Ok(RPNExpr::from(vec![
+        num(7.0), num(2.0), MathToken::UOp("-".to_string()), op("mod")])));
+    assert_eq!(parse("not x + 1"), Ok(RPNExpr::from(vec![
+        MathToken::Variable("x".to_string()), num(1.0), op("+"), MathToken::UOp("not".to_string())])));
+    // without extensions it's the same as ShuntingParser
+    assert_eq!(RPNExpr::parse_with_extensions(MathTokenizer::new("1 - 2^x".chars()),
+                                              &OperatorTable::new()),
+               ShuntingParser::parse_str("1 - 2^x"));
+    assert_eq!(ops.define("if", 1, Assoc::Left, 3),
+               Err("Operator if has arity 3, expected 1 or 2".to_string()));
 }
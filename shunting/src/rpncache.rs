@@ -0,0 +1,111 @@
+use crate::parser::RPNExpr;
+use lexers::MathToken;
+
+// Text format to cache parsed expressions: a version header then one token
+// per line, fields separated by tabs, eg: "fn\tmax\t2". Bump the version
+// whenever the token set or the way RPNExpr is evaluated changes.
+const CACHE_HEADER: &str = "shunting-rpn";
+const CACHE_VERSION: u32 = 1;
+
+fn escape(field: &str) -> String {
+    field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+fn unescape(field: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        out.push(match (c, c == '\\') {
+            (_, false) => c,
+            (_, true) => match chars.next() {
+                Some('\\') => '\\',
+                Some('t') => '\t',
+                Some('n') => '\n',
+                other => return Err(format!("Bad escape in RPNExpr cache: \\{:?}", other)),
+            },
+        });
+    }
+    Ok(out)
+}
+
+fn encode(token: &MathToken) -> String {
+    match token {
+        MathToken::Unknown(s) => format!("unknown\t{}", escape(s)),
+        // f64 Display prints the shortest string that reads back the same
+        MathToken::Number(n) => format!("num\t{}", n),
+        MathToken::Quantity(n, prefix, unit) =>
+            format!("qty\t{}\t{}\t{}", n, escape(prefix), escape(unit)),
+        MathToken::Variable(v) => format!("var\t{}", escape(v)),
+        MathToken::Function(fname, arity) => format!("fn\t{}\t{}", escape(fname), arity),
+        MathToken::UOp(op) => format!("uop\t{}", escape(op)),
+        MathToken::BOp(op) => format!("bop\t{}", escape(op)),
+        MathToken::OParen => "(".to_string(),
+        MathToken::CParen => ")".to_string(),
+        MathToken::Comma => ",".to_string(),
+    }
+}
+
+fn decode(line: &str) -> Result<MathToken, String> {
+    let fields: Vec<_> = line.split('\t').collect();
+    let number = |n: &str| n.parse::<f64>()
+        .map_err(|_| format!("Bad number in RPNExpr cache: {}", n));
+    Ok(match fields[..] {
+        ["unknown", s] => MathToken::Unknown(unescape(s)?),
+        ["num", n] => MathToken::Number(number(n)?),
+        ["qty", n, prefix, unit] =>
+            MathToken::Quantity(number(n)?, unescape(prefix)?, unescape(unit)?),
+        ["var", v] => MathToken::Variable(unescape(v)?),
+        ["fn", fname, arity] => MathToken::Function(unescape(fname)?, arity.parse()
+            .map_err(|_| format!("Bad arity in RPNExpr cache: {}", arity))?),
+        ["uop", op] => MathToken::UOp(unescape(op)?),
+        ["bop", op] => MathToken::BOp(unescape(op)?),
+        ["("] => MathToken::OParen,
+        [")"] => MathToken::CParen,
+        [","] => MathToken::Comma,
+        _ => return Err(format!("Bad token in RPNExpr cache: {:?}", line)),
+    })
+}
+
+impl RPNExpr {
+    // Serialize to a versioned text format that from_cache reads back
+    pub fn to_cache(&self) -> String {
+        let mut out = format!("{} {}\n", CACHE_HEADER, CACHE_VERSION);
+        for token in &self.0 {
+            out += &encode(token);
+            out.push('\n');
+        }
+        out
+    }
+
+    // Load an expression written by to_cache. Files from other versions or
+    // that don't reduce to a single value are rejected, not mis-evaluated.
+    pub fn from_cache(cache: &str) -> Result<RPNExpr, String> {
+        let mut lines = cache.lines();
+        let header = lines.next().unwrap_or("");
+        match header.split_once(' ') {
+            Some((CACHE_HEADER, version)) if version == CACHE_VERSION.to_string() => (),
+            Some((CACHE_HEADER, version)) => return Err(format!(
+                "Incompatible RPNExpr cache version: {}, expected {}", version, CACHE_VERSION)),
+            _ => return Err(format!("Not an RPNExpr cache: {:?}", header)),
+        }
+        let tokens = lines.map(decode).collect::<Result<Vec<_>, _>>()?;
+        // Check operands so a truncated file doesn't evaluate to something else
+        let mut depth = 0usize;
+        for token in &tokens {
+            let (pops, pushes) = match token {
+                MathToken::Number(_) | MathToken::Quantity(..) | MathToken::Variable(_) => (0, 1),
+                MathToken::Function(_, arity) => (*arity, 1),
+                MathToken::UOp(_) => (1, 1),
+                MathToken::BOp(_) => (2, 1),
+                _ => return Err(format!("Unexpected token in RPNExpr cache: {:?}", token)),
+            };
+            depth = depth.checked_sub(pops)
+                .ok_or(format!("Missing operands in RPNExpr cache for {:?}", token))? + pushes;
+        }
+        if depth != 1 {
+            return Err(format!("RPNExpr cache leaves {} values, expected 1", depth));
+        }
+        // operators are looked up by name, registered ones aren't saved
+        Ok(RPNExpr::from(tokens))
+    }
+}
@@ -0,0 +1,48 @@
+use crate::parser::{RPNExpr, ShuntingParser};
+use crate::rpneval::MathContext;
+use lexers::MathToken;
+
+#[test]
+fn test_cache_roundtrip() {
+    let cx = MathContext::new();
+    cx.setvar("x", crate::MathOp::Number(0.1));
+    for input in ["3+4*2/-(1-5)^2^3", "max(1, x, 2, min(x, 3)) - 2.5e-3 % 7!",
+                  "atan2(x, 1/3) ** 0.5", "nCr(10, 3) + sin(pi * x)"] {
+        let expr = ShuntingParser::parse_str(input).unwrap();
+        let cache = expr.to_cache();
+        let loaded = RPNExpr::from_cache(&cache).unwrap();
+        assert_eq!(loaded, expr);
+        assert_eq!(loaded.to_string(), expr.to_string());
+        assert_eq!(cx.eval(&loaded), cx.eval(&expr));
+        assert_eq!(loaded.to_cache(), cache);
+    }
+    let expr = ShuntingParser::parse_str("max(1, 2, 3) - -x").unwrap();
+    assert_eq!(expr.to_cache(), "shunting-rpn 1\nnum\t1\nnum\t2\nnum\t3\n\
+                                 fn\tmax\t3\nvar\tx\nuop\t-\nbop\t-\n");
+    // other tokens and awkward strings
+    let expr = RPNExpr::from(vec![
+        MathToken::Quantity(30.0, "k".to_string(), "m".to_string()),
+        MathToken::Quantity(1e-300, "".to_string(), "s".to_string()),
+        MathToken::BOp("/".to_string()),
+        MathToken::Variable("a\tb\\n".to_string()),
+        MathToken::BOp("*".to_string()),
+    ]);
+    assert_eq!(RPNExpr::from_cache(&expr.to_cache()), Ok(expr));
+}
+
+#[test]
+fn test_cache_rejects() {
+    let cache = ShuntingParser::parse_str("1 + 2").unwrap().to_cache();
+    let load = |cache: &str| RPNExpr::from_cache(cache).unwrap_err();
+    assert_eq!(load(&cache.replace("rpn 1", "rpn 2")),
+               "Incompatible RPNExpr cache version: 2, expected 1");
+    assert_eq!(load("1 + 2"), "Not an RPNExpr cache: \"1 + 2\"");
+    assert_eq!(load(""), "Not an RPNExpr cache: \"\"");
+    assert_eq!(load(&cache.replace("bop\t+\n", "")), "RPNExpr cache leaves 2 values, expected 1");
+    assert_eq!(load(&cache.replace("num\t2\n", "")),
+               "Missing operands in RPNExpr cache for BOp(\"+\")");
+    assert_eq!(load(&cache.replace("num\t2", "num\ttwo")), "Bad number in RPNExpr cache: two");
+    assert_eq!(load(&cache.replace("bop\t+", "op\t+")), "Bad token in RPNExpr cache: \"op\\t+\"");
+    assert_eq!(load(&cache.replace("bop\t+", "(")), "Unexpected token in RPNExpr cache: OParen");
+    assert_eq!(load(&cache.replace("num\t2", "fn\tabs\t-1")), "Bad arity in RPNExpr cache: -1");
+}
@@ -1,9 +1,42 @@
 use crate::parser::RPNExpr;
-use lexers::MathToken;
+use lexers::{did_you_mean, MathToken, SourceError};
 use std::collections::HashMap;
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::fmt;
 
+#[derive(PartialEq, Debug, Clone)]
+pub enum EvalError {
+    UnknownVariable(String),
+    UnknownOp(String),
+    UnexpectedToken(MathToken),
+    MissingOperands,
+    DivisionByZero,
+    Overflow,
+    NaN,
+    NotRational,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::UnknownVariable(v) => write!(f, "Unknown Variable: {}", v),
+            EvalError::UnknownOp(op) => write!(f, "Unknown Op: {}", op),
+            EvalError::UnexpectedToken(t) => write!(f, "Unexpected token for RPN eval: {:?}", t),
+            EvalError::MissingOperands => write!(f, "Missing operands"),
+            EvalError::DivisionByZero => write!(f, "Division by zero"),
+            EvalError::Overflow => write!(f, "Overflow"),
+            EvalError::NaN => write!(f, "Result is not a number"),
+            EvalError::NotRational => write!(f, "Result is not rational"),
+        }
+    }
+}
+
+impl SourceError for EvalError {
+    fn message(&self) -> String {
+        self.to_string()
+    }
+}
 
 pub trait RandomVariable {
     fn eval(&self) -> f64;
@@ -22,12 +55,14 @@ pub enum MathOp {
     Dynamic(Rc<dyn Fn() -> Result<f64, String>>),
 }
 
-impl RandomVariable for MathOp {
-    fn eval(&self) -> f64 {
+impl MathOp {
+    // The value or a sample of it. Dynamic ones can fail, eg: a function
+    // on a random variable sampled out of its domain.
+    pub fn eval(&self) -> Result<f64, String> {
         match self {
-            MathOp::Number(n) => *n,
-            MathOp::RandVar(r) => r.eval(),
-            MathOp::Dynamic(f) => f().unwrap(),
+            MathOp::Number(n) => Ok(*n),
+            MathOp::RandVar(r) => Ok(r.eval()),
+            MathOp::Dynamic(f) => f(),
         }
     }
 }
@@ -40,9 +75,11 @@ pub struct Histogram<const BUCKETS: usize> {
 }
 
 impl MathOp {
-    pub fn histogram<const BUCKETS: usize>(&self, samples: usize) -> Histogram<BUCKETS> {
+    // Fails with the first sample that does
+    pub fn histogram<const BUCKETS: usize>(&self, samples: usize)
+            -> Result<Histogram<BUCKETS>, String> {
         // collect samples from random variable
-        let data: Vec<_> = (0..samples).map(|_| self.eval()).collect();
+        let data = (0..samples).map(|_| self.eval()).collect::<Result<Vec<_>, _>>()?;
         // extract info from data to build histogram
         let (min, max) = data.iter().fold((f64::MAX, f64::MIN), |(min, max), &x| {
             (min.min(x), max.max(x))
@@ -53,10 +90,11 @@ impl MathOp {
         for bucket in data.into_iter().map(|x| (x - min) / bucket_size) {
             histogram.buckets[bucket as usize] += 1;
         }
-        histogram
+        Ok(histogram)
     }
 }
 
+// Variables to evaluate with, operators come in each RPNExpr's table
 pub struct MathContext(Rc<RefCell<HashMap<String, MathOp>>>);
 
 impl MathContext {
@@ -72,6 +110,28 @@ impl MathContext {
         self.0.borrow_mut().insert(name.to_string(), value);
     }
 
+    // Sorted names of the defined variables (constants included)
+    pub fn variables(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.0.borrow().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn functions(&self) -> &'static [&'static str] {
+        &FUNCTION_NAMES
+    }
+
+    fn unknown_variable(&self, v: &str) -> String {
+        let names = self.variables();
+        format!("Unknown Variable: {}{}", v, did_you_mean(v, names.iter().map(|n| n.as_str())))
+    }
+
+    // Checked before evaluating args so typos don't look like wrong arity
+    fn known_function(&self, fname: &str) -> Result<&'static Function, String> {
+        function(fname).ok_or_else(|| format!("Unknown Function: {}{}",
+                                              fname, did_you_mean(fname, FUNCTION_NAMES)))
+    }
+
     pub fn eval(&self, rpn: &RPNExpr) -> Result<f64, String> {
         let mut operands = Vec::new();
 
@@ -80,13 +140,17 @@ impl MathContext {
                 MathToken::Number(num) => operands.push(*num),
                 MathToken::Variable(ref v) => operands.push(
                     match self.0.borrow().get(v) {
-                        Some(mathop) => mathop.eval(),
-                        None => return Err(format!("Unknown Variable: {}", v)),
+                        Some(mathop) => mathop.eval()?,
+                        None => return Err(self.unknown_variable(v)),
                     }
                 ),
                 MathToken::BOp(op) => {
                     let rhs = operands.pop().ok_or("Missing operands")?;
                     let lhs = operands.pop().ok_or("Missing operands")?;
+                    if let Some(value) = rpn.1.eval(op, &[lhs, rhs]) {
+                        operands.push(value);
+                        continue;
+                    }
                     operands.push(match &op[..] {
                         "+" => lhs + rhs,
                         "-" => lhs - rhs,
@@ -94,25 +158,29 @@ impl MathContext {
                         "/" => lhs / rhs,
                         "%" => lhs % rhs,
                         "^" | "**" => lhs.powf(rhs),
-                        _ => return Err(format!("Unknown BOp: {}", op)),
+                        _ => predicate(op, lhs, rhs).ok_or(format!("Unknown BOp: {}", op))?,
                     });
                 }
                 MathToken::UOp(op) => {
                     let arg = operands.pop().ok_or("Missing operands")?;
+                    if let Some(value) = rpn.1.eval(op, &[arg]) {
+                        operands.push(value);
+                        continue;
+                    }
                     operands.push(match &op[..] {
                         "-" => -arg,
+                        "not" => truth(arg == 0.0),
                         "!" => libm::tgamma(arg + 1.0),
                         _ => return Err(format!("Unknown UOp: {}", op)),
                     });
                 }
                 MathToken::Function(fname, arity) => {
+                    let function = self.known_function(fname)?;
                     if *arity > operands.len() {
                         return Err(format!("Missing args for function {}", fname));
                     }
                     let args: Vec<_> = operands.split_off(operands.len() - arity);
-                    operands.push(
-                        eval_fn(fname, &args).or_else::<String, _>(
-                            |_| Ok(build_rv(fname, &args)?.eval()))?);
+                    operands.push(function.call(&args)?.eval()?);
                 }
                 _ => return Err(format!("Unexpected token for RPN eval: {:?}", token)),
             }
@@ -126,22 +194,26 @@ impl MathContext {
             match token {
                 MathToken::Number(n) => stack.push(MathOp::Number(*n)),
                 MathToken::Variable(v) => stack.push(
-                    self.0.borrow().get(v).ok_or(format!("Unknown variable: {}", v))?.clone()),
+                    self.0.borrow().get(v).ok_or_else(|| self.unknown_variable(v))?.clone()),
                 MathToken::BOp(op) => {
                     let rhs = stack.pop().ok_or(format!("Missing operands for {}", op))?;
                     let lhs = stack.pop().ok_or(format!("Missing operands for {}", op))?;
                     let dynamic = !(
                         matches!(rhs, MathOp::Number(_)) && matches!(lhs, MathOp::Number(_)));
-                    let op = op.clone();
+                    let (op, operators) = (op.clone(), rpn.1.clone());
                     let eval = move || {
+                        let (lhs, rhs) = (lhs.eval()?, rhs.eval()?);
+                        if let Some(value) = operators.eval(&op, &[lhs, rhs]) {
+                            return Ok(value);
+                        }
                         Ok(match op.as_str() {
-                            "+" => lhs.eval() + rhs.eval(),
-                            "-" => lhs.eval() - rhs.eval(),
-                            "*" => lhs.eval() * rhs.eval(),
-                            "/" => lhs.eval() / rhs.eval(),
-                            "%" => lhs.eval() % rhs.eval(),
-                            "^" | "**" => lhs.eval().powf(rhs.eval()),
-                            _ => return Err(format!("Unknown BOp: {}", op)),
+                            "+" => lhs + rhs,
+                            "-" => lhs - rhs,
+                            "*" => lhs * rhs,
+                            "/" => lhs / rhs,
+                            "%" => lhs % rhs,
+                            "^" | "**" => lhs.powf(rhs),
+                            _ => predicate(&op, lhs, rhs).ok_or(format!("Unknown BOp: {}", op))?,
                         })
                     };
                     stack.push(if dynamic {
@@ -153,11 +225,16 @@ impl MathContext {
                 MathToken::UOp(op) => {
                     let arg = stack.pop().ok_or(format!("Missing operands for {}", op))?;
                     let dynamic = !matches!(arg, MathOp::Number(_));
-                    let op = op.clone();
+                    let (op, operators) = (op.clone(), rpn.1.clone());
                     let eval = move || {
+                        let arg = arg.eval()?;
+                        if let Some(value) = operators.eval(&op, &[arg]) {
+                            return Ok(value);
+                        }
                         Ok(match op.as_str() {
-                            "-" => -arg.eval(),
-                            "!" => libm::tgamma(arg.eval() + 1.0),
+                            "-" => -arg,
+                            "not" => truth(arg == 0.0),
+                            "!" => libm::tgamma(arg + 1.0),
                             _ => return Err(format!("Unknown UOp: {}", op)),
                         })
                     };
@@ -168,22 +245,20 @@ impl MathContext {
                     });
                 }
                 MathToken::Function(fname, arity) => {
+                    let function = self.known_function(fname)?;
                     if *arity > stack.len() {
                         return Err(format!("Missing args for {}", fname));
                     }
                     let args: Vec<_> = stack.split_off(stack.len() - arity);
-                    let dynamic = !args.iter().all(|arg| matches!(arg, MathOp::Number(_)));
-                    let fname = fname.clone();
+                    // samples (eg: rand(10)) aren't fixed even on numbers
+                    let dynamic = matches!(function.body, Body::Sample(_)) ||
+                        !args.iter().all(|arg| matches!(arg, MathOp::Number(_)));
                     let eval = move || -> Result<MathOp, String> {
-                        let args: Vec<_> = args.iter().map(|v| v.eval()).collect();
-                        Ok(if let Ok(rv) = build_rv(&fname, &args) {
-                            MathOp::RandVar(rv)
-                        } else {
-                            MathOp::Number(eval_fn(&fname, &args)?)
-                        })
+                        let args = args.iter().map(|v| v.eval()).collect::<Result<Vec<_>, _>>()?;
+                        function.call(&args)
                     };
                     stack.push(if dynamic {
-                        MathOp::Dynamic(Rc::new(move || eval().map(|v| v.eval())))
+                        MathOp::Dynamic(Rc::new(move || eval()?.eval()))
                     } else {
                         eval()?
                     });
@@ -191,39 +266,274 @@ impl MathContext {
                 _ => return Err(format!("Unexpected token for RPN compile: {:?}", token)),
             }
         }
-        assert_eq!(stack.len(), 1);
-        Ok(stack.pop().ok_or("Failed to compile RPNExpr")?)
-    }
-}
-
-fn eval_fn(fname: &str, args: &[f64]) -> Result<f64, String> {
-    Ok(match fname {
-        "abs" if args.len() == 1 => args[0].abs(),
-        "atan2" if args.len() == 2 => args[0].atan2(args[1]),
-        "cos" if args.len() == 1 => args[0].cos(),
-        "log" if args.len() == 1 => args[0].log10(),
-        "max" if !args.is_empty() => args.iter().fold(args[0], |a, &b| a.max(b)),
-        "min" if !args.is_empty() => args.iter().fold(args[0], |a, &b| a.min(b)),
-        // Order not important
-        "nCr" if args.len() == 2 => funcs::combinations(args[0], args[1])?,
-        "nMCr" if args.len() == 2 => funcs::multicombinations(args[0], args[1])?,
-        // Order is important
-        "nMPr" if args.len() == 2 => args[0].powf(args[1]),
-        "nPr" if args.len() == 2 => funcs::permutations(args[0], args[1])?,
-        "rand" if args.len() == 1 => rand::random::<f64>() * args[0],
-        "sin" if args.len() == 1 => args[0].sin(),
-        _ => return Err(format!("Unknown Function: {} with {} args", fname, args.len()))
-    })
-}
-
-fn build_rv(dname: &str, args: &[f64]) -> Result<Rc<dyn RandomVariable>, String> {
-    use rand_distr::*;
-    Ok(match dname {
-        "normal" if args.len() == 2 => Rc::new(Normal::new(args[0], args[1]).unwrap()),
-        "uniform" if args.len() == 2 => Rc::new(Uniform::new(args[0], args[1])),
-        "lognormal" if args.len() == 2 => Rc::new(LogNormal::new(args[0], args[1]).unwrap()),
-        _ => return Err(format!("Unknown distribution: {} with {} args", dname, args.len()))
-    })
+        match (stack.pop(), stack.is_empty()) {
+            (Some(op), true) => Ok(op),
+            _ => Err("Failed to compile RPNExpr".to_string()),
+        }
+    }
+
+    // `expr` as a function of `var`, eg: to plot or solve it. The other
+    // variables must be defined, they're taken as they are now: setting
+    // them (or registering operators on `expr`'s table) later doesn't
+    // change the function. It's compiled once so calls only evaluate.
+    // Random variables and MathOp::Dynamic values are shared, not copied:
+    // they're sampled or called on each evaluation, so a Dynamic reading
+    // outside state still sees it change.
+    pub fn as_function(&self, expr: &RPNExpr, var: &str)
+            -> Result<impl Fn(f64) -> Result<f64, EvalError>, EvalError> {
+        let vars = self.0.borrow();
+        let mut unbound: Vec<_> = expr.0.iter()
+            .filter_map(|token| match token {
+                MathToken::Variable(v) if v != var && !vars.contains_key(v) => Some(v.as_str()),
+                _ => None,
+            })
+            .collect();
+        if !unbound.is_empty() {
+            unbound.sort();
+            unbound.dedup();
+            return Err(EvalError::UnknownVariable(unbound.join(", ")));
+        }
+        self.check_function(expr)?;
+        let mut snapshot: HashMap<_, _> = expr.0.iter()
+            .filter_map(|token| match token {
+                MathToken::Variable(v) => Some((v.clone(), vars.get(v)?.clone())),
+                _ => None,
+            })
+            .collect();
+        drop(vars);
+        let x = Rc::new(Cell::new(0.0));
+        let arg = x.clone();
+        snapshot.insert(var.to_string(), MathOp::Dynamic(Rc::new(move || Ok(arg.get()))));
+        let compiled = MathContext(Rc::new(RefCell::new(snapshot)))
+            .compile(&RPNExpr(expr.0.clone(), expr.1.copy()))
+            .map_err(EvalError::UnknownOp)?;
+        Ok(move |value| {
+            x.set(value);
+            checked(compiled.eval().map_err(EvalError::UnknownOp)?)
+        })
+    }
+
+    // Errors compiled expressions would only find (or panic on) when
+    // evaluated: unknown operators, function arities and missing operands
+    fn check_function(&self, expr: &RPNExpr) -> Result<(), EvalError> {
+        let mut operands = 0;
+        for token in &expr.0 {
+            let arity = match token {
+                MathToken::Number(_) | MathToken::Variable(_) => 0,
+                MathToken::BOp(op) => {
+                    expr.1.get(op, 2).ok_or_else(|| EvalError::UnknownOp(op.clone()))?;
+                    2
+                },
+                MathToken::UOp(op) => {
+                    expr.1.get(op, 1).ok_or_else(|| EvalError::UnknownOp(op.clone()))?;
+                    1
+                },
+                MathToken::Function(fname, arity) => {
+                    if !function(fname).is_some_and(|function| function.takes(*arity)) {
+                        return Err(EvalError::UnknownOp(fname.clone()));
+                    }
+                    *arity
+                },
+                _ => return Err(EvalError::UnexpectedToken(token.clone())),
+            };
+            if arity > operands {
+                return Err(EvalError::MissingOperands);
+            }
+            operands = operands - arity + 1;
+        }
+        match operands {
+            1 => Ok(()),
+            _ => Err(EvalError::MissingOperands),
+        }
+    }
+}
+
+fn truth(value: bool) -> f64 {
+    if value { 1.0 } else { 0.0 }
+}
+
+// Comparisons and logic ops for predicates, 1.0 is true and 0.0 false (as
+// is any nonzero operand and zero)
+fn predicate(op: &str, lhs: f64, rhs: f64) -> Option<f64> {
+    Some(truth(match op {
+        "==" => lhs == rhs,
+        "!=" => lhs != rhs,
+        "<" => lhs < rhs,
+        "<=" => lhs <= rhs,
+        ">" => lhs > rhs,
+        ">=" => lhs >= rhs,
+        "&&" => lhs != 0.0 && rhs != 0.0,
+        "||" => lhs != 0.0 || rhs != 0.0,
+        _ => return None,
+    }))
+}
+
+// Infinite or NaN results are errors rather than values to keep computing on
+fn checked(value: f64) -> Result<f64, EvalError> {
+    if value.is_nan() {
+        Err(EvalError::NaN)
+    } else if !value.is_finite() {
+        Err(EvalError::Overflow)
+    } else {
+        Ok(value)
+    }
+}
+
+impl RPNExpr {
+    // Like MathContext::eval but fails instead of producing inf or NaN
+    pub fn evaluate_checked(&self, vars: &HashMap<String, f64>) -> Result<f64, EvalError> {
+        let mut operands = Vec::new();
+        for token in &self.0 {
+            match token {
+                MathToken::Number(n) => operands.push(checked(*n)?),
+                MathToken::Variable(v) => operands.push(
+                    checked(*vars.get(v).ok_or_else(|| EvalError::UnknownVariable(v.clone()))?)?),
+                MathToken::BOp(op) => {
+                    let rhs = operands.pop().ok_or(EvalError::MissingOperands)?;
+                    let lhs = operands.pop().ok_or(EvalError::MissingOperands)?;
+                    // 0/0 is left to be reported as NaN
+                    if matches!(&op[..], "/" | "%") && rhs == 0.0 && lhs != 0.0 {
+                        return Err(EvalError::DivisionByZero);
+                    }
+                    if let Some(value) = self.1.eval(op, &[lhs, rhs]) {
+                        operands.push(checked(value)?);
+                        continue;
+                    }
+                    operands.push(checked(match &op[..] {
+                        "+" => lhs + rhs,
+                        "-" => lhs - rhs,
+                        "*" => lhs * rhs,
+                        "/" => lhs / rhs,
+                        "%" => lhs % rhs,
+                        "^" | "**" => lhs.powf(rhs),
+                        _ => predicate(op, lhs, rhs).ok_or_else(|| EvalError::UnknownOp(op.clone()))?,
+                    })?);
+                }
+                MathToken::UOp(op) => {
+                    let arg = operands.pop().ok_or(EvalError::MissingOperands)?;
+                    if let Some(value) = self.1.eval(op, &[arg]) {
+                        operands.push(checked(value)?);
+                        continue;
+                    }
+                    operands.push(checked(match &op[..] {
+                        "-" => -arg,
+                        "not" => truth(arg == 0.0),
+                        "!" => libm::tgamma(arg + 1.0),
+                        _ => return Err(EvalError::UnknownOp(op.clone())),
+                    })?);
+                }
+                MathToken::Function(fname, arity) => {
+                    if *arity > operands.len() {
+                        return Err(EvalError::MissingOperands);
+                    }
+                    let args = operands.split_off(operands.len() - arity);
+                    let value = function(fname)
+                        .and_then(|function| function.call(&args).ok()?.eval().ok())
+                        .ok_or_else(|| EvalError::UnknownOp(fname.clone()))?;
+                    operands.push(checked(value)?);
+                }
+                _ => return Err(EvalError::UnexpectedToken(token.clone())),
+            }
+        }
+        operands.pop().ok_or(EvalError::MissingOperands)
+    }
+}
+
+type ValueFn = fn(&[f64]) -> Result<f64, String>;
+type DistributionFn = fn(&[f64]) -> Result<Rc<dyn RandomVariable>, String>;
+
+enum Body {
+    Value(ValueFn),
+    // a new value on each call, eg: rand(10)
+    Sample(ValueFn),
+    // a random variable, sampled each time it's evaluated
+    Distribution(DistributionFn),
+}
+
+// Builtin function taking `args` arguments, any number from 1 when None
+struct Function {
+    name: &'static str,
+    args: Option<usize>,
+    body: Body,
+}
+
+impl Function {
+    fn takes(&self, args: usize) -> bool {
+        self.args.map_or(args > 0, |n| n == args)
+    }
+
+    fn is_random(&self) -> bool {
+        !matches!(self.body, Body::Value(_))
+    }
+
+    fn call(&self, args: &[f64]) -> Result<MathOp, String> {
+        if !self.takes(args.len()) {
+            return Err(format!("Unknown Function: {} with {} args", self.name, args.len()));
+        }
+        Ok(match self.body {
+            Body::Value(f) | Body::Sample(f) => MathOp::Number(f(args)?),
+            Body::Distribution(f) => MathOp::RandVar(f(args)?),
+        })
+    }
+}
+
+const FUNCTIONS: &[Function] = &[
+    Function{name: "abs", args: Some(1), body: Body::Value(|args| Ok(args[0].abs()))},
+    Function{name: "atan2", args: Some(2), body: Body::Value(|args| Ok(args[0].atan2(args[1])))},
+    Function{name: "cos", args: Some(1), body: Body::Value(|args| Ok(args[0].cos()))},
+    Function{name: "log", args: Some(1), body: Body::Value(|args| Ok(args[0].log10()))},
+    Function{name: "max", args: None,
+             body: Body::Value(|args| Ok(args.iter().fold(args[0], |a, &b| a.max(b))))},
+    Function{name: "min", args: None,
+             body: Body::Value(|args| Ok(args.iter().fold(args[0], |a, &b| a.min(b))))},
+    // Order not important
+    Function{name: "nCr", args: Some(2),
+             body: Body::Value(|args| funcs::combinations(args[0], args[1]))},
+    Function{name: "nMCr", args: Some(2),
+             body: Body::Value(|args| funcs::multicombinations(args[0], args[1]))},
+    // Order is important
+    Function{name: "nMPr", args: Some(2), body: Body::Value(|args| Ok(args[0].powf(args[1])))},
+    Function{name: "nPr", args: Some(2),
+             body: Body::Value(|args| funcs::permutations(args[0], args[1]))},
+    Function{name: "rand", args: Some(1),
+             body: Body::Sample(|args| Ok(rand::random::<f64>() * args[0]))},
+    Function{name: "sin", args: Some(1), body: Body::Value(|args| Ok(args[0].sin()))},
+    Function{name: "normal", args: Some(2), body: Body::Distribution(|args| {
+        let normal = rand_distr::Normal::new(args[0], args[1])
+            .map_err(|e| format!("Bad normal({}, {}): {}", args[0], args[1], e))?;
+        Ok(Rc::new(normal))
+    })},
+    Function{name: "uniform", args: Some(2), body: Body::Distribution(|args| {
+        // Uniform::new panics unless low < high with a finite range
+        if !(args[0] < args[1] && (args[1] - args[0]).is_finite()) {
+            return Err(format!("Bad uniform({}, {}): empty or infinite range", args[0], args[1]));
+        }
+        Ok(Rc::new(rand_distr::Uniform::new(args[0], args[1])))
+    })},
+    Function{name: "lognormal", args: Some(2), body: Body::Distribution(|args| {
+        let lognormal = rand_distr::LogNormal::new(args[0], args[1])
+            .map_err(|e| format!("Bad lognormal({}, {}): {}", args[0], args[1], e))?;
+        Ok(Rc::new(lognormal))
+    })},
+];
+
+const FUNCTION_NAMES: [&str; FUNCTIONS.len()] = {
+    let mut names = [""; FUNCTIONS.len()];
+    let mut idx = 0;
+    while idx < FUNCTIONS.len() {
+        names[idx] = FUNCTIONS[idx].name;
+        idx += 1;
+    }
+    names
+};
+
+fn function(fname: &str) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|f| f.name == fname)
+}
+
+// Functions whose result changes from one evaluation to the next
+pub(crate) fn is_random(fname: &str) -> bool {
+    function(fname).is_some_and(Function::is_random)
 }
 
 mod funcs {
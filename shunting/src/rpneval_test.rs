@@ -1,5 +1,5 @@
 use crate::parser::ShuntingParser;
-use crate::rpneval::MathContext;
+use crate::rpneval::{EvalError, MathContext};
 
 macro_rules! fuzzy_eq {
     ($lhs:expr, $rhs:expr) => {
@@ -63,3 +63,240 @@ fn test_eval8() {
     let expr = ShuntingParser::parse_str("-2^-3").unwrap();
     fuzzy_eq!(MathContext::new().eval(&expr).unwrap(), -0.125);
 }
+
+#[test]
+fn test_eval_rational() {
+    use crate::rpnrational::Rational64;
+    use std::collections::HashMap;
+    let eval = |input: &str| ShuntingParser::parse_str(input).unwrap()
+        .evaluate_rational(&HashMap::new());
+    assert_eq!(eval("1/3 + 1/6"), Ok(Rational64::new(1, 2)));
+    assert_eq!(eval("(1/2)^3"), Ok(Rational64::new(1, 8)));
+    assert_eq!(eval("(2/3)^-2"), Ok(Rational64::new(9, 4)));
+    assert_eq!(eval("0.1 + 0.2"), Ok(Rational64::new(3, 10)));
+    assert_eq!(eval("(4/9)^0.5"), Ok(Rational64::new(2, 3)));
+    assert_eq!(eval("-7 % 3"), Ok(Rational64::new(-1, 1)));
+    assert_eq!(eval("4! / max(3, 8/3, -1)"), Ok(Rational64::new(8, 1)));
+    assert_eq!(eval("2^0.5"), Err(EvalError::NotRational));
+    assert_eq!(eval("sin(1)"), Err(EvalError::NotRational));
+    assert_eq!(eval("1/(1/2 - 0.5)"), Err(EvalError::DivisionByZero));
+    assert_eq!(eval("2^100"), Err(EvalError::Overflow));
+    // predicates compare exactly, 0.1 + 0.2 is 0.3
+    assert_eq!(eval("0.1 + 0.2 == 0.3"), Ok(Rational64::new(1, 1)));
+    assert_eq!(eval("1/3 != 2/6 || !(1/2 < 2/3)"), Ok(Rational64::new(0, 1)));
+    assert_eq!(eval("!0 && 3/4 >= 0.75"), Ok(Rational64::new(1, 1)));
+
+    let mut vars = HashMap::new();
+    vars.insert("x".to_string(), Rational64::new(3, 4));
+    let expr = ShuntingParser::parse_str("x * x - 1/16").unwrap();
+    assert_eq!(expr.evaluate_rational(&vars), Ok(Rational64::new(1, 2)));
+    let expr = ShuntingParser::parse_str("y + 1").unwrap();
+    assert_eq!(expr.evaluate_rational(&vars), Err(EvalError::UnknownVariable("y".to_string())));
+
+    // i64::MIN has no positive counterpart, results that don't fit overflow
+    vars.insert("x".to_string(), Rational64::new(i64::MIN, 1));
+    let eval_x = |input: &str| ShuntingParser::parse_str(input).unwrap().evaluate_rational(&vars);
+    assert_eq!(eval_x("x / x"), Ok(Rational64::new(1, 1)));
+    assert_eq!(eval_x("x^3.52"), Err(EvalError::NotRational));
+    assert_eq!(eval_x("x^(1/63)"), Ok(Rational64::new(-2, 1)));
+    assert_eq!(eval_x("x / 4"), Ok(Rational64::new(i64::MIN / 4, 1)));
+    assert_eq!(eval_x("1 / x"), Err(EvalError::Overflow));
+    assert_eq!(eval_x("-x"), Err(EvalError::Overflow));
+    assert_eq!(eval_x("abs(x)"), Err(EvalError::Overflow));
+    assert_eq!(eval_x("x % 3"), Ok(Rational64::new(-2, 1)));
+    assert_eq!(Rational64::new(i64::MIN, i64::MIN), Rational64::new(1, 1));
+    assert_eq!(Rational64::new(2, i64::MIN), Rational64::new(-1, 1 << 62));
+}
+
+#[test]
+fn test_eval_logical() {
+    let eval = |input: &str| MathContext::new().eval(&ShuntingParser::parse_str(input).unwrap());
+    assert_eq!(eval("!0"), Ok(1.0));
+    assert_eq!(eval("!0.5"), Ok(0.0));
+    assert_eq!(eval("!(1 > 2)"), Ok(1.0));
+    assert_eq!(eval("!!7"), Ok(1.0));
+    // (!1 > 2) && 1, not !(1 > 2 && 1)
+    assert_eq!(eval("!1 > 2 && 1"), Ok(0.0));
+    assert_eq!(eval("!0 && 2 >= 2"), Ok(1.0));
+    assert_eq!(eval("!1 && 1 || 3 == 3"), Ok(1.0));
+    assert_eq!(eval("3! - 1 < 5"), Ok(0.0));
+    assert_eq!(eval("1 != 2"), Ok(1.0));
+    assert_eq!(eval("3! != 6"), Ok(0.0));
+    let cx = MathContext::new();
+    cx.setvar("x", crate::rpneval::MathOp::Number(0.0));
+    let compiled = cx.compile(&ShuntingParser::parse_str("!x && x < 1").unwrap()).unwrap();
+    assert_eq!(compiled.eval(), Ok(1.0));
+    use std::collections::HashMap;
+    let expr = ShuntingParser::parse_str("!(2 <= 1) || 1 / 0").unwrap();
+    assert_eq!(expr.evaluate_checked(&HashMap::new()), Err(EvalError::DivisionByZero));
+}
+
+#[test]
+fn test_eval_checked() {
+    use std::collections::HashMap;
+    let eval = |input: &str| ShuntingParser::parse_str(input).unwrap()
+        .evaluate_checked(&HashMap::new());
+    assert_eq!(eval("1.0 / 0.0"), Err(EvalError::DivisionByZero));
+    assert_eq!(eval("7 % 0"), Err(EvalError::DivisionByZero));
+    assert_eq!(eval("0.0 / 0.0"), Err(EvalError::NaN));
+    assert_eq!(eval("1e308 * 1e308"), Err(EvalError::Overflow));
+    assert_eq!(eval("-1e308 - 1e308"), Err(EvalError::Overflow));
+    assert_eq!(eval("1e308 * 1e308 - 1e308 * 1e308"), Err(EvalError::Overflow));
+    assert_eq!(eval("(-1)^0.5"), Err(EvalError::NaN));
+    assert_eq!(eval("2^10 / 4 + 1e300"), Ok(256.0 + 1e300));
+    assert_eq!(eval("max(3, 2^3) - 4!"), Ok(-16.0));
+    assert_eq!(eval("coss(1)"), Err(EvalError::UnknownOp("coss".to_string())));
+
+    let mut vars = HashMap::new();
+    vars.insert("x".to_string(), 1e200);
+    let expr = ShuntingParser::parse_str("x * 2").unwrap();
+    assert_eq!(expr.evaluate_checked(&vars), Ok(2e200));
+    let expr = ShuntingParser::parse_str("x^2").unwrap();
+    assert_eq!(expr.evaluate_checked(&vars), Err(EvalError::Overflow));
+    let expr = ShuntingParser::parse_str("y + 1").unwrap();
+    assert_eq!(expr.evaluate_checked(&vars), Err(EvalError::UnknownVariable("y".to_string())));
+}
+
+#[test]
+fn test_did_you_mean() {
+    let cx = MathContext::new();
+    cx.setvar("width", crate::rpneval::MathOp::Number(3.0));
+    let error = |expr| {
+        let rpn = ShuntingParser::parse_str(expr).unwrap();
+        let evaled = cx.eval(&rpn).unwrap_err();
+        assert_eq!(cx.compile(&rpn).err(), Some(evaled.clone()));
+        evaled
+    };
+    assert_eq!(error("coss(pi)"), "Unknown Function: coss, did you mean 'cos'?");
+    assert_eq!(error("2 * widht"), "Unknown Variable: widht, did you mean 'width'?");
+    assert_eq!(error("1 + wdth"), "Unknown Variable: wdth, did you mean 'width'?");
+    // nothing close enough
+    assert_eq!(error("banana(1)"), "Unknown Function: banana");
+    assert_eq!(error("height + 1"), "Unknown Variable: height");
+    assert!(cx.variables().contains(&"width".to_string()));
+    assert!(cx.functions().contains(&"cos"));
+    assert!(cx.functions().contains(&"lognormal"));
+    // arities come from the same table
+    assert_eq!(error("atan2(1)"), "Unknown Function: atan2 with 1 args");
+    assert_eq!(error("normal(1)"), "Unknown Function: normal with 1 args");
+    // samples stay random when compiled on numbers, others are folded
+    let compiled = |expr| cx.compile(&ShuntingParser::parse_str(expr).unwrap()).unwrap();
+    assert!(matches!(compiled("rand(10)"), crate::rpneval::MathOp::Dynamic(_)));
+    assert!(matches!(compiled("uniform(0, 1)"), crate::rpneval::MathOp::RandVar(_)));
+    assert!(matches!(compiled("max(1, 2)"), crate::rpneval::MathOp::Number(n) if n == 2.0));
+    // errors sampling dynamic values are passed on, not turned into NaN
+    cx.setvar("s", compiled("uniform(-2, -1)"));
+    let error = compiled("uniform(0, s) + 1").eval().unwrap_err();
+    assert!(error.starts_with("Bad uniform(0, -1."), "{}", error);
+    let error = cx.eval(&ShuntingParser::parse_str("uniform(0, s)").unwrap()).unwrap_err();
+    assert!(error.ends_with("empty or infinite range"), "{}", error);
+}
+
+#[test]
+fn test_eval_locales() {
+    use lexers::NumberLocale;
+    let cx = MathContext::new();
+    let eval = |input: &str, decimal_comma, thousands| {
+        let locale = NumberLocale{decimal_comma, thousands};
+        ShuntingParser::parse_str_locale(input, locale).map_err(String::from)
+            .and_then(|expr| cx.eval(&expr))
+    };
+    // input, then decimal point, decimal comma, decimal comma with thousands
+    let cases = [
+        ("2,75", None, Some(2.75), Some(2.75)),
+        ("2.75", Some(2.75), None, None),
+        ("max(1,5)", Some(5.0), Some(1.5), Some(1.5)),
+        ("max(1;5)", None, Some(5.0), Some(5.0)),
+        ("max(1,5; 2,25)", None, Some(2.25), Some(2.25)),
+        ("1.234,56 * 2", None, None, Some(2469.12)),
+        ("2 * 1,5e2", None, Some(300.0), Some(300.0)),
+    ];
+    for (input, point, comma, grouped) in cases {
+        assert_eq!(eval(input, false, false).ok(), point, "{}", input);
+        assert_eq!(eval(input, true, false).ok(), comma, "{}", input);
+        assert_eq!(eval(input, true, true).ok(), grouped, "{}", input);
+        // the default locale is the plain parser
+        assert_eq!(eval(input, false, false),
+                   ShuntingParser::parse_str(input).map_err(String::from)
+                       .and_then(|expr| cx.eval(&expr)));
+    }
+    assert_eq!(eval("1,234,567.5 - 1", false, true), Ok(1234566.5));
+    assert_eq!(eval("1,23 + 1", false, true), Err("Bad token: 1,23".to_string()));
+    assert_eq!(eval("max(1, 5)", true, false), Err("Bad token: ,".to_string()));
+    assert_eq!(eval("2.75", true, true), Err("Bad token: 2.75".to_string()));
+}
+
+#[test]
+fn test_render_errors() {
+    use lexers::render;
+    let input = "2 * x + 1";
+    let expr = ShuntingParser::parse_str(input).unwrap();
+    let error = expr.evaluate_checked(&Default::default()).unwrap_err();
+    assert_eq!(render(&error, input), "error: Unknown Variable: x\n");
+    let error = MathContext::new().eval(&expr).unwrap_err();
+    assert_eq!(render(&error, input), "error: Unknown Variable: x\n");
+    // parse errors point at the tokens they're about
+    let input = "2 * (x + 1";
+    let error = ShuntingParser::parse_str(input).unwrap_err();
+    assert_eq!(render(&error, input), concat!(
+        "error: Missing Closing Paren\n",
+        " --> 1:5\n",
+        "  |\n",
+        "1 | 2 * (x + 1\n",
+        "  |     ^\n"));
+    let input = "max(1, 2) 3 + x";
+    let error = ShuntingParser::parse_str(input).unwrap_err();
+    assert_eq!(render(&error, input), concat!(
+        "error: Expression leaves 2 values, expected 1\n",
+        " --> 1:11\n",
+        "  |\n",
+        "1 | max(1, 2) 3 + x\n",
+        "  |           ^^^^^\n"));
+}
+
+#[test]
+fn test_as_function() {
+    use crate::rpneval::MathOp;
+    let cx = MathContext::new();
+    cx.setvar("a", MathOp::Number(2.0));
+    let expr = ShuntingParser::parse_str("a*x^2").unwrap();
+    let f = cx.as_function(&expr, "x").unwrap();
+    assert_eq!(f(3.0), Ok(18.0));
+    // a was taken when f was made
+    cx.setvar("a", MathOp::Number(5.0));
+    assert_eq!(f(3.0), Ok(18.0));
+    assert_eq!(f(-1.0), Ok(2.0));
+    assert_eq!(cx.as_function(&expr, "x").unwrap()(3.0), Ok(45.0));
+    // x is the argument even when it's defined
+    cx.setvar("x", MathOp::Number(100.0));
+    assert_eq!(cx.as_function(&expr, "x").unwrap()(1.0), Ok(5.0));
+
+    let expr = ShuntingParser::parse_str("b * x + c - b").unwrap();
+    assert_eq!(cx.as_function(&expr, "x").err(),
+               Some(EvalError::UnknownVariable("b, c".to_string())));
+    let expr = ShuntingParser::parse_str("sin(x, 2)").unwrap();
+    assert_eq!(cx.as_function(&expr, "x").err(), Some(EvalError::UnknownOp("sin".to_string())));
+    let expr = ShuntingParser::parse_str("1 / x").unwrap();
+    let f = cx.as_function(&expr, "x").unwrap();
+    assert_eq!(f(4.0), Ok(0.25));
+    assert_eq!(f(0.0), Err(EvalError::Overflow));
+    let f = cx.as_function(&ShuntingParser::parse_str("pi").unwrap(), "x").unwrap();
+    assert_eq!(f(1.0), Ok(std::f64::consts::PI));
+    // arities are checked without calling the function
+    let f = cx.as_function(&ShuntingParser::parse_str("uniform(x, x + 1)").unwrap(), "x").unwrap();
+    assert!(f(2.0).is_ok_and(|v| (2.0..3.0).contains(&v)));
+
+    // operators are copied, replacing them later doesn't change f
+    let mut parser = ShuntingParser::new();
+    parser.add_operator("⊕", 25, crate::Assoc::Left, 2, |args| args[0] + args[1]).unwrap();
+    let f = cx.as_function(&parser.parse_expr("x ⊕ 1").unwrap(), "x").unwrap();
+    parser.add_operator("⊕", 25, crate::Assoc::Left, 2, |args| args[0] * args[1]).unwrap();
+    assert_eq!(f(3.0), Ok(4.0));
+    // dynamic values are shared, they're called on each evaluation
+    let offset = std::rc::Rc::new(std::cell::Cell::new(1.0));
+    let shared = offset.clone();
+    cx.setvar("d", MathOp::Dynamic(std::rc::Rc::new(move || Ok(shared.get()))));
+    let f = cx.as_function(&ShuntingParser::parse_str("x + d").unwrap(), "x").unwrap();
+    offset.set(10.0);
+    assert_eq!(f(1.0), Ok(11.0));
+}
@@ -0,0 +1,152 @@
+use crate::operators::OperatorTable;
+use crate::parser::RPNExpr;
+use crate::rpneval::{is_random, MathContext};
+use lexers::MathToken;
+
+// Expression tree, commutative operators can have any number of operands
+enum Node {
+    Leaf(MathToken),
+    Op(MathToken, Vec<Node>),
+}
+
+fn build_tree(rpn: &RPNExpr) -> Option<Node> {
+    let mut stack = Vec::new();
+    for token in &rpn.0 {
+        let arity = match token {
+            MathToken::Number(_) | MathToken::Quantity(..) | MathToken::Variable(_) => {
+                stack.push(Node::Leaf(token.clone()));
+                continue;
+            }
+            MathToken::BOp(_) => 2,
+            MathToken::UOp(_) => 1,
+            MathToken::Function(_, arity) => *arity,
+            _ => return None,
+        };
+        let args = stack.split_off(stack.len().checked_sub(arity)?);
+        stack.push(Node::Op(token.clone(), args));
+    }
+    match stack.len() {
+        1 => stack.pop(),
+        _ => None,
+    }
+}
+
+fn to_rpn(node: &Node, out: &mut Vec<MathToken>) {
+    match node {
+        Node::Leaf(token) => out.push(token.clone()),
+        // n-ary chains are written back as left associative binary ops
+        Node::Op(op @ MathToken::BOp(_), args) => {
+            for (idx, arg) in args.iter().enumerate() {
+                to_rpn(arg, out);
+                if idx > 0 {
+                    out.push(op.clone());
+                }
+            }
+        }
+        Node::Op(op, args) => {
+            args.iter().for_each(|arg| to_rpn(arg, out));
+            out.push(op.clone());
+        }
+    }
+}
+
+fn key(node: &Node) -> String {
+    let mut tokens = Vec::new();
+    to_rpn(node, &mut tokens);
+    format!("{:?}", tokens)
+}
+
+fn number(node: &Node) -> Option<f64> {
+    match node {
+        Node::Leaf(MathToken::Number(n)) => Some(*n),
+        _ => None,
+    }
+}
+
+// An operation on numbers becomes its value, unless it's random or not finite
+fn fold_constant(node: Node, operators: &OperatorTable) -> Node {
+    let foldable = match &node {
+        // random functions can't be folded into a constant
+        Node::Op(MathToken::Function(fname, _), _) if is_random(fname) => false,
+        Node::Op(_, args) => args.iter().all(|arg| number(arg).is_some()),
+        Node::Leaf(_) => false,
+    };
+    if foldable {
+        let mut tokens = Vec::new();
+        to_rpn(&node, &mut tokens);
+        if let Ok(value) = MathContext::new().eval(&RPNExpr(tokens, operators.clone())) {
+            if value.is_finite() {
+                return Node::Leaf(MathToken::Number(value));
+            }
+        }
+    }
+    node
+}
+
+// Flatten (a + b) + c into a + b + c, fold the numbers and sort operands
+fn normalize_chain(op: &str, args: Vec<Node>) -> Vec<Node> {
+    let mut operands = Vec::new();
+    for arg in args {
+        match arg {
+            Node::Op(MathToken::BOp(inner), inner_args) if inner == op =>
+                operands.extend(inner_args),
+            other => operands.push(other),
+        }
+    }
+    let (numbers, mut operands): (Vec<_>, Vec<_>) = operands.into_iter()
+        .partition(|arg| number(arg).is_some());
+    let numbers: Vec<_> = numbers.iter().filter_map(number).collect();
+    let folded = match op {
+        "+" => numbers.iter().sum::<f64>(),
+        _ => numbers.iter().product::<f64>(),
+    };
+    match folded.is_finite() {
+        true if !numbers.is_empty() => operands.push(Node::Leaf(MathToken::Number(folded))),
+        true => (),
+        false => operands.extend(numbers.into_iter().map(|n| Node::Leaf(MathToken::Number(n)))),
+    }
+    operands.sort_by_cached_key(key);
+    operands
+}
+
+fn normalize_node(node: Node, operators: &OperatorTable) -> Node {
+    let (op, args) = match node {
+        Node::Op(op, args) => (op, args.into_iter().map(|arg| normalize_node(arg, operators)).collect()),
+        leaf => return leaf,
+    };
+    match op {
+        // registered replacements may not be commutative
+        MathToken::BOp(ref o) if (o == "+" || o == "*") && !operators.is_extra(o) => {
+            let mut operands = normalize_chain(o, args);
+            match operands.len() {
+                1 => operands.pop().unwrap(),
+                _ => Node::Op(op, operands),
+            }
+        }
+        op => fold_constant(Node::Op(op, args), operators),
+    }
+}
+
+impl RPNExpr {
+    // Canonical form to compare expressions. Only these rewrites apply:
+    // chains of + and * are flattened and their operands sorted, numbers in
+    // a chain are folded together, and operations (or deterministic
+    // functions) on numbers are evaluated. There's no distribution,
+    // cancellation (x - x stays), identities like x * 1, or trig rules.
+    // Malformed expressions are returned unchanged.
+    pub fn normalize(&self) -> RPNExpr {
+        match build_tree(self) {
+            Some(tree) => {
+                let mut tokens = Vec::new();
+                to_rpn(&normalize_node(tree, &self.1), &mut tokens);
+                RPNExpr(tokens, self.1.clone())
+            }
+            None => self.clone(),
+        }
+    }
+
+    // Same normalized form, see normalize for what that covers
+    pub fn equivalent(&self, other: &RPNExpr) -> bool {
+        self.normalize() == other.normalize()
+    }
+}
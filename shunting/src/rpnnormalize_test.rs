@@ -0,0 +1,49 @@
+use crate::parser::{RPNExpr, ShuntingParser};
+use lexers::MathToken;
+
+fn parse(input: &str) -> RPNExpr {
+    ShuntingParser::parse_str(input).unwrap()
+}
+
+fn equivalent(lhs: &str, rhs: &str) -> bool {
+    parse(lhs).equivalent(&parse(rhs))
+}
+
+#[test]
+fn test_equivalent() {
+    assert!(equivalent("a*2 + 1", "1 + 2*a"));
+    assert!(equivalent("(a + b) + c", "c + (b + a)"));
+    assert!(equivalent("2 * x * 3 * y", "y * (6 * x)"));
+    assert!(equivalent("1 + 2 + x + 3", "x + 6"));
+    assert!(equivalent("max(2^3, 1) - x", "8 - x"));
+    assert!(equivalent("sin(b * a) / (2 - 1)", "sin(a * b) / 1"));
+    assert!(equivalent("-(3) * a", "a * -3"));
+    // out of scope rewrites
+    assert!(!equivalent("x - x", "0"));
+    assert!(!equivalent("a - b", "b - a"));
+    assert!(!equivalent("a * (b + c)", "a * b + a * c"));
+    assert!(!equivalent("x * 1", "x"));
+    assert!(!equivalent("2 ^ x", "x ^ 2"));
+    // random functions aren't folded
+    assert_eq!(parse("rand(1)").normalize(), parse("rand(1)"));
+    assert!(equivalent("2 * rand(3 - 1)", "rand(2) * 2"));
+}
+
+#[test]
+fn test_normalize() {
+    assert_eq!(parse("(1 + 2) * 3").normalize(), RPNExpr::from(vec![MathToken::Number(9.0)]));
+    assert_eq!(parse("b * 2 + a").normalize(), parse("a + b * 2").normalize());
+    assert_eq!(parse("x + 1 + 1").normalize(), RPNExpr::from(vec![
+        MathToken::Number(2.0), MathToken::Variable("x".to_string()), MathToken::BOp("+".to_string())]));
+    // division by zero stays for eval to report
+    assert_eq!(parse("1 / 0").normalize(), parse("1 / 0"));
+    // idempotent
+    for input in ["a*2 + 1", "c * (b * a) - (z + y + 1) * 0.1 * 0.2",
+                  "max(b + a, 2 * 3, x) ^ -(y * x)", "0.1 + x + 0.2", "1e308 * x * 1e308"] {
+        let once = parse(input).normalize();
+        assert_eq!(once.normalize(), once, "{}", input);
+    }
+    // malformed expressions are returned as is
+    let bad = RPNExpr::from(vec![MathToken::BOp("+".to_string())]);
+    assert_eq!(bad.normalize(), bad);
+}
@@ -1,4 +1,5 @@
-use crate::parser::RPNExpr;
+use crate::operators::is_word;
+use crate::parser::{Assoc, RPNExpr};
 use lexers::MathToken;
 use std::fmt;
 
@@ -9,28 +10,41 @@ enum AST<'a> {
 }
 
 impl RPNExpr {
-    fn build_ast(&self) -> AST {
+    // RPNExprs are built by hand too so operands are checked
+    fn build_ast(&self) -> Result<AST<'_>, String> {
         let mut ops = Vec::new();
         for token in &self.0 {
-            match token {
-                MathToken::Number(_) | MathToken::Variable(_) =>
-                    ops.push(AST::Leaf(token)),
-                MathToken::Function(_, arity) => {
-                    let children = ops.split_off(ops.len() - arity);
-                    ops.push(AST::Node(token, children));
+            let arity = match token {
+                MathToken::Number(_) | MathToken::Variable(_) => {
+                    ops.push(AST::Leaf(token));
+                    continue;
                 },
-                MathToken::BOp(_) => {
-                    let children = ops.split_off(ops.len() - 2);
-                    ops.push(AST::Node(token, children));
-                },
-                MathToken::UOp(_) => {
-                    let children = ops.split_off(ops.len() - 1);
-                    ops.push(AST::Node(token, children));
-                },
-                _ => unreachable!(),
-            }
+                MathToken::Function(_, arity) => *arity,
+                MathToken::BOp(_) => 2,
+                MathToken::UOp(_) => 1,
+                _ => return Err(format!("Unexpected token for RPN print: {:?}", token)),
+            };
+            let start = ops.len().checked_sub(arity)
+                .ok_or(format!("Missing operands for {:?}", token))?;
+            let children = ops.split_off(start);
+            ops.push(AST::Node(token, children));
         }
-        ops.pop().unwrap()
+        match (ops.pop(), ops.is_empty()) {
+            (Some(root), true) => Ok(root),
+            _ => Err(format!("Failed to print RPN: {:?}", self)),
+        }
+    }
+}
+
+impl RPNExpr {
+    // Tokens in postfix order separated by spaces, eg: "2 3 4 * +"
+    pub fn to_rpn_string(&self) -> String {
+        self.0.iter().map(|token| match token {
+            MathToken::Number(n) => format!("{}", n),
+            MathToken::Variable(name) | MathToken::Function(name, _) |
+            MathToken::UOp(name) | MathToken::BOp(name) => name.clone(),
+            other => format!("{:?}", other),
+        }).collect::<Vec<_>>().join(" ")
     }
 }
 
@@ -54,8 +68,200 @@ impl fmt::Display for RPNExpr {
                 }
             }
         }
+        // malformed hand-built expressions fail, try_to_infix says why
+        let root = self.build_ast().map_err(|_| fmt::Error)?;
         let mut output = String::new();
-        print_helper(&self.build_ast(), "", &mut output);
+        print_helper(&root, "", &mut output);
         write!(f, "{}", output)
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FracStyle {
+    Inline,  // a/b
+    #[default]
+    Display, // \frac{a}{b}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MulSymbol {
+    #[default]
+    Cdot,  // a \cdot b
+    Times, // a \times b
+    None,  // a b (falls back to \cdot if the rhs starts with a digit)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatexOptions {
+    pub frac_style: FracStyle,
+    pub mul_symbol: MulSymbol,
+    // Always brace exponents, otherwise only when longer than a char
+    pub power_braces: bool,
+}
+
+impl Default for LatexOptions {
+    fn default() -> Self {
+        LatexOptions {
+            frac_style: FracStyle::default(),
+            mul_symbol: MulSymbol::default(),
+            power_braces: true,
+        }
+    }
+}
+
+// Binds tighter than any operator, never needs parens
+const ATOM: usize = usize::MAX;
+
+fn parens(expr: String) -> String {
+    format!("\\left({}\\right)", expr)
+}
+
+type Precedence<'a> = &'a dyn Fn(&MathToken) -> Result<(usize, Assoc), String>;
+
+// Returns the latex for root and the precedence of its top level operator
+fn latex_helper(root: &AST, opts: &LatexOptions, precedence: Precedence) -> (String, usize) {
+    let (tok, children) = match root {
+        AST::Leaf(MathToken::Number(n)) => return (format!("{}", n), ATOM),
+        AST::Leaf(MathToken::Variable(v)) => return (v.clone(), ATOM),
+        AST::Leaf(tok) => return (format!("{:?}", tok), ATOM),
+        AST::Node(tok, children) => (tok, children),
+    };
+    let mut args: Vec<_> = children.iter().map(|c| latex_helper(c, opts, precedence)).collect();
+    match tok {
+        MathToken::Function(name, _) if name == "sqrt" && args.len() == 1 =>
+            (format!("\\sqrt{{{}}}", args[0].0), ATOM),
+        MathToken::Function(name, _) => {
+            let args = args.into_iter().map(|(a, _)| a).collect::<Vec<_>>().join(", ");
+            let name = match name.as_str() {
+                "sin" | "cos" | "tan" | "log" | "ln" | "exp" | "min" | "max" =>
+                    format!("\\{}", name),
+                _ => format!("\\operatorname{{{}}}", name),
+            };
+            (format!("{}{}", name, parens(args)), ATOM)
+        },
+        MathToken::UOp(op) => {
+            let (prec, _) = precedence(tok).unwrap_or((0, Assoc::Left));
+            let (arg, arg_prec) = args.pop().unwrap_or_default();
+            let arg = if arg_prec < prec { parens(arg) } else { arg };
+            match op.as_str() {
+                "!" => (format!("{}!", arg), prec),
+                "not" => (format!("!{}", arg), prec),
+                _ => (format!("{}{}", op, arg), prec),
+            }
+        },
+        MathToken::BOp(op) => {
+            let (prec, assoc) = precedence(tok).unwrap_or((0, Assoc::Left));
+            let (rhs, rhs_prec) = args.pop().unwrap_or_default();
+            let (lhs, lhs_prec) = args.pop().unwrap_or_default();
+            match op.as_str() {
+                // \frac groups its operands, no parens needed
+                "/" if opts.frac_style == FracStyle::Display =>
+                    return (format!("\\frac{{{}}}{{{}}}", lhs, rhs), ATOM),
+                "^" | "**" => {
+                    let base = if lhs_prec < ATOM || lhs.starts_with("\\frac") {
+                        parens(lhs) } else { lhs };
+                    if !opts.power_braces && rhs.chars().count() == 1 {
+                        return (format!("{}^{}", base, rhs), prec);
+                    }
+                    return (format!("{}^{{{}}}", base, rhs), prec);
+                },
+                _ => (),
+            }
+            let lhs = if lhs_prec < prec || (lhs_prec == prec && assoc == Assoc::Right) {
+                parens(lhs) } else { lhs };
+            let rhs = if rhs_prec < prec || (rhs_prec == prec && assoc == Assoc::Left) {
+                parens(rhs) } else { rhs };
+            let op = match op.as_str() {
+                "*" => match opts.mul_symbol {
+                    MulSymbol::Cdot => " \\cdot ",
+                    MulSymbol::Times => " \\times ",
+                    // juxtaposed numbers would read as a single one
+                    MulSymbol::None if rhs.starts_with(|c: char| c.is_ascii_digit()) =>
+                        " \\cdot ",
+                    MulSymbol::None => " ",
+                },
+                "%" => " \\bmod ",
+                "/" => " / ",
+                "+" => " + ",
+                "-" => " - ",
+                "<=" => " \\leq ",
+                ">=" => " \\geq ",
+                "==" => " = ",
+                "!=" => " \\neq ",
+                "&&" => " \\land ",
+                "||" => " \\lor ",
+                other => return (format!("{} {} {}", lhs, other, rhs), prec),
+            };
+            (format!("{}{}{}", lhs, op, rhs), prec)
+        },
+        _ => (format!("{:?}", tok), ATOM),
+    }
+}
+
+// Parsed expressions always print. Hand-built ones that are malformed (eg:
+// operators short of operands) panic in the to_ printers, like to_string
+// does, and are errors in the try_ ones.
+impl RPNExpr {
+    pub fn to_latex(&self) -> String {
+        self.to_latex_with_style(LatexOptions::default())
+    }
+
+    pub fn to_latex_with_style(&self, options: LatexOptions) -> String {
+        self.try_to_latex(options).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    // Operators missing from the expression's table get parenthesized
+    pub fn try_to_latex(&self, options: LatexOptions) -> Result<String, String> {
+        let root = self.build_ast()?;
+        Ok(latex_helper(&root, &options, &|token| self.1.precedence(token)).0)
+    }
+
+    pub fn to_infix(&self) -> String {
+        self.try_to_infix().unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    // Infix form that parses back to the same RPNExpr, eg: "2 * (x + 1)".
+    // Registered operators need the parser that added them.
+    pub fn try_to_infix(&self) -> Result<String, String> {
+        let root = self.build_ast()?;
+        Ok(infix_helper(&root, &|token| self.1.precedence(token)).0)
+    }
+}
+
+// Returns the infix form of root and the precedence of its top level operator
+fn infix_helper(root: &AST, precedence: Precedence) -> (String, usize) {
+    let (tok, children) = match root {
+        AST::Leaf(MathToken::Number(n)) => return (format!("{}", n), ATOM),
+        AST::Leaf(MathToken::Variable(v)) => return (v.clone(), ATOM),
+        AST::Leaf(tok) => return (format!("{:?}", tok), ATOM),
+        AST::Node(tok, children) => (tok, children),
+    };
+    let mut args: Vec<_> = children.iter().map(|c| infix_helper(c, precedence)).collect();
+    let parens = |arg: String, needed: bool| if needed { format!("({})", arg) } else { arg };
+    match tok {
+        MathToken::Function(name, _) => {
+            let args = args.into_iter().map(|(a, _)| a).collect::<Vec<_>>().join(", ");
+            (format!("{}({})", name, args), ATOM)
+        },
+        MathToken::UOp(op) => {
+            let (prec, _) = precedence(tok).unwrap_or((0, Assoc::Left));
+            let (arg, arg_prec) = args.pop().unwrap_or_default();
+            let arg = parens(arg, arg_prec < prec);
+            match op.as_str() {
+                "!" => (format!("{}!", arg), prec),
+                "not" => (format!("!{}", arg), prec),
+                _ if is_word(op) => (format!("{} {}", op, arg), prec),
+                _ => (format!("{}{}", op, arg), prec),
+            }
+        },
+        MathToken::BOp(op) => {
+            let (prec, assoc) = precedence(tok).unwrap_or((0, Assoc::Left));
+            let (rhs, rhs_prec) = args.pop().unwrap_or_default();
+            let (lhs, lhs_prec) = args.pop().unwrap_or_default();
+            let lhs = parens(lhs, lhs_prec < prec || (lhs_prec == prec && assoc == Assoc::Right));
+            let rhs = parens(rhs, rhs_prec < prec || (rhs_prec == prec && assoc == Assoc::Left));
+            (format!("{} {} {}", lhs, op, rhs), prec)
+        },
+        _ => (format!("{:?}", tok), ATOM),
+    }
+}
@@ -0,0 +1,129 @@
+use crate::parser::{RPNExpr, ShuntingParser};
+use crate::rpnprint::{FracStyle, LatexOptions, MulSymbol};
+use lexers::MathToken;
+
+fn latex(expr: &str, options: LatexOptions) -> String {
+    ShuntingParser::parse_str(expr).unwrap().to_latex_with_style(options)
+}
+
+// Braces and \left/\right balance, exponents are a char or braced
+fn well_formed(latex: &str) -> bool {
+    let mut depth = 0i32;
+    for ch in latex.chars() {
+        match ch {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => (),
+        }
+        if depth < 0 {
+            return false;
+        }
+    }
+    let exponents_ok = latex.split('^').skip(1).all(|exp| {
+        let mut chars = exp.chars();
+        match (chars.next(), chars.next()) {
+            (Some('{'), _) => true,
+            (Some(c), next) => c.is_alphanumeric() &&
+                next.is_none_or(|n| !n.is_alphanumeric() && n != '.'),
+            (None, _) => false,
+        }
+    });
+    depth == 0 && exponents_ok &&
+        latex.matches("\\left(").count() == latex.matches("\\right)").count()
+}
+
+#[test]
+fn test_rpn_string() {
+    let rpn = |expr| ShuntingParser::parse_str(expr).unwrap().to_rpn_string();
+    assert_eq!(rpn("2 + 3 * 4"), "2 3 4 * +");
+    assert_eq!(rpn("max(x, -1.5)^2 - 3!"), "x 1.5 - max 2 ^ 3 ! -");
+}
+
+#[test]
+fn test_infix() {
+    let infix = |expr| ShuntingParser::parse_str(expr).unwrap().to_infix();
+    assert_eq!(infix("3+4*2/-(1-5)^2^3"), "3 + 4 * 2 / -(1 - 5) ^ 2 ^ 3");
+    assert_eq!(infix("(a - b) - (c - d)"), "a - b - (c - d)");
+    assert_eq!(infix("(2^3)^-x"), "(2 ^ 3) ^ -x");
+    assert_eq!(infix("-(x + 1)! * max(!a, b && c)"), "-(x + 1)! * max(!a, b && c)");
+    for input in ["3+4*2/-(1-5)^2^3", "(2^3)^-x!", "!(1 > 2) || x <= 3 % 2", "--x - -1"] {
+        let expr = ShuntingParser::parse_str(input).unwrap();
+        assert_eq!(ShuntingParser::parse_str(&expr.to_infix()), Ok(expr));
+    }
+}
+
+#[test]
+fn test_latex_default() {
+    let expr = ShuntingParser::parse_str("(a + b) / 2 * c^2").unwrap();
+    assert_eq!(expr.to_latex(), "\\frac{a + b}{2} \\cdot c^{2}");
+    assert_eq!(expr.to_latex(), expr.to_latex_with_style(LatexOptions::default()));
+    let latex = |expr| latex(expr, LatexOptions::default());
+    assert_eq!(latex("a - (b - c)"), "a - \\left(b - c\\right)");
+    assert_eq!(latex("(a - b) - c"), "a - b - c");
+    assert_eq!(latex("2^3^4"), "2^{3^{4}}");
+    assert_eq!(latex("(2^3)^4"), "\\left(2^{3}\\right)^{4}");
+    assert_eq!(latex("-(x + 1)!"), "-\\left(x + 1\\right)!");
+    assert_eq!(latex("sqrt(x) + sin(x) * f(x, 2)"),
+               "\\sqrt{x} + \\sin\\left(x\\right) \\cdot \\operatorname{f}\\left(x, 2\\right)");
+    assert_eq!(latex("7 % 3"), "7 \\bmod 3");
+    assert_eq!(latex("!x"), "!x");
+    assert_eq!(latex("!(1 > 2)"), "!\\left(1 > 2\\right)");
+    assert_eq!(latex("!x! && y <= 2 || z"), "!x! \\land y \\leq 2 \\lor z");
+    assert_eq!(latex("x != 2"), "x \\neq 2");
+}
+
+#[test]
+fn test_latex_options() {
+    let options = |frac_style, mul_symbol, power_braces|
+        LatexOptions{frac_style, mul_symbol, power_braces};
+    let expr = "2 * x / (y + 1)^10 * 3";
+    assert_eq!(latex(expr, options(FracStyle::Display, MulSymbol::Cdot, true)),
+               "\\frac{2 \\cdot x}{\\left(y + 1\\right)^{10}} \\cdot 3");
+    assert_eq!(latex(expr, options(FracStyle::Inline, MulSymbol::Times, true)),
+               "2 \\times x / \\left(y + 1\\right)^{10} \\times 3");
+    assert_eq!(latex(expr, options(FracStyle::Inline, MulSymbol::None, false)),
+               "2 x / \\left(y + 1\\right)^{10} \\cdot 3");
+    assert_eq!(latex("x^2 * y", options(FracStyle::Display, MulSymbol::None, false)),
+               "x^2 y");
+    assert_eq!(latex("(a / b)^2", options(FracStyle::Display, MulSymbol::Cdot, false)),
+               "\\left(\\frac{a}{b}\\right)^2");
+
+    // every combination renders well-formed latex
+    let exprs = ["2 * x / (y + 1)^10 * 3", "(a / b)^-2", "-x^(1/2) % 3!",
+                 "max(a * b, c / d)^2 - 1.5^x"];
+    for frac_style in [FracStyle::Inline, FracStyle::Display] {
+        for mul_symbol in [MulSymbol::Cdot, MulSymbol::Times, MulSymbol::None] {
+            for power_braces in [true, false] {
+                for expr in exprs {
+                    let out = latex(expr, options(frac_style, mul_symbol, power_braces));
+                    assert!(well_formed(&out), "{} => {}", expr, out);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn print_malformed() {
+    use std::fmt::Write;
+    // hand built expressions fail to print, the try_ printers say why
+    let rpn = RPNExpr::from(vec![MathToken::Number(1.0), MathToken::Function("max".to_string(), 3)]);
+    assert_eq!(rpn.try_to_infix(), Err("Missing operands for Function(\"max\", 3)".to_string()));
+    assert_eq!(rpn.try_to_latex(LatexOptions::default()),
+               Err("Missing operands for Function(\"max\", 3)".to_string()));
+    assert!(write!(String::new(), "{}", rpn).is_err());
+    let rpn = RPNExpr::from(vec![MathToken::OParen]);
+    assert_eq!(rpn.try_to_latex(LatexOptions::default()),
+               Err("Unexpected token for RPN print: OParen".to_string()));
+    let rpn = RPNExpr::from(vec![]);
+    assert_eq!(rpn.try_to_infix(), Err("Failed to print RPN: RPNExpr([])".to_string()));
+    assert!(write!(String::new(), "{}", rpn).is_err());
+    let rpn = ShuntingParser::parse_str("2 * (x + 1)").unwrap();
+    assert_eq!(rpn.try_to_infix(), Ok(rpn.to_infix()));
+}
+
+#[test]
+#[should_panic(expected = "Missing operands for BOp")]
+fn print_malformed_panics() {
+    RPNExpr::from(vec![MathToken::BOp("+".to_string())]).to_latex();
+}
@@ -0,0 +1,237 @@
+use crate::parser::RPNExpr;
+use crate::rpneval::EvalError;
+use lexers::MathToken;
+use std::collections::HashMap;
+use std::fmt;
+
+// An exact fraction, always kept reduced and with a positive denominator
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub struct Rational64(i64, i64);
+
+// On magnitudes so i64::MIN has one, it's 2^63 when both are i64::MIN
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+impl Rational64 {
+    pub fn new(num: i64, den: i64) -> Rational64 {
+        Self::checked_new(num, den).expect("Rational64 with zero denominator")
+    }
+
+    fn checked_new(num: i64, den: i64) -> Result<Rational64, EvalError> {
+        if den == 0 {
+            return Err(EvalError::DivisionByZero);
+        }
+        // reduced in i128 so the gcd and flipped signs always fit
+        let g = i128::from(gcd(num.unsigned_abs(), den.unsigned_abs()));
+        let (num, den) = (i128::from(num) / g, i128::from(den) / g);
+        let (num, den) = if den < 0 { (-num, -den) } else { (num, den) };
+        match (i64::try_from(num), i64::try_from(den)) {
+            (Ok(num), Ok(den)) => Ok(Rational64(num, den)),
+            _ => Err(EvalError::Overflow),
+        }
+    }
+
+    // Build from the shortest decimal representation, eg: 0.1 => 1/10
+    pub fn from_f64(n: f64) -> Result<Rational64, EvalError> {
+        if !n.is_finite() {
+            return Err(EvalError::NotRational);
+        }
+        let repr = n.to_string();
+        let (int_part, frac_part) = repr.split_once('.').unwrap_or((&repr, ""));
+        let digits = format!("{}{}", int_part, frac_part);
+        let num = digits.parse::<i64>().map_err(|_| EvalError::Overflow)?;
+        let den = u32::try_from(frac_part.len()).ok()
+            .and_then(|exp| 10i64.checked_pow(exp))
+            .ok_or(EvalError::Overflow)?;
+        Self::checked_new(num, den)
+    }
+
+    pub fn numer(&self) -> i64 { self.0 }
+
+    pub fn denom(&self) -> i64 { self.1 }
+
+    pub fn is_integer(&self) -> bool { self.1 == 1 }
+
+    pub fn to_f64(&self) -> f64 { self.0 as f64 / self.1 as f64 }
+
+    pub fn checked_add(self, other: Rational64) -> Result<Rational64, EvalError> {
+        let num = self.0.checked_mul(other.1)
+            .zip(other.0.checked_mul(self.1))
+            .and_then(|(a, b)| a.checked_add(b));
+        let den = self.1.checked_mul(other.1);
+        Self::checked_new(num.ok_or(EvalError::Overflow)?, den.ok_or(EvalError::Overflow)?)
+    }
+
+    pub fn checked_neg(self) -> Result<Rational64, EvalError> {
+        Ok(Rational64(self.0.checked_neg().ok_or(EvalError::Overflow)?, self.1))
+    }
+
+    pub fn checked_sub(self, other: Rational64) -> Result<Rational64, EvalError> {
+        self.checked_add(other.checked_neg()?)
+    }
+
+    pub fn checked_mul(self, other: Rational64) -> Result<Rational64, EvalError> {
+        let num = self.0.checked_mul(other.0).ok_or(EvalError::Overflow)?;
+        let den = self.1.checked_mul(other.1).ok_or(EvalError::Overflow)?;
+        Self::checked_new(num, den)
+    }
+
+    pub fn checked_recip(self) -> Result<Rational64, EvalError> {
+        Self::checked_new(self.1, self.0)
+    }
+
+    // Not through checked_recip, 1/i64::MIN doesn't fit but MIN/MIN does
+    pub fn checked_div(self, other: Rational64) -> Result<Rational64, EvalError> {
+        if other.0 == 0 {
+            return Err(EvalError::DivisionByZero);
+        }
+        let num = self.0.checked_mul(other.1).ok_or(EvalError::Overflow)?;
+        let den = self.1.checked_mul(other.0).ok_or(EvalError::Overflow)?;
+        Self::checked_new(num, den)
+    }
+
+    // Remainder truncating towards zero, same sign as self (like f64's %)
+    pub fn checked_rem(self, other: Rational64) -> Result<Rational64, EvalError> {
+        let quotient = self.checked_div(other)?;
+        let truncated = Rational64(quotient.0 / quotient.1, 1);
+        self.checked_sub(other.checked_mul(truncated)?)
+    }
+
+    // Exact powers. Fractional exponents only work for perfect roots.
+    pub fn checked_pow(self, exp: Rational64) -> Result<Rational64, EvalError> {
+        let base = if exp.1 == 1 { self } else { self.root(exp.1)? };
+        let magnitude = u32::try_from(exp.0.unsigned_abs()).map_err(|_| EvalError::Overflow)?;
+        let num = base.0.checked_pow(magnitude).ok_or(EvalError::Overflow)?;
+        let den = base.1.checked_pow(magnitude).ok_or(EvalError::Overflow)?;
+        let power = Self::checked_new(num, den)?;
+        if exp.0 < 0 { power.checked_recip() } else { Ok(power) }
+    }
+
+    fn root(self, n: i64) -> Result<Rational64, EvalError> {
+        fn int_root(x: u64, n: u32) -> Option<i64> {
+            let guess = (x as f64).powf(1.0 / n as f64).round() as u64;
+            // float guess may be off by one for large values
+            (guess.saturating_sub(1)..=guess + 1).find(|r| r.checked_pow(n) == Some(x))
+                .and_then(|root| i64::try_from(root).ok())
+        }
+        let n = u32::try_from(n).map_err(|_| EvalError::NotRational)?;
+        if self.0 < 0 && n % 2 == 0 {
+            return Err(EvalError::NotRational);
+        }
+        let num = int_root(self.0.unsigned_abs(), n).ok_or(EvalError::NotRational)?;
+        let den = int_root(self.1.unsigned_abs(), n).ok_or(EvalError::NotRational)?;
+        Ok(Rational64(self.0.signum() * num, den))
+    }
+
+    pub fn checked_factorial(self) -> Result<Rational64, EvalError> {
+        if !self.is_integer() || self.0 < 0 {
+            return Err(EvalError::NotRational);
+        }
+        let mut fact: i64 = 1;
+        for i in 2..=self.0 {
+            fact = fact.checked_mul(i).ok_or(EvalError::Overflow)?;
+        }
+        Ok(Rational64(fact, 1))
+    }
+}
+
+// Compare by cross-multiplying, can't overflow in i128
+impl Ord for Rational64 {
+    fn cmp(&self, other: &Rational64) -> std::cmp::Ordering {
+        (self.0 as i128 * other.1 as i128).cmp(&(other.0 as i128 * self.1 as i128))
+    }
+}
+
+impl PartialOrd for Rational64 {
+    fn partial_cmp(&self, other: &Rational64) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Rational64 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.1 {
+            1 => write!(f, "{}", self.0),
+            den => write!(f, "{}/{}", self.0, den),
+        }
+    }
+}
+
+const ZERO: Rational64 = Rational64(0, 1);
+
+// Same as MathContext::eval, predicates are 1 when true and 0 when false
+fn truth(value: bool) -> Rational64 {
+    Rational64(i64::from(value), 1)
+}
+
+impl RPNExpr {
+    // Exact evaluation, NotRational for irrational results and for
+    // registered operators, which evaluate on f64
+    pub fn evaluate_rational(&self, vars: &HashMap<String, Rational64>)
+            -> Result<Rational64, EvalError> {
+        let mut operands = Vec::new();
+        for token in &self.0 {
+            match token {
+                MathToken::Number(n) => operands.push(Rational64::from_f64(*n)?),
+                MathToken::Variable(v) => operands.push(
+                    *vars.get(v).ok_or_else(|| EvalError::UnknownVariable(v.clone()))?),
+                MathToken::BOp(op) => {
+                    let rhs = operands.pop().ok_or(EvalError::MissingOperands)?;
+                    let lhs = operands.pop().ok_or(EvalError::MissingOperands)?;
+                    if self.1.evaluates(op, 2) {
+                        return Err(EvalError::NotRational);
+                    }
+                    operands.push(match &op[..] {
+                        "+" => lhs.checked_add(rhs)?,
+                        "-" => lhs.checked_sub(rhs)?,
+                        "*" => lhs.checked_mul(rhs)?,
+                        "/" => lhs.checked_div(rhs)?,
+                        "%" => lhs.checked_rem(rhs)?,
+                        "^" | "**" => lhs.checked_pow(rhs)?,
+                        "==" => truth(lhs == rhs),
+                        "!=" => truth(lhs != rhs),
+                        "<" => truth(lhs < rhs),
+                        "<=" => truth(lhs <= rhs),
+                        ">" => truth(lhs > rhs),
+                        ">=" => truth(lhs >= rhs),
+                        "&&" => truth(lhs != ZERO && rhs != ZERO),
+                        "||" => truth(lhs != ZERO || rhs != ZERO),
+                        _ => return Err(EvalError::UnknownOp(op.clone())),
+                    });
+                }
+                MathToken::UOp(op) => {
+                    let arg = operands.pop().ok_or(EvalError::MissingOperands)?;
+                    if self.1.evaluates(op, 1) {
+                        return Err(EvalError::NotRational);
+                    }
+                    operands.push(match &op[..] {
+                        "-" => arg.checked_neg()?,
+                        "!" => arg.checked_factorial()?,
+                        "not" => truth(arg == ZERO),
+                        _ => return Err(EvalError::UnknownOp(op.clone())),
+                    });
+                }
+                MathToken::Function(fname, arity) => {
+                    if *arity > operands.len() {
+                        return Err(EvalError::MissingOperands);
+                    }
+                    let args = operands.split_off(operands.len() - arity);
+                    operands.push(match (&fname[..], args.len()) {
+                        ("abs", 1) => Rational64(args[0].0.checked_abs()
+                                                 .ok_or(EvalError::Overflow)?, args[0].1),
+                        ("max", n) if n > 0 => *args.iter().max().unwrap(),
+                        ("min", n) if n > 0 => *args.iter().min().unwrap(),
+                        // other functions (sin, log, ...) have irrational results
+                        _ => return Err(EvalError::NotRational),
+                    });
+                }
+                _ => return Err(EvalError::UnexpectedToken(token.clone())),
+            }
+        }
+        operands.pop().ok_or(EvalError::MissingOperands)
+    }
+}
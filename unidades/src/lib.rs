@@ -1,4 +1,4 @@
-#![deny(warnings)]
+#![cfg_attr(feature = "strict", deny(warnings))]
 
 mod si_units;
 pub use si_units::units;